@@ -1,24 +1,56 @@
-use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::Duration;
+
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
 use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::timeout;
 
 use rust_huge_project::protocol::{
-    AlertDirection, AlertRequest, ClientMsg, ServerMsg, parse_server_msg,
+    AlertDirection, AlertRequest, CMD_ADD_MANY, CMD_BRACKET, CMD_PREFS, ClientMsg, HistoryRange,
+    ServerMsg, parse_client_msg, parse_page, parse_server_line, parse_server_msg,
 };
 
+const USAGE: &str = "Usage: client [--script <FILE>] [--json]";
+
+// How long script mode waits for the reply to a single command.
+const SCRIPT_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
+    let mut script_path: Option<String> = None;
+    let mut json_output = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => match args.next() {
+                Some(path) => script_path = Some(path),
+                None => usage_error("--script requires a file path"),
+            },
+            "--json" => json_output = true,
+            other => usage_error(&format!("Unknown argument: {other}")),
+        }
+    }
+
     let addr = "127.0.0.1:1234";
     let stream = TcpStream::connect(addr).await?;
-    println!("[client] Connected to {addr}");
 
     // We split the socket so we can listen for incoming alerts
     // and send user commands at the exact same time without locking issues.
     let (read_half, mut write_half) = stream.into_split();
     let mut server_lines = BufReader::new(read_half).lines();
+
+    if let Some(path) = script_path {
+        return run_script(&path, &mut server_lines, &mut write_half).await;
+    }
+
+    // In JSON mode stdout carries only server messages, so status lines go to stderr.
+    eprintln!("[client] Connected to {addr}");
     let stdin = tokio::io::stdin();
     let mut user_lines = BufReader::new(stdin).lines();
 
-    print_help();
+    if !json_output {
+        print_help();
+    }
 
     loop {
         tokio::select! {
@@ -26,14 +58,14 @@ async fn main() -> io::Result<()> {
             line = server_lines.next_line() => {
                 match line? {
                     Some(line) => {
-                        if let Some(msg) = handle_server_line(&line) {
+                        if let Some(msg) = handle_server_line(&line, json_output) {
                             let wire = msg.to_wire();
                             write_half.write_all(wire.as_bytes()).await?;
                             write_half.flush().await?;
                         }
                     }
                     None => {
-                        println!("[client] Server closed the connection.");
+                        eprintln!("[client] Server closed the connection.");
                         break;
                     }
                 }
@@ -48,7 +80,7 @@ async fn main() -> io::Result<()> {
                             continue;
                         }
                         if line.eq("quit") {
-                            println!("[client] Quitting");
+                            eprintln!("[client] Quitting");
                             break;
                         }
                         if line.eq("help") {
@@ -64,13 +96,13 @@ async fn main() -> io::Result<()> {
                                 write_half.flush().await?;
                             }
                             None => {
-                                println!("[client] Invalid command. Type 'help'.");
+                                eprintln!("[client] Invalid command. Type 'help'.");
                             }
                         }
                     }
 
                     None => {
-                        println!("[client] stdin closed.");
+                        eprintln!("[client] stdin closed.");
                         break;
                     }
 
@@ -82,31 +114,127 @@ async fn main() -> io::Result<()> {
     Ok(())
 }
 
+// Sends every command from the script file and prints the server replies as JSON lines.
+async fn run_script(
+    path: &str,
+    server_lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    write_half: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    let script = tokio::fs::read_to_string(path).await?;
+
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some(msg) = parse_user_cmd(line) else {
+            eprintln!("[client] Invalid command at line {}: {line}", idx + 1);
+            continue;
+        };
+
+        // The line number tags the command, the server echoes it on the reply.
+        let req_id = idx as u64 + 1;
+        write_half
+            .write_all(msg.to_wire_with_id(Some(req_id)).as_bytes())
+            .await?;
+        write_half.flush().await?;
+
+        // Pushes (triggers, bracket fills, heartbeats) may arrive before the actual reply, so we
+        // keep reading until the line with our id shows up.
+        loop {
+            let Ok(next) = timeout(SCRIPT_REPLY_TIMEOUT, server_lines.next_line()).await else {
+                eprintln!("[client] No reply for line {}: {line}", idx + 1);
+                break;
+            };
+
+            match next? {
+                Some(reply) => match parse_server_line(&reply) {
+                    (_, Some(ServerMsg::Ping)) => {
+                        write_half
+                            .write_all(ClientMsg::Pong.to_wire().as_bytes())
+                            .await?;
+                        write_half.flush().await?;
+                    }
+                    (id, Some(server_msg)) => {
+                        print_json(&server_msg);
+                        if id == Some(req_id) {
+                            break;
+                        }
+                    }
+                    (_, None) => eprintln!("[client] Unparsed server line: {reply}"),
+                },
+                None => {
+                    eprintln!("[client] Server closed the connection.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_json(msg: &ServerMsg) {
+    match serde_json::to_string(msg) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("[client] Failed to serialize server message: {e}"),
+    }
+}
+
 // Prints a short help for the user.
 fn print_help() {
     println!("Commands:");
     println!("  add <SYMBOL> <ABOVE|BELOW> <THRESHOLD>");
+    println!(
+        "  addmany <SYMBOL> <ABOVE|BELOW> <THRESHOLD> [<SYMBOL> <ABOVE|BELOW> <THRESHOLD> ...]"
+    );
     println!("  del <SYMBOL> <ABOVE|BELOW>");
+    println!("  update <SYMBOL> <ABOVE|BELOW> <NEW_THRESHOLD>");
     println!("  price <SYMBOL>");
     println!("  buy <SYMBOL> <QUANTITY>");
     println!("  sell <SYMBOL> <QUANTITY>");
-    println!("  data");
+    println!("  close <SYMBOL>");
+    println!("  bracket <SYMBOL> <STOP|-> <TARGET|->");
+    println!("  unbracket <SYMBOL>");
+    println!("  cancelall");
+    println!("  data [OFFSET [LIMIT]]");
+    println!("  symbols [OFFSET [LIMIT]]");
+    println!("  history <SYMBOL> [HOUR|DAY]");
     println!("  login <USERNAME> <PASSWORD>");
     println!("  register <USERNAME> <PASSWORD>");
+    println!("  reset <PASSWORD>");
+    println!("  leaderboard [LIMIT]");
+    println!("  whoami");
+    println!("  time");
+    println!("  export");
+    println!("  prefs [TRIGGERS=ON|OFF FILLS=ON|OFF]");
+    println!("  ping");
     println!("  help");
     println!("  quit");
     println!();
     println!("Examples:");
     println!("  add AAPL ABOVE 200");
     println!("  add TSLA BELOW 150");
+    println!("  addmany AAPL ABOVE 200 TSLA BELOW 150");
     println!("  del AAPL ABOVE");
+    println!("  update AAPL ABOVE 210");
     println!("  price AAPL");
     println!("  buy AAPL 5");
     println!("  sell AAPL 2");
+    println!("  close AAPL");
+    println!("  bracket AAPL 180 220");
+    println!("  prefs TRIGGERS=ON FILLS=ON");
     println!("  data");
     println!();
 }
 
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
 // Parses a user command into a ClientMsg.
 fn parse_user_cmd(line: &str) -> Option<ClientMsg> {
     let mut parts = line.split_whitespace();
@@ -116,7 +244,7 @@ fn parse_user_cmd(line: &str) -> Option<ClientMsg> {
         "add" => {
             let symbol = parts.next()?.to_string();
             let dir_str = parts.next()?;
-            let direction = AlertDirection::as_msg(&dir_str.to_ascii_uppercase())?;
+            let direction = AlertDirection::as_msg(dir_str)?;
             let threshold: f64 = parts.next()?.parse().ok()?;
 
             Some(ClientMsg::AddAlert(AlertRequest {
@@ -126,14 +254,32 @@ fn parse_user_cmd(line: &str) -> Option<ClientMsg> {
             }))
         }
 
+        // Same triples as on the wire, so the protocol parser does the work.
+        "addmany" => {
+            let rest: Vec<&str> = parts.collect();
+            parse_client_msg(&format!("{CMD_ADD_MANY} {}", rest.join(" ")))
+        }
+
         "del" => {
             let symbol = parts.next()?.to_string();
             let dir_str = parts.next()?;
-            let direction = AlertDirection::as_msg(&dir_str.to_ascii_uppercase())?;
+            let direction = AlertDirection::as_msg(dir_str)?;
 
             Some(ClientMsg::RemoveAlert { symbol, direction })
         }
 
+        "update" => {
+            let symbol = parts.next()?.to_string();
+            let direction = AlertDirection::as_msg(parts.next()?)?;
+            let new_threshold: f64 = parts.next()?.parse().ok()?;
+
+            Some(ClientMsg::UpdateAlert {
+                symbol,
+                direction,
+                new_threshold,
+            })
+        }
+
         "login" => {
             let username = parts.next()?.to_string();
             let password = parts.next()?.to_string();
@@ -148,6 +294,35 @@ fn parse_user_cmd(line: &str) -> Option<ClientMsg> {
             Some(ClientMsg::RegisterClient { username, password })
         }
 
+        "leaderboard" => {
+            let limit = match parts.next() {
+                Some(token) => Some(token.parse().ok()?),
+                None => None,
+            };
+            Some(ClientMsg::GetLeaderboard { limit })
+        }
+
+        "whoami" => Some(ClientMsg::WhoAmI),
+
+        "time" => Some(ClientMsg::GetServerTime),
+
+        "export" => Some(ClientMsg::ExportData),
+
+        // The send time is the nonce, the reply is timed against it.
+        "ping" => Some(ClientMsg::Ping {
+            nonce: unix_millis(),
+        }),
+
+        "prefs" => {
+            let rest: Vec<&str> = parts.collect();
+            parse_client_msg(&format!("{CMD_PREFS} {}", rest.join(" ")))
+        }
+
+        "reset" => {
+            let password = parts.next()?.to_string();
+            Some(ClientMsg::ResetAccount { password })
+        }
+
         "price" => {
             let symbol = parts.next()?.to_string();
             Some(ClientMsg::CheckPrice { symbol })
@@ -167,13 +342,74 @@ fn parse_user_cmd(line: &str) -> Option<ClientMsg> {
             Some(ClientMsg::SellStock { symbol, quantity })
         }
 
-        "data" => Some(ClientMsg::GetAllClientData),
+        "close" => {
+            let symbol = parts.next()?.to_string();
+            Some(ClientMsg::ClosePosition { symbol })
+        }
+
+        // Same arguments as on the wire, `-` leaves one side open.
+        "bracket" => {
+            let rest: Vec<&str> = parts.collect();
+            parse_client_msg(&format!("{CMD_BRACKET} {}", rest.join(" ")))
+        }
+
+        "unbracket" => {
+            let symbol = parts.next()?.to_string();
+            Some(ClientMsg::CancelBracket { symbol })
+        }
+
+        "cancelall" => Some(ClientMsg::CancelAllOrders),
+
+        "data" => {
+            let (offset, limit) = parse_page(&mut parts)?;
+            Some(ClientMsg::GetAllClientData { offset, limit })
+        }
+
+        "symbols" => {
+            let (offset, limit) = parse_page(&mut parts)?;
+            Some(ClientMsg::ListSymbols { offset, limit })
+        }
+
+        "history" => {
+            let symbol = parts.next()?.to_string();
+            let range = match parts.next() {
+                Some(token) => HistoryRange::as_msg(&token.to_ascii_uppercase())?,
+                None => HistoryRange::Day,
+            };
+
+            Some(ClientMsg::GetHistory { symbol, range })
+        }
 
         _ => None,
     }
 }
 
-fn handle_server_line(line: &str) -> Option<ClientMsg> {
+// Bad command line arguments exit with 2, like other CLI tools.
+fn usage_error(message: &str) -> ! {
+    eprintln!("[client] {message}");
+    eprintln!("{USAGE}");
+    std::process::exit(2);
+}
+
+fn handle_server_line(line: &str, json_output: bool) -> Option<ClientMsg> {
+    if json_output {
+        return match parse_server_msg(line) {
+            // Heartbeats are answered quietly so they don't pollute the JSON stream.
+            Some(ServerMsg::Ping) => Some(ClientMsg::Pong),
+            Some(msg) => {
+                print_json(&msg);
+                matches!(msg, ServerMsg::UserLogged).then_some(ClientMsg::GetAllClientData {
+                    offset: 0,
+                    limit: None,
+                })
+            }
+            None => {
+                eprintln!("[client] Unparsed server line: {line}");
+                None
+            }
+        };
+    }
+
     match parse_server_msg(line) {
         Some(ServerMsg::AlertTriggered {
             symbol,
@@ -182,46 +418,107 @@ fn handle_server_line(line: &str) -> Option<ClientMsg> {
             current_price,
         }) => {
             println!(
-                "[ALERT] {symbol} {:?} threshold={} current={}",
+                "[ALERT] {symbol} {} threshold={} current={}",
                 direction, threshold, current_price.value
             );
             None
         }
-        Some(ServerMsg::PriceChecked { symbol, price }) => {
-            println!("[PRICE INFO] {symbol} price={}", price);
+        Some(ServerMsg::PriceChecked {
+            symbol,
+            price,
+            stats,
+        }) => {
+            println!("[PRICE INFO] {symbol} price={}{}", price, stats.summary());
             None
         }
         Some(ServerMsg::AlertAdded {
             symbol,
             direction,
             threshold,
+            price_available,
+            triggers_now,
+        }) => {
+            println!(
+                "[ALERT ADDED] {symbol} {} threshold={}{}",
+                direction,
+                threshold,
+                if !price_available {
+                    " (no price data yet)"
+                } else if triggers_now {
+                    " (price already past the threshold, triggers on the next check)"
+                } else {
+                    ""
+                }
+            );
+            None
+        }
+        Some(ServerMsg::AlertsAdded {
+            added,
+            skipped,
+            unpriced,
+        }) => {
+            println!("[ALERTS ADDED] added={added} skipped={skipped} unpriced={unpriced}");
+            None
+        }
+        Some(ServerMsg::AlertFailed {
+            symbol,
+            direction,
+            threshold,
+            reason,
         }) => {
             println!(
-                "[ALERT ADDED] {symbol} {:?} threshold={}",
+                "[ALERT FAILED] {symbol} {} threshold={} reason={reason}",
                 direction, threshold
             );
             None
         }
+        Some(ServerMsg::TradeRejected { symbol, reason }) => {
+            println!("[TRADE REJECTED] {symbol} reason={reason}");
+            None
+        }
         Some(ServerMsg::AlertRemoved { symbol, direction }) => {
-            println!("[ALERT REMOVED] {symbol} {:?}", direction);
+            println!("[ALERT REMOVED] {symbol} {}", direction);
+            None
+        }
+        Some(ServerMsg::AlertUpdated {
+            symbol,
+            direction,
+            threshold,
+        }) => {
+            println!(
+                "[ALERT UPDATED] {symbol} {} threshold={}",
+                direction, threshold
+            );
             None
         }
         Some(ServerMsg::StockBought { symbol, quantity }) => {
             println!("[BOUGHT] {symbol} quantity={}", quantity);
             None
         }
-        Some(ServerMsg::StockSold { symbol, quantity }) => {
-            println!("[SOLD] {symbol} quantity={}", quantity);
+        Some(ServerMsg::StockSold {
+            symbol,
+            quantity,
+            profit,
+        }) => {
+            match profit {
+                Some(profit) => println!("[SOLD] {symbol} quantity={} profit={}", quantity, profit),
+                None => println!("[SOLD] {symbol} quantity={}", quantity),
+            }
             None
         }
-        Some(ServerMsg::AllClientData { stocks, alerts }) => {
+        Some(ServerMsg::AllClientData {
+            stocks,
+            alerts,
+            has_more,
+            failed,
+        }) => {
             println!("[DATA] Portfolio:");
             if stocks.is_empty() {
                 println!("  (empty)");
             } else {
                 for stock in stocks {
                     println!(
-                        "  {} quantity={} total_price={}",
+                        "  {} quantity={} cost={}",
                         stock.symbol, stock.quantity, stock.total_price
                     );
                 }
@@ -232,28 +529,129 @@ fn handle_server_line(line: &str) -> Option<ClientMsg> {
             } else {
                 for alert in alerts {
                     println!(
-                        "  {} {:?} threshold={}",
+                        "  {} {} threshold={}",
                         alert.symbol, alert.direction, alert.threshold
                     );
                 }
             }
+            if has_more {
+                println!("  (more available, use 'data <OFFSET> [LIMIT]')");
+            }
+            if !failed.is_empty() {
+                println!("  (partial, couldn't load: {})", failed.join(", "));
+            }
+            None
+        }
+        Some(ServerMsg::SymbolList {
+            symbols,
+            names,
+            has_more,
+        }) => {
+            let listed: Vec<String> = symbols
+                .into_iter()
+                .map(|symbol| match names.get(&symbol) {
+                    Some(name) => format!("{symbol} ({name})"),
+                    None => symbol,
+                })
+                .collect();
+            println!("[SYMBOLS] {}", listed.join(" "));
+            if has_more {
+                println!("  (more available, use 'symbols <OFFSET> [LIMIT]')");
+            }
+            None
+        }
+        Some(ServerMsg::History { symbol, points }) => {
+            println!("[HISTORY] {symbol} points={}", points.len());
+            for point in points {
+                println!("  {} close={}", point.timestamp, point.close);
+            }
             None
         }
         Some(ServerMsg::UserLogged) => {
             println!("[LOGIN] Logged in successfully.");
-            Some(ClientMsg::GetAllClientData)
+            Some(ClientMsg::GetAllClientData {
+                offset: 0,
+                limit: None,
+            })
         }
         Some(ServerMsg::UserRegistered) => {
             println!("[REGISTER] Registered successfully.");
             None
         }
+        Some(ServerMsg::AccountReset) => {
+            println!("[RESET] All positions removed, alerts are kept.");
+            None
+        }
+        Some(ServerMsg::Leaderboard { entries }) => {
+            println!("[LEADERBOARD]");
+            if entries.is_empty() {
+                println!("  (nobody ranked yet)");
+            }
+            for (rank, entry) in entries.iter().enumerate() {
+                println!("  {}. {}", rank + 1, entry.summary());
+            }
+            None
+        }
+        Some(ServerMsg::Identity { user_id, username }) => {
+            println!("[WHOAMI] {username} (id {user_id})");
+            None
+        }
+        Some(ServerMsg::ServerTime { unix_secs }) => {
+            let skew = (unix_millis() / 1000) as i64 - unix_secs;
+            println!("[TIME] {unix_secs} (local clock {skew:+}s from the server)");
+            None
+        }
+        Some(ServerMsg::DataExport { json }) => {
+            // Pretty printed when it parses, the raw line otherwise.
+            let pretty = serde_json::from_str::<serde_json::Value>(&json)
+                .and_then(|value| serde_json::to_string_pretty(&value));
+            println!("[EXPORT]\n{}", pretty.unwrap_or(json));
+            None
+        }
+        Some(ServerMsg::Preferences(prefs)) => {
+            println!("[PREFS] triggers={} fills={}", prefs.triggers, prefs.fills);
+            None
+        }
+        Some(ServerMsg::BracketSet {
+            symbol,
+            stop,
+            target,
+        }) => {
+            println!("[BRACKET] {symbol} stop={stop:?} target={target:?}");
+            None
+        }
+        Some(ServerMsg::BracketCancelled { symbol }) => {
+            println!("[UNBRACKET] {symbol}");
+            None
+        }
+        Some(ServerMsg::OrdersCancelled { count }) => {
+            println!("[CANCELALL] {count} orders cancelled");
+            None
+        }
+        Some(ServerMsg::BracketTriggered {
+            symbol,
+            quantity,
+            price,
+            profit,
+        }) => {
+            println!("[BRACKET SOLD] {symbol} quantity={quantity} price={price} profit={profit}");
+            None
+        }
         Some(ServerMsg::Error(msg)) => {
             println!("[SERVER ERROR] {msg}");
             None
         }
+        Some(ServerMsg::Ping) => Some(ClientMsg::Pong),
+        Some(ServerMsg::Heartbeat { nonce }) => {
+            println!(
+                "[PONG] round trip {} ms",
+                unix_millis().saturating_sub(nonce)
+            );
+            None
+        }
 
         None => {
-            println!("[client] Unparsed server line: {line}");
+            eprintln!("[client] Unparsed server line: {line}");
             None
         }
     }
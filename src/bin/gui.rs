@@ -1,23 +1,162 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::io::{self, BufRead, BufReader, Write};
 use std::net::TcpStream;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{Receiver, Sender, unbounded};
+use crossbeam_channel::{Receiver, Sender, TryRecvError, TrySendError, bounded};
 
-use rust_huge_project::database::PortfolioStock;
+use rust_huge_project::database::{ERR_ALERT_NOT_FOUND, PortfolioStock};
 use rust_huge_project::protocol::{
-    AlertDirection, AlertRequest, ClientMsg, ServerMsg, parse_server_msg,
+    AlertDirection, AlertRequest, ClientMsg, DEFAULT_MAX_THRESHOLD, ERR_DB_UNAVAILABLE,
+    ERR_LOGIN_FAILED, ERR_NOT_LOGGED_IN, ERR_SERVER_FULL, HistoryPoint, HistoryRange,
+    LeaderboardEntry, Preferences, QuoteStats, ServerMsg, check_threshold, parse_server_line,
 };
 
 use eframe::egui;
 
+// How many symbols the autocomplete dropdown shows at once.
+const MAX_SYMBOL_SUGGESTIONS: usize = 8;
+
+// How long a buy/sell waits for its price quote before it is dropped.
+const PENDING_TRADE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Portfolio and alerts are fetched in pages of this size after login.
+const DATA_PAGE_SIZE: usize = 100;
+
+// Portfolio and alerts are re-fetched every DEFAULT_DATA_REFRESH_SECS while logged in, the user
+// can change it between MIN_DATA_REFRESH_SECS and MAX_DATA_REFRESH_SECS or turn it off.
+const DEFAULT_DATA_REFRESH_SECS: u64 = 30;
+const MIN_DATA_REFRESH_SECS: u64 = 5;
+const MAX_DATA_REFRESH_SECS: u64 = 600;
+
+// Prices, thresholds and totals are shown with DEFAULT_PRICE_DECIMALS decimals and the
+// currency symbol in front, the user can change both. Editing a threshold keeps the raw value.
+const DEFAULT_PRICE_DECIMALS: usize = 2;
+const MAX_PRICE_DECIMALS: usize = 6;
+const DEFAULT_CURRENCY_SYMBOL: &str = "$";
+
+#[derive(Debug, Clone, PartialEq)]
+struct MoneyFormat {
+    decimals: usize,
+    currency: String,
+}
+
+impl Default for MoneyFormat {
+    fn default() -> Self {
+        MoneyFormat {
+            decimals: DEFAULT_PRICE_DECIMALS,
+            currency: DEFAULT_CURRENCY_SYMBOL.into(),
+        }
+    }
+}
+
+impl MoneyFormat {
+    // The sign goes before the currency: -$1.50.
+    fn format(&self, value: f64) -> String {
+        let sign = if value < 0.0 { "-" } else { "" };
+        let decimals = self.decimals.min(MAX_PRICE_DECIMALS);
+        format!("{sign}{}{:.*}", self.currency, decimals, value.abs())
+    }
+}
+
+// While connected a PING with a fresh nonce goes out this often, the matching PONG gives the
+// round-trip time shown in the top bar.
+const LATENCY_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+// A local clock further than this from the server's gets a warning in the log.
+const MAX_CLOCK_SKEW_SECS: i64 = 5;
+
+// The ticker bar asks for a quote of every watched symbol (alerts and held positions) this often.
+const TICKER_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+// Last quote of a ticker symbol and which way it moved from the one before.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TickerQuote {
+    price: f64,
+    trend: std::cmp::Ordering,
+}
+
+impl TickerQuote {
+    fn next(previous: Option<TickerQuote>, price: f64) -> Self {
+        let trend = previous
+            .and_then(|quote| price.partial_cmp(&quote.price))
+            .unwrap_or(std::cmp::Ordering::Equal);
+        TickerQuote { price, trend }
+    }
+}
+
+fn ticker_symbols(alerts: &[AlertRow], portfolio: &[PortfolioStock]) -> Vec<String> {
+    let symbols: BTreeSet<&str> = alerts
+        .iter()
+        .map(|a| a.symbol.as_str())
+        .chain(portfolio.iter().map(|p| p.symbol.as_str()))
+        .collect();
+    symbols.into_iter().map(str::to_string).collect()
+}
+
+// "—" until there is a quote, and for all of them while disconnected.
+fn ticker_text(symbol: &str, quote: Option<&TickerQuote>, money: &MoneyFormat) -> String {
+    match quote {
+        Some(quote) => format!("{symbol} {}", money.format(quote.price)),
+        None => format!("{symbol} —"),
+    }
+}
+
+// Toasts stay for TOAST_DURATION, fading out over the last TOAST_FADE; only the newest
+// MAX_TOASTS are kept on screen.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+const TOAST_FADE: Duration = Duration::from_secs(1);
+const MAX_TOASTS: usize = 4;
+
+// After losing a logged-in connection with remembered credentials the GUI reconnects on its
+// own, waiting RECONNECT_DELAY between tries and giving up after MAX_RECONNECT_ATTEMPTS.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const USER_DISCONNECT_REASON: &str = "Disconnected by user";
+
+// The chosen color theme is kept next to the app, so it survives a restart.
+const THEME_FILE: &str = "gui-theme.txt";
+
+// How many of the newest history points the chart keeps, saved next to the app like the theme.
+const CHART_DEPTH_FILE: &str = "gui-chart-depth.txt";
+const DEFAULT_CHART_DEPTH: usize = 200;
+const MIN_CHART_DEPTH: usize = 10;
+const MAX_CHART_DEPTH: usize = 2000;
+
+// EXPORT replies are saved next to the app as `<username>-export.json`.
+const EXPORT_FILE_SUFFIX: &str = "-export.json";
+
+// Both channels between the UI and the network worker are bounded.
+// Events: quotes nobody is waiting for and log lines are dropped when the queue is full,
+// everything else (connection state, trades, alerts, data) blocks the worker until the UI
+// catches up, which in turn stops reading the socket and pushes back on the server.
+// Commands: the UI never blocks, a command that doesn't fit is dropped and logged.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+const COMMAND_CHANNEL_CAPACITY: usize = 256;
+
+// Top-bar status during an outage, `retry_in` is set while waiting for the next try.
+fn reconnect_status(attempt: u32, retry_in: Option<Duration>) -> String {
+    match retry_in {
+        Some(delay) => format!(
+            "Reconnecting (attempt {attempt}/{MAX_RECONNECT_ATTEMPTS}) in {:.1}s...",
+            delay.as_secs_f32()
+        ),
+        None => format!("Reconnecting (attempt {attempt}/{MAX_RECONNECT_ATTEMPTS})..."),
+    }
+}
+
 fn main() -> eframe::Result<()> {
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Stock Alerts GUI",
         native_options,
-        Box::new(|_cc| Box::new(App::new())),
+        Box::new(|_cc| {
+            let mut app = App::new();
+            app.theme = load_theme(THEME_FILE);
+            app.chart_depth = load_chart_depth(CHART_DEPTH_FILE);
+            Box::new(app)
+        }),
     )
 }
 
@@ -36,6 +175,11 @@ enum UiCommand {
         symbol: String,
         dir: AlertDirection,
     },
+    UpdateAlert {
+        symbol: String,
+        dir: AlertDirection,
+        threshold: f64,
+    },
     LoginClient {
         username: String,
         password: String,
@@ -46,6 +190,7 @@ enum UiCommand {
     },
     CheckPrice {
         symbol: String,
+        req_id: Option<u64>,
     },
     BuyStock {
         symbol: String,
@@ -55,7 +200,80 @@ enum UiCommand {
         symbol: String,
         quantity: i32,
     },
-    GetAllClientData,
+    GetAllClientData {
+        offset: usize,
+    },
+    GetHistory {
+        symbol: String,
+    },
+    ListSymbols {
+        offset: usize,
+    },
+    AddAlerts {
+        alerts: Vec<AlertRow>,
+    },
+    ResetAccount {
+        password: String,
+    },
+    CancelAllOrders,
+    GetLeaderboard,
+    WhoAmI,
+    GetServerTime,
+    ExportData,
+    Ping {
+        nonce: u64,
+    },
+    GetPreferences,
+    SetPreferences(Preferences),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TradeFill {
+    Bought {
+        symbol: String,
+        quantity: i32,
+    },
+    // The profit is only known when the whole position was closed.
+    Sold {
+        symbol: String,
+        quantity: i32,
+        profit: Option<f64>,
+    },
+    Bracket {
+        symbol: String,
+        quantity: i32,
+        price: f64,
+        profit: f64,
+    },
+}
+
+impl TradeFill {
+    fn describe(&self, money: &MoneyFormat) -> String {
+        match self {
+            TradeFill::Bought { symbol, quantity } => format!("Bought {quantity}x {symbol}"),
+            TradeFill::Sold {
+                symbol,
+                quantity,
+                profit: Some(profit),
+            } => format!(
+                "Sold {quantity}x {symbol}, profit {}",
+                money.format(*profit)
+            ),
+            TradeFill::Sold {
+                symbol, quantity, ..
+            } => format!("Sold {quantity}x {symbol}"),
+            TradeFill::Bracket {
+                symbol,
+                quantity,
+                price,
+                profit,
+            } => format!(
+                "Bracket sold {quantity}x {symbol} at {}, profit {}",
+                money.format(*price),
+                money.format(*profit)
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -64,38 +282,101 @@ enum ClientEvent {
     Disconnected {
         reason: String,
     },
+    ServerFull,
+    SessionExpired,
     AlertTriggered {
         symbol: String,
         dir: AlertDirection,
         threshold: f64,
         current: f64,
+        // Synthesized locally by the "Test alert" button, the server knows nothing about it.
+        test: bool,
     },
     AlertAdded {
         symbol: String,
         dir: AlertDirection,
         threshold: f64,
+        price_available: bool,
+        triggers_now: bool,
     },
     AlertRemoved {
         symbol: String,
         dir: AlertDirection,
     },
+    AlertUpdated {
+        symbol: String,
+        dir: AlertDirection,
+        threshold: f64,
+    },
+    AlertFailed {
+        symbol: String,
+        dir: AlertDirection,
+        threshold: f64,
+        reason: String,
+    },
+    AlertsAdded {
+        added: usize,
+        skipped: usize,
+        unpriced: usize,
+    },
+    // BOUGHT/SOLD/BRACKETTRIGGERED confirmation, see TradeFill::describe.
+    TradeFilled(TradeFill),
+    TradeRejected {
+        symbol: String,
+        reason: String,
+    },
     AllClientData {
         stocks: Vec<PortfolioStock>,
         alerts: Vec<AlertRow>,
+        has_more: bool,
+        // Sections the server couldn't load, sent empty.
+        failed: Vec<String>,
+    },
+    SymbolList {
+        symbols: Vec<String>,
+        names: HashMap<String, String>,
+        has_more: bool,
+    },
+    History {
+        symbol: String,
+        points: Vec<HistoryPoint>,
     },
     UserLogged,
     UserRegistered,
-    ServerError(String),
+    AccountReset,
+    OrdersCancelled {
+        count: u64,
+    },
+    Leaderboard(Vec<LeaderboardEntry>),
+    Identity {
+        username: String,
+    },
+    ServerTime {
+        unix_secs: i64,
+    },
+    DataExport {
+        json: String,
+    },
+    Preferences(Preferences),
+    Heartbeat {
+        nonce: u64,
+    },
+    ServerError {
+        msg: String,
+        req_id: Option<u64>,
+    },
     PriceChecked {
         symbol: String,
         price: f64,
+        stats: QuoteStats,
+        req_id: Option<u64>,
     },
     Log(String),
 }
 
 fn spawn_network_worker() -> (Sender<UiCommand>, Receiver<ClientEvent>) {
-    let (cmd_tx, cmd_rx) = unbounded::<UiCommand>();
-    let (ev_tx, ev_rx) = unbounded::<ClientEvent>();
+    let (cmd_tx, cmd_rx) = bounded::<UiCommand>(COMMAND_CHANNEL_CAPACITY);
+    let (ev_tx, ev_rx) = bounded::<ClientEvent>(EVENT_CHANNEL_CAPACITY);
 
     thread::spawn(move || network_thread(cmd_rx, ev_tx));
 
@@ -154,7 +435,16 @@ fn network_thread(cmd_rx: Receiver<UiCommand>, ev_tx: Sender<ClientEvent>) {
 
                 match read_one_line(reader) {
                     Ok(Some(line)) => {
-                        handle_server_line(&line, &ev_tx);
+                        if let Some(reply) = heartbeat_reply(&line) {
+                            if stream.write_all(reply.as_bytes()).is_err() {
+                                state = NetState::Disconnected;
+                                let _ = ev_tx.send(ClientEvent::Disconnected {
+                                    reason: "write to server failed".into(),
+                                });
+                            }
+                        } else {
+                            handle_server_line(&line, &ev_tx);
+                        }
                     }
                     Ok(None) => {}
                     Err(e) => {
@@ -181,89 +471,155 @@ enum NetState {
     },
 }
 
+// What the worker does with a command while connected, decided apart from the socket.
+#[derive(Debug, PartialEq)]
+enum ConnectedAction {
+    Disconnect,
+    Send(String),
+    // Nothing goes to the server, the line only ends up in the log.
+    Log(String),
+}
+
+fn connected_action(cmd: UiCommand) -> Option<ConnectedAction> {
+    match cmd {
+        UiCommand::Disconnect => Some(ConnectedAction::Disconnect),
+        // The UI disables Connect while connected, this only catches one queued before that.
+        UiCommand::Connect { addr } => Some(ConnectedAction::Log(format!(
+            "Already connected, ignoring Connect to {addr}."
+        ))),
+        cmd => command_to_wire(cmd).map(ConnectedAction::Send),
+    }
+}
+
 fn handle_command_connected(
     cmd: UiCommand,
     stream: &mut TcpStream,
     ev_tx: &Sender<ClientEvent>,
 ) -> io::Result<()> {
-    match cmd {
-        UiCommand::Disconnect => {
+    match connected_action(cmd) {
+        Some(ConnectedAction::Disconnect) => {
             let _ = stream.shutdown(std::net::Shutdown::Both);
             let _ = ev_tx.send(ClientEvent::Disconnected {
-                reason: "Disconnected by user".into(),
+                reason: USER_DISCONNECT_REASON.into(),
             });
-            Ok(())
         }
+        Some(ConnectedAction::Send(wire)) => stream.write_all(wire.as_bytes())?,
+        Some(ConnectedAction::Log(msg)) => {
+            let _ = ev_tx.send(ClientEvent::Log(msg));
+        }
+        None => {}
+    }
+    Ok(())
+}
 
-        UiCommand::Connect { .. } => Ok(()),
+// Maps a UI command to the line sent to the server, None for commands handled by the worker itself.
+fn command_to_wire(cmd: UiCommand) -> Option<String> {
+    let (msg, req_id) = match cmd {
+        UiCommand::Connect { .. } | UiCommand::Disconnect => return None,
 
         UiCommand::AddAlert {
             symbol,
             dir,
             threshold,
-        } => {
-            let msg = ClientMsg::AddAlert(AlertRequest {
+        } => (
+            ClientMsg::AddAlert(AlertRequest {
                 symbol,
                 direction: dir,
                 threshold,
-            });
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
-        }
+            }),
+            None,
+        ),
+
+        UiCommand::AddAlerts { alerts } => (
+            ClientMsg::AddAlerts {
+                alerts: alerts
+                    .into_iter()
+                    .map(|a| AlertRequest {
+                        symbol: a.symbol,
+                        direction: a.dir,
+                        threshold: a.threshold,
+                    })
+                    .collect(),
+            },
+            None,
+        ),
 
-        UiCommand::RemoveAlert { symbol, dir } => {
-            let msg = ClientMsg::RemoveAlert {
+        UiCommand::RemoveAlert { symbol, dir } => (
+            ClientMsg::RemoveAlert {
                 symbol,
                 direction: dir,
-            };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
-        }
+            },
+            None,
+        ),
+
+        UiCommand::UpdateAlert {
+            symbol,
+            dir,
+            threshold,
+        } => (
+            ClientMsg::UpdateAlert {
+                symbol,
+                direction: dir,
+                new_threshold: threshold,
+            },
+            None,
+        ),
 
         UiCommand::LoginClient { username, password } => {
-            let msg = ClientMsg::LoginClient { username, password };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
+            (ClientMsg::LoginClient { username, password }, None)
         }
 
         UiCommand::RegisterClient { username, password } => {
-            let msg = ClientMsg::RegisterClient { username, password };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
+            (ClientMsg::RegisterClient { username, password }, None)
         }
 
-        UiCommand::CheckPrice { symbol } => {
-            let msg = ClientMsg::CheckPrice { symbol };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
-        }
+        UiCommand::ResetAccount { password } => (ClientMsg::ResetAccount { password }, None),
+        UiCommand::CancelAllOrders => (ClientMsg::CancelAllOrders, None),
+
+        UiCommand::GetLeaderboard => (ClientMsg::GetLeaderboard { limit: None }, None),
+        UiCommand::WhoAmI => (ClientMsg::WhoAmI, None),
+        UiCommand::GetServerTime => (ClientMsg::GetServerTime, None),
+        UiCommand::ExportData => (ClientMsg::ExportData, None),
+        UiCommand::Ping { nonce } => (ClientMsg::Ping { nonce }, None),
+        UiCommand::GetPreferences => (ClientMsg::GetPreferences, None),
+        UiCommand::SetPreferences(prefs) => (ClientMsg::SetPreferences(prefs), None),
+
+        UiCommand::CheckPrice { symbol, req_id } => (ClientMsg::CheckPrice { symbol }, req_id),
 
         UiCommand::BuyStock { symbol, quantity } => {
-            let msg = ClientMsg::BuyStock { symbol, quantity };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
+            (ClientMsg::BuyStock { symbol, quantity }, None)
         }
 
         UiCommand::SellStock { symbol, quantity } => {
-            let msg = ClientMsg::SellStock { symbol, quantity };
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
+            (ClientMsg::SellStock { symbol, quantity }, None)
         }
 
-        UiCommand::GetAllClientData => {
-            let msg = ClientMsg::GetAllClientData;
-            let wire = msg.to_wire();
-            stream.write_all(wire.as_bytes())?;
-            Ok(())
-        }
-    }
+        UiCommand::GetAllClientData { offset } => (
+            ClientMsg::GetAllClientData {
+                offset,
+                limit: Some(DATA_PAGE_SIZE),
+            },
+            None,
+        ),
+
+        UiCommand::ListSymbols { offset } => (
+            ClientMsg::ListSymbols {
+                offset,
+                limit: None,
+            },
+            None,
+        ),
+
+        UiCommand::GetHistory { symbol } => (
+            ClientMsg::GetHistory {
+                symbol,
+                range: HistoryRange::Day,
+            },
+            None,
+        ),
+    };
+
+    Some(msg.to_wire_with_id(req_id))
 }
 
 fn read_one_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>> {
@@ -279,49 +635,124 @@ fn read_one_line(reader: &mut BufReader<TcpStream>) -> io::Result<Option<String>
 }
 
 fn handle_server_line(line: &str, ev_tx: &Sender<ClientEvent>) {
-    match parse_server_msg(line) {
+    let ev = server_line_to_event(line);
+    if is_droppable(&ev) {
+        let _ = ev_tx.try_send(ev);
+    } else {
+        let _ = ev_tx.send(ev);
+    }
+}
+
+// Events that can be lost without the UI ending up in a wrong state. Trade confirmations
+// (TradeFilled, TradeRejected) never are: a lost one leaves the trade pending and the
+// portfolio stale, so they must not be sent as Log either.
+fn is_droppable(ev: &ClientEvent) -> bool {
+    matches!(
+        ev,
+        ClientEvent::PriceChecked { req_id: None, .. } | ClientEvent::Log(_)
+    )
+}
+
+// The worker answers PING itself so heartbeats never reach the UI.
+fn heartbeat_reply(line: &str) -> Option<String> {
+    match parse_server_line(line) {
+        (_, Some(ServerMsg::Ping)) => Some(ClientMsg::Pong.to_wire()),
+        _ => None,
+    }
+}
+
+fn server_line_to_event(line: &str) -> ClientEvent {
+    let (req_id, msg) = parse_server_line(line);
+    match msg {
         Some(ServerMsg::AlertTriggered {
             symbol,
             direction,
             threshold,
             current_price,
-        }) => {
-            let _ = ev_tx.send(ClientEvent::AlertTriggered {
-                symbol,
-                dir: direction,
-                threshold,
-                current: current_price.value,
-            });
-        }
+        }) => ClientEvent::AlertTriggered {
+            symbol,
+            dir: direction,
+            threshold,
+            current: current_price.value,
+            test: false,
+        },
         Some(ServerMsg::AlertAdded {
             symbol,
             direction,
             threshold,
-        }) => {
-            let _ = ev_tx.send(ClientEvent::AlertAdded {
-                symbol,
-                dir: direction,
-                threshold,
-            });
-        }
-        Some(ServerMsg::AlertRemoved { symbol, direction }) => {
-            let _ = ev_tx.send(ClientEvent::AlertRemoved {
-                symbol,
-                dir: direction,
-            });
-        }
+            price_available,
+            triggers_now,
+        }) => ClientEvent::AlertAdded {
+            symbol,
+            dir: direction,
+            threshold,
+            price_available,
+            triggers_now,
+        },
+        Some(ServerMsg::AlertRemoved { symbol, direction }) => ClientEvent::AlertRemoved {
+            symbol,
+            dir: direction,
+        },
+        Some(ServerMsg::AlertUpdated {
+            symbol,
+            direction,
+            threshold,
+        }) => ClientEvent::AlertUpdated {
+            symbol,
+            dir: direction,
+            threshold,
+        },
+        Some(ServerMsg::AlertFailed {
+            symbol,
+            direction,
+            threshold,
+            reason,
+        }) => ClientEvent::AlertFailed {
+            symbol,
+            dir: direction,
+            threshold,
+            reason,
+        },
+        Some(ServerMsg::AlertsAdded {
+            added,
+            skipped,
+            unpriced,
+        }) => ClientEvent::AlertsAdded {
+            added,
+            skipped,
+            unpriced,
+        },
         Some(ServerMsg::StockBought { symbol, quantity }) => {
-            let msg = format!("Bought {quantity}x {symbol}");
-            let _ = ev_tx.send(ClientEvent::Log(msg));
-        }
-        Some(ServerMsg::StockSold { symbol, quantity }) => {
-            let msg = format!("Sold {quantity}x {symbol}");
-            let _ = ev_tx.send(ClientEvent::Log(msg));
+            ClientEvent::TradeFilled(TradeFill::Bought { symbol, quantity })
         }
-        Some(ServerMsg::PriceChecked { symbol, price }) => {
-            let _ = ev_tx.send(ClientEvent::PriceChecked { symbol, price });
+        Some(ServerMsg::TradeRejected { symbol, reason }) => {
+            ClientEvent::TradeRejected { symbol, reason }
         }
-        Some(ServerMsg::AllClientData { stocks, alerts }) => {
+        Some(ServerMsg::StockSold {
+            symbol,
+            quantity,
+            profit,
+        }) => ClientEvent::TradeFilled(TradeFill::Sold {
+            symbol,
+            quantity,
+            profit,
+        }),
+        Some(ServerMsg::PriceChecked {
+            symbol,
+            price,
+            stats,
+        }) => ClientEvent::PriceChecked {
+            symbol,
+            price,
+            stats,
+            req_id,
+        },
+        Some(ServerMsg::AllClientData {
+            stocks,
+            alerts,
+            has_more,
+            failed,
+        }) => {
             let mapped_alerts = alerts
                 .into_iter()
                 .map(|alert| AlertRow {
@@ -330,51 +761,164 @@ fn handle_server_line(line: &str, ev_tx: &Sender<ClientEvent>) {
                     threshold: alert.threshold,
                 })
                 .collect::<Vec<_>>();
-            let _ = ev_tx.send(ClientEvent::AllClientData {
+            ClientEvent::AllClientData {
                 stocks,
                 alerts: mapped_alerts,
-            });
-        }
-        Some(ServerMsg::UserLogged) => {
-            let _ = ev_tx.send(ClientEvent::UserLogged);
-        }
-        Some(ServerMsg::UserRegistered) => {
-            let _ = ev_tx.send(ClientEvent::UserRegistered);
-        }
-        Some(ServerMsg::Error(msg)) => {
-            let _ = ev_tx.send(ClientEvent::ServerError(msg));
+                has_more,
+                failed,
+            }
         }
-        None => {
-            let _ = ev_tx.send(ClientEvent::Log(format!("Unparsed: {line}")));
+        Some(ServerMsg::SymbolList {
+            symbols,
+            names,
+            has_more,
+        }) => ClientEvent::SymbolList {
+            symbols,
+            names,
+            has_more,
+        },
+        Some(ServerMsg::History { symbol, points }) => ClientEvent::History { symbol, points },
+        Some(ServerMsg::UserLogged) => ClientEvent::UserLogged,
+        Some(ServerMsg::UserRegistered) => ClientEvent::UserRegistered,
+        Some(ServerMsg::AccountReset) => ClientEvent::AccountReset,
+        Some(ServerMsg::Leaderboard { entries }) => ClientEvent::Leaderboard(entries),
+        Some(ServerMsg::Identity { username, .. }) => ClientEvent::Identity { username },
+        Some(ServerMsg::ServerTime { unix_secs }) => ClientEvent::ServerTime { unix_secs },
+        Some(ServerMsg::DataExport { json }) => ClientEvent::DataExport { json },
+        Some(ServerMsg::Preferences(prefs)) => ClientEvent::Preferences(prefs),
+        Some(ServerMsg::Heartbeat { nonce }) => ClientEvent::Heartbeat { nonce },
+        Some(ServerMsg::BracketSet {
+            symbol,
+            stop,
+            target,
+        }) => ClientEvent::Log(format!(
+            "Bracket on {symbol}: stop {}, target {}",
+            stop.map_or("-".into(), |v| v.to_string()),
+            target.map_or("-".into(), |v| v.to_string())
+        )),
+        Some(ServerMsg::BracketCancelled { symbol }) => {
+            ClientEvent::Log(format!("Bracket on {symbol} cancelled"))
         }
+        Some(ServerMsg::OrdersCancelled { count }) => ClientEvent::OrdersCancelled { count },
+        Some(ServerMsg::BracketTriggered {
+            symbol,
+            quantity,
+            price,
+            profit,
+        }) => ClientEvent::TradeFilled(TradeFill::Bracket {
+            symbol,
+            quantity,
+            price,
+            profit,
+        }),
+        Some(ServerMsg::Error(msg)) if msg == ERR_SERVER_FULL => ClientEvent::ServerFull,
+        Some(ServerMsg::Error(msg)) if msg == ERR_NOT_LOGGED_IN => ClientEvent::SessionExpired,
+        Some(ServerMsg::Error(msg)) => ClientEvent::ServerError { msg, req_id },
+        Some(ServerMsg::Ping) => ClientEvent::Log("Heartbeat from server.".into()),
+        None => ClientEvent::Log(format!("Unparsed: {line}")),
     }
 }
 
 struct App {
     cmd_tx: Sender<UiCommand>,
     ev_rx: Receiver<ClientEvent>,
+    worker_alive: bool,
     addr: String,
     connected: bool,
+    // A Connect was sent and the worker hasn't reported back yet, Connect stays disabled.
+    connecting: bool,
     conn_status: String,
+    disconnect_notice: Option<String>,
     symbol_input: String,
     dir_input: AlertDirection,
     threshold_input: String,
+    // Pasted alerts for ADDMANY, one "SYMBOL DIRECTION THRESHOLD" per line.
+    import_input: String,
+    // Confirmation dialog for wiping the portfolio, open while Some.
+    reset_password_input: Option<String>,
+    // Confirmation dialog for cancelling every pending bracket.
+    cancel_orders_open: bool,
     quantity_input: String,
     username_input: String,
     password_input: String,
     command_kind: CommandKind,
     auth_mode: AuthMode,
     authenticated: bool,
+    // Account name reported by WHOAMI, shown in the top bar while logged in.
+    identity: Option<String>,
+    // What the server pushes to this account, None until it answered PREFS.
+    prefs: Option<Preferences>,
     auth_notice: Option<String>,
+    remember_login: bool,
+    session: SessionRecovery,
+    reconnect_at: Option<Instant>,
+    reconnect_attempts: u32,
+    reconnect_delay: Duration,
     alert_popup_open: bool,
+    // The popup is for triggered alerts, confirmations only go to the log unless this is on.
+    popup_on_alert_added: bool,
+    money: MoneyFormat,
     alert_popup_message: Option<String>,
     alert_popup_data: Option<AlertRow>,
     alerts: Vec<AlertRow>,
+    // Alerts sent to the server that were neither confirmed nor rejected yet.
+    pending_alerts: Vec<AlertRow>,
+    alert_notice: Option<String>,
+    // Alert whose threshold is being edited in the list.
+    alert_edit: Option<AlertEdit>,
     portfolio: Vec<PortfolioStock>,
+    symbols: Vec<String>,
+    // Company names from LIST, symbols without one are shown bare.
+    symbol_names: HashMap<String, String>,
+    // Intraday closes of the last symbol the user checked.
+    chart: Option<(String, Vec<HistoryPoint>)>,
+    // The symbol the chart follows, asked for again after every login so a reconnect
+    // brings the chart back without the user. The ticker restarts on its own (poll_ticker).
+    chart_symbol: Option<String>,
+    // Only the newest this many points of `chart` are kept.
+    chart_depth: usize,
+    // Server clock minus ours in seconds, asked for after every login so log
+    // timestamps follow the server rather than a drifting local clock.
+    clock_skew: Option<i64>,
     pending_trade: Option<PendingTrade>,
-    style_initialized: bool,
+    next_req_id: u64,
+    // Offset of the DATA page we are waiting for.
+    data_offset: usize,
+    auto_refresh: bool,
+    auto_refresh_secs: u64,
+    last_data_request: Instant,
+    // Set for refreshes nobody clicked, their result isn't logged.
+    quiet_data_refresh: bool,
+    last_probe_at: Instant,
+    probe_nonce: u64,
+    // Nonce and send time of the PING still waiting for its PONG.
+    pending_probe: Option<(u64, Instant)>,
+    latency: Option<Duration>,
+    ticker: HashMap<String, TickerQuote>,
+    // Quote requests sent for the ticker, their replies and errors don't go to the log.
+    ticker_requests: HashSet<u64>,
+    // None when the next refresh is due right away, like after logging in.
+    last_ticker_at: Option<Instant>,
+    theme: Theme,
+    // The theme the egui context has, None until the first frame.
+    applied_theme: Option<Theme>,
     logs: Vec<LogRow>,
     max_logs: usize,
+    toasts: VecDeque<Toast>,
+}
+
+// Short confirmation that disappears on its own, for things that need no decision.
+struct Toast {
+    msg: String,
+    shown_at: Instant,
+}
+
+impl Toast {
+    // 1.0 while fresh, falling to 0.0 at the end of its lifetime.
+    fn opacity(&self, now: Instant) -> f32 {
+        let left = TOAST_DURATION.saturating_sub(now.saturating_duration_since(self.shown_at));
+        (left.as_secs_f32() / TOAST_FADE.as_secs_f32()).min(1.0)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -384,6 +928,24 @@ struct AlertRow {
     threshold: f64,
 }
 
+struct AlertEdit {
+    symbol: String,
+    dir: AlertDirection,
+    input: String,
+}
+
+impl AlertEdit {
+    fn is_for(&self, row: &AlertRow) -> bool {
+        self.symbol == row.symbol && self.dir == row.dir
+    }
+}
+
+// What the user clicked next to an alert in the list.
+enum AlertRowAction {
+    Remove(AlertRow),
+    Save,
+}
+
 #[derive(Clone)]
 struct LogRow {
     ts: String,
@@ -419,11 +981,94 @@ enum TradeKind {
     Sell,
 }
 
+// Answers to the alert popup. Enter picks Remove, Escape picks Keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PopupAction {
+    Remove,
+    Keep,
+}
+
+// Keys are ignored while a text field has focus, so typing a command never answers the popup.
+fn popup_key_action(input: &egui::InputState, typing: bool) -> Option<PopupAction> {
+    if typing {
+        None
+    } else if input.key_pressed(egui::Key::Escape) {
+        Some(PopupAction::Keep)
+    } else if input.key_pressed(egui::Key::Enter) {
+        Some(PopupAction::Remove)
+    } else {
+        None
+    }
+}
+
+// Re-logs in once with remembered credentials when the server reports the session is gone,
+// then retries the command that failed. A second failure hands control back to the user.
+#[derive(Default)]
+struct SessionRecovery {
+    credentials: Option<(String, String)>,
+    last_command: Option<UiCommand>,
+    retry: Option<UiCommand>,
+    relogin_in_flight: bool,
+}
+
+impl SessionRecovery {
+    // Remembers the last command that needs a session so it can be retried.
+    fn track(&mut self, cmd: &UiCommand) {
+        match cmd {
+            UiCommand::Connect { .. }
+            | UiCommand::Disconnect
+            | UiCommand::Ping { .. }
+            | UiCommand::LoginClient { .. }
+            | UiCommand::RegisterClient { .. } => {}
+            cmd => self.last_command = Some(cmd.clone()),
+        }
+    }
+
+    // Returns the login command to send, None when the user has to log in by hand.
+    fn on_expired(&mut self) -> Option<UiCommand> {
+        if self.relogin_in_flight {
+            // Expired again right after re-login, don't loop.
+            self.relogin_in_flight = false;
+            self.retry = None;
+            return None;
+        }
+        let (username, password) = self.credentials.clone()?;
+        self.relogin_in_flight = true;
+        self.retry = self.last_command.take();
+        Some(UiCommand::LoginClient { username, password })
+    }
+
+    // Login to send after an automatic reconnect, failures are handled like a re-login.
+    fn on_reconnected(&mut self) -> Option<UiCommand> {
+        let (username, password) = self.credentials.clone()?;
+        self.relogin_in_flight = true;
+        Some(UiCommand::LoginClient { username, password })
+    }
+
+    fn on_logged_in(&mut self) -> Option<UiCommand> {
+        self.relogin_in_flight = false;
+        self.retry.take()
+    }
+
+    // True if the failed login was ours, the remembered credentials are dropped then.
+    fn on_login_failed(&mut self) -> bool {
+        if !self.relogin_in_flight {
+            return false;
+        }
+        self.relogin_in_flight = false;
+        self.retry = None;
+        self.credentials = None;
+        true
+    }
+}
+
 #[derive(Clone)]
 struct PendingTrade {
     symbol: String,
     quantity: i32,
     kind: TradeKind,
+    req_id: u64,
+    sent_at: Instant,
 }
 
 impl App {
@@ -432,33 +1077,111 @@ impl App {
         Self {
             cmd_tx,
             ev_rx,
+            worker_alive: true,
             addr: "127.0.0.1:1234".into(),
             connected: false,
+            connecting: false,
             conn_status: "Disconnected".into(),
+            disconnect_notice: None,
             symbol_input: "AAPL".into(),
             dir_input: AlertDirection::Above,
             threshold_input: "200".into(),
+            import_input: String::new(),
+            reset_password_input: None,
+            cancel_orders_open: false,
             quantity_input: "1".into(),
             username_input: "user".into(),
             password_input: "pass".into(),
             command_kind: CommandKind::AddAlert,
             auth_mode: AuthMode::Login,
+            remember_login: false,
+            session: SessionRecovery::default(),
+            reconnect_at: None,
+            reconnect_attempts: 0,
+            reconnect_delay: RECONNECT_DELAY,
             authenticated: false,
+            identity: None,
+            prefs: None,
             auth_notice: None,
             alert_popup_open: false,
+            popup_on_alert_added: false,
+            money: MoneyFormat::default(),
             alert_popup_message: None,
             alert_popup_data: None,
             alerts: Vec::new(),
+            pending_alerts: Vec::new(),
+            alert_notice: None,
+            alert_edit: None,
             portfolio: Vec::new(),
+            symbols: Vec::new(),
+            symbol_names: HashMap::new(),
+            chart: None,
+            chart_symbol: None,
+            chart_depth: DEFAULT_CHART_DEPTH,
+            clock_skew: None,
             pending_trade: None,
-            style_initialized: false,
+            next_req_id: 1,
+            data_offset: 0,
+            auto_refresh: true,
+            auto_refresh_secs: DEFAULT_DATA_REFRESH_SECS,
+            last_data_request: Instant::now(),
+            last_probe_at: Instant::now(),
+            probe_nonce: 0,
+            pending_probe: None,
+            latency: None,
+            ticker: HashMap::new(),
+            ticker_requests: HashSet::new(),
+            last_ticker_at: None,
+            quiet_data_refresh: false,
+            theme: Theme::Light,
+            applied_theme: None,
             logs: Vec::new(),
             max_logs: 500,
+            toasts: VecDeque::new(),
+        }
+    }
+
+    // Newest toast at the bottom right, older ones stacked above it.
+    fn render_toasts(&self, ctx: &egui::Context) {
+        if self.toasts.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-12.0, -12.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                for toast in &self.toasts {
+                    let alpha = toast.opacity(now);
+                    egui::Frame::popup(ui.style())
+                        .multiply_with_opacity(alpha)
+                        .show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(&toast.msg)
+                                    .color(ui.visuals().text_color().gamma_multiply(alpha)),
+                            );
+                        });
+                    ui.add_space(4.0);
+                }
+            });
+    }
+
+    fn push_toast(&mut self, msg: impl Into<String>) {
+        self.toasts.push_back(Toast {
+            msg: msg.into(),
+            shown_at: Instant::now(),
+        });
+        while self.toasts.len() > MAX_TOASTS {
+            self.toasts.pop_front();
         }
     }
 
+    fn expire_toasts(&mut self, now: Instant) {
+        self.toasts.retain(|toast| toast.opacity(now) > 0.0);
+    }
+
     fn push_log(&mut self, kind: LogKind, msg: impl Into<String>) {
-        let ts = now_hhmmss();
+        let ts = hhmmss(unix_now() + self.clock_skew.unwrap_or(0));
         self.logs.push(LogRow {
             ts,
             msg: msg.into(),
@@ -471,163 +1194,677 @@ impl App {
     }
 
     fn drain_events(&mut self) {
-        while let Ok(ev) = self.ev_rx.try_recv() {
-            match ev {
-                ClientEvent::Connected => {
-                    self.connected = true;
-                    self.conn_status = "Connected".into();
-                    self.push_log(LogKind::Info, "Connected to server.");
+        let mut events = Vec::new();
+        loop {
+            match self.ev_rx.try_recv() {
+                Ok(ev) => events.push(ev),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.mark_worker_dead();
+                    break;
                 }
-                ClientEvent::Disconnected { reason } => {
-                    self.connected = false;
-                    self.conn_status = format!("Disconnected: {reason}");
-                    self.authenticated = false;
-                    self.auth_notice = Some("Disconnected from server.".into());
-                    self.push_log(LogKind::Error, format!("Disconnected: {reason}"));
+            }
+        }
+
+        for ev in coalesce_quotes(events) {
+            self.handle_event(ev);
+        }
+    }
+
+    fn handle_event(&mut self, ev: ClientEvent) {
+        match ev {
+            ClientEvent::Connected => {
+                self.connected = true;
+                self.connecting = false;
+                self.conn_status = "Connected".into();
+                self.push_log(LogKind::Info, "Connected to server.");
+                if self.reconnect_attempts > 0
+                    && let Some(login) = self.session.on_reconnected()
+                {
+                    self.push_log(LogKind::Info, "Reconnected, logging in again.");
+                    self.send(login);
                 }
-                ClientEvent::AlertTriggered {
-                    symbol,
+            }
+            ClientEvent::Disconnected { reason } => {
+                self.connecting = false;
+                self.pending_probe = None;
+                self.latency = None;
+                self.ticker.clear();
+                self.ticker_requests.clear();
+                self.last_ticker_at = None;
+                let rejected = self.disconnect_notice.is_some();
+                let reason = self.disconnect_notice.take().unwrap_or(reason);
+                let resume = !rejected
+                    && reason != USER_DISCONNECT_REASON
+                    && self.session.credentials.is_some()
+                    && (self.authenticated || self.reconnect_attempts > 0)
+                    && self.reconnect_attempts < MAX_RECONNECT_ATTEMPTS;
+                self.connected = false;
+                self.conn_status = format!("Disconnected: {reason}");
+                self.authenticated = false;
+                self.auth_notice = Some("Disconnected from server.".into());
+                self.pending_alerts.clear();
+                self.pending_trade = None;
+                // Dialogs belong to the session that just ended, triggered alerts are still in the log.
+                self.alert_popup_open = false;
+                self.alert_popup_message = None;
+                self.alert_popup_data = None;
+                self.alert_edit = None;
+                self.reset_password_input = None;
+                self.cancel_orders_open = false;
+                self.push_log(LogKind::Error, format!("Disconnected: {reason}"));
+                if resume {
+                    self.reconnect_at = Some(Instant::now() + self.reconnect_delay);
+                    self.conn_status =
+                        reconnect_status(self.reconnect_attempts + 1, Some(self.reconnect_delay));
+                } else {
+                    self.reconnect_at = None;
+                    self.reconnect_attempts = 0;
+                }
+            }
+            ClientEvent::ServerFull => {
+                self.disconnect_notice = Some("server full, try again later".into());
+                self.conn_status = "Rejected: server full".into();
+                self.push_log(LogKind::Error, "Server is at capacity, try again later.");
+            }
+            ClientEvent::AlertTriggered {
+                symbol,
+                dir,
+                threshold,
+                current,
+                test,
+            } => {
+                let tag = if test { "[TEST ALERT]" } else { "[ALERT]" };
+                let msg = format!(
+                    "{tag} {symbol} {} threshold={} current={}",
+                    dir,
+                    self.money.format(threshold),
+                    self.money.format(current)
+                );
+                self.alert_popup_message = Some(msg.clone());
+                // A test alert has nothing to remove on the server.
+                self.alert_popup_data = (!test).then(|| AlertRow {
+                    symbol: symbol.clone(),
                     dir,
                     threshold,
-                    current,
-                } => {
-                    self.alert_popup_message = Some(format!(
-                        "[ALERT] {symbol} {:?} threshold={threshold} current={current}",
-                        dir
-                    ));
+                });
+                self.alert_popup_open = true;
+                play_alert_sound();
+                self.push_log(LogKind::Alert, msg);
+            }
+            ClientEvent::AlertAdded {
+                symbol,
+                dir,
+                threshold,
+                price_available,
+                triggers_now,
+            } => {
+                self.take_pending_alert(&symbol, dir);
+                self.alert_notice = (!price_available)
+                    .then(|| format!("Alert added for {symbol}, but there is no price data yet."));
+                if let Some(notice) = &self.alert_notice {
+                    self.push_log(LogKind::Error, notice.clone());
+                }
+                let popup_msg = format!(
+                    "Alert added: {symbol} {} threshold={}",
+                    dir,
+                    self.money.format(threshold)
+                );
+                if let Some(existing) = self
+                    .alerts
+                    .iter_mut()
+                    .find(|a| a.symbol == symbol && a.dir == dir)
+                {
+                    existing.threshold = threshold;
+                } else {
+                    self.alerts.push(AlertRow {
+                        symbol: symbol.clone(),
+                        dir,
+                        threshold,
+                    });
+                }
+                // Already past the threshold: ask whether to keep it before it fires.
+                if triggers_now || self.popup_on_alert_added {
+                    self.alert_popup_message = Some(if triggers_now {
+                        format!(
+                            "{symbol} {} threshold={} will trigger right away. Add anyway?",
+                            dir,
+                            self.money.format(threshold)
+                        )
+                    } else {
+                        popup_msg.clone()
+                    });
                     self.alert_popup_data = Some(AlertRow {
                         symbol: symbol.clone(),
                         dir,
                         threshold,
                     });
                     self.alert_popup_open = true;
-                    play_alert_sound();
+                } else {
+                    self.push_toast(popup_msg.clone());
+                }
+                self.push_log(LogKind::Info, popup_msg);
+            }
+            ClientEvent::AlertFailed {
+                symbol,
+                dir,
+                threshold,
+                reason,
+            } => {
+                self.take_pending_alert(&symbol, dir);
+                let msg = alert_failure_message(&symbol, dir, threshold, &reason, &self.money);
+                self.push_log(LogKind::Error, format!("[ALERT FAILED] {msg}"));
+                self.alert_notice = Some(msg);
+            }
+            ClientEvent::AlertsAdded {
+                added,
+                skipped,
+                unpriced,
+            } => {
+                self.push_toast(format!("Imported {added} alerts, skipped {skipped}"));
+                self.push_log(
+                    LogKind::Info,
+                    format!("Imported {added} alerts, skipped {skipped} duplicates."),
+                );
+                if unpriced > 0 {
                     self.push_log(
-                        LogKind::Alert,
+                        LogKind::Error,
                         format!(
-                            "[ALERT] {symbol} {:?} threshold={threshold} current={current}",
-                            dir
+                            "{unpriced} imported alerts have no price yet and won't fire until one appears."
                         ),
                     );
                 }
-                ClientEvent::AlertAdded {
-                    symbol,
+                // The server doesn't echo each alert, so reload the list.
+                self.request_client_data(0);
+            }
+            ClientEvent::AlertRemoved { symbol, dir } => {
+                self.remove_local_alert(&symbol, dir);
+                self.push_log(LogKind::Info, format!("Alert removed: {symbol} {}", dir));
+            }
+            ClientEvent::AlertUpdated {
+                symbol,
+                dir,
+                threshold,
+            } => {
+                if let Some(row) = self
+                    .alerts
+                    .iter_mut()
+                    .find(|a| a.symbol == symbol && a.dir == dir)
+                {
+                    row.threshold = threshold;
+                }
+                let msg = format!(
+                    "Alert updated: {symbol} {} threshold={}",
                     dir,
-                    threshold,
-                } => {
-                    let popup_msg =
-                        format!("Alert added: {symbol} {:?} threshold={threshold}", dir);
-                    if !self
-                        .alerts
+                    self.money.format(threshold)
+                );
+                self.push_toast(msg.clone());
+                self.push_log(LogKind::Info, msg);
+            }
+            ClientEvent::PriceChecked {
+                symbol,
+                price,
+                stats,
+                req_id,
+            } => {
+                let previous = self.ticker.get(&symbol).copied();
+                self.ticker
+                    .insert(symbol.clone(), TickerQuote::next(previous, price));
+                if let Some(id) = req_id
+                    && self.ticker_requests.remove(&id)
+                {
+                    return;
+                }
+                // Only the quote the trade asked for executes it, a manual PRICE for the
+                // same symbol has no request id and just goes to the log.
+                if let Some(pending) = self.pending_trade.clone()
+                    && req_id == Some(pending.req_id)
+                {
+                    self.pending_trade = None;
+                    match pending.kind {
+                        TradeKind::Buy => {
+                            self.send(UiCommand::BuyStock {
+                                symbol: pending.symbol.clone(),
+                                quantity: pending.quantity,
+                            });
+                            self.push_log(
+                                LogKind::Info,
+                                format!(
+                                    "[BUY] {symbol} qty={} price={}",
+                                    pending.quantity,
+                                    self.money.format(price)
+                                ),
+                            );
+                        }
+                        TradeKind::Sell => {
+                            self.send(UiCommand::SellStock {
+                                symbol: pending.symbol.clone(),
+                                quantity: pending.quantity,
+                            });
+                            self.push_log(
+                                LogKind::Info,
+                                format!(
+                                    "[SELL] {symbol} qty={} price={}",
+                                    pending.quantity,
+                                    self.money.format(price)
+                                ),
+                            );
+                        }
+                    }
+                    return;
+                }
+                self.push_log(
+                    LogKind::Info,
+                    format!(
+                        "[PRICE] {symbol} price={}{}",
+                        self.money.format(price),
+                        stats.summary()
+                    ),
+                );
+            }
+            ClientEvent::AllClientData {
+                stocks,
+                alerts,
+                has_more,
+                failed,
+            } => {
+                // Only a logged-in session has data of its own, a snapshot on the auth screen
+                // may be left over from a session that just ended or a login that failed.
+                if !self.authenticated {
+                    self.push_log(LogKind::Info, "Ignored account data received before login.");
+                    return;
+                }
+                // Shown even on a quiet refresh, the tables below are incomplete.
+                if !failed.is_empty() {
+                    self.push_log(
+                        LogKind::Error,
+                        format!("[DATA] Partial data, couldn't load: {}", failed.join(", ")),
+                    );
+                }
+                // The first page replaces what we had, later pages are appended.
+                if self.data_offset == 0 {
+                    self.alerts.clear();
+                    self.portfolio.clear();
+                }
+                let mut deduped = std::mem::take(&mut self.alerts);
+                for alert in alerts {
+                    if !deduped
                         .iter()
-                        .any(|a| a.symbol == symbol && a.dir == dir)
+                        .any(|a: &AlertRow| a.symbol == alert.symbol && a.dir == alert.dir)
                     {
-                        self.alerts.push(AlertRow {
-                            symbol: symbol.clone(),
-                            dir,
-                            threshold,
-                        });
+                        deduped.push(alert);
                     }
-                    self.alert_popup_message = Some(popup_msg);
-                    self.alert_popup_data = Some(AlertRow {
-                        symbol: symbol.clone(),
-                        dir,
-                        threshold,
+                }
+                self.alerts = deduped;
+                self.portfolio.extend(stocks);
+                if has_more {
+                    self.request_client_data(self.data_offset + DATA_PAGE_SIZE);
+                    return;
+                }
+                if std::mem::take(&mut self.quiet_data_refresh) {
+                    return;
+                }
+                self.push_log(
+                    LogKind::Info,
+                    format!(
+                        "Loaded {} portfolio entries and {} alerts.",
+                        self.portfolio.len(),
+                        self.alerts.len()
+                    ),
+                );
+            }
+            ClientEvent::History { symbol, mut points } => {
+                keep_newest(&mut points, self.chart_depth);
+                self.chart = Some((symbol, points));
+            }
+            ClientEvent::SymbolList {
+                symbols,
+                names,
+                has_more,
+            } => {
+                self.symbols.extend(symbols);
+                self.symbol_names.extend(names);
+                if has_more {
+                    self.send(UiCommand::ListSymbols {
+                        offset: self.symbols.len(),
                     });
-                    self.alert_popup_open = true;
+                } else {
                     self.push_log(
                         LogKind::Info,
-                        format!("Alert added: {symbol} {:?} threshold={threshold}", dir),
+                        format!("Loaded {} tracked symbols.", self.symbols.len()),
                     );
                 }
-                ClientEvent::AlertRemoved { symbol, dir } => {
-                    self.remove_local_alert(&symbol, dir);
-                    self.push_log(LogKind::Info, format!("Alert removed: {symbol} {:?}", dir));
+            }
+            ClientEvent::UserLogged => {
+                self.reconnect_attempts = 0;
+                if let Some(cmd) = self.session.on_logged_in() {
+                    self.push_log(LogKind::Info, "Session restored, retrying last command.");
+                    self.send(cmd);
                 }
-                ClientEvent::PriceChecked { symbol, price } => {
-                    if let Some(pending) = self.pending_trade.clone()
-                        && pending.symbol == symbol
-                    {
-                        self.pending_trade = None;
-                        match pending.kind {
-                            TradeKind::Buy => {
-                                self.send(UiCommand::BuyStock {
-                                    symbol: pending.symbol.clone(),
-                                    quantity: pending.quantity,
-                                });
-                                self.push_log(
-                                    LogKind::Info,
-                                    format!(
-                                        "[BUY] {symbol} qty={} price={price}",
-                                        pending.quantity
-                                    ),
-                                );
-                            }
-                            TradeKind::Sell => {
-                                self.send(UiCommand::SellStock {
-                                    symbol: pending.symbol.clone(),
-                                    quantity: pending.quantity,
-                                });
-                                self.push_log(
-                                    LogKind::Info,
-                                    format!(
-                                        "[SELL] {symbol} qty={} price={price}",
-                                        pending.quantity
-                                    ),
-                                );
-                            }
-                        }
-                        return;
-                    }
-                    self.push_log(LogKind::Info, format!("[PRICE] {symbol} price={price}"));
+                self.authenticated = true;
+                // Asked after every login, a resumed session may be a different account.
+                self.identity = None;
+                self.send(UiCommand::WhoAmI);
+                self.send(UiCommand::GetServerTime);
+                self.prefs = None;
+                self.send(UiCommand::GetPreferences);
+                self.auth_notice = Some("Logged in successfully.".into());
+                self.push_log(LogKind::Info, "Logged in successfully.");
+                self.request_client_data(0);
+                if self.symbols.is_empty() {
+                    self.send(UiCommand::ListSymbols { offset: 0 });
                 }
-                ClientEvent::AllClientData { stocks, alerts } => {
-                    let mut deduped = Vec::new();
-                    for alert in alerts {
-                        if !deduped
-                            .iter()
-                            .any(|a: &AlertRow| a.symbol == alert.symbol && a.dir == alert.dir)
-                        {
-                            deduped.push(alert);
-                        }
-                    }
-                    self.alerts = deduped;
-                    self.portfolio = stocks;
+                if let Some(symbol) = self.chart_symbol.clone() {
+                    self.send(UiCommand::GetHistory { symbol });
+                }
+            }
+            ClientEvent::Identity { username } => {
+                self.identity = Some(username);
+            }
+            ClientEvent::ServerTime { unix_secs } => {
+                let skew = unix_secs - unix_now();
+                self.clock_skew = Some(skew);
+                if skew.abs() > MAX_CLOCK_SKEW_SECS {
                     self.push_log(
-                        LogKind::Info,
-                        format!(
-                            "Loaded {} portfolio entries and {} alerts.",
-                            self.portfolio.len(),
-                            self.alerts.len()
-                        ),
+                        LogKind::Error,
+                        format!("Local clock is {skew:+}s off the server, using server time."),
                     );
                 }
-                ClientEvent::UserLogged => {
-                    self.authenticated = true;
-                    self.auth_notice = Some("Logged in successfully.".into());
-                    self.push_log(LogKind::Info, "Logged in successfully.");
-                    self.send(UiCommand::GetAllClientData);
+            }
+            ClientEvent::Preferences(prefs) => {
+                self.prefs = Some(prefs);
+            }
+            // A PONG for an older probe is late, only the newest one counts.
+            ClientEvent::Heartbeat { nonce } => {
+                if let Some((pending, sent_at)) = self.pending_probe
+                    && pending == nonce
+                {
+                    self.latency = Some(sent_at.elapsed());
+                    self.pending_probe = None;
+                }
+            }
+            ClientEvent::DataExport { json } => {
+                let path = export_file_name(self.identity.as_deref());
+                match std::fs::write(&path, pretty_export(&json)) {
+                    Ok(()) => {
+                        let msg = format!("Data exported to {path}");
+                        self.push_toast(msg.clone());
+                        self.push_log(LogKind::Info, msg);
+                    }
+                    Err(e) => self.push_log(LogKind::Error, format!("Failed to save {path}: {e}")),
+                }
+            }
+            ClientEvent::Leaderboard(entries) => {
+                let ranking = if entries.is_empty() {
+                    "nobody ranked yet".to_string()
+                } else {
+                    entries
+                        .iter()
+                        .enumerate()
+                        .map(|(rank, entry)| format!("{}. {}", rank + 1, entry.summary()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                self.push_log(LogKind::Info, format!("[LEADERBOARD] {ranking}"));
+            }
+            ClientEvent::AccountReset => {
+                self.portfolio.clear();
+                self.push_log(LogKind::Info, "Account reset, all positions removed.");
+                self.push_toast("Account reset");
+                self.request_client_data(0);
+            }
+            ClientEvent::OrdersCancelled { count } => {
+                self.push_log(LogKind::Info, format!("{count} pending orders cancelled."));
+                self.push_toast(format!("Cancelled {count} orders"));
+            }
+            ClientEvent::UserRegistered => {
+                self.authenticated = false;
+                self.auth_notice = Some("Registered successfully. You can log in now.".into());
+                self.push_log(LogKind::Info, "Registered successfully.");
+            }
+            ClientEvent::SessionExpired => match self.session.on_expired() {
+                Some(login) => {
+                    self.push_log(LogKind::Info, "Session expired, logging in again.");
+                    self.send(login);
+                }
+                // A command that slipped out before logging in gets the same answer.
+                None => {
+                    let notice = if self.authenticated {
+                        "Session expired, please log in again."
+                    } else {
+                        "Please log in first."
+                    };
+                    self.authenticated = false;
+                    self.pending_trade = None;
+                    self.auth_notice = Some(notice.into());
+                    self.push_log(LogKind::Error, notice);
                 }
-                ClientEvent::UserRegistered => {
+            },
+            ClientEvent::ServerError { msg, req_id } => {
+                if msg == ERR_LOGIN_FAILED && self.session.on_login_failed() {
                     self.authenticated = false;
-                    self.auth_notice = Some("Registered successfully. You can log in now.".into());
-                    self.push_log(LogKind::Info, "Registered successfully.");
+                    self.pending_trade = None;
+                    self.auth_notice = Some("Automatic log-in failed, please log in again.".into());
+                    self.push_log(LogKind::Error, "Automatic log-in failed.");
+                    return;
                 }
-                ClientEvent::ServerError(msg) => {
-                    self.auth_notice = Some(msg.clone());
-                    self.push_log(LogKind::Error, format!("[SERVER ERR] {msg}"));
+                // A watched symbol without a price yet, the ticker keeps showing "—".
+                if let Some(id) = req_id
+                    && self.ticker_requests.remove(&id)
+                {
+                    return;
                 }
-                ClientEvent::Log(s) => {
-                    self.push_log(LogKind::Info, s);
+                if let Some(pending) = &self.pending_trade
+                    && req_id == Some(pending.req_id)
+                {
+                    let symbol = pending.symbol.clone();
+                    self.pending_trade = None;
+                    self.push_log(
+                        LogKind::Error,
+                        format!("Trade for {symbol} cancelled: {msg}"),
+                    );
+                }
+                self.auth_notice = Some(msg.clone());
+                if msg == ERR_ALERT_NOT_FOUND {
+                    // Our list was out of date, fetch the server's view again.
+                    self.request_client_data(0);
+                }
+                if msg == ERR_DB_UNAVAILABLE {
+                    // Nothing was changed on the server, the user can just send it again.
+                    self.push_log(
+                        LogKind::Info,
+                        "[SERVER BUSY] Database unavailable, retry shortly.",
+                    );
+                    return;
+                }
+                self.push_log(LogKind::Error, format!("[SERVER ERR] {msg}"));
+            }
+            ClientEvent::TradeFilled(fill) => {
+                let msg = fill.describe(&self.money);
+                self.push_toast(msg.clone());
+                self.push_log(LogKind::Info, msg);
+            }
+            ClientEvent::TradeRejected { symbol, reason } => {
+                if self
+                    .pending_trade
+                    .as_ref()
+                    .is_some_and(|pending| pending.symbol == symbol)
+                {
+                    self.pending_trade = None;
                 }
+                let msg = format!("Trade for {symbol} rejected: {reason}");
+                self.push_toast(msg.clone());
+                self.push_log(
+                    LogKind::Error,
+                    format!("[TRADE REJECTED] {symbol}: {reason}"),
+                );
             }
+            ClientEvent::Log(s) => {
+                self.push_log(LogKind::Info, s);
+            }
+        }
+    }
+
+    fn expire_pending_trade(&mut self) {
+        if let Some(pending) = &self.pending_trade
+            && pending.sent_at.elapsed() >= PENDING_TRADE_TIMEOUT
+        {
+            let symbol = pending.symbol.clone();
+            self.pending_trade = None;
+            self.push_log(
+                LogKind::Error,
+                format!("Trade for {symbol} timed out waiting for a price."),
+            );
         }
     }
 
     fn send(&mut self, cmd: UiCommand) {
-        if self.cmd_tx.send(cmd).is_err() {
-            self.push_log(LogKind::Error, "Network worker not available.");
+        self.session.track(&cmd);
+        match self.cmd_tx.try_send(cmd) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.push_log(LogKind::Error, "Network worker is busy, command dropped.");
+            }
+            Err(TrySendError::Disconnected(_)) => self.mark_worker_dead(),
+        }
+    }
+
+    fn take_pending_alert(&mut self, symbol: &str, dir: AlertDirection) {
+        self.pending_alerts
+            .retain(|a| !(a.symbol == symbol && a.dir == dir));
+    }
+
+    fn request_client_data(&mut self, offset: usize) {
+        if offset == 0 {
+            self.last_data_request = Instant::now();
+            self.quiet_data_refresh = false;
+        }
+        self.data_offset = offset;
+        self.send(UiCommand::GetAllClientData { offset });
+    }
+
+    // Paused while logged out or disconnected, the first refresh after login is the usual one.
+    fn poll_auto_refresh(&mut self, now: Instant) {
+        if !self.auto_refresh || !self.connected || !self.authenticated {
+            return;
+        }
+        let interval = Duration::from_secs(self.auto_refresh_secs.max(MIN_DATA_REFRESH_SECS));
+        if now.duration_since(self.last_data_request) < interval {
+            return;
+        }
+        self.request_client_data(0);
+        self.quiet_data_refresh = true;
+    }
+
+    fn poll_latency_probe(&mut self, now: Instant) {
+        if !self.connected || now.duration_since(self.last_probe_at) < LATENCY_PROBE_INTERVAL {
+            return;
+        }
+        self.last_probe_at = now;
+        self.probe_nonce += 1;
+        self.pending_probe = Some((self.probe_nonce, now));
+        self.send(UiCommand::Ping {
+            nonce: self.probe_nonce,
+        });
+    }
+
+    fn poll_ticker(&mut self, now: Instant) {
+        if !self.connected || !self.authenticated {
+            return;
         }
+        if let Some(at) = self.last_ticker_at
+            && now.duration_since(at) < TICKER_REFRESH_INTERVAL
+        {
+            return;
+        }
+        let symbols = ticker_symbols(&self.alerts, &self.portfolio);
+        if symbols.is_empty() {
+            return;
+        }
+        self.last_ticker_at = Some(now);
+        for symbol in symbols {
+            let req_id = self.take_req_id();
+            self.ticker_requests.insert(req_id);
+            self.send(UiCommand::CheckPrice {
+                symbol,
+                req_id: Some(req_id),
+            });
+        }
+    }
+
+    fn render_ticker(&self, ui: &mut egui::Ui) {
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for symbol in ticker_symbols(&self.alerts, &self.portfolio) {
+                    let quote = self.ticker.get(&symbol).filter(|_| self.connected);
+                    let palette = self.theme.palette();
+                    let color = match quote.map(|q| q.trend) {
+                        Some(std::cmp::Ordering::Greater) => palette.gain,
+                        Some(std::cmp::Ordering::Less) => palette.loss,
+                        _ => ui.visuals().text_color(),
+                    };
+                    ui.colored_label(color, ticker_text(&symbol, quote, &self.money));
+                    ui.separator();
+                }
+            });
+        });
+    }
+
+    fn poll_reconnect(&mut self, now: Instant) {
+        let Some(at) = self.reconnect_at else {
+            return;
+        };
+        if self.connected || !self.worker_alive {
+            self.reconnect_at = None;
+            return;
+        }
+        if now < at {
+            self.conn_status = reconnect_status(self.reconnect_attempts + 1, Some(at - now));
+            return;
+        }
+        self.reconnect_at = None;
+        self.reconnect_attempts += 1;
+        let addr = self.addr.trim().to_string();
+        self.conn_status = reconnect_status(self.reconnect_attempts, None);
+        self.push_log(
+            LogKind::Info,
+            format!(
+                "Reconnecting to {addr} (attempt {}/{MAX_RECONNECT_ATTEMPTS})...",
+                self.reconnect_attempts
+            ),
+        );
+        self.connecting = true;
+        self.send(UiCommand::Connect { addr });
+    }
+
+    // The worker thread owns the socket, so once it is gone the connection is gone too.
+    fn mark_worker_dead(&mut self) {
+        if !self.worker_alive {
+            return;
+        }
+        self.worker_alive = false;
+        self.connected = false;
+        self.authenticated = false;
+        self.pending_trade = None;
+        self.conn_status = "Network worker stopped".into();
+        self.push_log(LogKind::Error, "Network worker stopped unexpectedly.");
+    }
+
+    fn restart_network_worker(&mut self) {
+        let (cmd_tx, ev_rx) = spawn_network_worker();
+        self.cmd_tx = cmd_tx;
+        self.ev_rx = ev_rx;
+        self.worker_alive = true;
+        self.connecting = false;
+        self.conn_status = "Disconnected".into();
+        self.auth_notice = None;
+        self.push_log(LogKind::Info, "Network worker restarted.");
+    }
+
+    fn take_req_id(&mut self) -> u64 {
+        let req_id = self.next_req_id;
+        self.next_req_id += 1;
+        req_id
     }
 
     fn normalize_symbol(&self) -> String {
@@ -636,11 +1873,108 @@ impl App {
         symbol
     }
 
+    // Closes the popup; Remove only does something for a real (non-test) alert.
+    fn answer_alert_popup(&mut self, action: PopupAction) {
+        if action == PopupAction::Remove
+            && let Some(alert) = self.alert_popup_data.clone()
+        {
+            self.send(UiCommand::RemoveAlert {
+                symbol: alert.symbol.clone(),
+                dir: alert.dir,
+            });
+            self.remove_local_alert(&alert.symbol, alert.dir);
+        }
+        self.alert_popup_open = false;
+        self.alert_popup_message = None;
+        self.alert_popup_data = None;
+    }
+
     fn remove_local_alert(&mut self, symbol: &str, dir: AlertDirection) {
         self.alerts
             .retain(|row| !(row.symbol == symbol && row.dir == dir));
     }
 
+    // Applied on the next frame and saved right away.
+    fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        if let Err(e) = save_theme(THEME_FILE, theme) {
+            self.push_log(LogKind::Error, format!("Failed to save {THEME_FILE}: {e}"));
+        }
+    }
+
+    // Replaces whatever the chart followed before, there is one chart at a time.
+    fn watch_chart(&mut self, symbol: String) {
+        self.chart_symbol = Some(symbol.clone());
+        self.send(UiCommand::GetHistory { symbol });
+    }
+
+    // Lowering the depth drops the oldest points of the current chart right away.
+    fn set_chart_depth(&mut self, depth: usize) {
+        self.chart_depth = depth.clamp(MIN_CHART_DEPTH, MAX_CHART_DEPTH);
+        if let Some((_, points)) = &mut self.chart {
+            keep_newest(points, self.chart_depth);
+        }
+        if let Err(e) = save_chart_depth(CHART_DEPTH_FILE, self.chart_depth) {
+            self.push_log(
+                LogKind::Error,
+                format!("Failed to save {CHART_DEPTH_FILE}: {e}"),
+            );
+        }
+    }
+
+    // The list keeps the old threshold until the server confirms with ALERTUPDATED.
+    fn save_alert_edit(&mut self) {
+        let Some(edit) = &self.alert_edit else {
+            return;
+        };
+        match parse_threshold(&edit.input) {
+            Ok(threshold) => {
+                let cmd = UiCommand::UpdateAlert {
+                    symbol: edit.symbol.clone(),
+                    dir: edit.dir,
+                    threshold,
+                };
+                self.alert_edit = None;
+                self.send(cmd);
+            }
+            Err(e) => self.push_log(LogKind::Error, e),
+        }
+    }
+
+    // Shown right away, the PREFS reply puts back what the server actually saved.
+    fn set_preferences(&mut self, prefs: Preferences) {
+        self.prefs = Some(prefs);
+        self.send(UiCommand::SetPreferences(prefs));
+    }
+
+    // Symbol field with a dropdown of matching tracked symbols; plain text until the list is loaded.
+    fn render_symbol_input(&mut self, ui: &mut egui::Ui) {
+        let response = ui
+            .horizontal(|ui| {
+                ui.label("Symbol:");
+                ui.text_edit_singleline(&mut self.symbol_input)
+            })
+            .inner;
+
+        let suggestions = symbol_suggestions(&self.symbols, &self.symbol_input);
+        if suggestions.is_empty() {
+            return;
+        }
+
+        let popup_id = ui.make_persistent_id("symbol_autocomplete");
+        if response.has_focus() {
+            ui.memory_mut(|mem| mem.open_popup(popup_id));
+        }
+        egui::popup_below_widget(ui, popup_id, &response, |ui| {
+            for symbol in suggestions {
+                let label = symbol_label(&self.symbol_names, &symbol);
+                if ui.selectable_label(false, label).clicked() {
+                    self.symbol_input = symbol;
+                }
+            }
+        });
+    }
+
     fn render_auth_screen(&mut self, ui: &mut egui::Ui) {
         ui.heading("Login / Register");
 
@@ -661,6 +1995,11 @@ impl App {
             ui.add(egui::TextEdit::singleline(&mut self.password_input).password(true));
         });
 
+        ui.checkbox(
+            &mut self.remember_login,
+            "Remember credentials to log in again when the session expires",
+        );
+
         ui.add_space(8.0);
 
         let action_label = match self.auth_mode {
@@ -676,7 +2015,12 @@ impl App {
             let password = self.password_input.trim().to_string();
             self.auth_notice = Some("Waiting for server response...".into());
             match self.auth_mode {
-                AuthMode::Login => self.send(UiCommand::LoginClient { username, password }),
+                AuthMode::Login => {
+                    self.session.credentials = self
+                        .remember_login
+                        .then(|| (username.clone(), password.clone()));
+                    self.send(UiCommand::LoginClient { username, password })
+                }
                 AuthMode::Register => self.send(UiCommand::RegisterClient { username, password }),
             }
         }
@@ -690,10 +2034,78 @@ impl App {
         ui.small("You must be connected to log in or register.");
     }
 
+    fn render_reset_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut password) = self.reset_password_input.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Reset account")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label("All positions will be removed. Alerts and your login stay.");
+                ui.horizontal(|ui| {
+                    ui.label("Password:");
+                    ui.add(egui::TextEdit::singleline(&mut password).password(true));
+                });
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    let can_reset = self.connected && !password.trim().is_empty();
+                    confirmed = ui
+                        .add_enabled(can_reset, egui::Button::new("Reset"))
+                        .clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+        if confirmed {
+            self.send(UiCommand::ResetAccount {
+                password: password.trim().to_string(),
+            });
+        } else if open && !cancelled {
+            self.reset_password_input = Some(password);
+        }
+    }
+
+    fn render_cancel_orders_dialog(&mut self, ctx: &egui::Context) {
+        if !self.cancel_orders_open {
+            return;
+        }
+        let mut open = true;
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Cancel all orders")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label("Every pending bracket will be cancelled. Positions stay.");
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    confirmed = ui
+                        .add_enabled(self.connected, egui::Button::new("Cancel all"))
+                        .clicked();
+                    cancelled = ui.button("Keep").clicked();
+                });
+            });
+        if confirmed {
+            self.send(UiCommand::CancelAllOrders);
+        }
+        self.cancel_orders_open = open && !confirmed && !cancelled;
+    }
+
     fn render_main_screen(&mut self, ui: &mut egui::Ui) {
         ui.columns(2, |cols| {
             cols[0].group(|ui| {
                 ui.heading("Command");
+                if let Some(pending) = &self.pending_trade {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(pending_trade_status(pending));
+                    });
+                }
 
                 ui.horizontal(|ui| {
                     ui.label("Command:");
@@ -736,28 +2148,22 @@ impl App {
 
                 match self.command_kind {
                     CommandKind::AddAlert => {
-                        ui.horizontal(|ui| {
-                            ui.label("Symbol:");
-                            ui.text_edit_singleline(&mut self.symbol_input);
-                        });
+                        self.render_symbol_input(ui);
 
                         ui.horizontal(|ui| {
                             ui.label("Direction:");
                             egui::ComboBox::from_id_source("dir_combo")
-                                .selected_text(match self.dir_input {
-                                    AlertDirection::Above => "ABOVE",
-                                    AlertDirection::Below => "BELOW",
-                                })
+                                .selected_text(self.dir_input.as_str())
                                 .show_ui(ui, |ui| {
                                     ui.selectable_value(
                                         &mut self.dir_input,
                                         AlertDirection::Above,
-                                        "ABOVE",
+                                        AlertDirection::Above.as_str(),
                                     );
                                     ui.selectable_value(
                                         &mut self.dir_input,
                                         AlertDirection::Below,
-                                        "BELOW",
+                                        AlertDirection::Below.as_str(),
                                     );
                                 });
                         });
@@ -775,11 +2181,17 @@ impl App {
                             .clicked()
                         {
                             let symbol = self.normalize_symbol();
-                            let threshold = self.threshold_input.trim().parse::<f64>();
+                            let threshold = parse_threshold(&self.threshold_input);
                             match threshold {
                                 Ok(th) => {
-                                    if self
-                                        .alerts
+                                    // The server keeps one alert per symbol and direction, sending
+                                    // another threshold moves the existing one.
+                                    if self.alerts.iter().any(|a| {
+                                        a.symbol == symbol
+                                            && a.dir == self.dir_input
+                                            && a.threshold == th
+                                    }) || self
+                                        .pending_alerts
                                         .iter()
                                         .any(|a| a.symbol == symbol && a.dir == self.dir_input)
                                     {
@@ -789,44 +2201,38 @@ impl App {
                                         );
                                         return;
                                     }
+                                    self.pending_alerts.push(AlertRow {
+                                        symbol: symbol.clone(),
+                                        dir: self.dir_input,
+                                        threshold: th,
+                                    });
                                     self.send(UiCommand::AddAlert {
                                         symbol,
                                         dir: self.dir_input,
                                         threshold: th,
                                     });
                                 }
-                                Err(_) => {
-                                    self.push_log(
-                                        LogKind::Error,
-                                        "Invalid threshold (expected number).",
-                                    );
-                                }
+                                Err(e) => self.push_log(LogKind::Error, e),
                             }
                         }
                     }
                     CommandKind::RemoveAlert => {
-                        ui.horizontal(|ui| {
-                            ui.label("Symbol:");
-                            ui.text_edit_singleline(&mut self.symbol_input);
-                        });
+                        self.render_symbol_input(ui);
 
                         ui.horizontal(|ui| {
                             ui.label("Direction:");
                             egui::ComboBox::from_id_source("dir_combo")
-                                .selected_text(match self.dir_input {
-                                    AlertDirection::Above => "ABOVE",
-                                    AlertDirection::Below => "BELOW",
-                                })
+                                .selected_text(self.dir_input.as_str())
                                 .show_ui(ui, |ui| {
                                     ui.selectable_value(
                                         &mut self.dir_input,
                                         AlertDirection::Above,
-                                        "ABOVE",
+                                        AlertDirection::Above.as_str(),
                                     );
                                     ui.selectable_value(
                                         &mut self.dir_input,
                                         AlertDirection::Below,
-                                        "BELOW",
+                                        AlertDirection::Below.as_str(),
                                     );
                                 });
                         });
@@ -847,10 +2253,7 @@ impl App {
                         }
                     }
                     CommandKind::CheckPrice => {
-                        ui.horizontal(|ui| {
-                            ui.label("Symbol:");
-                            ui.text_edit_singleline(&mut self.symbol_input);
-                        });
+                        self.render_symbol_input(ui);
 
                         ui.add_space(8.0);
 
@@ -860,14 +2263,15 @@ impl App {
                             .clicked()
                         {
                             let symbol = self.normalize_symbol();
-                            self.send(UiCommand::CheckPrice { symbol });
+                            self.watch_chart(symbol.clone());
+                            self.send(UiCommand::CheckPrice {
+                                symbol,
+                                req_id: None,
+                            });
                         }
                     }
                     CommandKind::BuyStock => {
-                        ui.horizontal(|ui| {
-                            ui.label("Symbol:");
-                            ui.text_edit_singleline(&mut self.symbol_input);
-                        });
+                        self.render_symbol_input(ui);
 
                         ui.horizontal(|ui| {
                             ui.label("Quantity:");
@@ -885,12 +2289,18 @@ impl App {
                             let quantity = self.quantity_input.trim().parse::<i32>();
                             match quantity {
                                 Ok(qty) => {
+                                    let req_id = self.take_req_id();
                                     self.pending_trade = Some(PendingTrade {
                                         symbol: symbol.clone(),
                                         quantity: qty,
                                         kind: TradeKind::Buy,
+                                        req_id,
+                                        sent_at: Instant::now(),
+                                    });
+                                    self.send(UiCommand::CheckPrice {
+                                        symbol,
+                                        req_id: Some(req_id),
                                     });
-                                    self.send(UiCommand::CheckPrice { symbol });
                                 }
                                 Err(_) => {
                                     self.push_log(
@@ -902,10 +2312,7 @@ impl App {
                         }
                     }
                     CommandKind::SellStock => {
-                        ui.horizontal(|ui| {
-                            ui.label("Symbol:");
-                            ui.text_edit_singleline(&mut self.symbol_input);
-                        });
+                        self.render_symbol_input(ui);
 
                         ui.horizontal(|ui| {
                             ui.label("Quantity:");
@@ -923,12 +2330,18 @@ impl App {
                             let quantity = self.quantity_input.trim().parse::<i32>();
                             match quantity {
                                 Ok(qty) => {
+                                    let req_id = self.take_req_id();
                                     self.pending_trade = Some(PendingTrade {
                                         symbol: symbol.clone(),
                                         quantity: qty,
                                         kind: TradeKind::Sell,
+                                        req_id,
+                                        sent_at: Instant::now(),
+                                    });
+                                    self.send(UiCommand::CheckPrice {
+                                        symbol,
+                                        req_id: Some(req_id),
                                     });
-                                    self.send(UiCommand::CheckPrice { symbol });
                                 }
                                 Err(_) => {
                                     self.push_log(
@@ -941,6 +2354,11 @@ impl App {
                     }
                 }
 
+                if let Some((symbol, points)) = &self.chart {
+                    ui.add_space(12.0);
+                    render_chart(ui, symbol, points);
+                }
+
                 ui.add_space(16.0);
                 ui.label("Notes:");
                 ui.small("You must be connected to send commands.");
@@ -954,44 +2372,214 @@ impl App {
                         .add_enabled(refresh_enabled, egui::Button::new("Refresh data"))
                         .clicked()
                     {
-                        self.send(UiCommand::GetAllClientData);
+                        self.request_client_data(0);
                     }
                     ui.add_space(6.0);
                 }
-
-                if self.alerts.is_empty() {
-                    ui.label("No alerts added yet.");
-                } else {
-                    egui::ScrollArea::vertical()
-                        .id_source("alerts_scroll")
-                        .max_height(240.0)
-                        .show(ui, |ui| {
-                            for (idx, a) in self.alerts.clone().into_iter().enumerate() {
-                                ui.horizontal(|ui| {
-                                    ui.label(format!("{} {:?} {}", a.symbol, a.dir, a.threshold));
-
-                                    let del_enabled = self.connected;
-                                    if ui
-                                        .add_enabled(del_enabled, egui::Button::new("Del"))
-                                        .clicked()
-                                    {
-                                        self.send(UiCommand::RemoveAlert {
-                                            symbol: a.symbol.clone(),
-                                            dir: a.dir,
-                                        });
-                                        if idx < self.alerts.len() {
-                                            self.alerts.remove(idx);
-                                        }
-                                    }
-                                });
-                                ui.separator();
+                if ui
+                    .button("Test alert")
+                    .on_hover_text("Plays the alert sound and shows the popup without the server.")
+                    .clicked()
+                {
+                    self.handle_event(test_alert_event());
+                }
+                ui.checkbox(
+                    &mut self.popup_on_alert_added,
+                    "Popup when an alert is added",
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Price decimals:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.money.decimals)
+                            .clamp_range(0..=MAX_PRICE_DECIMALS),
+                    );
+                    ui.label("Currency:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.money.currency).desired_width(30.0),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Theme:");
+                    let mut theme = self.theme;
+                    egui::ComboBox::from_id_source("theme")
+                        .selected_text(theme.label())
+                        .show_ui(ui, |ui| {
+                            for option in Theme::ALL {
+                                ui.selectable_value(&mut theme, option, option.label());
                             }
                         });
-                }
-            });
-
-            cols[1].group(|ui| {
+                    if theme != self.theme {
+                        self.set_theme(theme);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Chart points:");
+                    let mut depth = self.chart_depth;
+                    ui.add(
+                        egui::DragValue::new(&mut depth)
+                            .clamp_range(MIN_CHART_DEPTH..=MAX_CHART_DEPTH),
+                    );
+                    if depth != self.chart_depth {
+                        self.set_chart_depth(depth);
+                    }
+                });
+                if self.authenticated
+                    && let Some(mut prefs) = self.prefs
+                {
+                    let changed = ui
+                        .add_enabled_ui(self.connected, |ui| {
+                            ui.checkbox(&mut prefs.triggers, "Notify on alert triggers")
+                                .changed()
+                                | ui.checkbox(&mut prefs.fills, "Notify on bracket fills")
+                                    .changed()
+                        })
+                        .inner;
+                    if changed {
+                        self.set_preferences(prefs);
+                    }
+                }
+                ui.add_space(6.0);
+
+                if self.authenticated {
+                    ui.collapsing("Import alerts", |ui| {
+                        ui.small("One alert per line: SYMBOL ABOVE|BELOW THRESHOLD");
+                        ui.text_edit_multiline(&mut self.import_input);
+                        if ui
+                            .add_enabled(self.connected, egui::Button::new("Import"))
+                            .clicked()
+                        {
+                            match parse_alert_import(&self.import_input) {
+                                Ok(alerts) if alerts.is_empty() => {
+                                    self.push_log(LogKind::Error, "Nothing to import.");
+                                }
+                                Ok(alerts) => {
+                                    self.send(UiCommand::AddAlerts { alerts });
+                                    self.import_input.clear();
+                                }
+                                Err(e) => self.push_log(LogKind::Error, e),
+                            }
+                        }
+                    });
+                    ui.add_space(6.0);
+                }
+
+                if let Some(notice) = &self.alert_notice {
+                    ui.colored_label(self.theme.palette().error, notice);
+                    ui.add_space(6.0);
+                }
+                for a in &self.pending_alerts {
+                    ui.weak(format!(
+                        "{} {} {} (waiting for server...)",
+                        a.symbol,
+                        a.dir,
+                        self.money.format(a.threshold)
+                    ));
+                }
+
+                if self.alerts.is_empty() {
+                    ui.label("No alerts added yet.");
+                } else {
+                    let del_enabled = self.connected;
+                    let mut action: Option<AlertRowAction> = None;
+                    egui::ScrollArea::vertical()
+                        .id_source("alerts_scroll")
+                        .max_height(240.0)
+                        .show(ui, |ui| {
+                            for (symbol, rows) in group_alerts(&self.alerts) {
+                                // A lone alert keeps the flat one-line layout.
+                                if let [a] = rows.as_slice() {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!(
+                                            "{} {}",
+                                            symbol_label(&self.symbol_names, &a.symbol),
+                                            a.dir
+                                        ));
+                                        alert_row_controls(
+                                            ui,
+                                            a,
+                                            del_enabled,
+                                            &mut self.alert_edit,
+                                            &mut action,
+                                            &self.money,
+                                        );
+                                    });
+                                } else {
+                                    egui::CollapsingHeader::new(format!(
+                                        "{} ({} alerts)",
+                                        symbol_label(&self.symbol_names, &symbol),
+                                        rows.len()
+                                    ))
+                                    .id_source(("alert_group", &symbol))
+                                    .default_open(true)
+                                    .show(ui, |ui| {
+                                        for a in &rows {
+                                            ui.horizontal(|ui| {
+                                                ui.label(a.dir.as_str());
+                                                alert_row_controls(
+                                                    ui,
+                                                    a,
+                                                    del_enabled,
+                                                    &mut self.alert_edit,
+                                                    &mut action,
+                                                    &self.money,
+                                                );
+                                            });
+                                        }
+                                    });
+                                }
+                                ui.separator();
+                            }
+                        });
+                    match action {
+                        Some(AlertRowAction::Remove(a)) => {
+                            self.send(UiCommand::RemoveAlert {
+                                symbol: a.symbol.clone(),
+                                dir: a.dir,
+                            });
+                            self.remove_local_alert(&a.symbol, a.dir);
+                        }
+                        Some(AlertRowAction::Save) => self.save_alert_edit(),
+                        None => {}
+                    }
+                }
+            });
+
+            cols[1].group(|ui| {
                 ui.heading("Portfolio");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.auto_refresh, "Refresh every");
+                    ui.add_enabled(
+                        self.auto_refresh,
+                        egui::DragValue::new(&mut self.auto_refresh_secs)
+                            .clamp_range(MIN_DATA_REFRESH_SECS..=MAX_DATA_REFRESH_SECS)
+                            .suffix(" s"),
+                    );
+                    if ui
+                        .add_enabled(self.connected, egui::Button::new("Leaderboard"))
+                        .clicked()
+                    {
+                        self.send(UiCommand::GetLeaderboard);
+                    }
+                    if ui
+                        .add_enabled(self.connected, egui::Button::new("Export"))
+                        .on_hover_text("Save alerts, positions and brackets as JSON")
+                        .clicked()
+                    {
+                        self.send(UiCommand::ExportData);
+                    }
+                    if ui
+                        .add_enabled(self.connected, egui::Button::new("Reset account..."))
+                        .clicked()
+                    {
+                        self.reset_password_input = Some(String::new());
+                    }
+                    if ui
+                        .add_enabled(self.connected, egui::Button::new("Cancel all orders..."))
+                        .clicked()
+                    {
+                        self.cancel_orders_open = true;
+                    }
+                });
 
                 if self.portfolio.is_empty() {
                     ui.label("No portfolio entries.");
@@ -1001,15 +2589,7 @@ impl App {
                         .max_height(240.0)
                         .show(ui, |ui| {
                             for stock in &self.portfolio {
-                                let (amount_label, amount_value) = if stock.total_price >= 0.0 {
-                                    ("spent", stock.total_price)
-                                } else {
-                                    ("earned", -stock.total_price)
-                                };
-                                ui.label(format!(
-                                    "{} quantity={} {} {:.3}",
-                                    stock.symbol, stock.quantity, amount_label, amount_value
-                                ));
+                                ui.label(portfolio_line(stock, &self.symbol_names, &self.money));
                                 ui.separator();
                             }
                         });
@@ -1021,23 +2601,39 @@ impl App {
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if !self.style_initialized {
-            configure_dashboard_light_style(ctx);
-            self.style_initialized = true;
+        if self.applied_theme != Some(self.theme) {
+            ctx.set_style(self.theme.style(&ctx.style()));
+            self.applied_theme = Some(self.theme);
         }
 
         self.drain_events();
+        self.poll_reconnect(Instant::now());
+        self.poll_auto_refresh(Instant::now());
+        self.poll_latency_probe(Instant::now());
+        self.poll_ticker(Instant::now());
+        self.expire_pending_trade();
+        self.expire_toasts(Instant::now());
 
         egui::TopBottomPanel::top("top_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.label("Server:");
                 ui.text_edit_singleline(&mut self.addr);
 
-                if !self.connected {
-                    if ui.button("Connect").clicked() {
+                if !self.worker_alive {
+                    if ui.button("Restart network worker").clicked() {
+                        self.restart_network_worker();
+                    }
+                } else if !self.connected {
+                    if ui
+                        .add_enabled(!self.connecting, egui::Button::new("Connect"))
+                        .clicked()
+                    {
                         let addr = self.addr.trim().to_string();
+                        self.reconnect_at = None;
+                        self.reconnect_attempts = 0;
                         self.conn_status = "Connecting...".into();
                         self.push_log(LogKind::Info, format!("Connecting to {addr}..."));
+                        self.connecting = true;
                         self.send(UiCommand::Connect { addr });
                     }
                 } else if ui.button("Disconnect").clicked() {
@@ -1046,9 +2642,24 @@ impl eframe::App for App {
 
                 ui.separator();
                 ui.label(format!("Status: {}", self.conn_status));
+                if self.connected
+                    && let Some(rtt) = self.latency
+                {
+                    ui.label(format!("RTT {} ms", rtt.as_millis()));
+                }
+                if self.authenticated
+                    && let Some(username) = &self.identity
+                {
+                    ui.separator();
+                    ui.label(format!("Logged in as {username}"));
+                }
             });
         });
 
+        if self.authenticated {
+            egui::TopBottomPanel::top("ticker").show(ctx, |ui| self.render_ticker(ui));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if self.authenticated {
                 self.render_main_screen(ui);
@@ -1070,18 +2681,17 @@ impl eframe::App for App {
             egui::ScrollArea::vertical()
                 .auto_shrink([false; 2])
                 .show(ui, |ui| {
+                    let palette = self.theme.palette();
                     for row in &self.logs {
                         let prefix = format!("[{}] ", row.ts);
                         match row.kind {
                             LogKind::Info => ui.label(format!("{prefix}{}", row.msg)),
-                            LogKind::Error => ui.colored_label(
-                                egui::Color32::LIGHT_RED,
-                                format!("{prefix}{}", row.msg),
-                            ),
-                            LogKind::Alert => ui.colored_label(
-                                egui::Color32::LIGHT_YELLOW,
-                                format!("{prefix}{}", row.msg),
-                            ),
+                            LogKind::Error => {
+                                ui.colored_label(palette.error, format!("{prefix}{}", row.msg))
+                            }
+                            LogKind::Alert => {
+                                ui.colored_label(palette.alert, format!("{prefix}{}", row.msg))
+                            }
                         };
                     }
                 });
@@ -1089,105 +2699,531 @@ impl eframe::App for App {
 
         if self.alert_popup_open {
             let mut open = self.alert_popup_open;
-            let mut should_close = false;
+            let typing = ctx.wants_keyboard_input();
+            let mut action = ctx.input(|i| popup_key_action(i, typing));
             egui::Window::new("Alert")
                 .collapsible(false)
-                .resizable(false)
+                .resizable(true)
+                .default_width(360.0)
+                .min_width(240.0)
                 .open(&mut open)
                 .show(ctx, |ui| {
-                    if let Some(msg) = &self.alert_popup_message {
-                        ui.label(msg);
-                    } else {
-                        ui.label("Alert added.");
+                    let msg = self
+                        .alert_popup_message
+                        .as_deref()
+                        .unwrap_or("Alert added.");
+                    ui.add(egui::Label::new(msg).wrap(true));
+                    if self.alert_popup_data.is_none() {
+                        ui.label("This is a test alert, nothing was sent to the server.");
+                        ui.add_space(8.0);
+                        if ui.button("Close").clicked() {
+                            action = Some(PopupAction::Keep);
+                        }
+                        return;
                     }
-                    ui.label("You can remove this alert if you no longer want it, or keep it.");
+                    ui.add(
+                        egui::Label::new(
+                            "You can remove this alert if you no longer want it, or keep it.",
+                        )
+                        .wrap(true),
+                    );
                     ui.add_space(8.0);
                     ui.horizontal(|ui| {
-                        if ui.button("Remove alert").clicked() {
-                            if let Some(alert) = self.alert_popup_data.clone() {
-                                self.send(UiCommand::RemoveAlert {
-                                    symbol: alert.symbol.clone(),
-                                    dir: alert.dir,
-                                });
-                                self.remove_local_alert(&alert.symbol, alert.dir);
-                            }
-                            should_close = true;
+                        if ui
+                            .add(egui::Button::new("Remove alert (Enter)").selected(true))
+                            .clicked()
+                        {
+                            action = Some(PopupAction::Remove);
                         }
-                        if ui.button("Keep alert").clicked() {
-                            should_close = true;
+                        if ui.button("Keep alert (Esc)").clicked() {
+                            action = Some(PopupAction::Keep);
                         }
                     });
                 });
-            if should_close {
-                open = false;
+            if !open {
+                action = Some(PopupAction::Keep);
             }
-            self.alert_popup_open = open;
-            if !self.alert_popup_open {
-                self.alert_popup_message = None;
-                self.alert_popup_data = None;
+            if let Some(action) = action {
+                self.answer_alert_popup(action);
             }
         }
 
+        self.render_reset_dialog(ctx);
+        self.render_cancel_orders_dialog(ctx);
+        self.render_toasts(ctx);
+
         ctx.request_repaint_after(Duration::from_millis(50));
     }
 }
 
-fn configure_dashboard_light_style(ctx: &egui::Context) {
-    let mut style = (*ctx.style()).clone();
-    style.visuals = egui::Visuals::light();
-    style.visuals.window_fill = egui::Color32::from_rgb(244, 247, 251);
-    style.visuals.panel_fill = egui::Color32::from_rgb(236, 242, 248);
-    style.visuals.extreme_bg_color = egui::Color32::from_rgb(228, 236, 244);
-    style.visuals.selection.bg_fill = egui::Color32::from_rgb(26, 110, 192);
-    style.visuals.hyperlink_color = egui::Color32::from_rgb(20, 120, 200);
-    style.visuals.widgets.inactive.bg_fill = egui::Color32::from_rgb(246, 249, 252);
-    style.visuals.widgets.inactive.fg_stroke =
+// "AAPL — Apple Inc." when the server knows the company, else just the ticker.
+fn symbol_label(names: &HashMap<String, String>, symbol: &str) -> String {
+    match names.get(symbol) {
+        Some(name) => format!("{symbol} — {name}"),
+        None => symbol.to_string(),
+    }
+}
+
+// total_price is the cost of the shares held, so it is never "earned" money.
+fn portfolio_line(
+    stock: &PortfolioStock,
+    names: &HashMap<String, String>,
+    money: &MoneyFormat,
+) -> String {
+    let label = symbol_label(names, &stock.symbol);
+    match stock.average_price() {
+        Some(avg) => format!(
+            "{} quantity={} cost {} (avg {})",
+            label,
+            stock.quantity,
+            money.format(stock.total_price),
+            money.format(avg)
+        ),
+        None => format!("{label} closed"),
+    }
+}
+
+// The colors that carry meaning: gains and losses (ticker) and the log's errors and alerts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Palette {
+    gain: egui::Color32,
+    loss: egui::Color32,
+    error: egui::Color32,
+    alert: egui::Color32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Theme {
+    Light,
+    Dark,
+    HighContrast,
+}
+
+impl Theme {
+    const ALL: [Theme; 3] = [Theme::Light, Theme::Dark, Theme::HighContrast];
+
+    // Name kept in THEME_FILE.
+    fn as_str(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Theme> {
+        Theme::ALL
+            .into_iter()
+            .find(|theme| theme.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+            Theme::HighContrast => "High contrast",
+        }
+    }
+
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                gain: egui::Color32::DARK_GREEN,
+                loss: egui::Color32::DARK_RED,
+                error: egui::Color32::from_rgb(180, 30, 30),
+                alert: egui::Color32::from_rgb(150, 95, 0),
+            },
+            Theme::Dark => Palette {
+                gain: egui::Color32::LIGHT_GREEN,
+                loss: egui::Color32::LIGHT_RED,
+                error: egui::Color32::LIGHT_RED,
+                alert: egui::Color32::LIGHT_YELLOW,
+            },
+            Theme::HighContrast => Palette {
+                gain: egui::Color32::from_rgb(0, 255, 0),
+                loss: egui::Color32::from_rgb(255, 80, 80),
+                error: egui::Color32::from_rgb(255, 80, 80),
+                alert: egui::Color32::YELLOW,
+            },
+        }
+    }
+
+    // Every theme keeps the dashboard's spacing and text sizes, only the visuals differ.
+    fn style(self, base: &egui::Style) -> egui::Style {
+        let mut style = base.clone();
+        style.visuals = match self {
+            Theme::Light => light_visuals(),
+            Theme::Dark => egui::Visuals::dark(),
+            Theme::HighContrast => high_contrast_visuals(),
+        };
+        style.visuals.window_rounding = egui::Rounding::same(10.0);
+
+        style.spacing.button_padding = egui::vec2(12.0, 8.0);
+        style.spacing.item_spacing = egui::vec2(10.0, 10.0);
+        style.spacing.window_margin = egui::Margin::same(12.0);
+
+        style.text_styles.insert(
+            egui::TextStyle::Heading,
+            egui::FontId::new(22.0, egui::FontFamily::Proportional),
+        );
+        style.text_styles.insert(
+            egui::TextStyle::Body,
+            egui::FontId::new(16.0, egui::FontFamily::Proportional),
+        );
+        style.text_styles.insert(
+            egui::TextStyle::Button,
+            egui::FontId::new(16.0, egui::FontFamily::Proportional),
+        );
+        style.text_styles.insert(
+            egui::TextStyle::Small,
+            egui::FontId::new(12.0, egui::FontFamily::Proportional),
+        );
+        style
+    }
+}
+
+fn light_visuals() -> egui::Visuals {
+    let mut visuals = egui::Visuals::light();
+    visuals.window_fill = egui::Color32::from_rgb(244, 247, 251);
+    visuals.panel_fill = egui::Color32::from_rgb(236, 242, 248);
+    visuals.extreme_bg_color = egui::Color32::from_rgb(228, 236, 244);
+    visuals.selection.bg_fill = egui::Color32::from_rgb(26, 110, 192);
+    visuals.hyperlink_color = egui::Color32::from_rgb(20, 120, 200);
+    visuals.widgets.inactive.bg_fill = egui::Color32::from_rgb(246, 249, 252);
+    visuals.widgets.inactive.fg_stroke =
         egui::Stroke::new(1.0, egui::Color32::from_rgb(35, 45, 55));
-    style.visuals.widgets.hovered.bg_fill = egui::Color32::from_rgb(220, 234, 248);
-    style.visuals.widgets.active.bg_fill = egui::Color32::from_rgb(200, 224, 246);
-    style.visuals.widgets.active.fg_stroke =
-        egui::Stroke::new(1.2, egui::Color32::from_rgb(25, 35, 45));
-    style.visuals.window_rounding = egui::Rounding::same(10.0);
-    style.visuals.widgets.noninteractive.bg_fill = egui::Color32::from_rgb(236, 242, 248);
-    style.visuals.widgets.noninteractive.fg_stroke =
+    visuals.widgets.hovered.bg_fill = egui::Color32::from_rgb(220, 234, 248);
+    visuals.widgets.active.bg_fill = egui::Color32::from_rgb(200, 224, 246);
+    visuals.widgets.active.fg_stroke = egui::Stroke::new(1.2, egui::Color32::from_rgb(25, 35, 45));
+    visuals.widgets.noninteractive.bg_fill = egui::Color32::from_rgb(236, 242, 248);
+    visuals.widgets.noninteractive.fg_stroke =
         egui::Stroke::new(1.0, egui::Color32::from_rgb(55, 65, 75));
+    visuals
+}
 
-    style.spacing.button_padding = egui::vec2(12.0, 8.0);
-    style.spacing.item_spacing = egui::vec2(10.0, 10.0);
-    style.spacing.window_margin = egui::Margin::same(12.0);
+// White on black with thick outlines, so every widget stands out from the background.
+fn high_contrast_visuals() -> egui::Visuals {
+    let mut visuals = egui::Visuals::dark();
+    visuals.override_text_color = Some(egui::Color32::WHITE);
+    visuals.window_fill = egui::Color32::BLACK;
+    visuals.panel_fill = egui::Color32::BLACK;
+    visuals.extreme_bg_color = egui::Color32::BLACK;
+    visuals.faint_bg_color = egui::Color32::from_gray(30);
+    visuals.window_stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+    visuals.selection.bg_fill = egui::Color32::from_rgb(0, 90, 255);
+    visuals.selection.stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+    visuals.hyperlink_color = egui::Color32::from_rgb(0, 255, 255);
+    let outline = egui::Stroke::new(2.0, egui::Color32::WHITE);
+    for widget in [
+        &mut visuals.widgets.noninteractive,
+        &mut visuals.widgets.inactive,
+        &mut visuals.widgets.hovered,
+        &mut visuals.widgets.active,
+        &mut visuals.widgets.open,
+    ] {
+        widget.fg_stroke = outline;
+        widget.bg_stroke = outline;
+    }
+    visuals.widgets.noninteractive.bg_fill = egui::Color32::BLACK;
+    visuals.widgets.inactive.bg_fill = egui::Color32::BLACK;
+    visuals.widgets.inactive.weak_bg_fill = egui::Color32::BLACK;
+    visuals.widgets.hovered.bg_fill = egui::Color32::from_gray(60);
+    visuals.widgets.hovered.weak_bg_fill = egui::Color32::from_gray(60);
+    visuals.widgets.active.bg_fill = egui::Color32::from_rgb(0, 90, 255);
+    visuals.widgets.active.weak_bg_fill = egui::Color32::from_rgb(0, 90, 255);
+    visuals
+}
 
-    style.text_styles.insert(
-        egui::TextStyle::Heading,
-        egui::FontId::new(22.0, egui::FontFamily::Proportional),
-    );
-    style.text_styles.insert(
-        egui::TextStyle::Body,
-        egui::FontId::new(16.0, egui::FontFamily::Proportional),
-    );
-    style.text_styles.insert(
-        egui::TextStyle::Button,
-        egui::FontId::new(16.0, egui::FontFamily::Proportional),
-    );
-    style.text_styles.insert(
-        egui::TextStyle::Small,
-        egui::FontId::new(12.0, egui::FontFamily::Proportional),
-    );
+// A missing or unreadable file means the default theme.
+fn load_theme(path: &str) -> Theme {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|name| Theme::parse(&name))
+        .unwrap_or(Theme::Light)
+}
+
+fn save_theme(path: &str, theme: Theme) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", theme.as_str()))
+}
 
-    ctx.set_style(style);
+// Same for the chart depth, a saved value out of range is clamped.
+fn load_chart_depth(path: &str) -> usize {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| raw.trim().parse::<usize>().ok())
+        .map_or(DEFAULT_CHART_DEPTH, |depth| {
+            depth.clamp(MIN_CHART_DEPTH, MAX_CHART_DEPTH)
+        })
 }
 
-fn now_hhmmss() -> String {
+fn save_chart_depth(path: &str, depth: usize) -> io::Result<()> {
+    std::fs::write(path, format!("{depth}\n"))
+}
+
+// Keeps only the newest unrequested quote per symbol from one drain pass, other events stay in order.
+fn coalesce_quotes(events: Vec<ClientEvent>) -> Vec<ClientEvent> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (idx, ev) in events.iter().enumerate() {
+        if let ClientEvent::PriceChecked {
+            symbol,
+            req_id: None,
+            ..
+        } = ev
+        {
+            latest.insert(symbol.clone(), idx);
+        }
+    }
+
+    events
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, ev)| match ev {
+            ClientEvent::PriceChecked {
+                symbol,
+                req_id: None,
+                ..
+            } => latest.get(symbol) == Some(idx),
+            _ => true,
+        })
+        .map(|(_, ev)| ev)
+        .collect()
+}
+
+fn symbol_suggestions(symbols: &[String], input: &str) -> Vec<String> {
+    let prefix = input.trim().to_ascii_uppercase();
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+
+    symbols
+        .iter()
+        .filter(|symbol| symbol.starts_with(&prefix) && **symbol != prefix)
+        .take(MAX_SYMBOL_SUGGESTIONS)
+        .cloned()
+        .collect()
+}
+
+fn unix_now() -> i64 {
     use std::time::{SystemTime, UNIX_EPOCH};
-    let secs = SystemTime::now()
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+fn hhmmss(secs: i64) -> String {
+    let secs = secs.rem_euclid(86_400);
     let s = secs % 60;
     let m = (secs / 60) % 60;
     let h = (secs / 3600) % 24;
     format!("{:02}:{:02}:{:02}", h, m, s)
 }
 
+fn render_chart(ui: &mut egui::Ui, symbol: &str, points: &[HistoryPoint]) {
+    if points.is_empty() {
+        ui.label(format!("{symbol}: no prices today yet"));
+        return;
+    }
+    ui.label(format!("{symbol} today ({} points)", points.len()));
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(ui.available_width(), 120.0),
+        egui::Sense::hover(),
+    );
+    let rect = response.rect;
+    painter.rect_stroke(rect, 2.0, egui::Stroke::new(1.0, egui::Color32::GRAY));
+    let color = egui::Color32::from_rgb(40, 110, 200);
+    match chart_line(points, rect).as_slice() {
+        [] => {}
+        [point] => {
+            painter.circle_filled(*point, 3.0, color);
+        }
+        line => {
+            painter.add(egui::Shape::line(
+                line.to_vec(),
+                egui::Stroke::new(1.5, color),
+            ));
+        }
+    }
+}
+
+fn keep_newest(points: &mut Vec<HistoryPoint>, depth: usize) {
+    if points.len() > depth {
+        points.drain(..points.len() - depth);
+    }
+}
+
+// Scales the closes into `rect`, oldest on the left and the highest close at the top.
+// A single point sits in the middle.
+fn chart_line(points: &[HistoryPoint], rect: egui::Rect) -> Vec<egui::Pos2> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Vec::new();
+    };
+    if points.len() == 1 {
+        return vec![rect.center()];
+    }
+    let (low, high) = points.iter().fold((f64::MAX, f64::MIN), |(low, high), p| {
+        (low.min(p.close), high.max(p.close))
+    });
+    let span_t = (last.timestamp - first.timestamp).max(1) as f32;
+    let span_p = (high - low).max(f64::EPSILON) as f32;
+
+    points
+        .iter()
+        .map(|p| {
+            let x = (p.timestamp - first.timestamp) as f32 / span_t;
+            let y = (p.close - low) as f32 / span_p;
+            egui::pos2(
+                rect.left() + x * rect.width(),
+                rect.bottom() - y * rect.height(),
+            )
+        })
+        .collect()
+}
+
+fn pending_trade_status(pending: &PendingTrade) -> String {
+    let action = match pending.kind {
+        TradeKind::Buy => "Buying",
+        TradeKind::Sell => "Selling",
+    };
+    format!(
+        "{action} {}x {}, waiting for a quote ({}s)",
+        pending.quantity,
+        pending.symbol,
+        pending.sent_at.elapsed().as_secs()
+    )
+}
+
+fn alert_failure_message(
+    symbol: &str,
+    dir: AlertDirection,
+    threshold: f64,
+    reason: &str,
+    money: &MoneyFormat,
+) -> String {
+    format!(
+        "{symbol} {} {}: {reason}",
+        dir.as_str(),
+        money.format(threshold)
+    )
+}
+
+// Threshold with Edit/Del buttons, or a text field with Save/Cancel while the row is edited.
+fn alert_row_controls(
+    ui: &mut egui::Ui,
+    row: &AlertRow,
+    enabled: bool,
+    edit: &mut Option<AlertEdit>,
+    action: &mut Option<AlertRowAction>,
+    money: &MoneyFormat,
+) {
+    match edit.as_mut().filter(|e| e.is_for(row)) {
+        Some(e) => {
+            let field = ui.add(egui::TextEdit::singleline(&mut e.input).desired_width(70.0));
+            let enter = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.add_enabled(enabled, egui::Button::new("Save")).clicked() || (enter && enabled) {
+                *action = Some(AlertRowAction::Save);
+            }
+            if ui.button("Cancel").clicked() {
+                *edit = None;
+            }
+        }
+        None => {
+            ui.label(money.format(row.threshold));
+            if ui.add_enabled(enabled, egui::Button::new("Edit")).clicked() {
+                *edit = Some(AlertEdit {
+                    symbol: row.symbol.clone(),
+                    dir: row.dir,
+                    input: row.threshold.to_string(),
+                });
+            }
+            if ui.add_enabled(enabled, egui::Button::new("Del")).clicked() {
+                *action = Some(AlertRowAction::Remove(row.clone()));
+            }
+        }
+    }
+}
+
+fn export_file_name(username: Option<&str>) -> String {
+    format!("{}{EXPORT_FILE_SUFFIX}", username.unwrap_or("account"))
+}
+
+// Indented for people opening the file, the server's line is kept if it doesn't parse.
+fn pretty_export(json: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(json)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| json.to_string())
+}
+
+// Alerts grouped by symbol in order of first appearance, ABOVE before BELOW inside a group.
+fn group_alerts(alerts: &[AlertRow]) -> Vec<(String, Vec<AlertRow>)> {
+    let mut groups: Vec<(String, Vec<AlertRow>)> = Vec::new();
+    for alert in alerts {
+        match groups
+            .iter_mut()
+            .find(|(symbol, _)| *symbol == alert.symbol)
+        {
+            Some((_, rows)) => rows.push(alert.clone()),
+            None => groups.push((alert.symbol.clone(), vec![alert.clone()])),
+        }
+    }
+    for (_, rows) in &mut groups {
+        rows.sort_by_key(|row| row.dir != AlertDirection::Above);
+    }
+    groups
+}
+
+// Caught here instead of waiting for the server to refuse it, against the default limit.
+fn parse_threshold(input: &str) -> Result<f64, String> {
+    let threshold: f64 = input
+        .trim()
+        .parse()
+        .map_err(|_| "Invalid threshold (expected number).".to_string())?;
+    check_threshold(threshold, DEFAULT_MAX_THRESHOLD)
+        .map_err(|e| format!("Invalid threshold ({e})."))?;
+    Ok(threshold)
+}
+
+// Blank lines and lines starting with '#' are ignored.
+fn parse_alert_import(text: &str) -> Result<Vec<AlertRow>, String> {
+    let mut alerts = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let parsed = match parts.as_slice() {
+            [symbol, dir, threshold] => AlertDirection::as_msg(dir).and_then(|dir| {
+                Some(AlertRow {
+                    symbol: symbol.to_ascii_uppercase(),
+                    dir,
+                    threshold: parse_threshold(threshold).ok()?,
+                })
+            }),
+            _ => None,
+        };
+        match parsed {
+            Some(alert) => alerts.push(alert),
+            None => {
+                return Err(format!(
+                    "Import line {}: expected SYMBOL ABOVE|BELOW THRESHOLD, got {line:?}",
+                    idx + 1
+                ));
+            }
+        }
+    }
+    Ok(alerts)
+}
+
+fn test_alert_event() -> ClientEvent {
+    ClientEvent::AlertTriggered {
+        symbol: "TEST".into(),
+        dir: AlertDirection::Above,
+        threshold: 100.0,
+        current: 101.0,
+        test: true,
+    }
+}
+
 fn play_alert_sound() {
     #[cfg(windows)]
     {
@@ -1202,3 +3238,1465 @@ fn play_alert_sound() {
         let _ = stdout.flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_map_to_wire() {
+        let cases = vec![
+            (
+                UiCommand::AddAlert {
+                    symbol: "AAPL".into(),
+                    dir: AlertDirection::Above,
+                    threshold: 200.5,
+                },
+                "ADD AAPL ABOVE 200.5\n",
+            ),
+            (
+                UiCommand::RemoveAlert {
+                    symbol: "AAPL".into(),
+                    dir: AlertDirection::Below,
+                },
+                "DEL AAPL BELOW\n",
+            ),
+            (
+                UiCommand::LoginClient {
+                    username: "alice".into(),
+                    password: "secret".into(),
+                },
+                "LOGIN alice secret\n",
+            ),
+            (
+                UiCommand::RegisterClient {
+                    username: "bob".into(),
+                    password: "hunter2".into(),
+                },
+                "REGISTER bob hunter2\n",
+            ),
+            (
+                UiCommand::CheckPrice {
+                    symbol: "AAPL".into(),
+                    req_id: None,
+                },
+                "PRICE AAPL\n",
+            ),
+            (
+                UiCommand::CheckPrice {
+                    symbol: "AAPL".into(),
+                    req_id: Some(4),
+                },
+                "#4 PRICE AAPL\n",
+            ),
+            (
+                UiCommand::BuyStock {
+                    symbol: "AAPL".into(),
+                    quantity: 3,
+                },
+                "BUY AAPL 3\n",
+            ),
+            (
+                UiCommand::SellStock {
+                    symbol: "TSLA".into(),
+                    quantity: 2,
+                },
+                "SELL TSLA 2\n",
+            ),
+            (
+                UiCommand::GetAllClientData { offset: 100 },
+                "DATA 100 100\n",
+            ),
+            (UiCommand::ListSymbols { offset: 500 }, "LIST 500\n"),
+            (
+                UiCommand::AddAlerts {
+                    alerts: vec![AlertRow {
+                        symbol: "AAPL".into(),
+                        dir: AlertDirection::Below,
+                        threshold: 150.0,
+                    }],
+                },
+                "ADDMANY AAPL BELOW 150\n",
+            ),
+        ];
+
+        for (cmd, expected) in cases {
+            assert_eq!(command_to_wire(cmd).as_deref(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn alert_added_pops_up_only_when_enabled() {
+        let added = || ClientEvent::AlertAdded {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 200.0,
+            price_available: true,
+            triggers_now: false,
+        };
+        let mut app = App::new();
+
+        app.handle_event(added());
+        assert!(!app.alert_popup_open);
+        assert_eq!(app.alerts.len(), 1);
+        assert!(app.logs.last().unwrap().msg.contains("Alert added: AAPL"));
+
+        app.popup_on_alert_added = true;
+        app.handle_event(added());
+        assert!(app.alert_popup_open);
+
+        // Without a price the alert is still listed, with a warning next to it.
+        app.handle_event(ClientEvent::AlertAdded {
+            symbol: "NEWCO".into(),
+            dir: AlertDirection::Below,
+            threshold: 5.0,
+            price_available: false,
+            triggers_now: false,
+        });
+        assert_eq!(app.alerts.len(), 2);
+        assert_eq!(
+            app.alert_notice.as_deref(),
+            Some("Alert added for NEWCO, but there is no price data yet.")
+        );
+
+        // One that would fire right away asks first, even with the popup off.
+        app.popup_on_alert_added = false;
+        app.alert_popup_open = false;
+        app.handle_event(ClientEvent::AlertAdded {
+            symbol: "MSFT".into(),
+            dir: AlertDirection::Above,
+            threshold: 100.0,
+            price_available: true,
+            triggers_now: true,
+        });
+        assert!(app.alert_popup_open);
+        assert!(
+            app.alert_popup_message
+                .as_deref()
+                .unwrap()
+                .ends_with("will trigger right away. Add anyway?")
+        );
+        app.answer_alert_popup(PopupAction::Remove);
+        assert!(!app.alerts.iter().any(|a| a.symbol == "MSFT"));
+
+        // Triggered alerts always pop up.
+        app.popup_on_alert_added = false;
+        app.alert_popup_open = false;
+        app.handle_event(test_alert_event());
+        assert!(app.alert_popup_open);
+    }
+
+    #[test]
+    fn toasts_stack_and_expire() {
+        let mut app = App::new();
+        app.handle_event(ClientEvent::TradeFilled(TradeFill::Bought {
+            symbol: "AAPL".into(),
+            quantity: 1,
+        }));
+        app.handle_event(ClientEvent::AlertAdded {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 200.0,
+            price_available: true,
+            triggers_now: false,
+        });
+        let shown: Vec<&str> = app.toasts.iter().map(|t| t.msg.as_str()).collect();
+        assert_eq!(
+            shown,
+            vec![
+                "Bought 1x AAPL",
+                "Alert added: AAPL ABOVE threshold=$200.00"
+            ]
+        );
+
+        for i in 0..MAX_TOASTS {
+            app.push_toast(format!("toast {i}"));
+        }
+        assert_eq!(app.toasts.len(), MAX_TOASTS);
+        assert_eq!(app.toasts.front().unwrap().msg, "toast 0");
+
+        let start = app.toasts.front().unwrap().shown_at;
+        assert_eq!(app.toasts[0].opacity(start), 1.0);
+        let fading = app.toasts[0].opacity(start + TOAST_DURATION - TOAST_FADE / 2);
+        assert!(fading > 0.0 && fading < 1.0);
+
+        let newest = app.toasts.back().unwrap().shown_at;
+        app.expire_toasts(newest + TOAST_DURATION);
+        assert!(app.toasts.is_empty());
+    }
+
+    #[test]
+    fn alerts_are_grouped_by_symbol() {
+        let row = |symbol: &str, dir, threshold| AlertRow {
+            symbol: symbol.into(),
+            dir,
+            threshold,
+        };
+        let alerts = vec![
+            row("TSLA", AlertDirection::Below, 150.0),
+            row("AAPL", AlertDirection::Above, 200.0),
+            row("TSLA", AlertDirection::Above, 300.0),
+        ];
+
+        let groups: Vec<(String, Vec<(AlertDirection, f64)>)> = group_alerts(&alerts)
+            .into_iter()
+            .map(|(symbol, rows)| (symbol, rows.iter().map(|r| (r.dir, r.threshold)).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    "TSLA".to_string(),
+                    vec![
+                        (AlertDirection::Above, 300.0),
+                        (AlertDirection::Below, 150.0)
+                    ]
+                ),
+                ("AAPL".to_string(), vec![(AlertDirection::Above, 200.0)]),
+            ]
+        );
+        assert!(group_alerts(&[]).is_empty());
+    }
+
+    #[test]
+    fn data_refreshes_on_interval_only_while_logged_in() {
+        let mut app = App::new();
+        let start = app.last_data_request;
+        let later = start + Duration::from_secs(DEFAULT_DATA_REFRESH_SECS);
+
+        // Disconnected: paused.
+        app.poll_auto_refresh(later);
+        assert_eq!(app.last_data_request, start);
+
+        app.connected = true;
+        app.authenticated = true;
+        app.poll_auto_refresh(later - Duration::from_secs(1));
+        assert_eq!(app.last_data_request, start);
+        app.poll_auto_refresh(later);
+        assert!(app.last_data_request > start);
+        assert!(app.quiet_data_refresh);
+
+        // The automatic reload doesn't fill the log.
+        let logged = app.logs.len();
+        app.handle_event(ClientEvent::AllClientData {
+            stocks: Vec::new(),
+            alerts: Vec::new(),
+            has_more: false,
+            failed: Vec::new(),
+        });
+        assert_eq!(app.logs.len(), logged);
+        assert!(!app.quiet_data_refresh);
+
+        app.auto_refresh = false;
+        let refreshed = app.last_data_request;
+        app.poll_auto_refresh(refreshed + Duration::from_secs(MAX_DATA_REFRESH_SECS));
+        assert_eq!(app.last_data_request, refreshed);
+    }
+
+    fn pressed(key: egui::Key) -> egui::InputState {
+        let raw = egui::RawInput {
+            events: vec![egui::Event::Key {
+                key,
+                physical_key: None,
+                pressed: true,
+                repeat: false,
+                modifiers: egui::Modifiers::NONE,
+            }],
+            ..Default::default()
+        };
+        egui::InputState::default().begin_frame(raw, false, 1.0)
+    }
+
+    #[test]
+    fn alert_popup_answers_from_keyboard() {
+        assert_eq!(
+            popup_key_action(&pressed(egui::Key::Enter), false),
+            Some(PopupAction::Remove)
+        );
+        assert_eq!(
+            popup_key_action(&pressed(egui::Key::Escape), false),
+            Some(PopupAction::Keep)
+        );
+        assert_eq!(popup_key_action(&pressed(egui::Key::Enter), true), None);
+        assert_eq!(popup_key_action(&pressed(egui::Key::A), false), None);
+
+        let mut app = App::new();
+        let alert = AlertRow {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 200.0,
+        };
+        app.alerts.push(alert.clone());
+        app.handle_event(ClientEvent::AlertTriggered {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 200.0,
+            current: 201.0,
+            test: false,
+        });
+        app.answer_alert_popup(PopupAction::Keep);
+        assert!(!app.alert_popup_open);
+        assert_eq!(app.alerts.len(), 1);
+
+        app.alert_popup_open = true;
+        app.alert_popup_data = Some(alert);
+        app.answer_alert_popup(PopupAction::Remove);
+        assert!(!app.alert_popup_open);
+        assert!(app.alerts.is_empty());
+        assert!(app.alert_popup_data.is_none());
+    }
+
+    #[test]
+    fn money_follows_the_chosen_precision() {
+        let mut money = MoneyFormat::default();
+        assert_eq!(money.format(12.5), "$12.50");
+        assert_eq!(money.format(-0.126), "-$0.13");
+
+        money.decimals = 3;
+        money.currency = "€".into();
+        assert_eq!(money.format(199.62), "€199.620");
+        money.decimals = 0;
+        money.currency.clear();
+        assert_eq!(money.format(199.62), "200");
+    }
+
+    #[test]
+    fn portfolio_lines_show_cost_basis() {
+        let stock = |quantity, total_price| PortfolioStock {
+            symbol: "AAPL".into(),
+            quantity,
+            total_price,
+        };
+        let no_names = HashMap::new();
+        let money = MoneyFormat::default();
+        assert_eq!(
+            portfolio_line(&stock(4, 50.0), &no_names, &money),
+            "AAPL quantity=4 cost $50.00 (avg $12.50)"
+        );
+        assert_eq!(
+            portfolio_line(&stock(0, 0.0), &no_names, &money),
+            "AAPL closed"
+        );
+
+        let names = HashMap::from([("AAPL".to_string(), "Apple Inc.".to_string())]);
+        assert_eq!(
+            portfolio_line(&stock(0, 0.0), &names, &money),
+            "AAPL — Apple Inc. closed"
+        );
+    }
+
+    #[test]
+    fn account_reset_clears_portfolio() {
+        assert_eq!(
+            command_to_wire(UiCommand::ResetAccount {
+                password: "hunter2".into()
+            })
+            .as_deref(),
+            Some("RESET hunter2\n")
+        );
+        assert!(matches!(
+            server_line_to_event("RESET"),
+            ClientEvent::AccountReset
+        ));
+
+        let mut app = App::new();
+        app.portfolio.push(PortfolioStock {
+            symbol: "AAPL".into(),
+            quantity: 3,
+            total_price: 30.0,
+        });
+        app.handle_event(ClientEvent::AccountReset);
+        assert!(app.portfolio.is_empty());
+        assert_eq!(app.toasts.back().unwrap().msg, "Account reset");
+    }
+
+    #[test]
+    fn cancel_all_orders_reports_the_count() {
+        assert_eq!(
+            command_to_wire(UiCommand::CancelAllOrders).as_deref(),
+            Some("CANCELALL\n")
+        );
+        assert!(matches!(
+            server_line_to_event("CANCELALL 3"),
+            ClientEvent::OrdersCancelled { count: 3 }
+        ));
+
+        let mut app = App::new();
+        app.cancel_orders_open = true;
+        app.handle_event(ClientEvent::OrdersCancelled { count: 3 });
+        assert_eq!(app.toasts.back().unwrap().msg, "Cancelled 3 orders");
+
+        app.handle_event(ClientEvent::Disconnected {
+            reason: "connection reset".into(),
+        });
+        assert!(!app.cancel_orders_open);
+    }
+
+    #[test]
+    fn thresholds_are_checked_before_sending() {
+        assert_eq!(parse_threshold(" 120.5 "), Ok(120.5));
+        assert_eq!(
+            parse_threshold("abc"),
+            Err("Invalid threshold (expected number).".to_string())
+        );
+        for bad in ["inf", "NaN", "-inf"] {
+            assert_eq!(
+                parse_threshold(bad),
+                Err("Invalid threshold (threshold must be a finite number).".to_string())
+            );
+        }
+        assert_eq!(
+            parse_threshold("1e308"),
+            Err("Invalid threshold (threshold out of range, the limit is 1000000).".to_string())
+        );
+    }
+
+    #[test]
+    fn alert_import_parses_lines() {
+        let alerts =
+            parse_alert_import("# watchlist\naapl above 200\n\n  MSFT BELOW 310.5  \n").unwrap();
+        let rows: Vec<(String, AlertDirection, f64)> = alerts
+            .into_iter()
+            .map(|a| (a.symbol, a.dir, a.threshold))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("AAPL".to_string(), AlertDirection::Above, 200.0),
+                ("MSFT".to_string(), AlertDirection::Below, 310.5),
+            ]
+        );
+
+        let err = parse_alert_import("AAPL ABOVE 200\nMSFT SIDEWAYS 1").unwrap_err();
+        assert!(err.contains("line 2"), "{err}");
+        assert!(parse_alert_import("AAPL ABOVE").is_err());
+        assert!(parse_alert_import("AAPL ABOVE inf").is_err());
+
+        assert!(matches!(
+            server_line_to_event("ALERTSADDED 8 2 1"),
+            ClientEvent::AlertsAdded {
+                added: 8,
+                skipped: 2,
+                unpriced: 1,
+            }
+        ));
+    }
+
+    #[test]
+    fn alert_threshold_is_edited_in_place() {
+        assert_eq!(
+            command_to_wire(UiCommand::UpdateAlert {
+                symbol: "AAPL".into(),
+                dir: AlertDirection::Above,
+                threshold: 120.5,
+            })
+            .as_deref(),
+            Some("UPDATE AAPL ABOVE 120.5\n")
+        );
+
+        let mut app = App::new();
+        app.alerts.push(AlertRow {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 150.0,
+        });
+        app.alert_edit = Some(AlertEdit {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            input: "abc".into(),
+        });
+        app.save_alert_edit();
+        assert!(
+            app.alert_edit.is_some(),
+            "a bad number keeps the field open"
+        );
+        assert!(matches!(app.logs.last().unwrap().kind, LogKind::Error));
+
+        app.alert_edit.as_mut().unwrap().input = " 120.5 ".into();
+        app.save_alert_edit();
+        assert!(app.alert_edit.is_none());
+        // Moved only once the server confirms.
+        assert_eq!(app.alerts[0].threshold, 150.0);
+    }
+
+    #[test]
+    fn alert_updated_moves_the_local_threshold() {
+        let mut app = App::new();
+        app.alerts.push(AlertRow {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            threshold: 150.0,
+        });
+        app.handle_event(server_line_to_event("ALERTUPDATED AAPL ABOVE 120.5"));
+        assert_eq!(app.alerts[0].threshold, 120.5);
+        assert_eq!(
+            app.toasts.back().unwrap().msg,
+            "Alert updated: AAPL ABOVE threshold=$120.50"
+        );
+    }
+
+    #[test]
+    fn export_reply_is_saved_per_user() {
+        assert_eq!(
+            command_to_wire(UiCommand::ExportData).as_deref(),
+            Some("EXPORT\n")
+        );
+        let json = r#"{"username":"alice","alerts":[],"positions":[],"brackets":[]}"#;
+        match server_line_to_event(&format!("EXPORT {json}")) {
+            ClientEvent::DataExport { json: got } => assert_eq!(got, json),
+            other => panic!("expected DataExport, got {other:?}"),
+        }
+
+        assert_eq!(export_file_name(Some("alice")), "alice-export.json");
+        assert_eq!(export_file_name(None), "account-export.json");
+        assert!(pretty_export(json).contains("\n  \"alerts\": []"));
+        assert_eq!(pretty_export("not json"), "not json");
+    }
+
+    #[test]
+    fn preferences_follow_the_server() {
+        let prefs = Preferences {
+            triggers: false,
+            fills: true,
+        };
+        assert_eq!(
+            command_to_wire(UiCommand::SetPreferences(prefs)).as_deref(),
+            Some("PREFS TRIGGERS=OFF FILLS=ON\n")
+        );
+        assert_eq!(
+            command_to_wire(UiCommand::GetPreferences).as_deref(),
+            Some("PREFS\n")
+        );
+
+        let mut app = App::new();
+        app.set_preferences(prefs);
+        assert_eq!(app.prefs, Some(prefs));
+        app.handle_event(server_line_to_event("PREFS TRIGGERS=ON FILLS=OFF"));
+        assert_eq!(app.prefs, Some(Preferences::default()));
+    }
+
+    #[test]
+    fn local_commands_send_nothing() {
+        let connect = UiCommand::Connect {
+            addr: "127.0.0.1:1234".into(),
+        };
+        assert!(command_to_wire(connect).is_none());
+        assert!(command_to_wire(UiCommand::Disconnect).is_none());
+    }
+
+    #[test]
+    fn redundant_connect_is_only_logged() {
+        let connect = UiCommand::Connect {
+            addr: "127.0.0.1:1234".into(),
+        };
+        assert_eq!(
+            connected_action(connect),
+            Some(ConnectedAction::Log(
+                "Already connected, ignoring Connect to 127.0.0.1:1234.".into()
+            ))
+        );
+        assert_eq!(
+            connected_action(UiCommand::Disconnect),
+            Some(ConnectedAction::Disconnect)
+        );
+        assert_eq!(
+            connected_action(UiCommand::WhoAmI),
+            Some(ConnectedAction::Send("WHOAMI\n".into()))
+        );
+
+        let mut app = App::new();
+        app.connecting = true;
+        app.handle_event(ClientEvent::Connected);
+        assert!(!app.connecting);
+    }
+
+    #[test]
+    fn alert_lines_map_to_events() {
+        match server_line_to_event("TRIGGER AAPL ABOVE 150 155") {
+            ClientEvent::AlertTriggered {
+                symbol,
+                dir,
+                threshold,
+                current,
+                test,
+            } => {
+                assert!(!test);
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(dir, AlertDirection::Above);
+                assert_eq!(threshold, 150.0);
+                assert_eq!(current, 155.0);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        match server_line_to_event("ALERTADDED AAPL BELOW 120.25") {
+            ClientEvent::AlertAdded {
+                symbol,
+                dir,
+                threshold,
+                ..
+            } => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(dir, AlertDirection::Below);
+                assert_eq!(threshold, 120.25);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        match server_line_to_event("ALERTFAILED AAPL ABOVE 200 Database busy") {
+            ClientEvent::AlertFailed {
+                symbol,
+                dir,
+                threshold,
+                reason,
+            } => {
+                assert_eq!(
+                    alert_failure_message(
+                        &symbol,
+                        dir,
+                        threshold,
+                        &reason,
+                        &MoneyFormat::default()
+                    ),
+                    "AAPL ABOVE $200.00: Database busy"
+                );
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        match server_line_to_event("ALERTDELETED AAPL ABOVE") {
+            ClientEvent::AlertRemoved { symbol, dir } => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(dir, AlertDirection::Above);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ping_is_answered_by_the_worker() {
+        assert_eq!(heartbeat_reply("PING").as_deref(), Some("PONG\n"));
+        assert_eq!(heartbeat_reply("PING\r\n").as_deref(), Some("PONG\n"));
+        assert!(heartbeat_reply("PRICE AAPL 1.0").is_none());
+    }
+
+    #[test]
+    fn trade_lines_map_to_events() {
+        match server_line_to_event("#9 PRICE AAPL 123.45") {
+            ClientEvent::PriceChecked {
+                symbol,
+                price,
+                stats,
+                req_id,
+            } => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(price, 123.45);
+                assert!(stats.is_empty());
+                assert_eq!(req_id, Some(9));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        let money = MoneyFormat::default();
+        for (line, expected) in [
+            ("BOUGHT AAPL 3", "Bought 3x AAPL"),
+            ("SOLD TSLA 2", "Sold 2x TSLA"),
+            ("SOLD TSLA 2 10.5", "Sold 2x TSLA, profit $10.50"),
+            (
+                "BRACKETTRIGGERED AAPL 10 85 -150",
+                "Bracket sold 10x AAPL at $85.00, profit -$150.00",
+            ),
+        ] {
+            match server_line_to_event(line) {
+                ClientEvent::TradeFilled(fill) => assert_eq!(fill.describe(&money), expected),
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn account_lines_map_to_events() {
+        assert!(matches!(
+            server_line_to_event("LOGIN"),
+            ClientEvent::UserLogged
+        ));
+        assert!(matches!(
+            server_line_to_event("REGISTER"),
+            ClientEvent::UserRegistered
+        ));
+        assert!(matches!(
+            server_line_to_event("WHOAMI 3 alice"),
+            ClientEvent::Identity { username } if username == "alice"
+        ));
+
+        let data = r#"DATA {"stocks":[{"symbol":"AAPL","quantity":2,"total_price":123.0}],"alerts":[{"symbol":"AAPL","direction":"ABOVE","threshold":150.0}]}"#;
+        match server_line_to_event(data) {
+            ClientEvent::AllClientData {
+                stocks,
+                alerts,
+                has_more,
+                failed,
+            } => {
+                assert!(!has_more);
+                assert!(failed.is_empty());
+                assert_eq!(stocks.len(), 1);
+                assert_eq!(stocks[0].symbol, "AAPL");
+                assert_eq!(alerts.len(), 1);
+                assert_eq!(alerts[0].symbol, "AAPL");
+                assert_eq!(alerts[0].dir, AlertDirection::Above);
+                assert_eq!(alerts[0].threshold, 150.0);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_lines_map_to_events() {
+        match server_line_to_event("#2 ERR Stock not available!") {
+            ClientEvent::ServerError { msg, req_id } => {
+                assert_eq!(msg, "Stock not available!");
+                assert_eq!(req_id, Some(2));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        assert!(matches!(
+            server_line_to_event(&format!("ERR {ERR_SERVER_FULL}")),
+            ClientEvent::ServerFull
+        ));
+    }
+
+    #[test]
+    fn symbol_list_line_maps_to_event() {
+        match server_line_to_event(r"SYMBOLS false AAPL:Apple\sInc. MSFT") {
+            ClientEvent::SymbolList {
+                symbols,
+                names,
+                has_more,
+            } => {
+                assert_eq!(symbols, vec!["AAPL", "MSFT"]);
+                assert_eq!(symbol_label(&names, "AAPL"), "AAPL — Apple Inc.");
+                assert_eq!(symbol_label(&names, "MSFT"), "MSFT");
+                assert!(!has_more);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn suggestions_match_prefix() {
+        let symbols: Vec<String> = ["AAPL", "AAL", "ABNB", "MSFT"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(symbol_suggestions(&symbols, "aa"), vec!["AAPL", "AAL"]);
+        assert_eq!(symbol_suggestions(&symbols, "AAPL"), Vec::<String>::new());
+        assert!(symbol_suggestions(&symbols, "  ").is_empty());
+        assert!(symbol_suggestions(&[], "AA").is_empty());
+    }
+
+    #[test]
+    fn unparsed_line_is_logged() {
+        match server_line_to_event("WHATEVER 1 2 3") {
+            ClientEvent::Log(msg) => assert_eq!(msg, "Unparsed: WHATEVER 1 2 3"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn full_event_channel_drops_only_quotes() {
+        let (ev_tx, ev_rx) = bounded::<ClientEvent>(1);
+        handle_server_line("LOGIN", &ev_tx);
+
+        // The queue is full, a quote nobody asked for must not block the worker.
+        handle_server_line("PRICE AAPL 190.5", &ev_tx);
+        assert_eq!(ev_rx.len(), 1);
+        assert!(matches!(ev_rx.try_recv(), Ok(ClientEvent::UserLogged)));
+
+        assert!(is_droppable(&server_line_to_event("PRICE AAPL 190.5")));
+        assert!(!is_droppable(&server_line_to_event("#4 PRICE AAPL 190.5")));
+        assert!(!is_droppable(&ClientEvent::Disconnected {
+            reason: "server closed".into(),
+        }));
+        assert!(!is_droppable(&server_line_to_event(
+            "TRIGGER AAPL ABOVE 150 151"
+        )));
+    }
+
+    #[test]
+    fn trade_confirmations_are_never_dropped() {
+        let lines = [
+            "BOUGHT AAPL 5",
+            "SOLD AAPL 2",
+            "SOLD AAPL 3 12.5",
+            "BRACKETTRIGGERED AAPL 3 85 -15",
+            "TRADEREJECTED AAPL You have only 2 actions of given stock!.",
+        ];
+        for line in lines {
+            let ev = server_line_to_event(line);
+            assert!(
+                matches!(
+                    ev,
+                    ClientEvent::TradeFilled(_) | ClientEvent::TradeRejected { .. }
+                ),
+                "{line}: {ev:?}"
+            );
+            assert!(!is_droppable(&ev), "{line}");
+        }
+
+        // With the queue full the worker waits for room instead of losing them.
+        let (ev_tx, ev_rx) = bounded::<ClientEvent>(1);
+        handle_server_line("LOGIN", &ev_tx);
+        let worker = thread::spawn(move || {
+            for line in lines {
+                handle_server_line(line, &ev_tx);
+            }
+        });
+        assert!(matches!(ev_rx.recv(), Ok(ClientEvent::UserLogged)));
+        let delivered: Vec<ClientEvent> = ev_rx.iter().collect();
+        worker.join().unwrap();
+        assert_eq!(delivered.len(), lines.len());
+    }
+
+    #[test]
+    fn only_the_trade_quote_executes_the_trade() {
+        let mut app = App::new();
+        app.connected = true;
+        app.authenticated = true;
+        let req_id = app.take_req_id();
+        app.pending_trade = Some(PendingTrade {
+            symbol: "AAPL".into(),
+            quantity: 5,
+            kind: TradeKind::Buy,
+            req_id,
+            sent_at: Instant::now(),
+        });
+
+        // A manual check of the same symbol and a reply to some other request come first.
+        app.handle_event(server_line_to_event("PRICE AAPL 180"));
+        assert!(app.pending_trade.is_some());
+        assert_eq!(app.logs.last().unwrap().msg, "[PRICE] AAPL price=$180.00");
+        app.handle_event(server_line_to_event(&format!(
+            "#{} PRICE AAPL 181",
+            req_id + 1
+        )));
+        assert!(app.pending_trade.is_some());
+
+        app.handle_event(server_line_to_event(&format!("#{req_id} PRICE AAPL 182")));
+        assert!(app.pending_trade.is_none());
+        assert_eq!(
+            app.logs.last().unwrap().msg,
+            "[BUY] AAPL qty=5 price=$182.00"
+        );
+
+        // A late manual quote after the trade only goes to the log.
+        app.handle_event(server_line_to_event("PRICE AAPL 183"));
+        assert_eq!(app.logs.last().unwrap().msg, "[PRICE] AAPL price=$183.00");
+    }
+
+    #[test]
+    fn rejected_trade_clears_its_pending_order() {
+        let mut app = App::new();
+        app.pending_trade = Some(PendingTrade {
+            symbol: "AAPL".into(),
+            quantity: 5,
+            kind: TradeKind::Sell,
+            req_id: 1,
+            sent_at: Instant::now(),
+        });
+
+        // Another symbol's rejection leaves the order alone.
+        app.handle_event(server_line_to_event(
+            "TRADEREJECTED MSFT Stock not available!",
+        ));
+        assert!(app.pending_trade.is_some());
+
+        app.handle_event(server_line_to_event(
+            "TRADEREJECTED AAPL You have only 2 actions of given stock!.",
+        ));
+        assert!(app.pending_trade.is_none());
+        assert_eq!(
+            app.toasts.back().unwrap().msg,
+            "Trade for AAPL rejected: You have only 2 actions of given stock!."
+        );
+        assert_eq!(
+            app.logs.last().unwrap().msg,
+            "[TRADE REJECTED] AAPL: You have only 2 actions of given stock!."
+        );
+        assert!(app.auth_notice.is_none());
+    }
+
+    #[test]
+    fn quotes_are_coalesced_per_symbol() {
+        let quote = |symbol: &str, price: f64, req_id: Option<u64>| ClientEvent::PriceChecked {
+            symbol: symbol.into(),
+            price,
+            stats: QuoteStats::default(),
+            req_id,
+        };
+        let events = vec![
+            quote("AAPL", 1.0, None),
+            quote("MSFT", 2.0, None),
+            ClientEvent::Log("between".into()),
+            quote("AAPL", 3.0, Some(7)),
+            quote("AAPL", 4.0, None),
+            ClientEvent::ServerError {
+                msg: "boom".into(),
+                req_id: None,
+            },
+        ];
+
+        let kept: Vec<String> = coalesce_quotes(events)
+            .into_iter()
+            .map(|ev| match ev {
+                ClientEvent::PriceChecked {
+                    symbol,
+                    price,
+                    req_id,
+                    ..
+                } => format!("{symbol}={price}/{req_id:?}"),
+                ClientEvent::Log(s) => s,
+                ClientEvent::ServerError { msg, .. } => msg,
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(
+            kept,
+            vec![
+                "MSFT=2/None",
+                "between",
+                "AAPL=3/Some(7)",
+                "AAPL=4/None",
+                "boom"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alert_is_marked() {
+        assert!(matches!(
+            test_alert_event(),
+            ClientEvent::AlertTriggered { test: true, .. }
+        ));
+    }
+
+    #[test]
+    fn pending_trade_status_names_the_order() {
+        let pending = PendingTrade {
+            symbol: "AAPL".into(),
+            quantity: 5,
+            kind: TradeKind::Sell,
+            req_id: 1,
+            sent_at: Instant::now(),
+        };
+        assert_eq!(
+            pending_trade_status(&pending),
+            "Selling 5x AAPL, waiting for a quote (0s)"
+        );
+    }
+
+    #[test]
+    fn session_expiry_relogs_in_once_and_retries() {
+        let mut session = SessionRecovery::default();
+        let price = UiCommand::CheckPrice {
+            symbol: "AAPL".into(),
+            req_id: None,
+        };
+
+        // Without remembered credentials the user has to log in again.
+        session.track(&price);
+        assert!(session.on_expired().is_none());
+
+        session.credentials = Some(("user".into(), "pass".into()));
+        session.track(&price);
+        session.track(&UiCommand::Disconnect);
+        assert!(matches!(
+            session.on_expired(),
+            Some(UiCommand::LoginClient { .. })
+        ));
+        assert!(matches!(
+            session.on_logged_in(),
+            Some(UiCommand::CheckPrice { .. })
+        ));
+
+        // Expiring again straight after the re-login gives up instead of looping.
+        session.track(&price);
+        assert!(session.on_expired().is_some());
+        assert!(session.on_expired().is_none());
+        assert!(session.on_logged_in().is_none());
+
+        // A rejected automatic login forgets the credentials.
+        assert!(session.on_expired().is_some());
+        assert!(session.on_login_failed());
+        assert!(session.credentials.is_none());
+        assert!(!session.on_login_failed());
+    }
+
+    #[test]
+    fn not_logged_in_error_maps_to_session_expired() {
+        assert!(matches!(
+            server_line_to_event(&format!("ERR {ERR_NOT_LOGGED_IN}")),
+            ClientEvent::SessionExpired
+        ));
+    }
+
+    #[test]
+    fn login_required_returns_to_auth_screen() {
+        let mut app = App::new();
+        app.handle_event(server_line_to_event(&format!("#4 ERR {ERR_NOT_LOGGED_IN}")));
+        assert!(!app.authenticated);
+        assert_eq!(app.auth_notice.as_deref(), Some("Please log in first."));
+
+        app.authenticated = true;
+        app.handle_event(server_line_to_event(&format!("ERR {ERR_NOT_LOGGED_IN}")));
+        assert!(!app.authenticated);
+        assert_eq!(
+            app.auth_notice.as_deref(),
+            Some("Session expired, please log in again.")
+        );
+    }
+
+    #[test]
+    fn latency_comes_from_the_matching_pong() {
+        let mut app = App::new();
+        let start = app.last_probe_at;
+        app.poll_latency_probe(start + LATENCY_PROBE_INTERVAL);
+        assert!(app.pending_probe.is_none(), "no probes while disconnected");
+
+        app.connected = true;
+        app.poll_latency_probe(start + LATENCY_PROBE_INTERVAL);
+        let (nonce, _) = app.pending_probe.unwrap();
+        assert_eq!(
+            command_to_wire(UiCommand::Ping { nonce }).as_deref(),
+            Some(format!("PING {nonce}\n").as_str())
+        );
+
+        app.handle_event(server_line_to_event(&format!("PONG {}", nonce + 1)));
+        assert!(app.latency.is_none());
+        app.handle_event(server_line_to_event(&format!("PONG {nonce}")));
+        assert!(app.latency.is_some());
+        assert!(app.pending_probe.is_none());
+
+        app.handle_event(ClientEvent::Disconnected {
+            reason: "gone".into(),
+        });
+        assert!(app.latency.is_none());
+    }
+
+    #[test]
+    fn ticker_follows_watched_symbols() {
+        let mut app = App::new();
+        app.connected = true;
+        app.authenticated = true;
+        let now = Instant::now();
+        app.poll_ticker(now);
+        assert!(app.ticker_requests.is_empty(), "nothing to watch yet");
+
+        app.alerts.push(AlertRow {
+            symbol: "MSFT".into(),
+            dir: AlertDirection::Above,
+            threshold: 400.0,
+        });
+        app.portfolio.push(PortfolioStock {
+            symbol: "AAPL".into(),
+            quantity: 1,
+            total_price: 100.0,
+        });
+        assert_eq!(
+            ticker_symbols(&app.alerts, &app.portfolio),
+            ["AAPL", "MSFT"]
+        );
+        app.poll_ticker(now);
+        let mut ids: Vec<u64> = app.ticker_requests.iter().copied().collect();
+        ids.sort();
+        assert_eq!(ids.len(), 2);
+        app.poll_ticker(now + TICKER_REFRESH_INTERVAL / 2);
+        assert_eq!(app.ticker_requests.len(), 2, "not due yet");
+
+        // Ticker replies stay out of the log, a missing price too.
+        let logged = app.logs.len();
+        app.handle_event(server_line_to_event(&format!("#{} PRICE AAPL 101", ids[0])));
+        app.handle_event(server_line_to_event(&format!(
+            "#{} ERR Stock not available!",
+            ids[1]
+        )));
+        assert_eq!(app.logs.len(), logged);
+        assert!(app.ticker_requests.is_empty());
+
+        app.handle_event(server_line_to_event("PRICE AAPL 99.5"));
+        let quote = app.ticker["AAPL"];
+        assert_eq!(quote.price, 99.5);
+        assert_eq!(quote.trend, std::cmp::Ordering::Less);
+        let money = MoneyFormat::default();
+        assert_eq!(ticker_text("AAPL", Some(&quote), &money), "AAPL $99.50");
+        assert_eq!(
+            ticker_text("MSFT", app.ticker.get("MSFT"), &money),
+            "MSFT —"
+        );
+
+        app.handle_event(ClientEvent::Disconnected {
+            reason: "gone".into(),
+        });
+        assert!(app.ticker.is_empty());
+        assert!(app.last_ticker_at.is_none());
+    }
+
+    #[test]
+    fn disconnect_leaves_a_clean_auth_screen() {
+        let mut app = App::new();
+        app.connected = true;
+        app.authenticated = true;
+        app.pending_trade = Some(PendingTrade {
+            symbol: "AAPL".into(),
+            quantity: 1,
+            kind: TradeKind::Sell,
+            req_id: 1,
+            sent_at: Instant::now(),
+        });
+        app.handle_event(server_line_to_event("TRIGGER AAPL ABOVE 150 151"));
+        assert!(app.alert_popup_open);
+        app.alert_edit = Some(AlertEdit {
+            symbol: "AAPL".into(),
+            dir: AlertDirection::Above,
+            input: "160".into(),
+        });
+        app.reset_password_input = Some("secret".into());
+
+        app.handle_event(ClientEvent::Disconnected {
+            reason: "connection reset".into(),
+        });
+        assert!(!app.authenticated);
+        assert!(app.pending_trade.is_none());
+        assert!(!app.alert_popup_open);
+        assert!(app.alert_popup_message.is_none());
+        assert!(app.alert_popup_data.is_none());
+        assert!(app.alert_edit.is_none());
+        assert!(app.reset_password_input.is_none());
+        assert_eq!(
+            app.auth_notice.as_deref(),
+            Some("Disconnected from server.")
+        );
+    }
+
+    #[test]
+    fn themes_have_their_own_colors_and_are_saved() {
+        for theme in Theme::ALL {
+            assert_eq!(Theme::parse(theme.as_str()), Some(theme));
+            let style = theme.style(&egui::Style::default());
+            assert_eq!(
+                style.text_styles[&egui::TextStyle::Body],
+                egui::FontId::new(16.0, egui::FontFamily::Proportional)
+            );
+        }
+        assert_eq!(Theme::parse(" High-Contrast\n"), Some(Theme::HighContrast));
+        assert_eq!(Theme::parse("solarized"), None);
+
+        assert!(
+            !Theme::Light
+                .style(&egui::Style::default())
+                .visuals
+                .dark_mode
+        );
+        let high_contrast = Theme::HighContrast.style(&egui::Style::default()).visuals;
+        assert_eq!(high_contrast.panel_fill, egui::Color32::BLACK);
+        assert_eq!(
+            high_contrast.override_text_color,
+            Some(egui::Color32::WHITE)
+        );
+        assert_ne!(Theme::Light.palette(), Theme::Dark.palette());
+        assert_ne!(Theme::Dark.palette(), Theme::HighContrast.palette());
+
+        let path = std::env::temp_dir().join(format!("gui-theme-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(load_theme(path), Theme::Light, "no file yet");
+        save_theme(path, Theme::Dark).unwrap();
+        assert_eq!(load_theme(path), Theme::Dark);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn snapshot_before_login_is_ignored() {
+        let snapshot = || ClientEvent::AllClientData {
+            stocks: vec![PortfolioStock {
+                symbol: "AAPL".into(),
+                quantity: 2,
+                total_price: 300.0,
+            }],
+            alerts: vec![AlertRow {
+                symbol: "AAPL".into(),
+                dir: AlertDirection::Above,
+                threshold: 200.0,
+            }],
+            has_more: false,
+            failed: Vec::new(),
+        };
+
+        let mut app = App::new();
+        app.handle_event(snapshot());
+        assert!(app.alerts.is_empty());
+        assert!(app.portfolio.is_empty());
+        assert_eq!(
+            app.logs.last().unwrap().msg,
+            "Ignored account data received before login."
+        );
+
+        app.authenticated = true;
+        app.handle_event(snapshot());
+        assert_eq!(app.alerts.len(), 1);
+        assert_eq!(app.portfolio.len(), 1);
+    }
+
+    #[test]
+    fn chart_line_fills_rect() {
+        let rect = egui::Rect::from_min_size(egui::pos2(0.0, 0.0), egui::vec2(100.0, 50.0));
+        let points = [
+            HistoryPoint {
+                timestamp: 0,
+                close: 10.0,
+            },
+            HistoryPoint {
+                timestamp: 50,
+                close: 20.0,
+            },
+            HistoryPoint {
+                timestamp: 100,
+                close: 15.0,
+            },
+        ];
+
+        let line = chart_line(&points, rect);
+        assert_eq!(
+            line,
+            vec![
+                egui::pos2(0.0, 50.0),
+                egui::pos2(50.0, 0.0),
+                egui::pos2(100.0, 25.0)
+            ]
+        );
+        assert!(chart_line(&[], rect).is_empty());
+        assert_eq!(chart_line(&points[..1], rect), vec![egui::pos2(50.0, 25.0)]);
+        assert!(matches!(
+            server_line_to_event("HISTORY AAPL 0:10 100:15"),
+            ClientEvent::History { points, .. } if points.len() == 2
+        ));
+    }
+
+    #[test]
+    fn chart_keeps_the_newest_points_up_to_the_depth() {
+        let history = (0..300)
+            .map(|i| format!("{i}:{i}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut app = App::new();
+        app.handle_event(server_line_to_event(&format!("HISTORY AAPL {history}")));
+        let (_, points) = app.chart.as_ref().unwrap();
+        assert_eq!(points.len(), DEFAULT_CHART_DEPTH);
+        assert_eq!(points[0].timestamp, 100);
+
+        // What lowering the depth does to the chart on screen.
+        let (_, points) = app.chart.as_mut().unwrap();
+        keep_newest(points, MIN_CHART_DEPTH);
+        assert_eq!(points.len(), MIN_CHART_DEPTH);
+        assert_eq!(points.last().unwrap().timestamp, 299);
+        keep_newest(points, MAX_CHART_DEPTH);
+        assert_eq!(points.len(), MIN_CHART_DEPTH);
+
+        let path = std::env::temp_dir().join(format!("gui-depth-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(load_chart_depth(path), DEFAULT_CHART_DEPTH, "no file yet");
+        save_chart_depth(path, 50).unwrap();
+        assert_eq!(load_chart_depth(path), 50);
+        save_chart_depth(path, 1_000_000).unwrap();
+        assert_eq!(load_chart_depth(path), MAX_CHART_DEPTH);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // Stand-in server: answers LOGIN for a single client and closes everything on `stop`.
+    fn serve_one(
+        listener: std::net::TcpListener,
+        stop: Receiver<()>,
+        seen: Sender<String>,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(20)))
+                .unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            while stop.try_recv().is_err() {
+                let mut line = String::new();
+                match reader.read_line(&mut line) {
+                    Ok(0) => return,
+                    Ok(_) => {
+                        if line.starts_with("LOGIN ") {
+                            stream.write_all(b"LOGIN\n").unwrap();
+                        }
+                        let _ = seen.send(line.trim_end().to_string());
+                    }
+                    Err(_) => {}
+                }
+            }
+        })
+    }
+
+    fn pump_until(app: &mut App, done: impl Fn(&App) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done(app) {
+            assert!(
+                Instant::now() < deadline,
+                "timed out, status: {}",
+                app.conn_status
+            );
+            app.drain_events();
+            app.poll_reconnect(Instant::now());
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn reconnect_status_shows_attempt_and_delay() {
+        assert_eq!(
+            reconnect_status(3, Some(Duration::from_millis(1500))),
+            "Reconnecting (attempt 3/5) in 1.5s..."
+        );
+        assert_eq!(reconnect_status(3, None), "Reconnecting (attempt 3/5)...");
+    }
+
+    #[test]
+    fn reconnects_and_logs_in_again_after_server_restart() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen_tx, seen_rx) = crossbeam_channel::unbounded();
+        let (stop_tx, stop_rx) = bounded(1);
+        let first = serve_one(listener, stop_rx, seen_tx.clone());
+
+        let mut app = App::new();
+        app.addr = addr.to_string();
+        app.reconnect_delay = Duration::from_millis(50);
+        app.session.credentials = Some(("alice".into(), "secret".into()));
+        app.send(UiCommand::Connect {
+            addr: app.addr.clone(),
+        });
+        pump_until(&mut app, |app| app.connected);
+        app.send(UiCommand::LoginClient {
+            username: "alice".into(),
+            password: "secret".into(),
+        });
+        pump_until(&mut app, |app| app.authenticated);
+
+        stop_tx.send(()).unwrap();
+        first.join().unwrap();
+        pump_until(&mut app, |app| !app.connected);
+        assert!(!app.authenticated);
+        assert!(app.reconnect_at.is_some());
+        assert!(
+            app.conn_status.starts_with("Reconnecting (attempt 1/5)"),
+            "{}",
+            app.conn_status
+        );
+
+        // Nobody listens yet, so the first attempt fails and the second one is scheduled.
+        pump_until(&mut app, |app| {
+            app.conn_status.starts_with("Reconnecting (attempt 2/5)")
+        });
+
+        let listener = std::net::TcpListener::bind(addr).unwrap();
+        let (_stop_tx, stop_rx) = bounded(1);
+        let second = serve_one(listener, stop_rx, seen_tx);
+        pump_until(&mut app, |app| app.authenticated);
+        assert_eq!(app.reconnect_attempts, 0);
+        assert_eq!(app.conn_status, "Connected");
+
+        let logins = seen_rx
+            .try_iter()
+            .filter(|line| line == "LOGIN alice secret")
+            .count();
+        assert_eq!(logins, 2);
+
+        app.send(UiCommand::Disconnect);
+        pump_until(&mut app, |app| !app.connected);
+        assert!(app.reconnect_at.is_none());
+        second.join().unwrap();
+    }
+
+    #[test]
+    fn chart_is_requested_again_after_reconnect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen_tx, seen_rx) = crossbeam_channel::unbounded();
+        let (stop_tx, stop_rx) = bounded(1);
+        let first = serve_one(listener, stop_rx, seen_tx.clone());
+
+        let mut app = App::new();
+        app.addr = addr.to_string();
+        app.reconnect_delay = Duration::from_millis(50);
+        app.session.credentials = Some(("alice".into(), "secret".into()));
+        app.send(UiCommand::Connect {
+            addr: app.addr.clone(),
+        });
+        pump_until(&mut app, |app| app.connected);
+        app.send(UiCommand::LoginClient {
+            username: "alice".into(),
+            password: "secret".into(),
+        });
+        pump_until(&mut app, |app| app.authenticated);
+        app.watch_chart("AAPL".into());
+        let history = |seen: &Receiver<String>| loop {
+            let line = seen.recv_timeout(Duration::from_secs(5)).unwrap();
+            if line.starts_with("HISTORY ") {
+                return line;
+            }
+        };
+        assert_eq!(history(&seen_rx), "HISTORY AAPL DAY");
+
+        stop_tx.send(()).unwrap();
+        first.join().unwrap();
+        pump_until(&mut app, |app| !app.connected);
+
+        let listener = std::net::TcpListener::bind(addr).unwrap();
+        let (_stop_tx, stop_rx) = bounded(1);
+        let second = serve_one(listener, stop_rx, seen_tx);
+        pump_until(&mut app, |app| app.authenticated);
+        assert_eq!(history(&seen_rx), "HISTORY AAPL DAY");
+
+        app.send(UiCommand::Disconnect);
+        pump_until(&mut app, |app| !app.connected);
+        second.join().unwrap();
+        // Asked once per login, not once per reconnect attempt.
+        assert!(seen_rx.try_iter().all(|line| !line.starts_with("HISTORY ")));
+    }
+
+    #[test]
+    fn log_timestamps_follow_the_server_clock() {
+        let mut app = App::new();
+        assert_eq!(
+            command_to_wire(UiCommand::GetServerTime).as_deref(),
+            Some("TIME\n")
+        );
+
+        app.handle_event(ClientEvent::ServerTime {
+            unix_secs: unix_now() + 1,
+        });
+        // The clock may tick between the two reads.
+        assert!(matches!(app.clock_skew, Some(0..=1)));
+        assert!(app.logs.is_empty(), "a second off is not worth a warning");
+
+        app.handle_event(ClientEvent::ServerTime {
+            unix_secs: unix_now() - 3600,
+        });
+        assert!(matches!(app.clock_skew, Some(-3600..=-3599)));
+        let row = app.logs.last().unwrap();
+        assert!(matches!(row.kind, LogKind::Error));
+        let server_now = unix_now() + app.clock_skew.unwrap();
+        assert!([hhmmss(server_now), hhmmss(server_now - 1)].contains(&row.ts));
+    }
+
+    #[test]
+    fn hhmmss_wraps_around_midnight() {
+        assert_eq!(hhmmss(0), "00:00:00");
+        assert_eq!(hhmmss(86_399), "23:59:59");
+        assert_eq!(hhmmss(-1), "23:59:59");
+    }
+}
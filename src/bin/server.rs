@@ -1,26 +1,531 @@
-use reqwest::header::ACCEPT;
-use reqwest::header::USER_AGENT;
+use reqwest::header::{ACCEPT, USER_AGENT};
 use rust_huge_project::database;
 use rust_huge_project::protocol::AlertRequest;
 use rust_huge_project::protocol::Price;
-use rust_huge_project::protocol::parse_client_msg;
-use rust_huge_project::protocol::{AlertDirection, ClientMsg, ServerMsg};
-use serde::Deserialize;
+use rust_huge_project::protocol::parse_client_line;
+use rust_huge_project::protocol::{
+    AlertDirection, ClientMsg, DATA_SECTION_ALERTS, DATA_SECTION_PORTFOLIO, ERR_BATCH_TOO_LARGE,
+    ERR_DB_UNAVAILABLE, ERR_EXPORT_TOO_LARGE, ERR_IDLE_TIMEOUT, ERR_INTERNAL, ERR_LINE_TOO_LONG,
+    ERR_LOGIN_FAILED, ERR_NOT_LOGGED_IN, ERR_PRICE_NOT_READY, ERR_SERVER_FULL, ERR_UNKNOWN_SYMBOL,
+    ServerMsg,
+};
+use rust_huge_project::protocol::{
+    DEFAULT_MAX_THRESHOLD, HistoryPoint, HistoryRange, LeaderboardEntry, Preferences, QuoteStats,
+    check_threshold,
+};
+use serde::{Deserialize, Serialize};
 use sqlx::sqlite;
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::OwnedWriteHalf;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
-type MapLock = Arc<RwLock<HashMap<String, f64>>>;
+use tokio::sync::{Notify, OwnedSemaphorePermit, RwLock, Semaphore, mpsc, oneshot};
+type MapLock = Arc<RwLock<HashMap<String, Quote>>>;
+// Today's intraday closes per symbol, refreshed on every scrape cycle.
+type HistoryLock = Arc<RwLock<HashMap<String, Vec<HistoryPoint>>>>;
+// Replaced as a whole when the symbols file is reloaded, readers clone the inner Arc.
+type SymbolList = Arc<RwLock<Arc<Vec<String>>>>;
 use anyhow::{Context, Result};
 use tracing::{error, info, warn};
 
+const LISTEN_ADDR: &str = "127.0.0.1:1234";
+const DATABASE_FILE: &str = "database.db";
+const STOCKS_FILE: &str = "stocks_small.txt";
+// Plain list of symbols, or .csv / .json with names and exchanges.
+const SYMBOLS_FILE_ENV: &str = "SYMBOLS_FILE";
+// Cached prices older than this are not loaded at startup, long enough to cover a weekend.
+const PRICE_CACHE_MAX_AGE: Duration = Duration::from_secs(4 * 24 * 60 * 60);
+
+// BUY, SELL or CLOSE of a symbol the scraper has no price for.
+const ERR_STOCK_NOT_AVAILABLE: &str = "Stock not available!";
+
+// Maximum number of clients served at the same time.
+const MAX_CLIENTS: usize = 100;
+// List replies (DATA, LIST) are paged so a huge account or the full NASDAQ list
+// never ends up in a single line; clients may ask for smaller pages.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 500;
+
+// Some networks need a different User-Agent or a proxy to reach Yahoo.
+// Several User-Agents separated by `|` are used in turn, one per request.
+const USER_AGENT_ENV: &str = "YAHOO_USER_AGENT";
+const USER_AGENT_SEPARATOR: char = '|';
+const PROXY_ENV: &str = "YAHOO_PROXY";
+// Path to a JSON seed (see new_user_seed.example.json) applied to every new account, off when unset.
+const USER_SEED_ENV: &str = "NEW_USER_SEED";
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7)";
+
+// A connection is pinged after every HEARTBEAT_INTERVAL of silence (the same tick checks alerts)
+// and dropped once it has sent nothing, not even PONG, for the idle timeout.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+const IDLE_TIMEOUT_ENV: &str = "CLIENT_IDLE_TIMEOUT_SECS";
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+// An alert that fired stays quiet this long, even if the price crosses its threshold again.
+const ALERT_COOLDOWN_ENV: &str = "ALERT_COOLDOWN_SECS";
+const DEFAULT_ALERT_COOLDOWN: Duration = Duration::from_secs(300);
+// Upper bound on alerts in a single ADDMANY, larger batches are rejected as a whole.
+const MAX_BATCH_ALERTS_ENV: &str = "MAX_BATCH_ALERTS";
+const DEFAULT_MAX_BATCH_ALERTS: usize = 100;
+// A command line is read only up to ConnectionConfig::max_line_bytes, a longer one closes the
+// connection before it is buffered. ADDMANY is the only command that grows with its input.
+const MIN_LINE_BYTES: usize = 16 * 1024;
+const LINE_BYTES_PER_ALERT: usize = 64;
+// ADD, ADDMANY and UPDATE refuse thresholds that are infinite, NaN or past this in absolute value.
+const MAX_THRESHOLD_ENV: &str = "MAX_ALERT_THRESHOLD";
+const MAX_POSITIONS_ENV: &str = "MAX_POSITIONS";
+// An EXPORT reply is a single line, accounts past this are refused instead of sent.
+const MAX_EXPORT_BYTES: usize = 1024 * 1024;
+// Commands read from a connection but not handled yet, once that many wait the server stops
+// reading the socket until it catches up, so a pipelining client can't grow the queue.
+const MAX_IN_FLIGHT_ENV: &str = "MAX_IN_FLIGHT_COMMANDS";
+const DEFAULT_MAX_IN_FLIGHT: usize = 32;
+// Leaderboard rows show usernames only with this set to 1, otherwise everyone but the
+// asking user is listed as ANONYMOUS_NAME.
+const LEADERBOARD_NAMES_ENV: &str = "LEADERBOARD_SHOW_NAMES";
+const ANONYMOUS_NAME: &str = "anonymous";
+const DEFAULT_LEADERBOARD_SIZE: usize = 10;
+const MAX_LEADERBOARD_SIZE: usize = 100;
+// Set to 1 to fail alert reads on rows with an unknown direction instead of skipping them.
+const STRICT_ALERTS_ENV: &str = "STRICT_ALERT_DIRECTIONS";
+
+// all: the whole symbols file every FULL_SCRAPE_INTERVAL.
+// active: only symbols in alerts and open positions, every ACTIVE_SCRAPE_INTERVAL.
+// both: active symbols every ACTIVE_SCRAPE_INTERVAL and everything every FULL_SCRAPE_INTERVAL.
+const SCRAPE_MODE_ENV: &str = "SCRAPE_MODE";
+const FULL_SCRAPE_INTERVAL: Duration = Duration::from_secs(60);
+const ACTIVE_SCRAPE_INTERVAL: Duration = Duration::from_secs(15);
+const YAHOO_CHART_URL: &str = "https://query1.finance.yahoo.com/v8/finance/chart/";
+// When the scraper can't start, the server exits unless this is 1, then it runs without prices.
+const ALLOW_NO_PRICES_ENV: &str = "ALLOW_NO_PRICES";
+// JSON lines with connections, commands, triggers and errors per user, off when unset.
+// Past the size limit the file is moved to `<path>.1` (replacing the older one) and started anew.
+const AUDIT_LOG_ENV: &str = "AUDIT_LOG_FILE";
+const AUDIT_LOG_MAX_BYTES_ENV: &str = "AUDIT_LOG_MAX_BYTES";
+const DEFAULT_AUDIT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+// Records waiting for the writer, a slow disk drops records instead of holding up clients.
+const AUDIT_QUEUE_SIZE: usize = 1024;
+
+// Every scrape cycle hits the same Yahoo host once per symbol, so idle connections are kept
+// around longer than one cycle (60s pause) and reused instead of doing a new TLS handshake.
+const HTTP_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const HTTP_POOL_MAX_IDLE_PER_HOST: usize = 8;
+const HTTP_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+struct ConnectionConfig {
+    heartbeat: Duration,
+    idle: Duration,
+    max_batch_alerts: usize,
+    leaderboard_names: bool,
+    alert_cooldown: Duration,
+    max_in_flight: usize,
+    max_threshold: f64,
+    max_positions: usize,
+    // Fail alert reads on rows with an unknown direction instead of skipping them.
+    strict_alerts: bool,
+    audit: AuditLog,
+}
+
+impl ConnectionConfig {
+    fn from_env() -> Result<Self> {
+        let idle = match std::env::var(IDLE_TIMEOUT_ENV) {
+            Ok(raw) => {
+                let secs: u64 = raw.trim().parse().with_context(|| {
+                    format!("[server] {IDLE_TIMEOUT_ENV} must be a number of seconds: {raw}")
+                })?;
+                Duration::from_secs(secs)
+            }
+            Err(_) => DEFAULT_IDLE_TIMEOUT,
+        };
+        let max_batch_alerts = match std::env::var(MAX_BATCH_ALERTS_ENV) {
+            Ok(raw) => raw.trim().parse().with_context(|| {
+                format!("[server] {MAX_BATCH_ALERTS_ENV} must be a number of alerts: {raw}")
+            })?,
+            Err(_) => DEFAULT_MAX_BATCH_ALERTS,
+        };
+        let leaderboard_names = std::env::var(LEADERBOARD_NAMES_ENV).is_ok_and(|v| v.trim() == "1");
+        let strict_alerts = std::env::var(STRICT_ALERTS_ENV).is_ok_and(|v| v.trim() == "1");
+        let alert_cooldown = match std::env::var(ALERT_COOLDOWN_ENV) {
+            Ok(raw) => {
+                let secs: u64 = raw.trim().parse().with_context(|| {
+                    format!("[server] {ALERT_COOLDOWN_ENV} must be a number of seconds: {raw}")
+                })?;
+                Duration::from_secs(secs)
+            }
+            Err(_) => DEFAULT_ALERT_COOLDOWN,
+        };
+        let max_in_flight = match std::env::var(MAX_IN_FLIGHT_ENV) {
+            Ok(raw) => match raw.trim().parse() {
+                Ok(0) | Err(_) => anyhow::bail!(
+                    "[server] {MAX_IN_FLIGHT_ENV} must be a positive number of commands: {raw}"
+                ),
+                Ok(limit) => limit,
+            },
+            Err(_) => DEFAULT_MAX_IN_FLIGHT,
+        };
+        let max_threshold = match std::env::var(MAX_THRESHOLD_ENV) {
+            Ok(raw) => match raw.trim().parse::<f64>() {
+                Ok(max) if max.is_finite() && max > 0.0 => max,
+                _ => anyhow::bail!("[server] {MAX_THRESHOLD_ENV} must be a positive number: {raw}"),
+            },
+            Err(_) => DEFAULT_MAX_THRESHOLD,
+        };
+        let max_positions = match std::env::var(MAX_POSITIONS_ENV) {
+            Ok(raw) => match raw.trim().parse() {
+                Ok(0) | Err(_) => anyhow::bail!(
+                    "[server] {MAX_POSITIONS_ENV} must be a positive number of stocks: {raw}"
+                ),
+                Ok(limit) => limit,
+            },
+            Err(_) => database::DEFAULT_MAX_POSITIONS,
+        };
+        Ok(Self {
+            heartbeat: HEARTBEAT_INTERVAL,
+            idle,
+            max_batch_alerts,
+            leaderboard_names,
+            alert_cooldown,
+            max_in_flight,
+            max_threshold,
+            max_positions,
+            strict_alerts,
+            audit: AuditLog::from_env()?,
+        })
+    }
+
+    fn max_line_bytes(&self) -> usize {
+        MIN_LINE_BYTES.max(self.max_batch_alerts.saturating_mul(LINE_BYTES_PER_ALERT))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    ts: String,
+    user_id: Option<i64>,
+    peer: String,
+    event: &'static str,
+    detail: String,
+}
+
+// Cheap to clone, records are written by a blocking task so clients never wait on the file.
+// The default one is off and drops everything.
+#[derive(Debug, Clone, Default)]
+struct AuditLog {
+    tx: Option<mpsc::Sender<AuditRecord>>,
+}
+
+impl AuditLog {
+    fn from_env() -> Result<Self> {
+        let Ok(path) = std::env::var(AUDIT_LOG_ENV) else {
+            return Ok(Self::default());
+        };
+        let max_bytes = match std::env::var(AUDIT_LOG_MAX_BYTES_ENV) {
+            Ok(raw) => match raw.trim().parse() {
+                Ok(0) | Err(_) => anyhow::bail!(
+                    "[server] {AUDIT_LOG_MAX_BYTES_ENV} must be a positive number of bytes: {raw}"
+                ),
+                Ok(limit) => limit,
+            },
+            Err(_) => DEFAULT_AUDIT_LOG_MAX_BYTES,
+        };
+        Self::open(Path::new(&path), max_bytes)
+            .with_context(|| format!("[server] Failed to open the audit log {path}"))
+    }
+
+    fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let mut writer = AuditWriter::open(path, max_bytes)?;
+        let (tx, mut rx) = mpsc::channel::<AuditRecord>(AUDIT_QUEUE_SIZE);
+        tokio::task::spawn_blocking(move || {
+            while let Some(record) = rx.blocking_recv() {
+                let line = match serde_json::to_string(&record) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        error!("[server-audit] Failed to encode a record: {}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = writer.append(&line) {
+                    error!("[server-audit] Failed to write the audit log: {}", e);
+                }
+            }
+        });
+        Ok(Self { tx: Some(tx) })
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    fn record(&self, user_id: Option<i64>, peer: &str, event: &'static str, detail: String) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let record = AuditRecord {
+            ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            user_id,
+            peer: peer.to_string(),
+            event,
+            detail,
+        };
+        if tx.try_send(record).is_err() {
+            warn!(
+                "[server-audit] Audit queue full, dropping a {} record",
+                event
+            );
+        }
+    }
+}
+
+struct AuditWriter {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl AuditWriter {
+    fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes,
+        })
+    }
+
+    // A line never gets split between files, so a full file may end a bit past the limit.
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+        *self = Self::open(&self.path, self.max_bytes)?;
+        Ok(())
+    }
+}
+
+// The audit log as seen from one connection, records carry its peer and logged-in user.
+#[derive(Default)]
+struct ClientAudit {
+    log: AuditLog,
+    peer: String,
+    user_id: Option<i64>,
+}
+
+impl ClientAudit {
+    fn record(&self, event: &'static str, detail: impl Into<String>) {
+        self.log
+            .record(self.user_id, &self.peer, event, detail.into());
+    }
+}
+
+// Only the command word goes to the audit log, LOGIN and RESET lines carry passwords.
+fn command_name(line: &str) -> &str {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some(word) if word.starts_with('#') => words.next().unwrap_or(""),
+        Some(word) => word,
+        None => "",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScrapeMode {
+    All,
+    Active,
+    Both,
+}
+
+impl ScrapeMode {
+    fn from_env() -> Result<Self> {
+        match std::env::var(SCRAPE_MODE_ENV) {
+            Ok(raw) => Self::parse(&raw).with_context(|| {
+                format!("[server] {SCRAPE_MODE_ENV} must be all, active or both: {raw}")
+            }),
+            Err(_) => Ok(Self::All),
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "all" => Some(Self::All),
+            "active" => Some(Self::Active),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Active => "active",
+            Self::Both => "both",
+        }
+    }
+
+    fn pause(self) -> Duration {
+        match self {
+            Self::All => FULL_SCRAPE_INTERVAL,
+            Self::Active | Self::Both => ACTIVE_SCRAPE_INTERVAL,
+        }
+    }
+}
+
+// Whether this cycle scrapes the whole symbols file. Only `both` goes back to it every
+// FULL_SCRAPE_INTERVAL, `active` does it once at the start (or on SIGUSR1).
+fn is_full_cycle(mode: ScrapeMode, last_full: Option<Instant>) -> bool {
+    match mode {
+        ScrapeMode::All => true,
+        ScrapeMode::Active => last_full.is_none(),
+        ScrapeMode::Both => last_full.is_none_or(|at| at.elapsed() >= FULL_SCRAPE_INTERVAL),
+    }
+}
+
+// The first cycle always covers the whole file so every symbol starts with a price,
+// `active` mode only refreshes what users hold or watch after that.
+fn cycle_symbols(
+    mode: ScrapeMode,
+    universe: &[SymbolInfo],
+    active: &[String],
+    full: bool,
+) -> Vec<SymbolInfo> {
+    let as_info = |symbol: &String| {
+        universe
+            .iter()
+            .find(|info| info.symbol == *symbol)
+            .cloned()
+            .unwrap_or_else(|| SymbolInfo {
+                symbol: symbol.clone(),
+                name: None,
+                exchange: None,
+            })
+    };
+    if !full {
+        return active.iter().map(as_info).collect();
+    }
+    let mut symbols = universe.to_vec();
+    if mode != ScrapeMode::All {
+        for symbol in active {
+            if !symbols.iter().any(|info| info.symbol == *symbol) {
+                symbols.push(as_info(symbol));
+            }
+        }
+    }
+    symbols
+}
+
+struct ScraperConfig {
+    user_agents: Vec<String>,
+    proxy: Option<reqwest::Url>,
+    chart_url: String,
+    // Woken by SIGUSR1, starts a full cycle right away instead of after the pause.
+    rescrape: Arc<Notify>,
+}
+
+impl ScraperConfig {
+    fn from_env() -> Result<Self> {
+        Self::new(
+            std::env::var(USER_AGENT_ENV).ok(),
+            std::env::var(PROXY_ENV).ok(),
+        )
+    }
+
+    fn new(user_agent: Option<String>, proxy: Option<String>) -> Result<Self> {
+        let mut user_agents: Vec<String> = user_agent
+            .unwrap_or_default()
+            .split(USER_AGENT_SEPARATOR)
+            .map(|ua| ua.trim().to_string())
+            .filter(|ua| !ua.is_empty())
+            .collect();
+        if user_agents.is_empty() {
+            user_agents.push(DEFAULT_USER_AGENT.to_string());
+        }
+
+        let proxy = match proxy.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(raw) => {
+                let url = reqwest::Url::parse(raw)
+                    .with_context(|| format!("[server] Invalid {PROXY_ENV} url: {raw}"))?;
+                if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+                    anyhow::bail!("[server] {PROXY_ENV} must be an http(s) url with a host: {raw}");
+                }
+                Some(url)
+            }
+        };
+
+        Ok(Self {
+            user_agents,
+            proxy,
+            chart_url: YAHOO_CHART_URL.to_string(),
+            rescrape: Arc::new(Notify::new()),
+        })
+    }
+
+    // HTTP/2 is used when the server negotiates it, otherwise keep-alive HTTP/1.1.
+    fn build_client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent(self.user_agents[0].as_str())
+            .pool_idle_timeout(HTTP_POOL_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(HTTP_POOL_MAX_IDLE_PER_HOST)
+            .tcp_keepalive(HTTP_TCP_KEEPALIVE)
+            .http2_adaptive_window(true);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+        }
+        builder.build()
+    }
+
+    fn rotation(&self) -> UserAgentRotation {
+        UserAgentRotation {
+            agents: self.user_agents.clone(),
+            next: 0,
+        }
+    }
+
+    fn fetcher(&self) -> Result<ChartFetcher, reqwest::Error> {
+        Ok(ChartFetcher {
+            client: self.build_client()?,
+            user_agents: self.rotation(),
+            url_base: self.chart_url.clone(),
+        })
+    }
+}
+
+// Round-robin over the configured User-Agents, so Yahoo doesn't see one static header.
+struct UserAgentRotation {
+    agents: Vec<String>,
+    next: usize,
+}
+
+impl UserAgentRotation {
+    fn next_agent(&mut self) -> &str {
+        let index = self.next % self.agents.len();
+        self.next = self.next.wrapping_add(1);
+        &self.agents[index]
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct YahooResponse {
     chart: Chart,
@@ -34,6 +539,41 @@ struct Chart {
 #[derive(Debug, Deserialize)]
 struct ChartResult {
     meta: Meta,
+    #[serde(default)]
+    timestamp: Vec<i64>,
+    #[serde(default)]
+    indicators: Indicators,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Indicators {
+    #[serde(default)]
+    quote: Vec<QuoteIndicator>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuoteIndicator {
+    // Yahoo sends null for intervals without trades.
+    #[serde(default)]
+    close: Vec<Option<f64>>,
+}
+
+impl ChartResult {
+    fn history(&self) -> Vec<HistoryPoint> {
+        let Some(quote) = self.indicators.quote.first() else {
+            return Vec::new();
+        };
+        self.timestamp
+            .iter()
+            .zip(&quote.close)
+            .filter_map(|(timestamp, close)| {
+                Some(HistoryPoint {
+                    timestamp: *timestamp,
+                    close: (*close)?,
+                })
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -42,346 +582,1335 @@ struct Meta {
     currency: String,
     symbol: String,
     regular_market_price: f64,
+    #[serde(default)]
+    regular_market_volume: Option<u64>,
+    #[serde(default)]
+    fifty_two_week_low: Option<f64>,
+    #[serde(default)]
+    fifty_two_week_high: Option<f64>,
+    #[serde(default)]
+    long_name: Option<String>,
+    #[serde(default)]
+    short_name: Option<String>,
+}
+
+impl Meta {
+    fn quote(&self) -> Quote {
+        Quote {
+            price: self.regular_market_price,
+            stats: QuoteStats {
+                volume: self.regular_market_volume,
+                low_52w: self.fifty_two_week_low,
+                high_52w: self.fifty_two_week_high,
+            },
+            name: self.long_name.clone().or_else(|| self.short_name.clone()),
+            cached: false,
+        }
+    }
+}
+
+// Latest scraped quote of one symbol.
+#[derive(Debug, Clone, PartialEq)]
+struct Quote {
+    price: f64,
+    stats: QuoteStats,
+    // Company name, from Yahoo or else from the symbols file.
+    name: Option<String>,
+    // Loaded from the price cache at startup and not scraped since. Good enough to value a
+    // portfolio, but never traded on or used to fire alerts and brackets.
+    cached: bool,
+}
+
+fn load_user_seed() -> Result<Option<Arc<database::UserSeed>>> {
+    let Ok(path) = std::env::var(USER_SEED_ENV) else {
+        return Ok(None);
+    };
+    let raw = fs::read_to_string(&path)
+        .with_context(|| format!("[server] Couldn't read {USER_SEED_ENV} file {path}"))?;
+    let seed = serde_json::from_str(&raw)
+        .with_context(|| format!("[server] Invalid {USER_SEED_ENV} file {path}"))?;
+    Ok(Some(Arc::new(seed)))
+}
+
+// One tracked symbol, the metadata only comes from CSV and JSON universes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct SymbolInfo {
+    symbol: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    exchange: Option<String>,
+}
+
+impl SymbolInfo {
+    fn label(&self) -> String {
+        match (&self.name, &self.exchange) {
+            (Some(name), Some(exchange)) => format!("{} ({name}, {exchange})", self.symbol),
+            (Some(name), None) => format!("{} ({name})", self.symbol),
+            (None, Some(exchange)) => format!("{} ({exchange})", self.symbol),
+            (None, None) => self.symbol.clone(),
+        }
+    }
+}
+
+// The symbols file as the scraper last read it. The scraper reads it again at the start of
+// every cycle and a SIGHUP starts a cycle right away; clients see the symbols through `list`.
+struct SymbolsFile {
+    path: String,
+    universe: Vec<SymbolInfo>,
+    list: SymbolList,
+    reload: Arc<Notify>,
 }
 
-fn read_all_stocks() -> Vec<String> {
-    let file = fs::read_to_string("stocks_small.txt").expect("Couldn't open a file");
+impl SymbolsFile {
+    fn load(path: &str) -> Result<Self> {
+        let universe = read_all_stocks(path)?;
+        let list = universe.iter().map(|info| info.symbol.clone()).collect();
+        Ok(Self {
+            path: path.to_string(),
+            universe,
+            list: Arc::new(RwLock::new(Arc::new(list))),
+            reload: Arc::new(Notify::new()),
+        })
+    }
+
+    // Returns the symbols that weren't there before. A file that can't be read or has no
+    // symbols (say, caught while being rewritten) keeps the previous list. Removed symbols
+    // lose their price at the end of the next cycle, see retain_tracked.
+    async fn reload(&mut self) -> Vec<String> {
+        let universe = match read_all_stocks(&self.path) {
+            Ok(universe) if !universe.is_empty() => universe,
+            Ok(_) => {
+                warn!(
+                    "[server scrapper] {} has no symbols, keeping the previous {}",
+                    self.path,
+                    self.universe.len()
+                );
+                return Vec::new();
+            }
+            Err(e) => {
+                warn!(
+                    "[server scrapper] {:#}, keeping the previous {} symbols",
+                    e,
+                    self.universe.len()
+                );
+                return Vec::new();
+            }
+        };
+
+        let old: HashSet<&str> = self
+            .universe
+            .iter()
+            .map(|info| info.symbol.as_str())
+            .collect();
+        let new: HashSet<&str> = universe.iter().map(|info| info.symbol.as_str()).collect();
+        let added: Vec<String> = universe
+            .iter()
+            .filter(|info| !old.contains(info.symbol.as_str()))
+            .map(|info| info.symbol.clone())
+            .collect();
+        let removed: Vec<&str> = self
+            .universe
+            .iter()
+            .map(|info| info.symbol.as_str())
+            .filter(|symbol| !new.contains(symbol))
+            .collect();
+
+        if !added.is_empty() {
+            info!("[server scrapper] Symbols added: {}", added.join(", "));
+        }
+        if !removed.is_empty() {
+            info!("[server scrapper] Symbols removed: {}", removed.join(", "));
+        }
+        if !added.is_empty() || !removed.is_empty() {
+            let list = universe.iter().map(|info| info.symbol.clone()).collect();
+            *self.list.write().await = Arc::new(list);
+        }
+        self.universe = universe;
+        added
+    }
+}
+
+// The format follows the extension, anything that isn't .csv or .json is one symbol per line.
+fn read_all_stocks(path: &str) -> Result<Vec<SymbolInfo>> {
+    let file = fs::read_to_string(path)
+        .with_context(|| format!("[server] Couldn't read symbols file {path}"))?;
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let mut symbols = match extension.as_deref() {
+        Some("json") => serde_json::from_str::<Vec<SymbolInfo>>(&file)
+            .with_context(|| format!("[server] Invalid symbols file {path}"))?,
+        Some("csv") => parse_symbols_csv(&file),
+        _ => file
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| SymbolInfo {
+                symbol: line.to_string(),
+                name: None,
+                exchange: None,
+            })
+            .collect(),
+    };
+    for info in &mut symbols {
+        info.symbol = info.symbol.trim().to_string();
+    }
+    symbols.retain(|info| !info.symbol.is_empty());
+    Ok(symbols)
+}
 
+// symbol,name,exchange with an optional header row, names with commas have to be quoted.
+fn parse_symbols_csv(file: &str) -> Vec<SymbolInfo> {
+    let cell = |value: Option<&String>| {
+        value
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
     file.lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .map(|line| line.to_string())
+        .map(split_csv_line)
+        .filter(|fields| {
+            fields
+                .first()
+                .is_some_and(|first| !first.trim().eq_ignore_ascii_case("symbol"))
+        })
+        .map(|fields| SymbolInfo {
+            symbol: fields[0].clone(),
+            name: cell(fields.get(1)),
+            exchange: cell(fields.get(2)),
+        })
         .collect()
 }
 
-async fn scrap_stocks(stock_map: MapLock, all_stocks: Vec<String>) -> Result<(), reqwest::Error> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()?;
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
 
-    let url_base = "https://query1.finance.yahoo.com/v8/finance/chart/";
+// Everything a single chart request needs, one client keeps its connections across cycles.
+struct ChartFetcher {
+    client: reqwest::Client,
+    user_agents: UserAgentRotation,
+    url_base: String,
+}
 
-    loop {
-        info!("[server scrapper] STARTING SCRAPPING");
-        let mut temp_map = HashMap::new();
+impl ChartFetcher {
+    // Returns the symbol as Yahoo spells it with its quote and today's history.
+    async fn fetch(&mut self, stock: &SymbolInfo) -> Option<(String, Quote, Vec<HistoryPoint>)> {
+        // The intraday series comes with the quote, so HISTORY needs no extra requests.
+        let url = format!("{}{}?range=1d&interval=5m", self.url_base, stock.symbol);
 
-        for i in &all_stocks {
-            let url = format!("{}{}", url_base, i);
+        let request = fetch_chart(&self.client, &url, self.user_agents.next_agent()).await;
 
-            let request = client
-                .get(url)
-                .header(
-                    USER_AGENT,
-                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7)",
-                )
-                .header(ACCEPT, "application/json")
-                .send()
-                .await;
-
-            match request {
-                Ok(request) => {
-                    if request.status().is_success() {
-                        let yahoo_response: Result<YahooResponse, _> = request.json().await;
-                        match yahoo_response {
-                            Ok(yahoo_response) => {
-                                let yahoo_chart = yahoo_response.chart;
-
-                                if let Some(stock_data) = yahoo_chart.result.first() {
-                                    info!(
-                                        "[server scrapper] Stock symbol and currency: {} {}",
-                                        stock_data.meta.symbol, stock_data.meta.currency
-                                    );
-                                    info!(
-                                        "[server scrapper] Stock price {}",
-                                        stock_data.meta.regular_market_price
-                                    );
-                                    temp_map.insert(
-                                        stock_data.meta.symbol.clone(),
-                                        stock_data.meta.regular_market_price,
-                                    );
+        match request {
+            Ok(request) => {
+                if request.status().is_success() {
+                    let yahoo_response: Result<YahooResponse, _> = request.json().await;
+                    match yahoo_response {
+                        Ok(yahoo_response) => {
+                            let yahoo_chart = yahoo_response.chart;
+
+                            if let Some(stock_data) = yahoo_chart.result.first() {
+                                info!(
+                                    "[server scrapper] Stock symbol and currency: {} {}",
+                                    stock.label(),
+                                    stock_data.meta.currency
+                                );
+                                info!(
+                                    "[server scrapper] Stock price {}",
+                                    stock_data.meta.regular_market_price
+                                );
+                                let mut quote = stock_data.meta.quote();
+                                if quote.name.is_none() {
+                                    quote.name = stock.name.clone();
                                 }
+                                return Some((
+                                    stock_data.meta.symbol.clone(),
+                                    quote,
+                                    stock_data.history(),
+                                ));
                             }
-                            Err(error) => {
-                                error!("[server scrapper] Failed Json convertion: {}", error)
-                            }
                         }
-                    } else {
-                        warn!("[server scrapper] Request not succesfull!");
+                        Err(error) => {
+                            error!("[server scrapper] Failed Json convertion: {}", error)
+                        }
                     }
+                } else {
+                    warn!("[server scrapper] Request not succesfull!");
+                }
+            }
+            Err(error) => warn!("[server scrapper] Scrapping network error: {}", error),
+        }
+        None
+    }
+}
+
+// Drops every symbol that is neither in the file nor in an alert or position (`tracked`,
+// upper case since Yahoo may spell a symbol differently) and returns the dropped ones.
+fn retain_tracked<V>(map: &mut HashMap<String, V>, tracked: &HashSet<String>) -> Vec<String> {
+    let mut evicted = Vec::new();
+    map.retain(|symbol, _| {
+        let keep = tracked.contains(&symbol.to_ascii_uppercase());
+        if !keep {
+            evicted.push(symbol.clone());
+        }
+        keep
+    });
+    evicted.sort();
+    evicted
+}
+
+// `first_scrape` gets the number of known prices once the first full pass is done,
+// or why the scraper couldn't start at all, main decides what happens then.
+async fn scrap_stocks(
+    stock_map: MapLock,
+    history_map: HistoryLock,
+    mut symbols_file: SymbolsFile,
+    scraper: ScraperConfig,
+    mode: ScrapeMode,
+    pool: SqlitePool,
+    first_scrape: oneshot::Sender<Result<usize, String>>,
+) {
+    let mut fetcher = match scraper.fetcher() {
+        Ok(fetcher) => fetcher,
+        Err(e) => {
+            error!("[server scrapper] Failed to build the HTTP client! {}", e);
+            let _ = first_scrape.send(Err(format!("Failed to build the HTTP client: {e}")));
+            return;
+        }
+    };
+    let mut first_scrape = Some(first_scrape);
+    let mut last_full: Option<Instant> = None;
+    let mut rescrape_requested = false;
+
+    loop {
+        let full = is_full_cycle(mode, last_full);
+        let added = symbols_file.reload().await;
+        // Without the active symbols nothing is evicted this cycle, their prices may still be wanted.
+        let (mut active, active_known) = if mode == ScrapeMode::All {
+            (Vec::new(), true)
+        } else {
+            match database::active_symbols(&pool).await {
+                Ok(active) => (active, true),
+                Err(e) => {
+                    error!("[server-database] Failed to read active symbols! {}", e);
+                    (Vec::new(), false)
                 }
-                Err(error) => warn!("[server scrapper] Scrapping network error: {}", error),
+            }
+        };
+        // New symbols get their first price now instead of at the next full cycle.
+        active.extend(added);
+        let symbols = cycle_symbols(mode, &symbols_file.universe, &active, full);
+        if full {
+            last_full = Some(Instant::now());
+        }
+
+        info!(
+            "[server scrapper] STARTING SCRAPPING {} symbols (mode {}, full {})",
+            symbols.len(),
+            mode.as_str(),
+            full
+        );
+        let mut temp_map = HashMap::new();
+        let mut temp_history = HashMap::new();
+
+        for stock in &symbols {
+            if let Some((symbol, quote, history)) = fetcher.fetch(stock).await {
+                temp_map.insert(symbol.clone(), quote);
+                temp_history.insert(symbol, history);
             }
             tokio::time::sleep(Duration::from_millis(10)).await;
         }
 
-        if !temp_map.is_empty() {
+        let fetched: Vec<(String, f64)> = temp_map
+            .iter()
+            .map(|(symbol, quote)| (symbol.clone(), quote.price))
+            .collect();
+        let tracked: HashSet<String> = symbols_file
+            .universe
+            .iter()
+            .map(|info| info.symbol.as_str())
+            .chain(active.iter().map(String::as_str))
+            .chain(temp_map.keys().map(String::as_str))
+            .map(str::to_ascii_uppercase)
+            .collect();
+
+        {
+            let mut history = history_map.write().await;
+            history.extend(temp_history);
+            if active_known {
+                retain_tracked(&mut history, &tracked);
+            }
+        }
+
+        let (priced, evicted) = {
             let mut writer = stock_map.write().await;
 
             writer.extend(temp_map);
+            let evicted = if active_known {
+                retain_tracked(&mut writer, &tracked)
+            } else {
+                Vec::new()
+            };
+            (writer.len(), evicted)
+        };
+        if !evicted.is_empty() {
+            info!(
+                "[server scrapper] No longer tracked, prices dropped: {}",
+                evicted.join(", ")
+            );
+        }
+        if let Err(e) = database::cache_prices(&pool, &fetched, &evicted).await {
+            error!("[server-database] Failed to cache prices! {}", e);
+        }
+
+        info!(
+            "[server scrapper] Scrape cycle done, {} prices known",
+            priced
+        );
+        if rescrape_requested {
+            info!("[server scrapper] Requested rescrape done");
+            rescrape_requested = false;
+        }
+        run_brackets(&pool, &stock_map).await;
+        if let Some(tx) = first_scrape.take() {
+            let _ = tx.send(Ok(priced));
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(mode.pause()) => {}
+            _ = symbols_file.reload.notified() => {
+                info!("[server scrapper] Reload requested, starting the next cycle now");
+            }
+            _ = scraper.rescrape.notified() => {
+                info!("[server scrapper] Rescrape requested, starting a full cycle now");
+                rescrape_requested = true;
+                last_full = None;
+            }
         }
+    }
+}
 
-        info!("[server] Completed scrapping all NASDAQ stocks, clients may join!");
+async fn fetch_chart(
+    client: &reqwest::Client,
+    url: &str,
+    user_agent: &str,
+) -> reqwest::Result<reqwest::Response> {
+    client
+        .get(url)
+        .header(ACCEPT, "application/json")
+        .header(USER_AGENT, user_agent)
+        .send()
+        .await
+}
 
-        tokio::time::sleep(Duration::from_secs(60)).await;
+// Log-in and register hide why they failed, except when the client should simply retry
+// or the server itself is broken.
+fn login_error<'a>(db_error: &str, fallback: &'a str) -> &'a str {
+    match db_error {
+        ERR_DB_UNAVAILABLE => ERR_DB_UNAVAILABLE,
+        ERR_INTERNAL => ERR_INTERNAL,
+        _ => fallback,
     }
 }
 
-async fn client_errors(error_message: &str, write_socket: &mut OwnedWriteHalf) -> io::Result<()> {
-    let message = ServerMsg::Error(error_message.to_string()).to_wire();
+async fn client_errors(
+    audit: &ClientAudit,
+    error_message: &str,
+    req_id: Option<u64>,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    audit.record("error", error_message);
+    let message = ServerMsg::Error(error_message.to_string()).to_wire_with_id(req_id);
     write_socket.write_all(message.as_bytes()).await?;
     write_socket.flush().await?;
 
     Ok(())
 }
 
+// The price lock is only held while copying values out of the map, so a slow
+// client socket never keeps the scraper or other clients waiting.
 async fn check_price(
+    audit: &ClientAudit,
     stock: &str,
+    req_id: Option<u64>,
     map_pointer: &MapLock,
+    symbols: &[String],
     write_socket: &mut OwnedWriteHalf,
 ) -> io::Result<()> {
-    let access = map_pointer.read().await;
-
-    match access.get(stock) {
-        Some(current_value) => {
+    match check_quote_of_stock(map_pointer, stock).await {
+        Some(quote) => {
             let message = ServerMsg::PriceChecked {
                 symbol: stock.to_string(),
-                price: *current_value,
+                price: quote.price,
+                stats: quote.stats,
             }
-            .to_wire();
+            .to_wire_with_id(req_id);
             write_socket.write_all(message.as_bytes()).await?;
             write_socket.flush().await?;
         }
         None => {
-            client_errors("Stock not available!", write_socket).await?;
+            client_errors(
+                audit,
+                missing_price_error(symbols, stock),
+                req_id,
+                write_socket,
+            )
+            .await?;
         }
     }
 
     Ok(())
 }
 
+// Points from the last `range` before the newest close.
+fn history_window(points: &[HistoryPoint], range: HistoryRange) -> Vec<HistoryPoint> {
+    let Some(last) = points.last() else {
+        return Vec::new();
+    };
+    let since = last.timestamp - range.seconds();
+    points
+        .iter()
+        .filter(|point| point.timestamp >= since)
+        .copied()
+        .collect()
+}
+
+// Symbols from the stocks file are known, they only lack a price until the scraper gets to them.
+fn missing_price_error(symbols: &[String], stock: &str) -> &'static str {
+    if symbols.iter().any(|symbol| symbol == stock) {
+        ERR_PRICE_NOT_READY
+    } else {
+        ERR_UNKNOWN_SYMBOL
+    }
+}
+
+fn alert_triggered(direction: AlertDirection, threshold: f64, current_price: f64) -> bool {
+    match direction {
+        AlertDirection::Above => current_price > threshold,
+        AlertDirection::Below => current_price < threshold,
+    }
+}
+
 async fn prepare_new_alert(
     pool: &sqlite::SqlitePool,
     user_id: i64,
     alert: &AlertRequest,
+    req_id: Option<u64>,
     map_pointer: &MapLock,
     write_socket: &mut OwnedWriteHalf,
 ) -> io::Result<()> {
-    let access = map_pointer.read().await;
-
-    match access.get(&alert.symbol) {
-        Some(current_value) => {
-            let triggered = match alert.direction {
-                AlertDirection::Above => *current_value > alert.threshold,
-                AlertDirection::Below => *current_value < alert.threshold,
-            };
-            if triggered {
-                let message = ServerMsg::AlertTriggered {
-                    symbol: alert.symbol.clone(),
-                    direction: alert.direction,
-                    threshold: alert.threshold,
-                    current_price: Price {
-                        value: *current_value,
-                    },
-                }
-                .to_wire();
-                write_socket.write_all(message.as_bytes()).await?;
-                write_socket.flush().await?;
-            }
+    // Without a price yet the alert is still stored, the reply tells the user it can't fire
+    // until the scraper has one. One that is already past its threshold is stored too, the
+    // reply warns that it fires on the next check so the user can take it back.
+    let current_value = check_price_of_stock(map_pointer, &alert.symbol).await;
+    let triggers_now =
+        current_value.is_some_and(|price| alert_triggered(alert.direction, alert.threshold, price));
+    if current_value.is_none() {
+        info!(
+            "[user: {}] Alert on {} added without a price",
+            user_id, alert.symbol
+        );
+    }
 
-            match database::add_alert(pool, user_id, alert).await {
-                Ok(_) => {
-                    let message = ServerMsg::AlertAdded {
-                        symbol: alert.symbol.clone(),
-                        direction: alert.direction,
-                        threshold: alert.threshold,
-                    }
-                    .to_wire();
-                    send_data(message, write_socket).await?;
-                }
-                Err(e) => {
-                    client_errors(&e, write_socket).await?;
-                }
+    match database::add_alert(pool, user_id, alert).await {
+        Ok(_) => {
+            let message = ServerMsg::AlertAdded {
+                symbol: alert.symbol.clone(),
+                direction: alert.direction,
+                threshold: alert.threshold,
+                price_available: current_value.is_some(),
+                triggers_now,
             }
+            .to_wire_with_id(req_id);
+            send_data(message, write_socket).await?;
         }
-        None => {
-            client_errors("Stock not available!", write_socket).await?;
+        Err(e) => {
+            alert_failed(alert, &e, req_id, write_socket).await?;
         }
     }
     Ok(())
 }
 
-async fn check_price_of_stock(map_pointer: &MapLock, stock: &str) -> Option<f64> {
-    let access = map_pointer.read().await;
-
-    access.get(stock).copied()
-}
-
-async fn send_data(message: String, write_socket: &mut OwnedWriteHalf) -> io::Result<()> {
-    write_socket.write_all(message.as_bytes()).await?;
-    write_socket.flush().await?;
-
-    Ok(())
-}
-
-async fn check_alerts_for_user(
+// Every alert goes to the database in one transaction, those for symbols without a price
+// too, like a single ADD keeps them. The reply counts how many of the added alerts wait for
+// a price. Nothing is checked for an immediate trigger here, the regular alert tick picks
+// those up.
+async fn prepare_new_alerts(
+    audit: &ClientAudit,
     pool: &SqlitePool,
     user_id: i64,
-    map_lock: &MapLock,
+    alerts: Vec<AlertRequest>,
+    req_id: Option<u64>,
+    map_pointer: &MapLock,
     write_socket: &mut OwnedWriteHalf,
 ) -> io::Result<()> {
-    let alerts = match database::get_user_alerts(pool, user_id).await {
-        Ok(a) => a,
-        Err(e) => {
-            error!("[server-database] Database error! {}", e);
-            return Ok(());
-        }
-    };
-
-    let prices = map_lock.read().await;
-
-    for alert in alerts.iter() {
-        if let Some(current_price) = prices.get(&alert.symbol) {
-            let triggered = match alert.direction {
-                AlertDirection::Above => *current_price > alert.threshold,
-                AlertDirection::Below => *current_price < alert.threshold,
+    match database::add_alerts(pool, user_id, &alerts).await {
+        Ok(added) => {
+            // Skipped alerts were already there, only the new ones count as unpriced.
+            let unpriced = {
+                let prices = map_pointer.read().await;
+                added
+                    .iter()
+                    .filter(|alert| !prices.contains_key(&alert.symbol))
+                    .count()
             };
-
-            if triggered {
-                let message = ServerMsg::AlertTriggered {
-                    symbol: alert.symbol.clone(),
-                    direction: alert.direction,
-                    threshold: alert.threshold,
-                    current_price: Price {
-                        value: *current_price,
-                    },
-                }
-                .to_wire();
-                write_socket.write_all(message.as_bytes()).await?;
-                write_socket.flush().await?;
+            let message = ServerMsg::AlertsAdded {
+                added: added.len(),
+                skipped: alerts.len() - added.len(),
+                unpriced,
             }
+            .to_wire_with_id(req_id);
+            send_data(message, write_socket).await
+        }
+        Err(e) => {
+            error!("[server-database] Failed to add alerts to database! {}", e);
+            client_errors(audit, &e, req_id, write_socket).await
         }
     }
-    Ok(())
 }
 
-async fn handle_client(socket: TcpStream, map_pointer: MapLock, pool: sqlx::SqlitePool) {
-    let (read_socket, mut write_socket) = socket.into_split();
-
-    let mut buffered_reads = BufReader::new(read_socket).lines();
-
-    let mut user_logged_in: Option<i64> = None;
-
+async fn alert_failed(
+    alert: &AlertRequest,
+    reason: &str,
+    req_id: Option<u64>,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    let message = ServerMsg::AlertFailed {
+        symbol: alert.symbol.clone(),
+        direction: alert.direction,
+        threshold: alert.threshold,
+        reason: reason.to_string(),
+    }
+    .to_wire_with_id(req_id);
+    send_data(message, write_socket).await
+}
+
+// Trade failures carry the symbol, so the client knows which order was refused.
+async fn trade_rejected(
+    audit: &ClientAudit,
+    symbol: &str,
+    reason: &str,
+    req_id: Option<u64>,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    audit.record("error", reason);
+    let message = ServerMsg::TradeRejected {
+        symbol: symbol.to_string(),
+        reason: reason.to_string(),
+    }
+    .to_wire_with_id(req_id);
+    send_data(message, write_socket).await
+}
+
+// Refusals (missing shares, a bad quantity, the position limit) become TRADEREJECTED, database
+// failures stay ERR so a retryable ERR_DB_UNAVAILABLE reaches the client as one.
+async fn trade_failed(
+    audit: &ClientAudit,
+    user_id: i64,
+    symbol: &str,
+    error: &str,
+    req_id: Option<u64>,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    if database::is_trade_refusal(error) {
+        warn!("[user: {}] Trade rejected: {}", user_id, error);
+        trade_rejected(audit, symbol, error, req_id, write_socket).await
+    } else {
+        error!("[server-database] Trade on {} failed! {}", symbol, error);
+        client_errors(audit, error, req_id, write_socket).await
+    }
+}
+
+async fn check_quote_of_stock(map_pointer: &MapLock, stock: &str) -> Option<Quote> {
+    let access = map_pointer.read().await;
+
+    access.get(stock).cloned()
+}
+
+async fn check_price_of_stock(map_pointer: &MapLock, stock: &str) -> Option<f64> {
+    check_quote_of_stock(map_pointer, stock)
+        .await
+        .map(|quote| quote.price)
+}
+
+// Trades only go through at a scraped price, a cached one may be days old.
+async fn trade_price_of_stock(map_pointer: &MapLock, stock: &str) -> Option<f64> {
+    check_quote_of_stock(map_pointer, stock)
+        .await
+        .filter(|quote| !quote.cached)
+        .map(|quote| quote.price)
+}
+
+async fn send_data(message: String, write_socket: &mut OwnedWriteHalf) -> io::Result<()> {
+    write_socket.write_all(message.as_bytes()).await?;
+    write_socket.flush().await?;
+
+    Ok(())
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+async fn check_alerts_for_user(
+    audit: &ClientAudit,
+    pool: &SqlitePool,
+    user_id: i64,
+    map_lock: &MapLock,
+    cooldown: Duration,
+    strict_alerts: bool,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    let alerts = match database::get_user_alerts(pool, user_id, strict_alerts).await {
+        Ok(a) => a,
+        Err(e) => {
+            error!("[server-database] Database error! {}", e);
+            return Ok(());
+        }
+    };
+
+    let triggered: Vec<(&database::StoredAlert, f64)> = {
+        let prices = map_lock.read().await;
+
+        alerts
+            .iter()
+            .filter_map(|alert| {
+                let quote = prices.get(&alert.symbol).filter(|quote| !quote.cached)?;
+                let current_price = quote.price;
+                alert_triggered(alert.direction, alert.threshold, current_price)
+                    .then_some((alert, current_price))
+            })
+            .collect()
+    };
+
+    let now = unix_now();
+    for (alert, current_price) in triggered {
+        let cooldown_secs = cooldown.as_secs() as i64;
+        match database::claim_alert_trigger(
+            pool,
+            user_id,
+            &alert.symbol,
+            alert.direction,
+            now,
+            cooldown_secs,
+        )
+        .await
+        {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!("[server-database] Database error! {}", e);
+                continue;
+            }
+        }
+        audit.record(
+            "trigger",
+            format!(
+                "{} {} {} at {}",
+                alert.symbol,
+                alert.direction.as_str(),
+                alert.threshold,
+                current_price
+            ),
+        );
+        let message = ServerMsg::AlertTriggered {
+            symbol: alert.symbol.clone(),
+            direction: alert.direction,
+            threshold: alert.threshold,
+            current_price: Price {
+                value: current_price,
+            },
+        }
+        .to_wire();
+        write_socket.write_all(message.as_bytes()).await?;
+        write_socket.flush().await?;
+    }
+    Ok(())
+}
+
+// A failed section is sent empty and listed in `failed`, only when nothing loads is it an error.
+async fn client_data(
+    pool: &SqlitePool,
+    user_id: i64,
+    offset: usize,
+    limit: usize,
+    strict_alerts: bool,
+) -> Result<ServerMsg, String> {
+    let (stocks, alerts) = tokio::join!(
+        database::get_portfolio_page(pool, user_id, offset, limit),
+        database::get_user_alerts_page(pool, user_id, offset, limit, strict_alerts)
+    );
+
+    let mut failed = Vec::new();
+    let mut first_error = None;
+    let mut note_failure = |name: &str, e: String| {
+        error!("[server-database] Failed to load {}! {}", name, e);
+        failed.push(name.to_string());
+        first_error.get_or_insert(e);
+    };
+    let (stocks, more_stocks) = stocks.unwrap_or_else(|e| {
+        note_failure(DATA_SECTION_PORTFOLIO, e);
+        (Vec::new(), false)
+    });
+    let (alerts, more_alerts) = alerts.unwrap_or_else(|e| {
+        note_failure(DATA_SECTION_ALERTS, e);
+        (Vec::new(), false)
+    });
+
+    match first_error {
+        Some(e) if failed.len() == 2 => Err(e),
+        _ => Ok(ServerMsg::AllClientData {
+            stocks,
+            alerts,
+            has_more: more_stocks || more_alerts,
+            failed,
+        }),
+    }
+}
+
+// Prices cached by the previous run, at most PRICE_CACHE_MAX_AGE old, so portfolios can be
+// valued before the first scrape brings fresh ones. Returns how many were loaded.
+async fn load_cached_prices(pool: &SqlitePool, map_lock: &MapLock) -> Result<usize, String> {
+    let since = unix_now() - PRICE_CACHE_MAX_AGE.as_secs() as i64;
+    let cached = database::cached_prices(pool, since).await?;
+    let mut writer = map_lock.write().await;
+    let mut loaded = 0;
+    for (symbol, price) in cached {
+        if let Entry::Vacant(entry) = writer.entry(symbol) {
+            entry.insert(Quote {
+                price,
+                stats: QuoteStats::default(),
+                name: None,
+                cached: true,
+            });
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+// A scraper that can't run stops the server, unless it may run without prices.
+fn first_scrape(
+    priced: Result<Result<usize, String>, oneshot::error::RecvError>,
+    allow_no_prices: bool,
+) -> Result<()> {
+    let priced = priced.context("[server scrapper] Scraper stopped before the first scrape")?;
+    match priced {
+        Ok(priced) => info!("[server] First scrape done, {} prices available", priced),
+        Err(e) if allow_no_prices => {
+            error!("[server scrapper] {}, continuing without price data", e);
+        }
+        Err(e) => anyhow::bail!("[server scrapper] {e}"),
+    }
+    Ok(())
+}
+
+// Runs after every price update, the fills reach their users on the next alert check.
+async fn run_brackets(pool: &SqlitePool, map_lock: &MapLock) {
+    let prices: HashMap<String, f64> = map_lock
+        .read()
+        .await
+        .iter()
+        .filter(|(_, quote)| !quote.cached)
+        .map(|(symbol, quote)| (symbol.clone(), quote.price))
+        .collect();
+    match database::execute_brackets(pool, &prices).await {
+        Ok(0) => {}
+        Ok(sold) => info!("[server] Brackets sold {} positions", sold),
+        Err(e) => error!("[server-database] Failed to run brackets! {}", e),
+    }
+}
+
+async fn send_bracket_fills(
+    pool: &SqlitePool,
+    user_id: i64,
+    write_socket: &mut OwnedWriteHalf,
+) -> io::Result<()> {
+    let fills = match database::take_bracket_fills(pool, user_id).await {
+        Ok(fills) => fills,
+        Err(e) => {
+            error!("[server-database] Database error! {}", e);
+            return Ok(());
+        }
+    };
+    for fill in fills {
+        let message = ServerMsg::BracketTriggered {
+            symbol: fill.symbol,
+            quantity: fill.quantity,
+            price: fill.price,
+            profit: fill.profit,
+        }
+        .to_wire();
+        send_data(message, write_socket).await?;
+    }
+    Ok(())
+}
+
+// One line without its line ending, None at the end of the stream. At most `max` bytes before
+// the newline are buffered, a longer line is an InvalidData error with ERR_LINE_TOO_LONG.
+async fn read_line_limited<R>(reader: &mut R, max: usize) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    let read = reader
+        .take(max as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    } else if line.len() > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            ERR_LINE_TOO_LONG,
+        ));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Reads lines on their own task and hands them over through a channel of `limit` slots.
+// With the channel full the task stops reading, so unhandled commands stay in the kernel
+// buffers and TCP flow control slows the client down. Ends with the connection or the receiver.
+fn spawn_command_reader<R>(
+    reader: R,
+    limit: usize,
+    max_line: usize,
+) -> mpsc::Receiver<io::Result<String>>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(limit);
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        loop {
+            let read = tokio::select! {
+                read = read_line_limited(&mut reader, max_line) => read,
+                _ = tx.closed() => break,
+            };
+            let command = match read {
+                Ok(Some(line)) => Ok(line),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = command.is_err();
+            if tx.send(command).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+async fn handle_client(
+    socket: TcpStream,
+    map_pointer: MapLock,
+    symbols: SymbolList,
+    pool: sqlx::SqlitePool,
+    seed: Option<Arc<database::UserSeed>>,
+    history_map: HistoryLock,
+    config: ConnectionConfig,
+) {
+    let peer = socket
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    let (read_socket, mut write_socket) = socket.into_split();
+
+    let mut commands =
+        spawn_command_reader(read_socket, config.max_in_flight, config.max_line_bytes());
+
+    let mut user_logged_in: Option<i64> = None;
+    let mut last_seen = Instant::now();
+    let mut audit = ClientAudit {
+        log: config.audit.clone(),
+        peer,
+        user_id: None,
+    };
+    audit.record("connect", "");
+
+    // PING goes out after `heartbeat` of silence, every command starts the wait over.
+    let heartbeat = tokio::time::sleep(config.heartbeat);
+    tokio::pin!(heartbeat);
+    // Alerts and fills are checked on their own clock that commands don't reset, so a client
+    // that keeps talking still gets its pushes.
+    let mut push_tick = tokio::time::interval_at(
+        tokio::time::Instant::now() + config.heartbeat,
+        config.heartbeat,
+    );
+    push_tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
     loop {
         tokio::select! {
-            read_input = buffered_reads.next_line() => {
+            read_input = commands.recv() => {
                 match read_input {
-                    Ok(Some(line)) => {
+                    Some(Ok(line)) => {
+                        let (req_id, parsed) = parse_client_line(&line);
+                        last_seen = Instant::now();
+                        heartbeat.as_mut().reset(tokio::time::Instant::now() + config.heartbeat);
+                        audit.record("command", command_name(&line));
                         if let Some(id) = user_logged_in  {
-                            match parse_client_msg(&line) {
+                            match parsed {
                                 Some(ClientMsg::AddAlert(alert)) => {
                                     info!("[user: {}] Alert Request:  {:?}{}{}", id, alert.direction, alert.symbol, alert.threshold);
-                                    if let Err(e) = prepare_new_alert(&pool, id, &alert, &map_pointer, &mut write_socket).await {
+                                    let result = match check_threshold(alert.threshold, config.max_threshold) {
+                                        Ok(()) => prepare_new_alert(&pool, id, &alert, req_id, &map_pointer, &mut write_socket).await,
+                                        Err(reason) => alert_failed(&alert, &reason, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
                                         error!("[server-database] Failed to add alert to database! {}", e);
                                     }
                                 },
+                                Some(ClientMsg::AddAlerts{alerts}) => {
+                                    info!("[user: {}] Batch alert request: {} alerts", id, alerts.len());
+                                    if alerts.len() > config.max_batch_alerts {
+                                        warn!("[user: {}] Batch of {} alerts is over the limit of {}", id, alerts.len(), config.max_batch_alerts);
+                                        if let Err(e) = client_errors(&audit, ERR_BATCH_TOO_LARGE, req_id, &mut write_socket).await {
+                                            error!("[server] Network error: {}", e);
+                                        }
+                                    }
+                                    // One bad threshold refuses the whole batch, like an oversized one.
+                                    else if let Some(reason) = alerts.iter().find_map(|alert| check_threshold(alert.threshold, config.max_threshold).err()) {
+                                        if let Err(e) = client_errors(&audit, &reason, req_id, &mut write_socket).await {
+                                            error!("[server] Network error: {}", e);
+                                        }
+                                    }
+                                    else if let Err(e) = prepare_new_alerts(&audit, &pool, id, alerts, req_id, &map_pointer, &mut write_socket).await {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
                                 Some(ClientMsg::RemoveAlert{symbol, direction}) => {
                                     info!("[user: {}] Remove Alert: {}{:?}", id, symbol, direction);
                                     if let Err(e) = database::remove_alert(&pool, id, &symbol, direction).await {
                                         error!("[server-database] Failed to remove from database! {}", e);
-                                        if let Err(socket_err) = client_errors(&e, &mut write_socket).await {
+                                        if let Err(socket_err) = client_errors(&audit, &e, req_id, &mut write_socket).await {
                                             error!("[server] Socket error: {}", socket_err);
                                             break;
                                         }
                                     }
-                                    let message = ServerMsg::AlertRemoved{symbol, direction}.to_wire();
-                                    if let Err(e) = send_data(message, &mut write_socket).await {
-                                        error!("[server] Network error: {}", e);
+                                    else {
+                                        let message = ServerMsg::AlertRemoved{symbol, direction}.to_wire_with_id(req_id);
+                                        if let Err(e) = send_data(message, &mut write_socket).await {
+                                            error!("[server] Network error: {}", e);
+                                        }
+                                    }
+                                },
+                                Some(ClientMsg::UpdateAlert{symbol, direction, new_threshold}) => {
+                                    info!("[user: {}] Update Alert: {}{:?} to {}", id, symbol, direction, new_threshold);
+                                    let updated = match check_threshold(new_threshold, config.max_threshold) {
+                                        Ok(()) => database::update_alert(&pool, id, &symbol, direction, new_threshold).await,
+                                        Err(reason) => Err(reason),
+                                    };
+                                    if let Err(e) = updated {
+                                        error!("[server-database] Failed to update the alert! {}", e);
+                                        if let Err(socket_err) = client_errors(&audit, &e, req_id, &mut write_socket).await {
+                                            error!("[server] Socket error: {}", socket_err);
+                                            break;
+                                        }
+                                    }
+                                    else {
+                                        let message = ServerMsg::AlertUpdated{symbol, direction, threshold: new_threshold}.to_wire_with_id(req_id);
+                                        if let Err(e) = send_data(message, &mut write_socket).await {
+                                            error!("[server] Network error: {}", e);
+                                        }
                                     }
                                 },
                                 Some(ClientMsg::LoginClient{username, password: _}) => {
                                     warn!("[user: {}] User already logged-in: {}", id, username);
-                                    if let Err(z) = client_errors("You are arleady logged-in!", &mut write_socket).await {
+                                    if let Err(z) = client_errors(&audit, "You are arleady logged-in!", req_id, &mut write_socket).await {
                                         error!("[server] Network error: {}", z);
                                     }
                                 },
                                 Some(ClientMsg::RegisterClient{username, password: _}) => {
                                     warn!("[user: {}] User already registered: {}", id, username);
-                                    if let Err(z) = client_errors("You are arleady logged-in!", &mut write_socket).await {
+                                    if let Err(z) = client_errors(&audit, "You are arleady logged-in!", req_id, &mut write_socket).await {
                                         error!("[server] Network error: {}", z);
                                     }
                                 },
                                 Some(ClientMsg::CheckPrice{symbol}) => {
                                     info!("[user: {}] Check price: {}", id, symbol);
-                                    if let Err(z) = check_price(&symbol, &map_pointer, &mut write_socket).await {
+                                    let known = symbols.read().await.clone();
+                                    if let Err(z) = check_price(&audit, &symbol, req_id, &map_pointer, &known, &mut write_socket).await {
                                         error!("[server] Network error: {}", z);
                                     }
                                 },
                                 Some(ClientMsg::SellStock{symbol, quantity}) => {
                                     info!("[user: {}] Sell stock: {} {}", id, symbol, quantity);
-                                    if let Some(price) = check_price_of_stock(&map_pointer, &symbol).await {
+                                    if let Some(price) = trade_price_of_stock(&map_pointer, &symbol).await {
                                         if let Err(e) = database::sell_stock(&pool, id, &symbol, quantity, price).await {
-                                            error!("[server-database] Database error! {}", e);
-                                            if let Err(z) = client_errors(&e, &mut write_socket).await {
+                                            if let Err(z) = trade_failed(&audit, id, &symbol, &e, req_id, &mut write_socket).await {
                                                 error!("[server] Network error: {}", z);
                                             }
                                         }
                                         else {
-                                            let message = ServerMsg::StockSold { symbol, quantity }.to_wire();
+                                            let message = ServerMsg::StockSold { symbol, quantity, profit: None }.to_wire_with_id(req_id);
                                             if let Err(e) = send_data(message, &mut write_socket).await {
                                                 error!("[server] Network error: {}", e);
                                             }
                                         }
                                     }
-                                    else if let Err(z) = client_errors("Stock not available!", &mut write_socket).await {
+                                    else if let Err(z) = trade_rejected(&audit, &symbol, ERR_STOCK_NOT_AVAILABLE, req_id, &mut write_socket).await {
                                             error!("[server] Network error: {}", z);
 
                                     }
 
                                 },
+                                Some(ClientMsg::ClosePosition{symbol}) => {
+                                    info!("[user: {}] Close position: {}", id, symbol);
+                                    if let Some(price) = trade_price_of_stock(&map_pointer, &symbol).await {
+                                        match database::close_position(&pool, id, &symbol, price).await {
+                                            Ok((quantity, profit)) => {
+                                                let message = ServerMsg::StockSold { symbol, quantity, profit: Some(profit) }.to_wire_with_id(req_id);
+                                                if let Err(e) = send_data(message, &mut write_socket).await {
+                                                    error!("[server] Network error: {}", e);
+                                                }
+                                            },
+                                            Err(e) => {
+                                                if let Err(z) = trade_failed(&audit, id, &symbol, &e, req_id, &mut write_socket).await {
+                                                    error!("[server] Network error: {}", z);
+                                                }
+                                            }
+                                        }
+                                    }
+                                    else if let Err(z) = trade_rejected(&audit, &symbol, ERR_STOCK_NOT_AVAILABLE, req_id, &mut write_socket).await {
+                                            error!("[server] Network error: {}", z);
+
+                                    }
+                                },
                                 Some(ClientMsg::BuyStock{symbol, quantity}) => {
                                     info!("[user: {}] Buy stock: {} {}", id, symbol, quantity);
-                                    if let Some(price) = check_price_of_stock(&map_pointer, &symbol).await {
-                                        if let Err(e) = database::buy_stock(&pool, id, &symbol, quantity, price).await {
-                                            error!("[server-database] Database error! {}", e);
-                                            if let Err(z) = client_errors(&e, &mut write_socket).await {
+                                    if let Some(price) = trade_price_of_stock(&map_pointer, &symbol).await {
+                                        if let Err(e) = database::buy_stock(&pool, id, &symbol, quantity, price, config.max_positions).await {
+                                            if let Err(z) = trade_failed(&audit, id, &symbol, &e, req_id, &mut write_socket).await {
                                                 error!("[server] Network error: {}", z);
                                             }
                                         }
-
-                                        let message = ServerMsg::StockBought { symbol, quantity }.to_wire();
-                                        if let Err(e) = send_data(message, &mut write_socket).await {
-                                            error!("[server] Network error: {}", e);
+                                        else {
+                                            let message = ServerMsg::StockBought { symbol, quantity }.to_wire_with_id(req_id);
+                                            if let Err(e) = send_data(message, &mut write_socket).await {
+                                                error!("[server] Network error: {}", e);
+                                            }
                                         }
                                     }
-                                    else if let Err(z) = client_errors("Stock not available!", &mut write_socket).await {
+                                    else if let Err(z) = trade_rejected(&audit, &symbol, ERR_STOCK_NOT_AVAILABLE, req_id, &mut write_socket).await {
                                             error!("[server] Network error: {}", z);
 
                                     }
                                 },
-                                Some(ClientMsg::GetAllClientData) => {
-                                    info!("[user: {}] DATA", id);
-                                    let stocks_fut = database::get_portfolio(&pool, id);
-                                    let alerts_fut = database::get_user_alerts(&pool, id);
-
-                                    match tokio::try_join!(stocks_fut, alerts_fut) {
-                                        Ok((stocks, alerts)) => {
-                                            let message = ServerMsg::AllClientData { stocks, alerts }.to_wire();
+                                Some(ClientMsg::GetAllClientData{offset, limit}) => {
+                                    info!("[user: {}] DATA {}", id, offset);
+                                    let limit = page_limit(limit, DEFAULT_PAGE_SIZE);
+                                    match client_data(&pool, id, offset, limit, config.strict_alerts).await {
+                                        Ok(data) => {
+                                            let message = data.to_wire_with_id(req_id);
 
                                             if let Err(e) = send_data(message, &mut write_socket).await {
                                                 error!("[server] Network error: {}", e);
                                             }
                                         },
                                         Err(e) => {
-                                            error!("[server-database] Database error! {}", e);
-                                            if let Err(z) = client_errors(&e, &mut write_socket).await {
+                                            if let Err(z) = client_errors(&audit, &e, req_id, &mut write_socket).await {
                                                 error!("[server] Network error sending error msg: {}", z);
                                             }
                                         }
                                     }
                                 },
+                                Some(ClientMsg::ListSymbols{offset, limit}) => {
+                                    info!("[user: {}] LIST {}", id, offset);
+                                    let known = symbols.read().await.clone();
+                                    let (page, has_more) = symbol_page(&known, offset, page_limit(limit, MAX_PAGE_SIZE));
+                                    let names = symbol_names(&map_pointer, &page).await;
+                                    let message = ServerMsg::SymbolList { symbols: page, names, has_more }.to_wire_with_id(req_id);
+                                    if let Err(e) = send_data(message, &mut write_socket).await {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::GetHistory{symbol, range}) => {
+                                    info!("[user: {}] History: {} {}", id, symbol, range.as_str());
+                                    let points = history_map
+                                        .read()
+                                        .await
+                                        .get(&symbol)
+                                        .map(|points| history_window(points, range));
+                                    let result = match points {
+                                        Some(points) => {
+                                            let message = ServerMsg::History { symbol, points }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        None => {
+                                            let known = symbols.read().await.clone();
+                                            client_errors(&audit, missing_price_error(&known, &symbol), req_id, &mut write_socket).await
+                                        }
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::ResetAccount{password}) => {
+                                    info!("[user: {}] Reset account", id);
+                                    match database::reset_account(&pool, id, &password).await {
+                                        Ok(removed) => {
+                                            info!("[user: {}] Account reset, {} positions removed", id, removed);
+                                            let message = ServerMsg::AccountReset.to_wire_with_id(req_id);
+                                            if let Err(e) = send_data(message, &mut write_socket).await {
+                                                error!("[server] Network error: {}", e);
+                                            }
+                                        },
+                                        Err(e) => {
+                                            warn!("[user: {}] Account reset refused: {}", id, e);
+                                            if let Err(z) = client_errors(&audit, &e, req_id, &mut write_socket).await {
+                                                error!("[server] Network error: {}", z);
+                                            }
+                                        }
+                                    }
+                                },
+                                Some(ClientMsg::GetLeaderboard{limit}) => {
+                                    info!("[user: {}] Leaderboard", id);
+                                    let limit = limit.unwrap_or(DEFAULT_LEADERBOARD_SIZE).min(MAX_LEADERBOARD_SIZE);
+                                    let prices: HashMap<String, f64> = map_pointer
+                                        .read()
+                                        .await
+                                        .iter()
+                                        .map(|(symbol, quote)| (symbol.clone(), quote.price))
+                                        .collect();
+                                    let result = match database::get_leaderboard(&pool, &prices, limit).await {
+                                        Ok(rows) => {
+                                            let entries = leaderboard_for(rows, id, config.leaderboard_names);
+                                            let message = ServerMsg::Leaderboard { entries }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        Err(e) => {
+                                            error!("[server-database] Database error! {}", e);
+                                            client_errors(&audit, &e, req_id, &mut write_socket).await
+                                        }
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::SetBracket{symbol, stop, target}) => {
+                                    info!("[user: {}] Bracket {} stop={:?} target={:?}", id, symbol, stop, target);
+                                    let result = match database::set_bracket(&pool, id, &symbol, stop, target).await {
+                                        Ok(()) => {
+                                            let message = ServerMsg::BracketSet { symbol, stop, target }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::CancelBracket{symbol}) => {
+                                    info!("[user: {}] Cancel bracket {}", id, symbol);
+                                    let result = match database::cancel_bracket(&pool, id, &symbol).await {
+                                        Ok(()) => {
+                                            let message = ServerMsg::BracketCancelled { symbol }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::CancelAllOrders) => {
+                                    info!("[user: {}] Cancel all orders", id);
+                                    let result = match database::cancel_all_orders(&pool, id).await {
+                                        Ok(count) => {
+                                            info!("[user: {}] {} orders cancelled", id, count);
+                                            let message = ServerMsg::OrdersCancelled { count }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::WhoAmI) => {
+                                    let result = match database::get_username(&pool, id).await {
+                                        Ok(username) => {
+                                            let message = ServerMsg::Identity { user_id: id, username }.to_wire_with_id(req_id);
+                                            send_data(message, &mut write_socket).await
+                                        }
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::GetServerTime) => {
+                                    let message = ServerMsg::ServerTime { unix_secs: unix_now() }.to_wire_with_id(req_id);
+                                    if let Err(e) = send_data(message, &mut write_socket).await {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::ExportData) => {
+                                    info!("[user: {}] Data export", id);
+                                    let result = match export_json(&pool, id, config.strict_alerts).await {
+                                        Ok(json) => send_data(ServerMsg::DataExport { json }.to_wire_with_id(req_id), &mut write_socket).await,
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::GetPreferences) => {
+                                    let result = match database::get_preferences(&pool, id).await {
+                                        Ok(prefs) => send_data(ServerMsg::Preferences(prefs).to_wire_with_id(req_id), &mut write_socket).await,
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                Some(ClientMsg::SetPreferences(prefs)) => {
+                                    info!("[user: {}] Preferences: {:?}", id, prefs);
+                                    let result = match database::set_preferences(&pool, id, &prefs).await {
+                                        Ok(()) => send_data(ServerMsg::Preferences(prefs).to_wire_with_id(req_id), &mut write_socket).await,
+                                        Err(e) => client_errors(&audit, &e, req_id, &mut write_socket).await,
+                                    };
+                                    if let Err(e) = result {
+                                        error!("[server] Network error: {}", e);
+                                    }
+                                },
+                                // Only refreshes last_seen.
+                                Some(ClientMsg::Pong) => {},
+                                Some(ClientMsg::Ping { nonce }) => {
+                                    if let Err(e) = send_data(ServerMsg::Heartbeat { nonce }.to_wire_with_id(req_id), &mut write_socket).await {
+                                        error!("[server] Network error: {}", e);
+                                        break;
+                                    }
+                                },
                                 None => {
                                     warn!("[user: {}] Wrong command!", id);
-                                    if let Err(e) = client_errors("Wrong command!", &mut write_socket).await {
+                                    if let Err(e) = client_errors(&audit, "Wrong command!", req_id, &mut write_socket).await {
                                         error!("[server] Network error: {}", e);
                                         break;
                                     }
@@ -389,20 +1918,22 @@ async fn handle_client(socket: TcpStream, map_pointer: MapLock, pool: sqlx::Sqli
                             }
                         }
                         else {
-                            match parse_client_msg(&line) {
+                            match parsed {
                                 Some(ClientMsg::LoginClient{username, password}) => {
                                     info!("New log-in request!");
                                     match database::login_user(&pool, &username, &password).await {
                                         Ok(id) => {
                                             user_logged_in = Some(id);
-                                            let message = ServerMsg::UserLogged.to_wire();
+                                            audit.user_id = Some(id);
+                                            audit.record("login", username);
+                                            let message = ServerMsg::UserLogged.to_wire_with_id(req_id);
                                             if let Err(e) = send_data(message, &mut write_socket).await {
                                                 error!("[server] Network error: {}", e);
                                                 break;
                                             }
                                         },
                                         Err(e) => {
-                                            if let Err(z) = client_errors("Failed to log-in!", &mut write_socket).await {
+                                            if let Err(z) = client_errors(&audit, login_error(&e, ERR_LOGIN_FAILED), req_id, &mut write_socket).await {
                                                 error!("[server] Network error: {}", z);
                                             }
                                             warn!("[server] Failed to log-in the client {}", e);
@@ -412,23 +1943,37 @@ async fn handle_client(socket: TcpStream, map_pointer: MapLock, pool: sqlx::Sqli
                                 Some(ClientMsg::RegisterClient{username, password}) => {
                                     info!("New register request!");
                                     match database::register_user(&pool, &username, &password).await {
-                                        Ok(_) => {
-                                            let message = ServerMsg::UserRegistered.to_wire();
+                                        Ok(new_id) => {
+                                            audit.log.record(Some(new_id), &audit.peer, "register", username.clone());
+                                            if let Some(seed) = &seed
+                                                && let Err(e) = database::seed_user(&pool, new_id, seed).await
+                                            {
+                                                error!("[server-database] Failed to seed new user {}: {}", username, e);
+                                            }
+                                            let message = ServerMsg::UserRegistered.to_wire_with_id(req_id);
                                             if let Err(e) = send_data(message, &mut write_socket).await {
                                                 error!("[server] Network error: {}", e);
                                                 break;
                                             }
                                         },
                                         Err(e) => {
-                                            if let Err(z) = client_errors("Failed to register!", &mut write_socket).await {
+                                            if let Err(z) = client_errors(&audit, login_error(&e, "Failed to register!"), req_id, &mut write_socket).await {
                                                 error!("[server] Network error: {}", z);
                                             }
                                             warn!("[server] Failed to register client {}", e);
                                         }
                                     }
                                 },
+                                Some(ClientMsg::Pong) => {},
+                                // Latency can be measured before logging in too.
+                                Some(ClientMsg::Ping { nonce }) => {
+                                    if let Err(e) = send_data(ServerMsg::Heartbeat { nonce }.to_wire_with_id(req_id), &mut write_socket).await {
+                                        error!("[server] Network error: {}", e);
+                                        break;
+                                    }
+                                },
                                 _ => {
-                                      if let Err(e) = client_errors("User not logged in!", &mut write_socket).await {
+                                      if let Err(e) = client_errors(&audit, ERR_NOT_LOGGED_IN, req_id, &mut write_socket).await {
                                         error!("[server] Network error: {}", e);
                                         break;
                                     }
@@ -436,21 +1981,52 @@ async fn handle_client(socket: TcpStream, map_pointer: MapLock, pool: sqlx::Sqli
                             }
                         }
                     }
-                    Ok(None) => {
+                    None => {
                        info!("[server] Client gracefully disconnected, ending current connection!");
+                       audit.record("disconnect", "closed by client");
                        break;
                     }
-                    Err(e) => {
+                    Some(Err(e)) => {
                         error!("[server] Network error: {}", e);
+                        audit.record("disconnect", format!("network error: {e}"));
+                        if e.kind() == io::ErrorKind::InvalidData && e.to_string() == ERR_LINE_TOO_LONG {
+                            let _ = client_errors(&audit, ERR_LINE_TOO_LONG, None, &mut write_socket).await;
+                        }
                         break;
                     }
                 }
             }
-            _ = tokio::time::sleep(Duration::from_secs(60)) => {
+            _ = &mut heartbeat => {
+                heartbeat.as_mut().reset(tokio::time::Instant::now() + config.heartbeat);
+                if last_seen.elapsed() >= config.idle {
+                    info!("[server] Dropping idle client (user {:?})", user_logged_in);
+                    let _ = client_errors(&audit, ERR_IDLE_TIMEOUT, None, &mut write_socket).await;
+                    let _ = write_socket.shutdown().await;
+                    audit.record("disconnect", "idle");
+                    break;
+                }
+                if let Err(e) = send_data(ServerMsg::Ping.to_wire(), &mut write_socket).await {
+                    error!("[server] Network error: {}", e);
+                    break;
+                }
+            }
+            _ = push_tick.tick() => {
                 info!("[server] Sending alerts to client!");
                 if let Some(uid) = user_logged_in {
+                    // Read every tick so a PREFS from another connection applies here too.
+                    let prefs = database::get_preferences(&pool, uid).await.unwrap_or_else(|e| {
+                        warn!("[user: {}] Using default preferences: {}", uid, e);
+                        Preferences::default()
+                    });
                     info!("[server] Checking alerts for user {}", uid);
-                    if let Err(e) = check_alerts_for_user(&pool, uid, &map_pointer, &mut write_socket).await {
+                    if prefs.triggers
+                        && let Err(e) = check_alerts_for_user(&audit, &pool, uid, &map_pointer, config.alert_cooldown, config.strict_alerts, &mut write_socket).await {
+                        error!("[server] Network error: {}", e);
+                        break;
+                    }
+                    // Fills that aren't wanted stay stored until the user turns them on.
+                    if prefs.fills
+                        && let Err(e) = send_bracket_fills(&pool, uid, &mut write_socket).await {
                         error!("[server] Network error: {}", e);
                         break;
                     }
@@ -461,53 +2037,247 @@ async fn handle_client(socket: TcpStream, map_pointer: MapLock, pool: sqlx::Sqli
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
+async fn export_json(
+    pool: &SqlitePool,
+    user_id: i64,
+    strict_alerts: bool,
+) -> Result<String, String> {
+    let export = database::export_user_data(pool, user_id, strict_alerts).await?;
+    let json = serde_json::to_string(&export).map_err(|e| e.to_string())?;
+    if json.len() > MAX_EXPORT_BYTES {
+        warn!(
+            "[user: {}] Export of {} bytes is over the limit",
+            user_id,
+            json.len()
+        );
+        return Err(ERR_EXPORT_TOO_LARGE.to_string());
+    }
+    Ok(json)
+}
 
-    let db_opts = SqliteConnectOptions::new()
-        .filename("database.db")
-        .create_if_missing(true);
+// Hides other users' names unless the server is configured to show them.
+fn leaderboard_for(
+    rows: Vec<(i64, LeaderboardEntry)>,
+    viewer: i64,
+    show_names: bool,
+) -> Vec<LeaderboardEntry> {
+    rows.into_iter()
+        .map(|(user_id, mut entry)| {
+            if !show_names && user_id != viewer {
+                entry.name = ANONYMOUS_NAME.to_string();
+            }
+            entry
+        })
+        .collect()
+}
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect_with(db_opts)
-        .await
-        .context("[server-database] Failed to connect to the database!")?;
+fn page_limit(requested: Option<usize>, default: usize) -> usize {
+    requested.unwrap_or(default).min(MAX_PAGE_SIZE)
+}
 
-    if let Err(e) = database::init_database(&pool).await {
-        error!("[server-database] Database Init error! {}", e);
-    }
+fn symbol_page(symbols: &[String], offset: usize, limit: usize) -> (Vec<String>, bool) {
+    let page: Vec<String> = symbols.iter().skip(offset).take(limit).cloned().collect();
+    let has_more = offset.saturating_add(page.len()) < symbols.len();
 
-    let stock_symbols = read_all_stocks();
+    (page, has_more)
+}
 
-    let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+// Names are only known for symbols the scraper already fetched.
+async fn symbol_names(map_pointer: &MapLock, page: &[String]) -> HashMap<String, String> {
+    let access = map_pointer.read().await;
+    page.iter()
+        .filter_map(|symbol| {
+            let name = access.get(symbol)?.name.clone()?;
+            Some((symbol.clone(), name))
+        })
+        .collect()
+}
 
-    let stock_map_clone = stock_map.clone();
-    tokio::spawn(async move {
-        if let Err(e) = scrap_stocks(stock_map_clone, stock_symbols).await {
-            error!("[server-scrapper] Scrapper failed {}", e);
+fn admit_client(connection_limit: &Arc<Semaphore>) -> Option<OwnedSemaphorePermit> {
+    connection_limit.clone().try_acquire_owned().ok()
+}
+
+// Tells the client why it is being dropped instead of just closing the socket.
+async fn reject_client(mut socket: TcpStream) {
+    let message = ServerMsg::Error(ERR_SERVER_FULL.to_string()).to_wire();
+    if let Err(e) = socket.write_all(message.as_bytes()).await {
+        warn!("[server] Failed to notify rejected client: {}", e);
+        return;
+    }
+    let _ = socket.flush().await;
+    let _ = socket.shutdown().await;
+}
+
+async fn bind_listener(addr: &str) -> Result<TcpListener> {
+    TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("[server] Failed to bind {addr}"))
+}
+
+// Startup failures are returned with context, Ctrl+C before or after startup exits with 0.
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt().with_env_filter("info").init();
+
+    let scraper_config = ScraperConfig::from_env()?;
+    let scrape_mode = ScrapeMode::from_env()?;
+    let symbols_file = std::env::var(SYMBOLS_FILE_ENV).unwrap_or_else(|_| STOCKS_FILE.to_string());
+    let user_seed = load_user_seed()?;
+    let config = ConnectionConfig::from_env()?;
+    let allow_no_prices = std::env::var(ALLOW_NO_PRICES_ENV).is_ok_and(|v| v.trim() == "1");
+    let proxy = match &scraper_config.proxy {
+        Some(proxy) => {
+            // Don't print proxy credentials to the log.
+            let mut shown = proxy.clone();
+            let _ = shown.set_password(None);
+            shown.to_string()
         }
-    });
+        None => "none".to_string(),
+    };
+    info!(
+        "[server] Config: listen={} db={} symbols={} max_clients={} proxy={} user_agents=\"{}\" seed={} idle_timeout={}s max_batch_alerts={} max_positions={} max_in_flight={} leaderboard_names={} alert_cooldown={}s strict_alerts={} scrape_mode={} allow_no_prices={} audit_log={}",
+        LISTEN_ADDR,
+        DATABASE_FILE,
+        symbols_file,
+        MAX_CLIENTS,
+        proxy,
+        scraper_config.user_agents.join(" | "),
+        if user_seed.is_some() {
+            USER_SEED_ENV
+        } else {
+            "off"
+        },
+        config.idle.as_secs(),
+        config.max_batch_alerts,
+        config.max_positions,
+        config.max_in_flight,
+        config.leaderboard_names,
+        config.alert_cooldown.as_secs(),
+        config.strict_alerts,
+        scrape_mode.as_str(),
+        allow_no_prices,
+        if config.audit.is_enabled() {
+            AUDIT_LOG_ENV
+        } else {
+            "off"
+        },
+    );
 
-    info!("[server] Server runs. Press CTR + C to stop it.");
+    let db_opts = SqliteConnectOptions::new()
+        .filename(DATABASE_FILE)
+        .create_if_missing(true);
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .connect_with(db_opts)
+        .await
+        .context("[server-database] Failed to connect to the database!")?;
 
-    let listener = TcpListener::bind("127.0.0.1:1234")
+    database::init_database(&pool)
         .await
-        .context("[server] Failed to bind")?;
+        .map_err(anyhow::Error::msg)
+        .context("[server-database] Failed to create the schema")?;
+    info!("[server-database] Schema ready");
+
+    let symbols = SymbolsFile::load(&symbols_file)?;
+    let stock_symbols = symbols.list.clone();
+    info!("[server] Loaded {} symbols", symbols.universe.len());
+    #[cfg(unix)]
+    {
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("[server] Failed to listen for SIGHUP")?;
+        let reload = symbols.reload.clone();
+        let path = symbols.path.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("[server] SIGHUP, reloading {}", path);
+                reload.notify_one();
+            }
+        });
+
+        let mut user1 =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+                .context("[server] Failed to listen for SIGUSR1")?;
+        let rescrape = scraper_config.rescrape.clone();
+        tokio::spawn(async move {
+            while user1.recv().await.is_some() {
+                info!("[server] SIGUSR1, rescraping all prices");
+                rescrape.notify_one();
+            }
+        });
+    }
+
+    let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+    let history_map: HistoryLock = Arc::new(RwLock::new(HashMap::new()));
+    let cached = match load_cached_prices(&pool, &stock_map).await {
+        Ok(cached) => {
+            info!("[server-database] Loaded {} cached prices", cached);
+            cached
+        }
+        Err(e) => {
+            error!("[server-database] Failed to load cached prices! {}", e);
+            0
+        }
+    };
+
+    let (first_scrape_tx, mut first_scrape_rx) = oneshot::channel();
+    tokio::spawn(scrap_stocks(
+        stock_map.clone(),
+        history_map.clone(),
+        symbols,
+        scraper_config,
+        scrape_mode,
+        pool.clone(),
+        first_scrape_tx,
+    ));
+
+    // Without cached prices clients are only accepted once the price map has been filled once.
+    let mut first_scrape_done = false;
+    if cached == 0 {
+        info!("[server] Waiting for the first scrape...");
+        tokio::select! {
+            priced = &mut first_scrape_rx => {
+                first_scrape(priced, allow_no_prices)?;
+                first_scrape_done = true;
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    } else {
+        info!("[server] Serving cached prices until the first scrape is done");
+    }
+
+    let listener = bind_listener(LISTEN_ADDR).await?;
+    info!("READY listening on {}", LISTEN_ADDR);
+
+    let connection_limit = Arc::new(Semaphore::new(MAX_CLIENTS));
 
     // Waiting for either new client or closing argument.
     loop {
         tokio::select! {
+            priced = &mut first_scrape_rx, if !first_scrape_done => {
+                first_scrape(priced, allow_no_prices)?;
+                first_scrape_done = true;
+            }
             listener = listener.accept() => {
                 match listener {
                     Ok((socket, addr)) => {
+                        let Some(permit) = admit_client(&connection_limit) else {
+                            warn!("[server] Server at capacity, rejecting: {}", addr);
+                            tokio::spawn(reject_client(socket));
+                            continue;
+                        };
+
                         info!("[server] New connection from: {}", addr);
                         let stock_map_client_clone = stock_map.clone();
+                        let symbols_client = stock_symbols.clone();
                         let pool_client = pool.clone();
+                        let seed_client = user_seed.clone();
+                        let history_client = history_map.clone();
+                        let config_client = config.clone();
 
                         tokio::spawn(async move {
-                            handle_client(socket, stock_map_client_clone, pool_client).await;
+                            handle_client(socket, stock_map_client_clone, symbols_client, pool_client, seed_client, history_client, config_client).await;
+                            drop(permit);
                         });
                     }
                     Err(_) => warn!("[server] Invlid incoming connection!")
@@ -520,3 +2290,1970 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::Lines;
+    use tokio::net::tcp::OwnedReadHalf;
+
+    impl ConnectionConfig {
+        // The server's defaults, tests change what they exercise with struct update syntax.
+        fn for_tests() -> Self {
+            Self {
+                heartbeat: HEARTBEAT_INTERVAL,
+                idle: DEFAULT_IDLE_TIMEOUT,
+                max_batch_alerts: DEFAULT_MAX_BATCH_ALERTS,
+                leaderboard_names: false,
+                alert_cooldown: DEFAULT_ALERT_COOLDOWN,
+                max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+                max_threshold: DEFAULT_MAX_THRESHOLD,
+                max_positions: database::DEFAULT_MAX_POSITIONS,
+                strict_alerts: false,
+                audit: AuditLog::default(),
+            }
+        }
+
+        // Alerts and fills are pushed within a few tens of milliseconds, nobody goes idle.
+        fn fast_heartbeat() -> Self {
+            Self {
+                heartbeat: Duration::from_millis(50),
+                idle: Duration::from_secs(60),
+                ..Self::for_tests()
+            }
+        }
+    }
+
+    // A live price without stats or a name.
+    fn quote(price: f64) -> Quote {
+        Quote {
+            price,
+            stats: QuoteStats::default(),
+            name: None,
+            cached: false,
+        }
+    }
+
+    // Registers `username` with password hunter2 and logs in, skipping heartbeat PINGs.
+    async fn login_as(
+        write: &mut OwnedWriteHalf,
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+        username: &str,
+    ) {
+        for (msg, reply) in [
+            (
+                ClientMsg::RegisterClient {
+                    username: username.into(),
+                    password: "hunter2".into(),
+                },
+                ServerMsg::UserRegistered,
+            ),
+            (
+                ClientMsg::LoginClient {
+                    username: username.into(),
+                    password: "hunter2".into(),
+                },
+                ServerMsg::UserLogged,
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = loop {
+                let line = lines.next_line().await.unwrap().unwrap();
+                if line != "PING" {
+                    break line;
+                }
+            };
+            assert_eq!(line, reply.to_wire().trim_end());
+        }
+    }
+
+    // Starts handle_client on one end of a local socket with short timeouts and returns the other end.
+    async fn connect_with_config(config: ConnectionConfig) -> TcpStream {
+        connect_to(
+            config,
+            Arc::new(RwLock::new(HashMap::new())),
+            memory_pool().await,
+        )
+        .await
+    }
+
+    // One connection, every new one would open its own empty in-memory database.
+    async fn memory_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        database::init_database(&pool).await.unwrap();
+        pool
+    }
+
+    async fn connect_to(config: ConnectionConfig, prices: MapLock, pool: SqlitePool) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server_side, _) = listener.accept().await.unwrap();
+
+        tokio::spawn(handle_client(
+            server_side,
+            prices,
+            Arc::new(RwLock::new(Arc::new(Vec::new()))),
+            pool,
+            None,
+            Arc::new(RwLock::new(HashMap::new())),
+            config,
+        ));
+        client
+    }
+
+    #[tokio::test]
+    async fn idle_client_is_dropped_unless_it_answers_ping() {
+        let config = ConnectionConfig {
+            idle: Duration::from_millis(300),
+            ..ConnectionConfig::fast_heartbeat()
+        };
+
+        // Never answers, gets pinged and then dropped with a reason.
+        let silent = connect_with_config(config.clone()).await;
+        let mut lines = BufReader::new(silent).lines();
+        let mut pings = 0;
+        let last = loop {
+            match lines.next_line().await.unwrap() {
+                Some(line) if line == "PING" => pings += 1,
+                other => break other,
+            }
+        };
+        assert!(pings > 0);
+        assert_eq!(last, Some(format!("ERR {ERR_IDLE_TIMEOUT}")));
+        assert_eq!(lines.next_line().await.unwrap(), None);
+
+        // Answers every PING, so it outlives the idle timeout several times over.
+        let (read, mut write) = connect_with_config(config.clone()).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let started = Instant::now();
+        while started.elapsed() < config.idle * 3 {
+            let line = lines.next_line().await.unwrap();
+            assert_eq!(line.as_deref(), Some("PING"));
+            write
+                .write_all(ClientMsg::Pong.to_wire().as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_alert_batch_is_rejected() {
+        let config = ConnectionConfig {
+            heartbeat: Duration::from_secs(60),
+            idle: Duration::from_secs(60),
+            max_batch_alerts: 2,
+            ..ConnectionConfig::for_tests()
+        };
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "bob").await;
+        let alert = |symbol: &str| AlertRequest {
+            symbol: symbol.into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        };
+
+        for (msg, reply) in [
+            (
+                ClientMsg::AddAlerts {
+                    alerts: vec![alert("AAPL"), alert("MSFT"), alert("TSLA")],
+                },
+                ServerMsg::Error(ERR_BATCH_TOO_LARGE.into()).to_wire(),
+            ),
+            // At the limit it goes through, nothing is priced in this test.
+            (
+                ClientMsg::AddAlerts {
+                    alerts: vec![alert("AAPL"), alert("MSFT")],
+                },
+                ServerMsg::AlertsAdded {
+                    added: 2,
+                    skipped: 0,
+                    unpriced: 2,
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap();
+            assert_eq!(line.as_deref(), Some(reply.trim_end()));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversell_is_rejected_with_the_symbol() {
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(100.0),
+        )])));
+        let config = ConnectionConfig {
+            heartbeat: Duration::from_secs(60),
+            idle: Duration::from_secs(60),
+            ..ConnectionConfig::for_tests()
+        };
+        let (read, mut write) = connect_to(config, prices, memory_pool().await)
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "seller").await;
+
+        for (msg, reply) in [
+            (
+                ClientMsg::BuyStock {
+                    symbol: "AAPL".into(),
+                    quantity: 2,
+                },
+                ServerMsg::StockBought {
+                    symbol: "AAPL".into(),
+                    quantity: 2,
+                }
+                .to_wire(),
+            ),
+            (
+                ClientMsg::SellStock {
+                    symbol: "AAPL".into(),
+                    quantity: 5,
+                },
+                ServerMsg::TradeRejected {
+                    symbol: "AAPL".into(),
+                    reason: "You have only 2 actions of given stock!.".into(),
+                }
+                .to_wire(),
+            ),
+            (
+                ClientMsg::BuyStock {
+                    symbol: "ZZZ".into(),
+                    quantity: 1,
+                },
+                ServerMsg::TradeRejected {
+                    symbol: "ZZZ".into(),
+                    reason: ERR_STOCK_NOT_AVAILABLE.into(),
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap();
+            assert_eq!(line.as_deref(), Some(reply.trim_end()));
+        }
+    }
+
+    #[tokio::test]
+    async fn bracket_fill_reaches_the_user() {
+        let config = ConnectionConfig::fast_heartbeat();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(100.0),
+        )])));
+        let pool = memory_pool().await;
+        let (read, mut write) = connect_to(config, prices.clone(), pool.clone())
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "bob").await;
+        // Skips the heartbeat PINGs that arrive in between.
+        let mut next_reply = async || loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line != "PING" {
+                return line;
+            }
+        };
+
+        for (msg, reply) in [
+            (
+                ClientMsg::BuyStock {
+                    symbol: "AAPL".into(),
+                    quantity: 10,
+                },
+                ServerMsg::StockBought {
+                    symbol: "AAPL".into(),
+                    quantity: 10,
+                }
+                .to_wire(),
+            ),
+            (
+                ClientMsg::SetBracket {
+                    symbol: "AAPL".into(),
+                    stop: Some(90.0),
+                    target: None,
+                },
+                ServerMsg::BracketSet {
+                    symbol: "AAPL".into(),
+                    stop: Some(90.0),
+                    target: None,
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            assert_eq!(next_reply().await, reply.trim_end());
+        }
+
+        // Above the stop nothing happens, then the price drops through it.
+        run_brackets(&pool, &prices).await;
+        prices.write().await.insert("AAPL".into(), quote(85.0));
+        run_brackets(&pool, &prices).await;
+
+        // Fills aren't pushed by default, the fill waits through a few heartbeats.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for (msg, prefs) in [
+            (ClientMsg::GetPreferences, Preferences::default()),
+            (
+                ClientMsg::SetPreferences(Preferences {
+                    triggers: true,
+                    fills: true,
+                }),
+                Preferences {
+                    triggers: true,
+                    fills: true,
+                },
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let reply = ServerMsg::Preferences(prefs).to_wire();
+            assert_eq!(next_reply().await, reply.trim_end());
+        }
+
+        let expected = ServerMsg::BracketTriggered {
+            symbol: "AAPL".into(),
+            quantity: 10,
+            price: 85.0,
+            profit: -150.0,
+        }
+        .to_wire();
+        assert_eq!(next_reply().await, expected.trim_end());
+        assert!(database::get_portfolio(&pool, 1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn oscillating_price_triggers_once_within_cooldown() {
+        let config = ConnectionConfig::fast_heartbeat();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(95.0),
+        )])));
+        let (read, mut write) = connect_to(config, prices.clone(), memory_pool().await)
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "bob").await;
+
+        let msg = ClientMsg::AddAlert(AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        });
+        write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+        // Password hashing is slow in debug builds, prices only move once the alert is set.
+        while lines.next_line().await.unwrap().unwrap() == "PING" {}
+
+        // Up, down and up again, each price held for a few heartbeats.
+        let mover = tokio::spawn(async move {
+            for price in [105.0, 95.0, 105.0] {
+                prices.write().await.insert("AAPL".into(), quote(price));
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        });
+
+        let mut triggers = 0;
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(800);
+        while let Ok(line) = tokio::time::timeout_at(deadline, lines.next_line()).await {
+            if line.unwrap().unwrap().starts_with("TRIGGER ") {
+                triggers += 1;
+            }
+        }
+        mover.await.unwrap();
+        assert_eq!(triggers, 1);
+    }
+
+    #[tokio::test]
+    async fn same_alert_triggers_for_every_watching_client() {
+        let config = ConnectionConfig::fast_heartbeat();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(195.0),
+        )])));
+        let pool = memory_pool().await;
+
+        // Both clients share the prices and the database, each with its own account.
+        let mut clients = Vec::new();
+        for username in ["alice", "carol"] {
+            let (read, mut write) = connect_to(config.clone(), prices.clone(), pool.clone())
+                .await
+                .into_split();
+            let mut lines = BufReader::new(read).lines();
+            login_as(&mut write, &mut lines, username).await;
+            let msg = ClientMsg::AddAlert(AlertRequest {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+            });
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            while lines.next_line().await.unwrap().unwrap() == "PING" {}
+            clients.push((lines, write));
+        }
+
+        prices.write().await.insert("AAPL".into(), quote(205.0));
+
+        let expected = ServerMsg::AlertTriggered {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 200.0,
+            current_price: Price { value: 205.0 },
+        }
+        .to_wire();
+        let watchers = clients.into_iter().map(|(mut lines, write)| {
+            let expected = expected.clone();
+            tokio::spawn(async move {
+                let mut triggers = Vec::new();
+                let deadline = tokio::time::Instant::now() + Duration::from_millis(600);
+                while let Ok(line) = tokio::time::timeout_at(deadline, lines.next_line()).await {
+                    let line = line.unwrap().unwrap();
+                    if line.starts_with("TRIGGER ") {
+                        triggers.push(line);
+                    }
+                }
+                drop(write);
+                assert_eq!(triggers, vec![expected.trim_end().to_string()]);
+            })
+        });
+        for watcher in watchers.collect::<Vec<_>>() {
+            watcher.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn busy_client_still_gets_triggers() {
+        let config = ConnectionConfig::fast_heartbeat();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(205.0),
+        )])));
+        let (read, mut write) = connect_to(config, prices, memory_pool().await)
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "henry").await;
+
+        let msg = ClientMsg::AddAlert(AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 200.0,
+        });
+        write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+
+        // Like the GUI's latency probe, only faster than the heartbeat: the server never goes
+        // quiet long enough for its heartbeat sleep to run out.
+        let pinger = tokio::spawn(async move {
+            for nonce in 0.. {
+                let line = format!("PING {nonce}\n");
+                if write.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+        let mut triggered = false;
+        while let Ok(line) = tokio::time::timeout_at(deadline, lines.next_line()).await {
+            if line.unwrap().unwrap().starts_with("TRIGGER AAPL ABOVE 200") {
+                triggered = true;
+                break;
+            }
+        }
+        pinger.abort();
+        assert!(triggered);
+    }
+
+    #[tokio::test]
+    async fn alert_add_warns_about_missing_price_and_immediate_trigger() {
+        let config = ConnectionConfig::for_tests();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(105.0),
+        )])));
+        let pool = memory_pool().await;
+        let (read, mut write) = connect_to(config, prices, pool.clone()).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "dave").await;
+
+        for (msg, reply) in [
+            (
+                ClientMsg::AddAlert(AlertRequest {
+                    symbol: "NEWCO".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 5.0,
+                }),
+                ServerMsg::AlertAdded {
+                    symbol: "NEWCO".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 5.0,
+                    price_available: false,
+                    triggers_now: false,
+                }
+                .to_wire(),
+            ),
+            // Already past the threshold, added anyway with a warning instead of a TRIGGER.
+            (
+                ClientMsg::AddAlert(AlertRequest {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 100.0,
+                }),
+                ServerMsg::AlertAdded {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 100.0,
+                    price_available: true,
+                    triggers_now: true,
+                }
+                .to_wire(),
+            ),
+            (
+                ClientMsg::AddAlert(AlertRequest {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Below,
+                    threshold: 100.0,
+                }),
+                ServerMsg::AlertAdded {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Below,
+                    threshold: 100.0,
+                    price_available: true,
+                    triggers_now: false,
+                }
+                .to_wire(),
+            ),
+            // ADDMANY keeps unpriced symbols the same way. NEWCO ABOVE 5 is already there, so
+            // it is skipped and not counted as unpriced.
+            (
+                ClientMsg::AddAlerts {
+                    alerts: vec![
+                        AlertRequest {
+                            symbol: "NEWCO".into(),
+                            direction: AlertDirection::Above,
+                            threshold: 5.0,
+                        },
+                        AlertRequest {
+                            symbol: "OTHERCO".into(),
+                            direction: AlertDirection::Below,
+                            threshold: 2.0,
+                        },
+                        AlertRequest {
+                            symbol: "AAPL".into(),
+                            direction: AlertDirection::Below,
+                            threshold: 90.0,
+                        },
+                    ],
+                },
+                ServerMsg::AlertsAdded {
+                    added: 2,
+                    skipped: 1,
+                    unpriced: 1,
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, reply.trim_end());
+        }
+
+        let alerts = database::get_user_alerts(&pool, 1, false).await.unwrap();
+        assert_eq!(alerts.len(), 4);
+        assert!(alerts.iter().any(|alert| alert.symbol == "OTHERCO"));
+    }
+
+    #[tokio::test]
+    async fn latency_probe_echoes_the_nonce() {
+        let config = ConnectionConfig::for_tests();
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        for (line, reply) in [
+            (
+                "#1 PING 18446744073709551615",
+                "#1 PONG 18446744073709551615",
+            ),
+            ("REGISTER gina hunter2", "REGISTER"),
+            ("LOGIN gina hunter2", "LOGIN"),
+            ("PING 1700000000123", "PONG 1700000000123"),
+        ] {
+            write
+                .write_all(format!("{line}\n").as_bytes())
+                .await
+                .unwrap();
+            let got = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(got, reply, "reply to {line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_thresholds_are_refused() {
+        let config = ConnectionConfig {
+            idle: Duration::from_secs(60),
+            max_threshold: 1000.0,
+            ..ConnectionConfig::for_tests()
+        };
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(105.0),
+        )])));
+        let pool = memory_pool().await;
+        let (read, mut write) = connect_to(config, prices, pool.clone()).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let refused = |threshold: f64, reason: &str| {
+            ServerMsg::AlertFailed {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold,
+                reason: reason.into(),
+            }
+            .to_wire()
+        };
+        let not_finite = rust_huge_project::protocol::ERR_THRESHOLD_NOT_FINITE;
+        let out_of_range = "threshold out of range, the limit is 1000";
+
+        for (line, reply) in [
+            (
+                "REGISTER frank hunter2",
+                ServerMsg::UserRegistered.to_wire(),
+            ),
+            ("LOGIN frank hunter2", ServerMsg::UserLogged.to_wire()),
+            ("ADD AAPL ABOVE inf", refused(f64::INFINITY, not_finite)),
+            ("ADD AAPL ABOVE NaN", refused(f64::NAN, not_finite)),
+            ("ADD AAPL ABOVE 1e308", refused(1e308, out_of_range)),
+            (
+                "ADDMANY AAPL ABOVE 100 AAPL BELOW 1e308",
+                ServerMsg::Error(out_of_range.into()).to_wire(),
+            ),
+            (
+                "ADD AAPL ABOVE 1000",
+                ServerMsg::AlertAdded {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 1000.0,
+                    price_available: true,
+                    triggers_now: false,
+                }
+                .to_wire(),
+            ),
+            (
+                "UPDATE AAPL ABOVE -inf",
+                ServerMsg::Error(not_finite.into()).to_wire(),
+            ),
+        ] {
+            write
+                .write_all(format!("{line}\n").as_bytes())
+                .await
+                .unwrap();
+            let got = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(got, reply.trim_end(), "reply to {line}");
+        }
+
+        let alerts = database::get_user_alerts(&pool, 1, false).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].threshold, 1000.0);
+    }
+
+    #[tokio::test]
+    async fn update_moves_the_threshold_of_an_existing_alert() {
+        let config = ConnectionConfig::fast_heartbeat();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(105.0),
+        )])));
+        let (read, mut write) = connect_to(config, prices, memory_pool().await)
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "erin").await;
+
+        for (msg, reply) in [
+            (
+                ClientMsg::UpdateAlert {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    new_threshold: 100.0,
+                },
+                ServerMsg::Error(database::ERR_ALERT_NOT_FOUND.into()).to_wire(),
+            ),
+            (
+                ClientMsg::AddAlert(AlertRequest {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 110.0,
+                }),
+                ServerMsg::AlertAdded {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 110.0,
+                    price_available: true,
+                    triggers_now: false,
+                }
+                .to_wire(),
+            ),
+            (
+                ClientMsg::UpdateAlert {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    new_threshold: 100.0,
+                },
+                ServerMsg::AlertUpdated {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 100.0,
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let mut line = lines.next_line().await.unwrap().unwrap();
+            while line == "PING" {
+                line = lines.next_line().await.unwrap().unwrap();
+            }
+            assert_eq!(line, reply.trim_end());
+        }
+
+        // 105 is past the new threshold, the next tick fires it.
+        let trigger = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let line = lines.next_line().await.unwrap().unwrap();
+                if line.starts_with("TRIGGER ") {
+                    return line;
+                }
+            }
+        })
+        .await
+        .expect("no TRIGGER after the update");
+        match rust_huge_project::protocol::parse_server_msg(&trigger) {
+            Some(ServerMsg::AlertTriggered { threshold, .. }) => assert_eq!(threshold, 100.0),
+            other => panic!("unexpected trigger line {trigger}: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn export_sends_the_account_as_one_json_line() {
+        let config = ConnectionConfig::for_tests();
+        let pool = memory_pool().await;
+        let (read, mut write) =
+            connect_to(config, Arc::new(RwLock::new(HashMap::new())), pool.clone())
+                .await
+                .into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "gina").await;
+
+        database::buy_stock(&pool, 1, "AAPL", 2, 150.0, database::DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+
+        write.write_all(b"#5 EXPORT\n").await.unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        let (req_id, reply) = rust_huge_project::protocol::parse_server_line(&line);
+        assert_eq!(req_id, Some(5));
+        let Some(ServerMsg::DataExport { json }) = reply else {
+            panic!("expected EXPORT, got {line}");
+        };
+        let export: database::UserExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            export,
+            database::export_user_data(&pool, 1, false).await.unwrap()
+        );
+        assert_eq!(export.username, "gina");
+        assert_eq!(export.positions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn whoami_needs_a_login() {
+        let config = ConnectionConfig::for_tests();
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        for (msg, reply) in [
+            (
+                ClientMsg::WhoAmI,
+                ServerMsg::Error(ERR_NOT_LOGGED_IN.into()).to_wire(),
+            ),
+            (
+                ClientMsg::RegisterClient {
+                    username: "carol".into(),
+                    password: "hunter2".into(),
+                },
+                ServerMsg::UserRegistered.to_wire(),
+            ),
+            (
+                ClientMsg::LoginClient {
+                    username: "carol".into(),
+                    password: "hunter2".into(),
+                },
+                ServerMsg::UserLogged.to_wire(),
+            ),
+            (
+                ClientMsg::WhoAmI,
+                ServerMsg::Identity {
+                    user_id: 1,
+                    username: "carol".into(),
+                }
+                .to_wire(),
+            ),
+        ] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, reply.trim_end());
+        }
+    }
+
+    #[tokio::test]
+    async fn server_time_follows_the_clock() {
+        let config = ConnectionConfig::for_tests();
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+        login_as(&mut write, &mut lines, "dave").await;
+
+        let before = unix_now();
+        write
+            .write_all(ClientMsg::GetServerTime.to_wire_with_id(Some(9)).as_bytes())
+            .await
+            .unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        let after = unix_now();
+        match rust_huge_project::protocol::parse_server_line(&line) {
+            (Some(9), Some(ServerMsg::ServerTime { unix_secs })) => {
+                assert!(
+                    (before - 2..=after + 2).contains(&unix_secs),
+                    "{unix_secs} not within {before}..={after}"
+                );
+            }
+            other => panic!("unexpected reply: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn check_price_before_login_is_rejected() {
+        let config = ConnectionConfig::for_tests();
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        let check = ClientMsg::CheckPrice {
+            symbol: "AAPL".into(),
+        };
+        write
+            .write_all(check.to_wire_with_id(Some(3)).as_bytes())
+            .await
+            .unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, "#3 ERR login required");
+
+        // The connection stays open for the login.
+        let register = ClientMsg::RegisterClient {
+            username: "dave".into(),
+            password: "hunter2".into(),
+        };
+        write
+            .write_all(register.to_wire().as_bytes())
+            .await
+            .unwrap();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, ServerMsg::UserRegistered.to_wire().trim_end());
+    }
+
+    #[tokio::test]
+    async fn data_is_partial_when_one_section_fails() {
+        let pool = memory_pool().await;
+        let user_id = database::register_user(&pool, "bob", "hunter2")
+            .await
+            .unwrap();
+        database::buy_stock(
+            &pool,
+            user_id,
+            "AAPL",
+            2,
+            100.0,
+            database::DEFAULT_MAX_POSITIONS,
+        )
+        .await
+        .unwrap();
+
+        // Breaks only the alerts query.
+        sqlx::query("DROP TABLE alerts")
+            .execute(&pool)
+            .await
+            .unwrap();
+        match client_data(&pool, user_id, 0, DEFAULT_PAGE_SIZE, false).await {
+            Ok(ServerMsg::AllClientData {
+                stocks,
+                alerts,
+                has_more,
+                failed,
+            }) => {
+                assert_eq!(stocks.len(), 1);
+                assert_eq!(stocks[0].symbol, "AAPL");
+                assert!(alerts.is_empty());
+                assert!(!has_more);
+                assert_eq!(failed, [DATA_SECTION_ALERTS]);
+            }
+            other => panic!("expected a partial snapshot, got {other:?}"),
+        }
+
+        // With nothing left to send the client gets an error.
+        sqlx::query("DROP TABLE positions")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            client_data(&pool, user_id, 0, DEFAULT_PAGE_SIZE, false)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn client_over_limit_gets_capacity_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connection_limit = Arc::new(Semaphore::new(2));
+
+        let mut admitted = Vec::new();
+        for _ in 0..2 {
+            let client = TcpStream::connect(addr).await.unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            let permit = admit_client(&connection_limit).expect("client within the limit");
+            admitted.push((client, socket, permit));
+        }
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        assert!(admit_client(&connection_limit).is_none());
+        reject_client(socket).await;
+
+        let mut lines = BufReader::new(client).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, format!("ERR {ERR_SERVER_FULL}"));
+        assert!(lines.next_line().await.unwrap().is_none());
+
+        admitted.pop();
+        assert!(admit_client(&connection_limit).is_some());
+    }
+
+    #[test]
+    fn symbol_pages_are_bounded() {
+        let symbols: Vec<String> = (0..MAX_PAGE_SIZE + 10).map(|i| format!("S{i}")).collect();
+        let limit = page_limit(Some(10_000), DEFAULT_PAGE_SIZE);
+        assert_eq!(limit, MAX_PAGE_SIZE);
+        assert_eq!(page_limit(None, DEFAULT_PAGE_SIZE), DEFAULT_PAGE_SIZE);
+
+        let (first, has_more) = symbol_page(&symbols, 0, limit);
+        assert_eq!(first.len(), MAX_PAGE_SIZE);
+        assert!(has_more);
+
+        let (second, has_more) = symbol_page(&symbols, first.len(), limit);
+        assert_eq!(second, symbols[MAX_PAGE_SIZE..]);
+        assert!(!has_more);
+
+        let (past_end, has_more) = symbol_page(&symbols, usize::MAX, limit);
+        assert!(past_end.is_empty());
+        assert!(!has_more);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn slow_client_does_not_block_prices() {
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(100.0),
+        )])));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // This client never reads, so writes to it eventually block.
+        let _slow_client = TcpStream::connect(addr).await.unwrap();
+        let (slow_socket, _) = listener.accept().await.unwrap();
+        let (_slow_read, mut slow_write) = slow_socket.into_split();
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let slow_task = {
+            let stock_map = stock_map.clone();
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                loop {
+                    check_price(
+                        &ClientAudit::default(),
+                        "AAPL",
+                        None,
+                        &stock_map,
+                        &[],
+                        &mut slow_write,
+                    )
+                    .await
+                    .unwrap();
+                    sent.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+
+        // Wait until the socket buffers are full and the slow writer is stuck.
+        let mut last_sent = usize::MAX;
+        loop {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let now_sent = sent.load(Ordering::Relaxed);
+            if now_sent == last_sent {
+                break;
+            }
+            last_sent = now_sent;
+        }
+
+        let writer = tokio::time::timeout(Duration::from_secs(1), stock_map.write())
+            .await
+            .expect("price lock is held by a slow client");
+        drop(writer);
+
+        let fast_client = TcpStream::connect(addr).await.unwrap();
+        let (fast_socket, _) = listener.accept().await.unwrap();
+        let (_fast_read, mut fast_write) = fast_socket.into_split();
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            check_price(
+                &ClientAudit::default(),
+                "AAPL",
+                None,
+                &stock_map,
+                &[],
+                &mut fast_write,
+            ),
+        )
+        .await
+        .expect("fast client waited for a slow one")
+        .unwrap();
+
+        let mut lines = BufReader::new(fast_client).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, "PRICE AAPL 100");
+
+        slow_task.abort();
+    }
+
+    #[test]
+    fn scraper_config_validates_proxy() {
+        let config = ScraperConfig::new(None, None).unwrap();
+        assert_eq!(config.user_agents, [DEFAULT_USER_AGENT]);
+        assert!(config.proxy.is_none());
+
+        let config = ScraperConfig::new(
+            Some("my-agent/1.0".into()),
+            Some("http://proxy.local:8080".into()),
+        )
+        .unwrap();
+        assert_eq!(config.user_agents, ["my-agent/1.0"]);
+        assert_eq!(
+            config.proxy.as_ref().map(|p| p.as_str()),
+            Some("http://proxy.local:8080/")
+        );
+        assert!(config.build_client().is_ok());
+
+        assert!(
+            ScraperConfig::new(None, Some("  ".into()))
+                .unwrap()
+                .proxy
+                .is_none()
+        );
+        assert!(ScraperConfig::new(None, Some("not a url".into())).is_err());
+        assert!(ScraperConfig::new(None, Some("ftp://proxy.local".into())).is_err());
+    }
+
+    #[tokio::test]
+    async fn scraper_rotates_user_agents() {
+        let config =
+            ScraperConfig::new(Some(" agent-a/1.0 | agent-b/2.0 ||".into()), None).unwrap();
+        assert_eq!(config.user_agents, ["agent-a/1.0", "agent-b/2.0"]);
+
+        // Answers every request with an empty 200 and reports its User-Agent.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/chart", listener.local_addr().unwrap());
+        let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                let seen_tx = seen_tx.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = socket.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if line.is_empty() {
+                            write
+                                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                                .await
+                                .unwrap();
+                        } else if let Some((name, value)) = line.split_once(':')
+                            && name.eq_ignore_ascii_case("user-agent")
+                        {
+                            seen_tx.send(value.trim().to_string()).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let client = config.build_client().unwrap();
+        let mut rotation = config.rotation();
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            let response = fetch_chart(&client, &url, rotation.next_agent())
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+            seen.push(seen_rx.recv().await.unwrap());
+        }
+        assert_eq!(
+            seen,
+            ["agent-a/1.0", "agent-b/2.0", "agent-a/1.0", "agent-b/2.0"]
+        );
+
+        let mut single = ScraperConfig::new(None, None).unwrap().rotation();
+        assert_eq!(single.next_agent(), DEFAULT_USER_AGENT);
+        assert_eq!(single.next_agent(), DEFAULT_USER_AGENT);
+    }
+
+    #[test]
+    fn audit_log_rotates_past_the_size_limit() {
+        let path = std::env::temp_dir().join(format!("{}-audit-rotate.jsonl", std::process::id()));
+        let rotated = PathBuf::from(format!("{}.1", path.display()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&rotated);
+
+        let mut writer = AuditWriter::open(&path, 20).unwrap();
+        writer.append("first line").unwrap();
+        writer.append("second line").unwrap();
+        writer.append("third").unwrap();
+
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "first line\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second line\nthird\n");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+    }
+
+    #[tokio::test]
+    async fn audit_log_records_a_session_without_passwords() {
+        let path = std::env::temp_dir().join(format!("{}-audit-session.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ConnectionConfig {
+            audit: AuditLog::open(&path, DEFAULT_AUDIT_LOG_MAX_BYTES).unwrap(),
+            ..ConnectionConfig::for_tests()
+        };
+        let (read, mut write) = connect_with_config(config).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        for msg in [
+            "REGISTER frank hunter2\n",
+            "#4 LOGIN frank hunter2\n",
+            "PRICE NOPE\n",
+        ] {
+            write.write_all(msg.as_bytes()).await.unwrap();
+            lines.next_line().await.unwrap().unwrap();
+        }
+        drop(write);
+        assert_eq!(lines.next_line().await.unwrap(), None);
+
+        // The writer task runs behind the connection, wait for the last record.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let contents = loop {
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            if contents.contains("\"disconnect\"") || Instant::now() > deadline {
+                break contents;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        fs::remove_file(&path).unwrap();
+        assert!(!contents.contains("hunter2"), "{contents}");
+
+        let records: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<(&str, &str, Option<i64>)> = records
+            .iter()
+            .map(|r| {
+                (
+                    r["event"].as_str().unwrap(),
+                    r["detail"].as_str().unwrap(),
+                    r["user_id"].as_i64(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("connect", "", None),
+                ("command", "REGISTER", None),
+                ("register", "frank", Some(1)),
+                ("command", "LOGIN", None),
+                ("login", "frank", Some(1)),
+                ("command", "PRICE", Some(1)),
+                ("error", ERR_UNKNOWN_SYMBOL, Some(1)),
+                ("disconnect", "closed by client", Some(1)),
+            ]
+        );
+        assert!(
+            records
+                .iter()
+                .all(|r| r["ts"].is_string() && r["peer"].is_string())
+        );
+    }
+
+    fn write_symbols_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn symbols_load_from_csv_with_names() {
+        let path = write_symbols_file(
+            "symbols.csv",
+            "symbol,name,exchange\nAAPL,Apple Inc.,NASDAQ\n\"TSLA\",\"Tesla, Inc.\",\n MSFT \n",
+        );
+        let symbols = read_all_stocks(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let info = |symbol: &str, name: Option<&str>, exchange: Option<&str>| SymbolInfo {
+            symbol: symbol.into(),
+            name: name.map(Into::into),
+            exchange: exchange.map(Into::into),
+        };
+        assert_eq!(
+            symbols,
+            [
+                info("AAPL", Some("Apple Inc."), Some("NASDAQ")),
+                info("TSLA", Some("Tesla, Inc."), None),
+                info("MSFT", None, None),
+            ]
+        );
+        assert_eq!(symbols[0].label(), "AAPL (Apple Inc., NASDAQ)");
+
+        // The same universe as JSON, and the plain list still works.
+        let path = write_symbols_file(
+            "symbols.json",
+            r#"[{"symbol":"AAPL","name":"Apple Inc.","exchange":"NASDAQ"},{"symbol":"MSFT"}]"#,
+        );
+        let symbols = read_all_stocks(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            symbols,
+            [
+                info("AAPL", Some("Apple Inc."), Some("NASDAQ")),
+                info("MSFT", None, None),
+            ]
+        );
+
+        let symbols = read_all_stocks(STOCKS_FILE).unwrap();
+        assert!(!symbols.is_empty());
+        assert!(symbols.iter().all(|info| info.name.is_none()));
+    }
+
+    #[test]
+    fn scrape_modes_pick_symbols() {
+        assert_eq!(ScrapeMode::parse(" Both "), Some(ScrapeMode::Both));
+        assert_eq!(ScrapeMode::parse("some"), None);
+
+        let info = |symbol: &str| SymbolInfo {
+            symbol: symbol.into(),
+            name: None,
+            exchange: None,
+        };
+        let universe = [info("AAPL"), info("MSFT")];
+        let active = ["MSFT".to_string(), "ZZZ".to_string()];
+        let symbols = |mode, full| -> Vec<String> {
+            cycle_symbols(mode, &universe, &active, full)
+                .into_iter()
+                .map(|info| info.symbol)
+                .collect()
+        };
+
+        assert_eq!(symbols(ScrapeMode::All, true), ["AAPL", "MSFT"]);
+        assert_eq!(symbols(ScrapeMode::Both, true), ["AAPL", "MSFT", "ZZZ"]);
+        assert_eq!(symbols(ScrapeMode::Both, false), ["MSFT", "ZZZ"]);
+        assert_eq!(symbols(ScrapeMode::Active, false), ["MSFT", "ZZZ"]);
+    }
+
+    #[test]
+    fn only_both_goes_back_to_the_whole_file() {
+        let long_ago = Instant::now()
+            .checked_sub(FULL_SCRAPE_INTERVAL * 2)
+            .unwrap();
+        for mode in [ScrapeMode::All, ScrapeMode::Active, ScrapeMode::Both] {
+            assert!(
+                is_full_cycle(mode, None),
+                "{} starts with the file",
+                mode.as_str()
+            );
+        }
+        assert!(is_full_cycle(ScrapeMode::All, Some(Instant::now())));
+        assert!(!is_full_cycle(ScrapeMode::Both, Some(Instant::now())));
+        assert!(is_full_cycle(ScrapeMode::Both, Some(long_ago)));
+        assert!(!is_full_cycle(ScrapeMode::Active, Some(Instant::now())));
+        assert!(!is_full_cycle(ScrapeMode::Active, Some(long_ago)));
+    }
+
+    // Answers every chart request with a price of 10 for the requested symbol, returns the
+    // URL to use as ScraperConfig::chart_url.
+    async fn mock_chart_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url_base = format!("http://{}/chart/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (read, mut write) = socket.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    let mut symbol = String::new();
+                    while let Some(line) = lines.next_line().await.unwrap() {
+                        if let Some(path) = line.strip_prefix("GET /chart/") {
+                            symbol = path.split('?').next().unwrap().to_string();
+                        } else if line.is_empty() {
+                            let body = format!(
+                                r#"{{"chart":{{"result":[{{"meta":{{"currency":"USD","symbol":"{symbol}","regularMarketPrice":10.0}}}}]}}}}"#
+                            );
+                            let response = format!(
+                                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                                body.len()
+                            );
+                            write.write_all(response.as_bytes()).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        url_base
+    }
+
+    // A symbols file in the temp dir, loaded the way main does it.
+    fn symbols_file(name: &str, contents: &str) -> SymbolsFile {
+        SymbolsFile::load(&write_symbols_file(name, contents)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_the_list_when_the_file_breaks() {
+        let mut file = symbols_file("reload-diff.txt", "AAPL\nMSFT\n");
+        let list = file.list.clone();
+
+        fs::write(&file.path, "AAPL\nTSLA\n").unwrap();
+        assert_eq!(file.reload().await, ["TSLA"]);
+        assert_eq!(**list.read().await, ["AAPL", "TSLA"]);
+
+        fs::write(&file.path, "\n").unwrap();
+        assert!(file.reload().await.is_empty());
+        fs::remove_file(&file.path).unwrap();
+        assert!(file.reload().await.is_empty());
+        assert_eq!(**list.read().await, ["AAPL", "TSLA"]);
+    }
+
+    #[tokio::test]
+    async fn symbol_added_to_the_file_is_scraped_next_cycle() {
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.chart_url = mock_chart_server().await;
+        let file = symbols_file("reload-scrape.txt", "AAPL\n");
+        let (path, list, reload) = (file.path.clone(), file.list.clone(), file.reload.clone());
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            stock_map.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            file,
+            config,
+            ScrapeMode::All,
+            memory_pool().await,
+            first_tx,
+        ));
+        assert_eq!(first_rx.await.unwrap(), Ok(1));
+
+        // What a SIGHUP does, without it the next cycle would only start after the pause.
+        fs::write(&path, "AAPL\nMSFT\n").unwrap();
+        reload.notify_one();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while check_price_of_stock(&stock_map, "MSFT").await.is_none() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("MSFT never got a price");
+        assert_eq!(**list.read().await, ["AAPL", "MSFT"]);
+
+        scraper.abort();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn symbol_dropped_from_the_file_is_evicted() {
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.chart_url = mock_chart_server().await;
+        let file = symbols_file("reload-evict.txt", "AAPL\nMSFT\n");
+        let (path, reload) = (file.path.clone(), file.reload.clone());
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        let history_map: HistoryLock = Arc::new(RwLock::new(HashMap::new()));
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            stock_map.clone(),
+            history_map.clone(),
+            file,
+            config,
+            ScrapeMode::All,
+            memory_pool().await,
+            first_tx,
+        ));
+        assert_eq!(first_rx.await.unwrap(), Ok(2));
+
+        fs::write(&path, "AAPL\n").unwrap();
+        reload.notify_one();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while check_price_of_stock(&stock_map, "MSFT").await.is_some() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("MSFT was never evicted");
+        assert_eq!(check_price_of_stock(&stock_map, "AAPL").await, Some(10.0));
+        let history = history_map.read().await;
+        assert!(history.contains_key("AAPL"));
+        assert!(!history.contains_key("MSFT"));
+        drop(history);
+
+        scraper.abort();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn untracked_symbols_are_dropped_in_any_case() {
+        let tracked = HashSet::from(["AAPL".to_string(), "BRK-B".to_string()]);
+        let mut map = HashMap::from([
+            ("AAPL".to_string(), 1),
+            ("brk-b".to_string(), 2),
+            ("MSFT".to_string(), 3),
+            ("GONE".to_string(), 4),
+        ]);
+        assert_eq!(retain_tracked(&mut map, &tracked), ["GONE", "MSFT"]);
+        assert_eq!(map.len(), 2);
+        assert!(retain_tracked(&mut map, &tracked).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rescrape_refreshes_prices_right_away() {
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.chart_url = mock_chart_server().await;
+        let rescrape = config.rescrape.clone();
+        let file = symbols_file("rescrape.txt", "AAPL\n");
+        let path = file.path.clone();
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            stock_map.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            file,
+            config,
+            ScrapeMode::All,
+            memory_pool().await,
+            first_tx,
+        ));
+        assert_eq!(first_rx.await.unwrap(), Ok(1));
+
+        // A stale price that the regular cycle would only fix after the 60s pause.
+        stock_map.write().await.get_mut("AAPL").unwrap().price = 1.0;
+        rescrape.notify_one();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while check_price_of_stock(&stock_map, "AAPL").await != Some(10.0) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("AAPL was never rescraped");
+
+        scraper.abort();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_recovers_cached_prices() {
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.chart_url = mock_chart_server().await;
+        let file = symbols_file("price-cache.txt", "AAPL\nMSFT\n");
+        let path = file.path.clone();
+        let pool = memory_pool().await;
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(HashMap::new())),
+            file,
+            config,
+            ScrapeMode::All,
+            pool.clone(),
+            first_tx,
+        ));
+        assert_eq!(first_rx.await.unwrap(), Ok(2));
+        scraper.abort();
+        fs::remove_file(&path).unwrap();
+
+        // What main does after a restart, before any scrape.
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        assert_eq!(load_cached_prices(&pool, &stock_map).await, Ok(2));
+        assert_eq!(check_price_of_stock(&stock_map, "AAPL").await, Some(10.0));
+        assert_eq!(check_price_of_stock(&stock_map, "MSFT").await, Some(10.0));
+
+        // A price already in the map is newer than the cached one.
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        stock_map.write().await.insert("AAPL".into(), quote(11.0));
+        load_cached_prices(&pool, &stock_map).await.unwrap();
+        assert_eq!(check_price_of_stock(&stock_map, "AAPL").await, Some(11.0));
+    }
+
+    #[tokio::test]
+    async fn cached_price_never_fills_a_bracket() {
+        let pool = memory_pool().await;
+        let user_id = database::register_user(&pool, "frank", "hunter2")
+            .await
+            .unwrap();
+        database::buy_stock(
+            &pool,
+            user_id,
+            "AAPL",
+            10,
+            100.0,
+            database::DEFAULT_MAX_POSITIONS,
+        )
+        .await
+        .unwrap();
+        database::set_bracket(&pool, user_id, "AAPL", Some(90.0), None)
+            .await
+            .unwrap();
+        database::cache_prices(&pool, &[("AAPL".into(), 85.0)], &[])
+            .await
+            .unwrap();
+
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        assert_eq!(load_cached_prices(&pool, &prices).await, Ok(1));
+        run_brackets(&pool, &prices).await;
+        assert_eq!(
+            database::get_portfolio(&pool, user_id).await.unwrap().len(),
+            1
+        );
+        // Valued at the cached price, but not traded at it.
+        assert_eq!(check_price_of_stock(&prices, "AAPL").await, Some(85.0));
+        assert_eq!(trade_price_of_stock(&prices, "AAPL").await, None);
+
+        prices.write().await.insert("AAPL".into(), quote(85.0));
+        run_brackets(&pool, &prices).await;
+        assert!(
+            database::get_portfolio(&pool, user_id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn alerted_symbol_gets_scraped() {
+        let url_base = mock_chart_server().await;
+
+        let pool = memory_pool().await;
+        let user_id = database::register_user(&pool, "erin", "hunter2")
+            .await
+            .unwrap();
+        let alert = AlertRequest {
+            symbol: "ZZZ".into(),
+            direction: AlertDirection::Above,
+            threshold: 20.0,
+        };
+        database::add_alert(&pool, user_id, &alert).await.unwrap();
+        assert_eq!(database::active_symbols(&pool).await.unwrap(), ["ZZZ"]);
+
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.chart_url = url_base;
+        let file = symbols_file("alerted.txt", "AAPL\n");
+        let path = file.path.clone();
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            stock_map.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            file,
+            config,
+            ScrapeMode::Active,
+            pool,
+            first_tx,
+        ));
+
+        assert_eq!(first_rx.await.unwrap(), Ok(2));
+        assert_eq!(check_price_of_stock(&stock_map, "ZZZ").await, Some(10.0));
+        assert_eq!(check_price_of_stock(&stock_map, "AAPL").await, Some(10.0));
+        scraper.abort();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn scraper_reports_client_build_failure() {
+        // `new` rejects this proxy, set directly it makes the reqwest builder fail.
+        let mut config = ScraperConfig::new(None, None).unwrap();
+        config.proxy = Some(reqwest::Url::parse("ftp://proxy.local").unwrap());
+
+        let file = symbols_file("no-client.txt", "AAPL\n");
+        fs::remove_file(&file.path).unwrap();
+        let stock_map: MapLock = Arc::new(RwLock::new(HashMap::new()));
+        let (first_tx, first_rx) = oneshot::channel();
+        let scraper = tokio::spawn(scrap_stocks(
+            stock_map.clone(),
+            Arc::new(RwLock::new(HashMap::new())),
+            file,
+            config,
+            ScrapeMode::All,
+            memory_pool().await,
+            first_tx,
+        ));
+
+        let error = first_rx.await.unwrap().unwrap_err();
+        assert!(error.contains("Failed to build the HTTP client"), "{error}");
+        // The scraper is gone instead of looping without a client.
+        tokio::time::timeout(Duration::from_secs(1), scraper)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stock_map.read().await.is_empty());
+    }
+
+    // Collects what the server logs, #[tokio::test] runs spawned tasks on the test's thread
+    // so a thread-local subscriber sees them too.
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn binary_garbage_drops_only_that_client() {
+        let logs = LogCapture::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_writer(move || writer.clone())
+                .with_ansi(false)
+                .finish(),
+        );
+
+        let config = ConnectionConfig::for_tests();
+        let prices: MapLock = Arc::new(RwLock::new(HashMap::from([(
+            "AAPL".to_string(),
+            quote(100.0),
+        )])));
+        let pool = memory_pool().await;
+        let mut garbage = connect_to(config.clone(), prices.clone(), pool.clone()).await;
+        let (read, mut write) = connect_to(config, prices, pool).await.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        garbage
+            .write_all(b"LOGIN \xff\xfe\xc3\x28 \x80\n")
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), garbage.read_to_end(&mut rest))
+            .await
+            .expect("the server should close the connection");
+        assert!(
+            read.is_err() || rest.is_empty(),
+            "unexpected reply {rest:?}"
+        );
+        let logged = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logged.contains("[server] Network error"), "{logged}");
+
+        login_as(&mut write, &mut lines, "frank").await;
+        for (msg, reply) in [(
+            ClientMsg::CheckPrice {
+                symbol: "AAPL".into(),
+            },
+            "PRICE AAPL 100\n".to_string(),
+        )] {
+            write.write_all(msg.to_wire().as_bytes()).await.unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, reply.trim_end());
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_commands_stop_the_reader_at_the_limit() {
+        // A tiny pipe, so the client's writes block as soon as the server stops reading.
+        let (server_end, mut client_end) = tokio::io::duplex(64);
+        let mut commands = spawn_command_reader(server_end, 4, MIN_LINE_BYTES);
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let writer = tokio::spawn({
+            let sent = sent.clone();
+            async move {
+                for _ in 0..1000 {
+                    client_end.write_all(b"PRICE AAPL\n").await.unwrap();
+                    sent.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!writer.is_finished());
+        assert_eq!(commands.len(), 4);
+        // The queue, the reader's buffer and the pipe hold a bounded number of commands.
+        assert!(sent.load(Ordering::SeqCst) < 20, "{sent:?}");
+
+        for _ in 0..1000 {
+            assert_eq!(commands.recv().await.unwrap().unwrap(), "PRICE AAPL");
+        }
+        writer.await.unwrap();
+        assert!(commands.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn overlong_line_closes_the_connection_unbuffered() {
+        let (read, mut write) = connect_with_config(ConnectionConfig::for_tests())
+            .await
+            .into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        // No newline anywhere, the server must give up once the limit is passed.
+        let huge = vec![b'A'; MIN_LINE_BYTES * 4];
+        let _ = write.write_all(&huge).await;
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("the server should answer")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            line,
+            ServerMsg::Error(ERR_LINE_TOO_LONG.into())
+                .to_wire()
+                .trim_end()
+        );
+        assert_eq!(lines.next_line().await.unwrap_or(None), None);
+    }
+
+    #[tokio::test]
+    async fn lines_are_read_up_to_the_limit() {
+        let mut reader: &[u8] = b"PRICE AAPL\r\nLIST 0\nTOOLONG\n";
+        assert_eq!(
+            read_line_limited(&mut reader, 11).await.unwrap().as_deref(),
+            Some("PRICE AAPL")
+        );
+        assert_eq!(
+            read_line_limited(&mut reader, 6).await.unwrap().as_deref(),
+            Some("LIST 0")
+        );
+        let err = read_line_limited(&mut reader, 6).await.unwrap_err();
+        assert_eq!(err.to_string(), ERR_LINE_TOO_LONG);
+        assert_eq!(
+            ConnectionConfig::for_tests().max_line_bytes(),
+            MIN_LINE_BYTES
+        );
+    }
+
+    #[tokio::test]
+    async fn startup_errors_carry_context() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap().to_string();
+        let err = bind_listener(&addr).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("[server] Failed to bind {addr}"));
+
+        let err = read_all_stocks("no-such-symbols.txt").unwrap_err();
+        assert!(err.to_string().contains("no-such-symbols.txt"));
+    }
+
+    #[tokio::test]
+    async fn price_miss_tells_unknown_from_not_scraped() {
+        let symbols = vec!["AAPL".to_string(), "MSFT".to_string(), "TSLA".to_string()];
+        let map: MapLock = Arc::new(RwLock::new(HashMap::from([
+            ("AAPL".to_string(), quote(190.0)),
+            (
+                "TSLA".to_string(),
+                Quote {
+                    price: 250.0,
+                    stats: QuoteStats {
+                        volume: Some(1000),
+                        low_52w: Some(140.5),
+                        high_52w: Some(300.0),
+                    },
+                    name: None,
+                    cached: false,
+                },
+            ),
+        ])));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server_side, _) = listener.accept().await.unwrap();
+        let (_, mut write_socket) = server_side.into_split();
+        let mut lines = BufReader::new(client).lines();
+
+        for (symbol, expected) in [
+            ("AAPL", "PRICE AAPL 190"),
+            ("TSLA", "PRICE TSLA 250 1000 140.5 300"),
+            ("MSFT", "ERR price not yet available"),
+            ("NOPE", "ERR unknown symbol"),
+        ] {
+            check_price(
+                &ClientAudit::default(),
+                symbol,
+                None,
+                &map,
+                &symbols,
+                &mut write_socket,
+            )
+            .await
+            .unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn chart_history_skips_missing_closes() {
+        let response: YahooResponse = serde_json::from_str(
+            r#"{"chart":{"result":[{
+                "meta":{"currency":"USD","symbol":"AAPL","regularMarketPrice":191.0},
+                "timestamp":[1700000000,1700000300,1700000600],
+                "indicators":{"quote":[{"close":[190.5,null,191.0]}]}
+            }]}}"#,
+        )
+        .unwrap();
+        let history = response.chart.result[0].history();
+        assert_eq!(
+            history,
+            vec![
+                HistoryPoint {
+                    timestamp: 1_700_000_000,
+                    close: 190.5
+                },
+                HistoryPoint {
+                    timestamp: 1_700_000_600,
+                    close: 191.0
+                },
+            ]
+        );
+
+        // Older responses without the series still parse.
+        let response: YahooResponse = serde_json::from_str(
+            r#"{"chart":{"result":[{"meta":{"currency":"USD","symbol":"AAPL","regularMarketPrice":191.0}}]}}"#,
+        )
+        .unwrap();
+        assert!(response.chart.result[0].history().is_empty());
+    }
+
+    #[test]
+    fn leaderboard_hides_other_names_unless_configured() {
+        let rows = || {
+            vec![
+                (
+                    1,
+                    LeaderboardEntry {
+                        name: "alice".into(),
+                        return_pct: 20.0,
+                    },
+                ),
+                (
+                    2,
+                    LeaderboardEntry {
+                        name: "bob".into(),
+                        return_pct: -45.0,
+                    },
+                ),
+            ]
+        };
+        let names = |entries: Vec<LeaderboardEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+
+        assert_eq!(
+            names(leaderboard_for(rows(), 2, false)),
+            vec![ANONYMOUS_NAME, "bob"]
+        );
+        assert_eq!(
+            names(leaderboard_for(rows(), 2, true)),
+            vec!["alice", "bob"]
+        );
+        assert_eq!(leaderboard_for(rows(), 2, false)[0].return_pct, 20.0);
+    }
+
+    #[test]
+    fn meta_carries_volume_and_52_week_range() {
+        let meta: Meta = serde_json::from_str(
+            r#"{"currency":"USD","symbol":"AAPL","regularMarketPrice":191.0,
+                "regularMarketVolume":48500000,"fiftyTwoWeekLow":164.08,"fiftyTwoWeekHigh":199.62,
+                "longName":"Apple Inc.","shortName":"Apple"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            meta.quote(),
+            Quote {
+                price: 191.0,
+                stats: QuoteStats {
+                    volume: Some(48_500_000),
+                    low_52w: Some(164.08),
+                    high_52w: Some(199.62),
+                },
+                name: Some("Apple Inc.".into()),
+                cached: false,
+            }
+        );
+
+        let meta: Meta = serde_json::from_str(
+            r#"{"currency":"USD","symbol":"AAPL","regularMarketPrice":191.0}"#,
+        )
+        .unwrap();
+        assert!(meta.quote().stats.is_empty());
+        assert!(meta.quote().name.is_none());
+
+        let meta: Meta = serde_json::from_str(
+            r#"{"currency":"USD","symbol":"AAPL","regularMarketPrice":191.0,"shortName":"Apple"}"#,
+        )
+        .unwrap();
+        assert_eq!(meta.quote().name.as_deref(), Some("Apple"));
+    }
+
+    #[tokio::test]
+    async fn symbol_list_names_come_from_quotes() {
+        let quote = |name: Option<&str>| Quote {
+            price: 1.0,
+            stats: QuoteStats::default(),
+            name: name.map(Into::into),
+            cached: false,
+        };
+        let map: MapLock = Arc::new(RwLock::new(HashMap::from([
+            ("AAPL".to_string(), quote(Some("Apple Inc."))),
+            ("MSFT".to_string(), quote(None)),
+        ])));
+        let page = vec!["AAPL".to_string(), "MSFT".to_string(), "TSLA".to_string()];
+        assert_eq!(
+            symbol_names(&map, &page).await,
+            HashMap::from([("AAPL".to_string(), "Apple Inc.".to_string())])
+        );
+    }
+
+    #[test]
+    fn history_window_keeps_recent_points() {
+        let points: Vec<HistoryPoint> = (0..30)
+            .map(|i| HistoryPoint {
+                timestamp: i * 300,
+                close: i as f64,
+            })
+            .collect();
+
+        let hour = history_window(&points, HistoryRange::Hour);
+        assert_eq!(hour.len(), 13);
+        assert_eq!(hour.first().unwrap().timestamp, 29 * 300 - 3600);
+        assert_eq!(history_window(&points, HistoryRange::Day).len(), 30);
+        assert!(history_window(&[], HistoryRange::Day).is_empty());
+    }
+}
@@ -1,14 +1,104 @@
-use crate::protocol::{AlertDirection, AlertRequest};
+use crate::protocol::{
+    AlertDirection, AlertRequest, ERR_DB_UNAVAILABLE, ERR_INTERNAL, LeaderboardEntry, Preferences,
+};
 use argon2::{
     Argon2,
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+    password_hash::{
+        self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng,
+    },
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{Row, sqlite};
+use std::collections::HashMap;
 use std::str;
+use tracing::{error, warn};
+
+pub const ERR_USER_EXISTS: &str = "User already exists";
+// DEL for an alert the user doesn't have.
+pub const ERR_ALERT_NOT_FOUND: &str = "Alert not found";
+pub const ERR_WRONG_PASSWORD: &str = "Wrong password";
+pub const ERR_NO_POSITION: &str = "You have no stocks of this company.";
+// BUY or SELL of zero or a negative number of shares.
+pub const ERR_INVALID_QUANTITY: &str = "Quantity must be a positive number of shares";
+// The position would hold more than i32::MAX shares, or its cost wouldn't fit an f64.
+pub const ERR_QUANTITY_TOO_LARGE: &str = "Position too large";
+// Bracket without a stop or target, or a stop that isn't below the target.
+pub const ERR_INVALID_BRACKET: &str = "Invalid bracket";
+pub const ERR_BRACKET_NOT_FOUND: &str = "Bracket not found";
+// How many different stocks a user can hold at once, the server can change it (MAX_POSITIONS).
+pub const DEFAULT_MAX_POSITIONS: usize = 100;
+// Starts of the trade refusals that carry a number, see is_trade_refusal.
+const NOT_ENOUGH_SHARES: &str = "You have only";
+const POSITIONS_LIMIT: &str = "You can hold at most";
+
+// SQLite primary result codes that go away on their own: BUSY, LOCKED, IOERR. FULL (13) is
+// left out, a full disk stays full and retrying it would only loop.
+const TRANSIENT_SQLITE_CODES: [i32; 3] = [5, 6, 10];
+
+// Extended codes keep the primary code in the low byte.
+fn is_transient_code(code: i32) -> bool {
+    TRANSIENT_SQLITE_CODES.contains(&(code & 0xff))
+}
+
+// Locks, I/O hiccups and an exhausted pool are worth retrying, everything else is permanent.
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::PoolTimedOut | sqlx::Error::Io(_) => true,
+        sqlx::Error::Database(db_err) => db_err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(is_transient_code),
+        _ => false,
+    }
+}
+
+fn db_error(e: sqlx::Error) -> String {
+    if is_transient(&e) {
+        ERR_DB_UNAVAILABLE.to_string()
+    } else {
+        e.to_string()
+    }
+}
+
+fn db_error_in(context: &str, e: sqlx::Error) -> String {
+    if is_transient(&e) {
+        ERR_DB_UNAVAILABLE.to_string()
+    } else {
+        format!("{context}: {e}")
+    }
+}
+
+// Argon2 failures are the server's fault, so the cause stays in the log and the caller gets
+// ERR_INTERNAL, which can't be mistaken for a taken username or a wrong password.
+fn hash_password(argon2: &Argon2<'_>, password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            error!("[database] Password hashing failed: {}", e);
+            ERR_INTERNAL.to_string()
+        })
+}
+
+// Ok(false) only for a password that doesn't match, an unreadable stored hash is ERR_INTERNAL.
+fn verify_password(stored_hash: &str, password: &str) -> Result<bool, String> {
+    let parsed_hash = PasswordHash::new(stored_hash).map_err(|e| {
+        error!("[database] Stored password hash is unreadable: {}", e);
+        ERR_INTERNAL.to_string()
+    })?;
+    match Argon2::default().verify_password(password.as_bytes(), &parsed_hash) {
+        Ok(()) => Ok(true),
+        Err(password_hash::Error::Password) => Ok(false),
+        Err(e) => {
+            error!("[database] Password verification failed: {}", e);
+            Err(ERR_INTERNAL.to_string())
+        }
+    }
+}
 
 // Struktura pomocnicza do wyciągania danych
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StoredAlert {
     pub symbol: String,
     pub direction: AlertDirection,
@@ -26,52 +116,101 @@ pub async fn init_database(pool: &sqlite::SqlitePool) -> Result<(), String> {
     Ok(())
 }
 
+// Adding an alert the user already has for the symbol and direction is not an error: the
+// threshold is updated, or nothing happens when it is the same, so a client can replay its
+// alerts after a reconnect.
 pub async fn add_alert(
     pool: &sqlite::SqlitePool,
     user_id: i64,
     alert: &AlertRequest,
 ) -> Result<(), String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+    upsert_alert(&mut tx, user_id, alert).await?;
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+// Adds or updates every alert in one transaction and returns the ones that were added or
+// moved. The rest are skipped: alerts the user already has with the same threshold,
+// including repeats inside the batch.
+pub async fn add_alerts<'a>(
+    pool: &sqlite::SqlitePool,
+    user_id: i64,
+    alerts: &'a [AlertRequest],
+) -> Result<Vec<&'a AlertRequest>, String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let mut added = Vec::new();
+    for alert in alerts {
+        if upsert_alert(&mut tx, user_id, alert).await? {
+            added.push(alert);
+        }
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(added)
+}
+
+// One alert per symbol and direction, enforced by a unique index. Returns false when the
+// alert already existed with the same threshold. A moved threshold is a new alert as far as
+// the trigger cooldown goes.
+async fn upsert_alert(
+    conn: &mut sqlite::SqliteConnection,
+    user_id: i64,
+    alert: &AlertRequest,
+) -> Result<bool, String> {
     let dir_str = alert.direction.as_str();
 
-    let existing = sqlx::query(
-        "SELECT 1 FROM alerts WHERE user_id = ? AND symbol = ? AND direction = ? LIMIT 1",
+    let changed = sqlx::query(
+        "INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, ?, ?, ?) \
+         ON CONFLICT(user_id, symbol, direction COLLATE NOCASE) DO UPDATE SET threshold = excluded.threshold \
+         WHERE alerts.threshold != excluded.threshold",
     )
     .bind(user_id)
     .bind(&alert.symbol)
     .bind(dir_str)
     .bind(alert.threshold)
-    .fetch_optional(pool)
+    .execute(&mut *conn)
     .await
-    .map_err(|e| format!("DB Error: {}", e))?;
+    .map_err(|e| db_error_in("Failed to add alert", e))?;
 
-    if existing.is_some() {
-        return Err("Alert already exists".to_string());
+    if changed.rows_affected() == 0 {
+        return Ok(false);
     }
 
-    sqlx::query("INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, ?, ?, ?)")
-        .bind(user_id)
-        .bind(&alert.symbol)
-        .bind(dir_str)
-        .bind(alert.threshold)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to add alert: {}", e))?;
+    sqlx::query(
+        "DELETE FROM alert_triggers WHERE user_id = ? AND symbol = ? AND direction = ? COLLATE NOCASE",
+    )
+    .bind(user_id)
+    .bind(&alert.symbol)
+    .bind(dir_str)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| db_error_in("Failed to add alert", e))?;
 
-    Ok(())
+    Ok(true)
 }
 
 pub async fn register_user(
     pool: &sqlite::SqlitePool,
     username: &str,
     password: &str,
-) -> Result<(), String> {
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| e.to_string())?
-        .to_string();
+) -> Result<i64, String> {
+    register_user_with(pool, &Argon2::default(), username, password).await
+}
+
+async fn register_user_with(
+    pool: &sqlite::SqlitePool,
+    argon2: &Argon2<'_>,
+    username: &str,
+    password: &str,
+) -> Result<i64, String> {
+    let password_hash = hash_password(argon2, password)?;
 
+    // No SELECT-then-INSERT here: SQLite serializes writers and checks the UNIQUE index inside
+    // the insert itself, so of two racing registrations exactly one can commit.
     let register_result = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
         .bind(username)
         .bind(password_hash)
@@ -79,12 +218,55 @@ pub async fn register_user(
         .await;
 
     match register_result {
-        Ok(_) => Ok(()),
+        Ok(done) => Ok(done.last_insert_rowid()),
         Err(sqlx::Error::Database(db_err)) if db_err.message().contains("UNIQUE constraint") => {
-            Err("User already exists".to_string())
+            Err(ERR_USER_EXISTS.to_string())
         }
-        Err(e) => Err(format!("Database error: {}", e)),
+        Err(e) => Err(db_error_in("Database error", e)),
+    }
+}
+
+// Optional starter content for new accounts (demos). There is no cash balance in the schema,
+// so a seed can only give alerts (the watchlist) and starting positions.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserSeed {
+    #[serde(default)]
+    pub alerts: Vec<StoredAlert>,
+    #[serde(default)]
+    pub positions: Vec<PortfolioStock>,
+}
+
+pub async fn seed_user(
+    pool: &sqlite::SqlitePool,
+    user_id: i64,
+    seed: &UserSeed,
+) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    // Same rule as ADD: a repeated alert moves the threshold instead of failing the seed.
+    for alert in &seed.alerts {
+        let alert = AlertRequest {
+            symbol: alert.symbol.clone(),
+            direction: alert.direction,
+            threshold: alert.threshold,
+        };
+        upsert_alert(&mut tx, user_id, &alert).await?;
+    }
+
+    for position in &seed.positions {
+        sqlx::query(
+            "INSERT INTO positions (user_id, symbol, quantity, price_total) VALUES (?, ?, ?, ?)",
+        )
+        .bind(user_id)
+        .bind(&position.symbol)
+        .bind(position.quantity)
+        .bind(position.total_price)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db_error_in("Failed to seed position", e))?;
     }
+
+    tx.commit().await.map_err(db_error)
 }
 
 pub async fn login_user(
@@ -96,18 +278,13 @@ pub async fn login_user(
         .bind(username)
         .fetch_optional(pool)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(db_error)?;
 
     if let Some(row) = row {
-        let stored_hash: String = row.try_get("password_hash").map_err(|e| e.to_string())?;
-        let user_id: i64 = row.try_get("id").map_err(|e| e.to_string())?;
+        let stored_hash: String = row.try_get("password_hash").map_err(db_error)?;
+        let user_id: i64 = row.try_get("id").map_err(db_error)?;
 
-        let parsed_hash = PasswordHash::new(&stored_hash).map_err(|e| e.to_string())?;
-
-        if Argon2::default()
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok()
-        {
+        if verify_password(&stored_hash, password)? {
             return Ok(user_id);
         }
     }
@@ -115,29 +292,141 @@ pub async fn login_user(
     Err("Invalid username or password".to_string())
 }
 
+// Symbols someone has an alert on or holds shares of, for the scraper's active mode.
+pub async fn active_symbols(pool: &sqlite::SqlitePool) -> Result<Vec<String>, String> {
+    sqlx::query_scalar(
+        "SELECT symbol FROM alerts UNION SELECT symbol FROM positions WHERE quantity > 0 ORDER BY symbol",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)
+}
+
+// Saves the prices of one scrape cycle and forgets the evicted symbols, in one transaction.
+pub async fn cache_prices(
+    pool: &sqlite::SqlitePool,
+    prices: &[(String, f64)],
+    evicted: &[String],
+) -> Result<(), String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    for (symbol, price) in prices {
+        sqlx::query(
+            "INSERT INTO price_cache (symbol, price, updated_at) VALUES (?, ?, ?) \
+             ON CONFLICT(symbol) DO UPDATE SET price = excluded.price, updated_at = excluded.updated_at",
+        )
+        .bind(symbol)
+        .bind(price)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| db_error_in("Failed to cache price", e))?;
+    }
+    for symbol in evicted {
+        sqlx::query("DELETE FROM price_cache WHERE symbol = ?")
+            .bind(symbol)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+    }
+
+    tx.commit().await.map_err(db_error)
+}
+
+// Prices cached at `since` (unix seconds) or later.
+pub async fn cached_prices(
+    pool: &sqlite::SqlitePool,
+    since: i64,
+) -> Result<Vec<(String, f64)>, String> {
+    sqlx::query_as("SELECT symbol, price FROM price_cache WHERE updated_at >= ? ORDER BY symbol")
+        .bind(since)
+        .fetch_all(pool)
+        .await
+        .map_err(db_error)
+}
+
+pub async fn get_username(pool: &sqlite::SqlitePool, user_id: i64) -> Result<String, String> {
+    sqlx::query_scalar("SELECT username FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| "User not found".to_string())
+}
+
+// `strict` decides what happens to alert rows with an unknown direction, see alerts_from_rows.
 pub async fn get_user_alerts(
     pool: &sqlx::SqlitePool,
     user_id: i64,
+    strict: bool,
 ) -> Result<Vec<StoredAlert>, String> {
     let rows = sqlx::query("SELECT symbol, direction, threshold FROM alerts WHERE user_id = ?")
         .bind(user_id)
         .fetch_all(pool)
         .await
-        .map_err(|e| format!("Failed to fetch alerts: {}", e))?;
+        .map_err(|e| db_error_in("Failed to fetch alerts", e))?;
+
+    alerts_from_rows(rows, strict)
+}
+
+// Returns one page of alerts and whether more come after it.
+pub async fn get_user_alerts_page(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    offset: usize,
+    limit: usize,
+    strict: bool,
+) -> Result<(Vec<StoredAlert>, bool), String> {
+    // One extra row tells us if there is another page without a separate COUNT query.
+    let rows = sqlx::query(
+        "SELECT symbol, direction, threshold FROM alerts WHERE user_id = ? ORDER BY id LIMIT ? OFFSET ?",
+    )
+    .bind(user_id)
+    .bind(limit as i64 + 1)
+    .bind(offset as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| db_error_in("Failed to fetch alerts", e))?;
+
+    let has_more = rows.len() > limit;
+    let mut alerts = alerts_from_rows(rows, strict)?;
+    alerts.truncate(limit);
+
+    Ok((alerts, has_more))
+}
 
+// Not strict: alert rows with an unknown direction are skipped with a warning.
+// Strict: such a row fails the whole request, so format drift can't hide alerts quietly.
+fn alerts_from_rows(
+    rows: Vec<sqlite::SqliteRow>,
+    strict: bool,
+) -> Result<Vec<StoredAlert>, String> {
     let mut alerts = Vec::new();
 
     for row in rows {
         let dir_str: String = row
             .try_get("direction")
-            .map_err(|e| format!("Failed to read row: {}", e))?;
+            .map_err(|e| db_error_in("Failed to read row", e))?;
+        let symbol: String = row.try_get("symbol").unwrap_or_default();
 
-        if let Some(direction) = AlertDirection::as_msg(&dir_str) {
-            alerts.push(StoredAlert {
-                symbol: row.try_get("symbol").unwrap_or_default(),
+        match AlertDirection::as_msg(&dir_str) {
+            Some(direction) => alerts.push(StoredAlert {
+                symbol,
                 threshold: row.try_get("threshold").unwrap_or_default(),
                 direction,
-            });
+            }),
+            None if strict => {
+                return Err(format!(
+                    "Unrecognized alert direction {dir_str:?} for {symbol}"
+                ));
+            }
+            None => warn!(
+                "[database] Skipping {} alert with unrecognized direction {:?}",
+                symbol, dir_str
+            ),
         }
     }
 
@@ -152,45 +441,209 @@ pub async fn remove_alert(
 ) -> Result<(), String> {
     let dir_str = direction.as_str();
 
-    sqlx::query("DELETE FROM alerts WHERE user_id = ? AND symbol = ? AND direction = ?")
-        .bind(user_id)
-        .bind(symbol)
-        .bind(dir_str)
-        .execute(pool)
-        .await
-        .map_err(|e| format!("Failed to remove the alert: {}", e))?;
+    // The alert and its cooldown go together or not at all.
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    // Directions may have been stored in any case, see AlertDirection::as_msg.
+    let deleted = sqlx::query(
+        "DELETE FROM alerts WHERE user_id = ? AND symbol = ? AND direction = ? COLLATE NOCASE",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .bind(dir_str)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db_error_in("Failed to remove the alert", e))?;
+
+    if deleted.rows_affected() == 0 {
+        return Err(ERR_ALERT_NOT_FOUND.to_string());
+    }
+
+    // A new alert on the same symbol starts without a cooldown.
+    sqlx::query(
+        "DELETE FROM alert_triggers WHERE user_id = ? AND symbol = ? AND direction = ? COLLATE NOCASE",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .bind(dir_str)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db_error_in("Failed to remove the alert", e))?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+// Moves the threshold of an existing alert, ERR_ALERT_NOT_FOUND when the user has none for
+// the symbol and direction. Like a re-added alert it may fire again without a cooldown.
+pub async fn update_alert(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    symbol: &str,
+    direction: AlertDirection,
+    threshold: f64,
+) -> Result<(), String> {
+    let dir_str = direction.as_str();
+
+    // The new threshold and the cleared cooldown land together or not at all.
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let updated = sqlx::query(
+        "UPDATE alerts SET threshold = ? WHERE user_id = ? AND symbol = ? AND direction = ? COLLATE NOCASE",
+    )
+    .bind(threshold)
+    .bind(user_id)
+    .bind(symbol)
+    .bind(dir_str)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db_error_in("Failed to update the alert", e))?;
+
+    if updated.rows_affected() == 0 {
+        return Err(ERR_ALERT_NOT_FOUND.to_string());
+    }
+
+    sqlx::query(
+        "DELETE FROM alert_triggers WHERE user_id = ? AND symbol = ? AND direction = ? COLLATE NOCASE",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .bind(dir_str)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| db_error_in("Failed to update the alert", e))?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+// Records that the alert fires at `now` (unix seconds) unless it already fired less than
+// `cooldown_secs` ago, returns whether it may fire.
+pub async fn claim_alert_trigger(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    symbol: &str,
+    direction: AlertDirection,
+    now: i64,
+    cooldown_secs: i64,
+) -> Result<bool, String> {
+    let claimed = sqlx::query(
+        "INSERT INTO alert_triggers (user_id, symbol, direction, triggered_at) VALUES (?, ?, ?, ?) \
+         ON CONFLICT(user_id, symbol, direction) DO UPDATE SET triggered_at = excluded.triggered_at \
+         WHERE alert_triggers.triggered_at <= ?",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .bind(direction.as_str())
+    .bind(now)
+    .bind(now.saturating_sub(cooldown_secs))
+    .execute(pool)
+    .await
+    .map_err(|e| db_error_in("Failed to record the alert trigger", e))?;
+
+    Ok(claimed.rows_affected() == 1)
+}
+
+pub async fn get_preferences(pool: &sqlx::SqlitePool, user_id: i64) -> Result<Preferences, String> {
+    let row: Option<(bool, bool)> =
+        sqlx::query_as("SELECT notify_triggers, notify_fills FROM preferences WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| db_error_in("Failed to fetch preferences", e))?;
+
+    Ok(row
+        .map(|(triggers, fills)| Preferences { triggers, fills })
+        .unwrap_or_default())
+}
+
+pub async fn set_preferences(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    prefs: &Preferences,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO preferences (user_id, notify_triggers, notify_fills) VALUES (?, ?, ?) \
+         ON CONFLICT(user_id) DO UPDATE SET notify_triggers = excluded.notify_triggers, \
+         notify_fills = excluded.notify_fills",
+    )
+    .bind(user_id)
+    .bind(prefs.triggers)
+    .bind(prefs.fills)
+    .execute(pool)
+    .await
+    .map_err(|e| db_error_in("Failed to save preferences", e))?;
 
     Ok(())
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PortfolioStock {
     pub symbol: String,
     pub quantity: i32,
+    // Cost basis of the shares still held: what they were bought for, at average cost.
+    // Selling takes its share of the basis out, a closed position is back at 0.
     pub total_price: f64,
 }
 
+impl PortfolioStock {
+    pub fn average_price(&self) -> Option<f64> {
+        (self.quantity > 0).then(|| self.total_price / self.quantity as f64)
+    }
+}
+
 pub async fn buy_stock(
     pool: &sqlx::SqlitePool,
     user_id: i64,
     symbol: &str,
     quantity: i32,
     current_price: f64,
+    max_positions: usize,
 ) -> Result<(), String> {
+    check_quantity(quantity)?;
+    let cost = checked_cost(quantity as f64 * current_price)?;
+
+    // BEGIN IMMEDIATE takes the write lock up front, so concurrent trades on the
+    // same position are serialized instead of overwriting each other's update.
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
     let stock_row =
         sqlx::query("SELECT quantity, price_total FROM positions WHERE user_id = ? AND symbol = ?")
             .bind(user_id)
             .bind(symbol)
-            .fetch_optional(pool)
+            .fetch_optional(&mut *tx)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(db_error)?;
+
+    // Databases from before sold out positions were deleted can still have rows with quantity 0,
+    // buying into one again takes a new slot.
+    let held = match &stock_row {
+        Some(row) => row.try_get::<i32, _>("quantity").unwrap_or(0) > 0,
+        None => false,
+    };
+    if !held {
+        let open: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM positions WHERE user_id = ? AND quantity > 0")
+                .bind(user_id)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        if open as usize >= max_positions {
+            return Err(positions_limit_error(max_positions));
+        }
+    }
 
     if let Some(row) = stock_row {
         let current_quantity: i32 = row.try_get("quantity").unwrap_or(0);
         let current_summary: f64 = row.try_get("price_total").unwrap_or(0.0);
 
-        let new_quantity = current_quantity + quantity;
+        let new_quantity = current_quantity
+            .checked_add(quantity)
+            .ok_or_else(|| ERR_QUANTITY_TOO_LARGE.to_string())?;
 
-        let total_value = current_summary + (quantity as f64 * current_price);
+        let total_value = checked_cost(current_summary + cost)?;
 
         sqlx::query(
             "UPDATE positions SET quantity = ?, price_total = ? WHERE user_id = ? AND symbol = ?",
@@ -199,11 +652,9 @@ pub async fn buy_stock(
         .bind(total_value)
         .bind(user_id)
         .bind(symbol)
-        .execute(pool)
+        .execute(&mut *tx)
         .await
-        .map_err(|e| e.to_string())?;
-
-        Ok(())
+        .map_err(db_error)?;
     } else {
         sqlx::query(
             "INSERT INTO positions (user_id, symbol, quantity, price_total) VALUES (?, ?, ?, ?)",
@@ -211,72 +662,620 @@ pub async fn buy_stock(
         .bind(user_id)
         .bind(symbol)
         .bind(quantity)
-        .bind(current_price * quantity as f64) // Twoja cena wejścia
-        .execute(pool)
+        .bind(cost) // Twoja cena wejścia
+        .execute(&mut *tx)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(db_error)?;
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(())
+}
+
+fn check_quantity(quantity: i32) -> Result<(), String> {
+    if quantity <= 0 {
+        return Err(ERR_INVALID_QUANTITY.to_string());
+    }
+    Ok(())
+}
 
-        Ok(())
+// Every i32 quantity is exact as an f64, only a cost that ran off to infinity is refused.
+fn checked_cost(cost: f64) -> Result<f64, String> {
+    if !cost.is_finite() {
+        return Err(ERR_QUANTITY_TOO_LARGE.to_string());
     }
+    Ok(cost)
+}
+
+pub fn positions_limit_error(max_positions: usize) -> String {
+    format!("{POSITIONS_LIMIT} {max_positions} different stocks, sell one of them first.")
+}
+
+// Trade errors the account caused, anything else a trade returns is a database failure.
+pub fn is_trade_refusal(error: &str) -> bool {
+    [
+        ERR_NO_POSITION,
+        ERR_INVALID_QUANTITY,
+        ERR_QUANTITY_TOO_LARGE,
+    ]
+    .contains(&error)
+        || error.starts_with(NOT_ENOUGH_SHARES)
+        || error.starts_with(POSITIONS_LIMIT)
 }
 
+// Returns the realized profit of the sold shares, measured against their average cost.
 pub async fn sell_stock(
     pool: &sqlx::SqlitePool,
     user_id: i64,
     symbol: &str,
     quantity: i32,
     stock_price: f64,
-) -> Result<(), String> {
+) -> Result<f64, String> {
+    check_quantity(quantity)?;
+
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+    let profit = sell_in(&mut tx, user_id, symbol, quantity, stock_price).await?;
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(profit)
+}
+
+// The sale itself, for callers that record something else in the same transaction.
+async fn sell_in(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: i64,
+    symbol: &str,
+    quantity: i32,
+    stock_price: f64,
+) -> Result<f64, String> {
     let stock_row =
         sqlx::query("SELECT quantity, price_total FROM positions WHERE user_id = ? AND symbol = ?")
             .bind(user_id)
             .bind(symbol)
-            .fetch_optional(pool)
+            .fetch_optional(&mut **tx)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(db_error)?;
 
     let (current_quantity, current_total_price): (i32, f64) = match stock_row {
         Some(row) => (
             row.try_get("quantity").unwrap_or(0),
             row.try_get("price_total").unwrap_or(0.0),
         ),
-        None => return Err("You have no stocks of this company.".to_string()),
+        None => return Err(ERR_NO_POSITION.to_string()),
     };
 
     if current_quantity < quantity {
         return Err(format!(
-            "You have only {} actions of given stock!.",
+            "{NOT_ENOUGH_SHARES} {} actions of given stock!.",
             current_quantity
         ));
     }
 
     let new_quantity = current_quantity - quantity;
-    let new_total_price = current_total_price - (quantity as f64 * stock_price);
+    let new_total_price = remaining_cost(current_total_price, current_quantity, new_quantity);
+    let profit = quantity as f64 * stock_price - (current_total_price - new_total_price);
 
-    sqlx::query(
-        "UPDATE positions SET quantity = ?, price_total = ? WHERE user_id = ? AND symbol = ?",
-    )
-    .bind(new_quantity)
-    .bind(new_total_price)
-    .bind(user_id)
-    .bind(symbol)
-    .execute(pool)
-    .await
-    .map_err(|e| e.to_string())?;
+    if new_quantity == 0 {
+        remove_position(tx, user_id, symbol).await?;
+    } else {
+        sqlx::query(
+            "UPDATE positions SET quantity = ?, price_total = ? WHERE user_id = ? AND symbol = ?",
+        )
+        .bind(new_quantity)
+        .bind(new_total_price)
+        .bind(user_id)
+        .bind(symbol)
+        .execute(&mut **tx)
+        .await
+        .map_err(db_error)?;
+    }
 
-    Ok(())
+    Ok(profit)
 }
 
-pub async fn get_portfolio(
+// Sells the whole position at the given price and returns the sold quantity with the realized profit.
+pub async fn close_position(
     pool: &sqlx::SqlitePool,
     user_id: i64,
-) -> Result<Vec<PortfolioStock>, String> {
-    let rows = sqlx::query("SELECT symbol, quantity, price_total FROM positions WHERE user_id = ?")
-        .bind(user_id)
-        .fetch_all(pool)
-        .await
-        .map_err(|e| e.to_string())?;
+    symbol: &str,
+    stock_price: f64,
+) -> Result<(i32, f64), String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
 
+    let stock_row =
+        sqlx::query("SELECT quantity, price_total FROM positions WHERE user_id = ? AND symbol = ?")
+            .bind(user_id)
+            .bind(symbol)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+    let (quantity, total_price): (i32, f64) = match stock_row {
+        Some(row) => (
+            row.try_get("quantity").unwrap_or(0),
+            row.try_get("price_total").unwrap_or(0.0),
+        ),
+        None => return Err(ERR_NO_POSITION.to_string()),
+    };
+
+    if quantity <= 0 {
+        return Err(ERR_NO_POSITION.to_string());
+    }
+
+    // price_total is what the held shares cost, so the profit is the proceeds minus that.
+    let profit = quantity as f64 * stock_price - total_price;
+
+    remove_position(&mut tx, user_id, symbol).await?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok((quantity, profit))
+}
+
+// A sold out position is deleted, so the portfolio only lists what is still held.
+async fn remove_position(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: i64,
+    symbol: &str,
+) -> Result<(), String> {
+    sqlx::query("DELETE FROM positions WHERE user_id = ? AND symbol = ?")
+        .bind(user_id)
+        .bind(symbol)
+        .execute(&mut **tx)
+        .await
+        .map_err(db_error)?;
+    Ok(())
+}
+
+// Cost basis left after selling down to `new_quantity` shares at average cost.
+fn remaining_cost(total_price: f64, quantity: i32, new_quantity: i32) -> f64 {
+    if new_quantity <= 0 || quantity <= 0 {
+        return 0.0;
+    }
+    total_price * new_quantity as f64 / quantity as f64
+}
+
+// Starts the portfolio over: every position of the user goes, alerts and credentials stay.
+// Returns how many positions were removed.
+pub async fn reset_account(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    password: &str,
+) -> Result<u64, String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let stored_hash: Option<String> =
+        sqlx::query_scalar("SELECT password_hash FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+    let stored_hash = stored_hash.ok_or_else(|| ERR_WRONG_PASSWORD.to_string())?;
+    if !verify_password(&stored_hash, password)? {
+        return Err(ERR_WRONG_PASSWORD.to_string());
+    }
+
+    let removed = sqlx::query("DELETE FROM positions WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected();
+    // Brackets belong to positions, armed ones go with them.
+    sqlx::query("DELETE FROM brackets WHERE user_id = ? AND fill_quantity IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(removed)
+}
+
+// A bracket that sold its position, waiting to be reported to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BracketFill {
+    pub symbol: String,
+    pub quantity: i32,
+    pub price: f64,
+    pub profit: f64,
+}
+
+// Sells the whole position once the price is at or below `stop`, or at or above `target`.
+fn bracket_hit(stop: Option<f64>, target: Option<f64>, price: f64) -> bool {
+    stop.is_some_and(|stop| price <= stop) || target.is_some_and(|target| price >= target)
+}
+
+// Arms (or re-arms) the bracket of a held position.
+pub async fn set_bracket(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    symbol: &str,
+    stop: Option<f64>,
+    target: Option<f64>,
+) -> Result<(), String> {
+    let valid = match (stop, target) {
+        (None, None) => false,
+        (Some(stop), Some(target)) => stop < target,
+        _ => true,
+    };
+    if !valid
+        || [stop, target]
+            .into_iter()
+            .flatten()
+            .any(|p| !p.is_finite() || p < 0.0)
+    {
+        return Err(ERR_INVALID_BRACKET.to_string());
+    }
+
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let quantity: Option<i32> =
+        sqlx::query_scalar("SELECT quantity FROM positions WHERE user_id = ? AND symbol = ?")
+            .bind(user_id)
+            .bind(symbol)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+    if quantity.unwrap_or(0) <= 0 {
+        return Err(ERR_NO_POSITION.to_string());
+    }
+
+    sqlx::query(
+        "INSERT INTO brackets (user_id, symbol, stop, target) VALUES (?, ?, ?, ?) \
+         ON CONFLICT(user_id, symbol) DO UPDATE SET stop = excluded.stop, target = excluded.target, \
+         fill_quantity = NULL, fill_price = NULL, fill_profit = NULL",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .bind(stop)
+    .bind(target)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)
+}
+
+pub async fn cancel_bracket(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    symbol: &str,
+) -> Result<(), String> {
+    let result = sqlx::query(
+        "DELETE FROM brackets WHERE user_id = ? AND symbol = ? AND fill_quantity IS NULL",
+    )
+    .bind(user_id)
+    .bind(symbol)
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(ERR_BRACKET_NOT_FOUND.to_string());
+    }
+    Ok(())
+}
+
+// Disarms every pending bracket of the user in one go, filled ones still wait to be reported.
+// Returns how many were cancelled.
+pub async fn cancel_all_orders(pool: &sqlx::SqlitePool, user_id: i64) -> Result<u64, String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let cancelled = sqlx::query("DELETE FROM brackets WHERE user_id = ? AND fill_quantity IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected();
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(cancelled)
+}
+
+// Called after every price update: sells each position whose bracket was hit and keeps the
+// fill for take_bracket_fills. Brackets of positions that are gone by now are dropped.
+// Each sale is committed together with its fill, so a failure in between sells nothing.
+// Returns how many positions were sold.
+pub async fn execute_brackets(
+    pool: &sqlx::SqlitePool,
+    prices: &HashMap<String, f64>,
+) -> Result<usize, String> {
+    let rows = sqlx::query(
+        "SELECT brackets.id, brackets.user_id, brackets.symbol, brackets.stop, brackets.target, \
+         positions.quantity FROM brackets LEFT JOIN positions \
+         ON positions.user_id = brackets.user_id AND positions.symbol = brackets.symbol \
+         WHERE brackets.fill_quantity IS NULL",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut sold = 0;
+    for row in rows {
+        let id: i64 = row.try_get("id").map_err(db_error)?;
+        let user_id: i64 = row.try_get("user_id").map_err(db_error)?;
+        let symbol: String = row.try_get("symbol").map_err(db_error)?;
+        let stop: Option<f64> = row.try_get("stop").map_err(db_error)?;
+        let target: Option<f64> = row.try_get("target").map_err(db_error)?;
+        let quantity: Option<i32> = row.try_get("quantity").map_err(db_error)?;
+
+        let Some(&price) = prices.get(&symbol) else {
+            continue;
+        };
+        if quantity.unwrap_or(0) > 0 && !bracket_hit(stop, target, price) {
+            continue;
+        }
+
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+        // The position may have changed since the scan, sell what is held now.
+        let quantity: i32 =
+            sqlx::query_scalar("SELECT quantity FROM positions WHERE user_id = ? AND symbol = ?")
+                .bind(user_id)
+                .bind(&symbol)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(db_error)?
+                .unwrap_or(0);
+
+        let fill = if quantity > 0 {
+            match sell_in(&mut tx, user_id, &symbol, quantity, price).await {
+                Ok(profit) => Some(profit),
+                Err(e) if is_trade_refusal(&e) => {
+                    warn!(
+                        "[database] Bracket {} for user {} not filled: {}",
+                        symbol, user_id, e
+                    );
+                    None
+                }
+                // The bracket stays armed, the next price update tries again.
+                Err(e) => return Err(e),
+            }
+        } else {
+            None
+        };
+
+        match fill {
+            Some(profit) => {
+                sqlx::query(
+                    "UPDATE brackets SET fill_quantity = ?, fill_price = ?, fill_profit = ? WHERE id = ?",
+                )
+                .bind(quantity)
+                .bind(price)
+                .bind(profit)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+                sold += 1;
+            }
+            None => {
+                sqlx::query("DELETE FROM brackets WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_error)?;
+            }
+        }
+
+        tx.commit().await.map_err(db_error)?;
+    }
+
+    Ok(sold)
+}
+
+// Hands out the user's filled brackets once, they are deleted here.
+pub async fn take_bracket_fills(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+) -> Result<Vec<BracketFill>, String> {
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await.map_err(db_error)?;
+
+    let rows = sqlx::query(
+        "SELECT symbol, fill_quantity, fill_price, fill_profit FROM brackets \
+         WHERE user_id = ? AND fill_quantity IS NOT NULL ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut fills = Vec::new();
+    for row in rows {
+        fills.push(BracketFill {
+            symbol: row.try_get("symbol").map_err(db_error)?,
+            quantity: row.try_get("fill_quantity").map_err(db_error)?,
+            price: row.try_get("fill_price").map_err(db_error)?,
+            profit: row.try_get("fill_profit").map_err(db_error)?,
+        });
+    }
+
+    sqlx::query("DELETE FROM brackets WHERE user_id = ? AND fill_quantity IS NOT NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(fills)
+}
+
+// Ranks users by the return on what their held shares cost, valued at `prices`. Positions
+// without a price are left out, users with nothing priced aren't ranked. Entries carry the
+// user id next to the username so the caller can decide what to show.
+pub async fn get_leaderboard(
+    pool: &sqlx::SqlitePool,
+    prices: &HashMap<String, f64>,
+    limit: usize,
+) -> Result<Vec<(i64, LeaderboardEntry)>, String> {
+    let rows = sqlx::query(
+        "SELECT users.id, users.username, positions.symbol, positions.quantity, positions.price_total \
+         FROM positions JOIN users ON users.id = positions.user_id WHERE positions.quantity > 0",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    // user id -> (username, cost, market value)
+    let mut totals: HashMap<i64, (String, f64, f64)> = HashMap::new();
+    for row in rows {
+        let symbol: String = row.try_get("symbol").map_err(db_error)?;
+        let Some(price) = prices.get(&symbol) else {
+            continue;
+        };
+        let user_id: i64 = row.try_get("id").map_err(db_error)?;
+        let quantity: i32 = row.try_get("quantity").map_err(db_error)?;
+        let cost: f64 = row.try_get("price_total").map_err(db_error)?;
+        let entry = match totals.get_mut(&user_id) {
+            Some(entry) => entry,
+            None => {
+                let username: String = row.try_get("username").map_err(db_error)?;
+                totals.entry(user_id).or_insert((username, 0.0, 0.0))
+            }
+        };
+        entry.1 += cost;
+        entry.2 += quantity as f64 * price;
+    }
+
+    let mut entries: Vec<(i64, LeaderboardEntry)> = totals
+        .into_iter()
+        .filter(|(_, (_, cost, _))| *cost > 0.0)
+        .map(|(user_id, (name, cost, value))| {
+            let return_pct = (value - cost) / cost * 100.0;
+            (user_id, LeaderboardEntry { name, return_pct })
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        b.1.return_pct
+            .total_cmp(&a.1.return_pct)
+            .then_with(|| a.1.name.cmp(&b.1.name))
+    });
+    entries.truncate(limit);
+
+    Ok(entries)
+}
+
+pub async fn get_portfolio(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+) -> Result<Vec<PortfolioStock>, String> {
+    let rows = sqlx::query(
+        "SELECT symbol, quantity, price_total FROM positions WHERE user_id = ? AND quantity > 0",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(portfolio_from_rows(rows))
+}
+
+pub async fn get_portfolio_page(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    offset: usize,
+    limit: usize,
+) -> Result<(Vec<PortfolioStock>, bool), String> {
+    let rows = sqlx::query(
+        "SELECT symbol, quantity, price_total FROM positions WHERE user_id = ? AND quantity > 0 ORDER BY id LIMIT ? OFFSET ?",
+    )
+    .bind(user_id)
+    .bind(limit as i64 + 1)
+    .bind(offset as i64)
+    .fetch_all(pool)
+    .await
+    .map_err(db_error)?;
+
+    let has_more = rows.len() > limit;
+    let mut items = portfolio_from_rows(rows);
+    items.truncate(limit);
+
+    Ok((items, has_more))
+}
+
+// A stop-loss / take-profit that hasn't sold its position yet.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StoredBracket {
+    pub symbol: String,
+    pub stop: Option<f64>,
+    pub target: Option<f64>,
+}
+
+// Everything kept for one account, for EXPORT. There is no trade history, positions only carry
+// their cost basis. The password hash stays out.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserExport {
+    pub username: String,
+    pub alerts: Vec<StoredAlert>,
+    pub positions: Vec<PortfolioStock>,
+    pub brackets: Vec<StoredBracket>,
+}
+
+// Read in one transaction, so a trade running at the same time can't show up in one
+// section and not in another. Closed positions (quantity 0) are left out.
+pub async fn export_user_data(
+    pool: &sqlx::SqlitePool,
+    user_id: i64,
+    strict: bool,
+) -> Result<UserExport, String> {
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let username: String = sqlx::query_scalar("SELECT username FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| "User not found".to_string())?;
+
+    let alert_rows = sqlx::query(
+        "SELECT symbol, direction, threshold FROM alerts WHERE user_id = ? ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| db_error_in("Failed to fetch alerts", e))?;
+
+    let position_rows = sqlx::query(
+        "SELECT symbol, quantity, price_total FROM positions WHERE user_id = ? AND quantity > 0 ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let brackets: Vec<(String, Option<f64>, Option<f64>)> = sqlx::query_as(
+        "SELECT symbol, stop, target FROM brackets WHERE user_id = ? AND fill_quantity IS NULL ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(UserExport {
+        username,
+        alerts: alerts_from_rows(alert_rows, strict)?,
+        positions: portfolio_from_rows(position_rows),
+        brackets: brackets
+            .into_iter()
+            .map(|(symbol, stop, target)| StoredBracket {
+                symbol,
+                stop,
+                target,
+            })
+            .collect(),
+    })
+}
+
+fn portfolio_from_rows(rows: Vec<sqlite::SqliteRow>) -> Vec<PortfolioStock> {
     let mut items = Vec::new();
     for row in rows {
         items.push(PortfolioStock {
@@ -286,5 +1285,1346 @@ pub async fn get_portfolio(
         });
     }
 
-    Ok(items)
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    async fn test_pool(name: &str) -> sqlite::SqlitePool {
+        let path = std::env::temp_dir().join(format!("{name}_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let opts = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(opts)
+            .await
+            .unwrap();
+        init_database(&pool).await.unwrap();
+        pool
+    }
+
+    async fn test_user(pool: &sqlite::SqlitePool, username: &str) -> i64 {
+        sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, 'x')")
+            .bind(username)
+            .execute(pool)
+            .await
+            .unwrap()
+            .last_insert_rowid()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_buy_and_sell_keep_quantity() {
+        let pool = test_pool("concurrent_trades").await;
+        let user_id = test_user(&pool, "trader").await;
+
+        buy_stock(&pool, user_id, "AAPL", 50, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let pool = pool.clone();
+            tasks.spawn(async move {
+                if i % 2 == 0 {
+                    buy_stock(&pool, user_id, "AAPL", 3, 10.0, DEFAULT_MAX_POSITIONS).await
+                } else {
+                    sell_stock(&pool, user_id, "AAPL", 2, 10.0)
+                        .await
+                        .map(|_| ())
+                }
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            result.unwrap().unwrap();
+        }
+
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio.len(), 1);
+        assert_eq!(portfolio[0].quantity, 50 + 10 * 3 - 10 * 2);
+        assert_eq!(portfolio[0].total_price, 600.0);
+    }
+
+    #[tokio::test]
+    async fn alerts_are_paged() {
+        let pool = test_pool("alerts_paging").await;
+        let user_id = test_user(&pool, "watcher").await;
+
+        for i in 0..250 {
+            let alert = AlertRequest {
+                symbol: format!("S{i}"),
+                direction: AlertDirection::Above,
+                threshold: i as f64,
+            };
+            add_alert(&pool, user_id, &alert).await.unwrap();
+        }
+
+        let mut offset = 0;
+        let mut pages = Vec::new();
+        loop {
+            let (page, has_more) = get_user_alerts_page(&pool, user_id, offset, 100, false)
+                .await
+                .unwrap();
+            offset += page.len();
+            pages.push(page);
+            if !has_more {
+                break;
+            }
+        }
+
+        let sizes: Vec<usize> = pages.iter().map(|page| page.len()).collect();
+        assert_eq!(sizes, vec![100, 100, 50]);
+        let all: Vec<StoredAlert> = pages.into_iter().flatten().collect();
+        assert_eq!(all[0].symbol, "S0");
+        assert_eq!(all[249].symbol, "S249");
+    }
+
+    #[tokio::test]
+    async fn portfolio_is_paged() {
+        let pool = test_pool("portfolio_paging").await;
+        let user_id = test_user(&pool, "holder").await;
+
+        for i in 0..3 {
+            buy_stock(
+                &pool,
+                user_id,
+                &format!("S{i}"),
+                1,
+                1.0,
+                DEFAULT_MAX_POSITIONS,
+            )
+            .await
+            .unwrap();
+        }
+
+        let (page, has_more) = get_portfolio_page(&pool, user_id, 0, 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(has_more);
+
+        let (page, has_more) = get_portfolio_page(&pool, user_id, 2, 2).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].symbol, "S2");
+        assert!(!has_more);
+    }
+
+    #[tokio::test]
+    async fn position_limit_counts_held_stocks() {
+        let pool = test_pool("position_limit").await;
+        let user_id = test_user(&pool, "collector").await;
+
+        buy_stock(&pool, user_id, "AAPL", 1, 10.0, 2).await.unwrap();
+        buy_stock(&pool, user_id, "MSFT", 1, 10.0, 2).await.unwrap();
+        let err = buy_stock(&pool, user_id, "TSLA", 1, 10.0, 2)
+            .await
+            .unwrap_err();
+        assert_eq!(err, positions_limit_error(2));
+
+        // Adding to a held stock doesn't need a new slot.
+        buy_stock(&pool, user_id, "AAPL", 5, 10.0, 2).await.unwrap();
+
+        // Selling out frees the slot, and the sold out stock needs one again.
+        sell_stock(&pool, user_id, "MSFT", 1, 10.0).await.unwrap();
+        buy_stock(&pool, user_id, "TSLA", 1, 10.0, 2).await.unwrap();
+        let err = buy_stock(&pool, user_id, "MSFT", 1, 10.0, 2)
+            .await
+            .unwrap_err();
+        assert_eq!(err, positions_limit_error(2));
+    }
+
+    #[tokio::test]
+    async fn sold_out_position_leaves_the_portfolio() {
+        let pool = test_pool("sold_out_position").await;
+        let user_id = test_user(&pool, "trader").await;
+
+        buy_stock(&pool, user_id, "AAPL", 3, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        sell_stock(&pool, user_id, "AAPL", 3, 12.0).await.unwrap();
+
+        assert!(get_portfolio(&pool, user_id).await.unwrap().is_empty());
+        let (page, has_more) = get_portfolio_page(&pool, user_id, 0, 10).await.unwrap();
+        assert!(page.is_empty());
+        assert!(!has_more);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM positions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[tokio::test]
+    async fn close_position_sells_everything() {
+        let pool = test_pool("close_position").await;
+        let user_id = test_user(&pool, "closer").await;
+
+        assert!(close_position(&pool, user_id, "AAPL", 10.0).await.is_err());
+
+        buy_stock(&pool, user_id, "AAPL", 5, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        sell_stock(&pool, user_id, "AAPL", 2, 12.0).await.unwrap();
+
+        let (quantity, profit) = close_position(&pool, user_id, "AAPL", 15.0).await.unwrap();
+        assert_eq!(quantity, 3);
+        // Only the closed shares count, the earlier sale isn't part of this profit.
+        assert_eq!(profit, 3.0 * 15.0 - 3.0 * 10.0);
+
+        assert!(get_portfolio(&pool, user_id).await.unwrap().is_empty());
+        assert!(close_position(&pool, user_id, "AAPL", 15.0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn selling_keeps_cost_basis_of_held_shares() {
+        let pool = test_pool("cost_basis").await;
+        let user_id = test_user(&pool, "investor").await;
+
+        buy_stock(&pool, user_id, "AAPL", 2, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, user_id, "AAPL", 2, 20.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        // Selling above cost doesn't push the basis down, only the sold shares' cost leaves.
+        let profit = sell_stock(&pool, user_id, "AAPL", 1, 100.0).await.unwrap();
+        assert_eq!(profit, 85.0);
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio[0].quantity, 3);
+        assert_eq!(portfolio[0].total_price, 45.0);
+        assert_eq!(portfolio[0].average_price(), Some(15.0));
+
+        // Fully closed by selling, then bought again: nothing from the old position is left.
+        let profit = sell_stock(&pool, user_id, "AAPL", 3, 100.0).await.unwrap();
+        assert_eq!(profit, 255.0);
+        assert!(get_portfolio(&pool, user_id).await.unwrap().is_empty());
+
+        buy_stock(&pool, user_id, "AAPL", 4, 5.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio.len(), 1);
+        assert_eq!(portfolio[0].quantity, 4);
+        assert_eq!(portfolio[0].total_price, 20.0);
+
+        let (quantity, profit) = close_position(&pool, user_id, "AAPL", 6.0).await.unwrap();
+        assert_eq!((quantity, profit), (4, 4.0));
+
+        // And once more after CLOSE.
+        buy_stock(&pool, user_id, "AAPL", 1, 7.5, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio[0].quantity, 1);
+        assert_eq!(portfolio[0].total_price, 7.5);
+    }
+
+    #[tokio::test]
+    async fn oversized_buys_fail_cleanly() {
+        let pool = test_pool("quantity_overflow").await;
+        let user_id = test_user(&pool, "whale").await;
+
+        buy_stock(&pool, user_id, "AAPL", i32::MAX, 1.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        // One more share would wrap past i32::MAX.
+        for quantity in [1, i32::MAX] {
+            assert_eq!(
+                buy_stock(&pool, user_id, "AAPL", quantity, 1.0, DEFAULT_MAX_POSITIONS).await,
+                Err(ERR_QUANTITY_TOO_LARGE.to_string())
+            );
+        }
+        assert_eq!(
+            buy_stock(
+                &pool,
+                user_id,
+                "MSFT",
+                i32::MAX,
+                f64::MAX,
+                DEFAULT_MAX_POSITIONS
+            )
+            .await,
+            Err(ERR_QUANTITY_TOO_LARGE.to_string())
+        );
+        for quantity in [0, -5] {
+            assert_eq!(
+                buy_stock(&pool, user_id, "AAPL", quantity, 1.0, DEFAULT_MAX_POSITIONS).await,
+                Err(ERR_INVALID_QUANTITY.to_string())
+            );
+            assert_eq!(
+                sell_stock(&pool, user_id, "AAPL", quantity, 1.0).await,
+                Err(ERR_INVALID_QUANTITY.to_string())
+            );
+        }
+
+        // The failed trades left the position as it was.
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio.len(), 1);
+        assert_eq!(portfolio[0].quantity, i32::MAX);
+        assert_eq!(portfolio[0].total_price, i32::MAX as f64);
+    }
+
+    // A step of a trading scenario, sells carry the profit or error they must return.
+    enum Trade {
+        Buy(i32, f64),
+        Sell(i32, f64, Result<f64, &'static str>),
+    }
+
+    // The trade and the (quantity, price_total) expected after it, None for no row.
+    type Step = (Trade, Option<(i32, f64)>);
+
+    #[tokio::test]
+    async fn trade_math_matrix() {
+        use Trade::{Buy, Sell};
+
+        let cases: Vec<(&str, Vec<Step>)> = vec![
+            ("first buy", vec![(Buy(10, 5.0), Some((10, 50.0)))]),
+            (
+                "additional buy at a different price",
+                vec![
+                    (Buy(2, 10.0), Some((2, 20.0))),
+                    (Buy(2, 20.0), Some((4, 60.0))),
+                ],
+            ),
+            (
+                "partial sell takes out the average cost",
+                vec![
+                    (Buy(4, 10.0), Some((4, 40.0))),
+                    (Buy(4, 20.0), Some((8, 120.0))),
+                    (Sell(2, 30.0, Ok(30.0)), Some((6, 90.0))),
+                    (Sell(3, 10.0, Ok(-15.0)), Some((3, 45.0))),
+                ],
+            ),
+            (
+                "full sell leaves no basis",
+                vec![
+                    (Buy(3, 10.0), Some((3, 30.0))),
+                    (Sell(3, 12.0, Ok(6.0)), None),
+                    (Buy(1, 8.0), Some((1, 8.0))),
+                ],
+            ),
+            (
+                "oversell is rejected and changes nothing",
+                vec![
+                    (Buy(2, 10.0), Some((2, 20.0))),
+                    (
+                        Sell(3, 10.0, Err("You have only 2 actions of given stock!.")),
+                        Some((2, 20.0)),
+                    ),
+                ],
+            ),
+            (
+                "sell after a full sell",
+                vec![
+                    (Buy(1, 10.0), Some((1, 10.0))),
+                    (Sell(1, 10.0, Ok(0.0)), None),
+                    (Sell(1, 10.0, Err(ERR_NO_POSITION)), None),
+                ],
+            ),
+            (
+                "sell with no position",
+                vec![(Sell(1, 10.0, Err(ERR_NO_POSITION)), None)],
+            ),
+        ];
+
+        let pool = test_pool("trade_math").await;
+        for (case, (name, steps)) in cases.into_iter().enumerate() {
+            let user_id = test_user(&pool, &format!("matrix{case}")).await;
+            for (step, (trade, expected)) in steps.into_iter().enumerate() {
+                match trade {
+                    Buy(quantity, price) => {
+                        buy_stock(
+                            &pool,
+                            user_id,
+                            "AAPL",
+                            quantity,
+                            price,
+                            DEFAULT_MAX_POSITIONS,
+                        )
+                        .await
+                        .unwrap();
+                    }
+                    Sell(quantity, price, outcome) => {
+                        let sold = sell_stock(&pool, user_id, "AAPL", quantity, price).await;
+                        assert_eq!(sold, outcome.map_err(str::to_string), "{name}, step {step}");
+                    }
+                }
+                let position = get_portfolio(&pool, user_id)
+                    .await
+                    .unwrap()
+                    .first()
+                    .map(|stock| (stock.quantity, stock.total_price));
+                assert_eq!(position, expected, "{name}, step {step}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_account_clears_positions_only() {
+        let pool = test_pool("reset_account").await;
+        let user_id = register_user(&pool, "demo", "hunter2").await.unwrap();
+        let other_id = register_user(&pool, "other", "hunter2").await.unwrap();
+
+        buy_stock(&pool, user_id, "AAPL", 3, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, user_id, "MSFT", 1, 300.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, other_id, "AAPL", 1, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        let alert = AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 200.0,
+        };
+        add_alert(&pool, user_id, &alert).await.unwrap();
+
+        let err = reset_account(&pool, user_id, "wrong").await.unwrap_err();
+        assert_eq!(err, ERR_WRONG_PASSWORD);
+        assert_eq!(get_portfolio(&pool, user_id).await.unwrap().len(), 2);
+
+        assert_eq!(reset_account(&pool, user_id, "hunter2").await.unwrap(), 2);
+        assert!(get_portfolio(&pool, user_id).await.unwrap().is_empty());
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap().len(),
+            1
+        );
+        assert_eq!(get_portfolio(&pool, other_id).await.unwrap().len(), 1);
+        assert_eq!(login_user(&pool, "demo", "hunter2").await.unwrap(), user_id);
+
+        // Trading starts from scratch afterwards.
+        buy_stock(&pool, user_id, "AAPL", 1, 12.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!((portfolio[0].quantity, portfolio[0].total_price), (1, 12.0));
+    }
+
+    #[tokio::test]
+    async fn export_bundles_the_account_and_survives_json() {
+        let pool = test_pool("export").await;
+        let user_id = test_user(&pool, "saver").await;
+        let other = test_user(&pool, "someone_else").await;
+
+        add_alert(
+            &pool,
+            user_id,
+            &AlertRequest {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+            },
+        )
+        .await
+        .unwrap();
+        add_alert(
+            &pool,
+            other,
+            &AlertRequest {
+                symbol: "MSFT".into(),
+                direction: AlertDirection::Below,
+                threshold: 300.0,
+            },
+        )
+        .await
+        .unwrap();
+        buy_stock(&pool, user_id, "AAPL", 4, 100.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, user_id, "TSLA", 1, 250.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        sell_stock(&pool, user_id, "TSLA", 1, 260.0).await.unwrap();
+        set_bracket(&pool, user_id, "AAPL", Some(90.0), None)
+            .await
+            .unwrap();
+
+        let export = export_user_data(&pool, user_id, false).await.unwrap();
+        assert_eq!(
+            export,
+            UserExport {
+                username: "saver".into(),
+                alerts: vec![StoredAlert {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 200.0,
+                }],
+                positions: vec![PortfolioStock {
+                    symbol: "AAPL".into(),
+                    quantity: 4,
+                    total_price: 400.0,
+                }],
+                brackets: vec![StoredBracket {
+                    symbol: "AAPL".into(),
+                    stop: Some(90.0),
+                    target: None,
+                }],
+            }
+        );
+
+        let json = serde_json::to_string(&export).unwrap();
+        assert!(!json.contains('\n'));
+        assert_eq!(serde_json::from_str::<UserExport>(&json).unwrap(), export);
+    }
+
+    #[tokio::test]
+    async fn bracket_sells_position_when_price_crosses_stop() {
+        let pool = test_pool("brackets").await;
+        let user_id = test_user(&pool, "hedger").await;
+
+        assert_eq!(
+            set_bracket(&pool, user_id, "AAPL", Some(90.0), None).await,
+            Err(ERR_NO_POSITION.to_string())
+        );
+        buy_stock(&pool, user_id, "AAPL", 10, 100.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, user_id, "MSFT", 1, 300.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        for (stop, target) in [
+            (None, None),
+            (Some(120.0), Some(110.0)),
+            (Some(f64::NAN), None),
+        ] {
+            assert_eq!(
+                set_bracket(&pool, user_id, "AAPL", stop, target).await,
+                Err(ERR_INVALID_BRACKET.to_string())
+            );
+        }
+        set_bracket(&pool, user_id, "AAPL", Some(90.0), Some(130.0))
+            .await
+            .unwrap();
+        set_bracket(&pool, user_id, "MSFT", None, Some(400.0))
+            .await
+            .unwrap();
+
+        // The mock price walks down towards the stop, MSFT stays below its target.
+        for price in [100.0, 95.0, 90.01] {
+            let prices = HashMap::from([("AAPL".to_string(), price), ("MSFT".to_string(), 310.0)]);
+            assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 0);
+        }
+        assert!(take_bracket_fills(&pool, user_id).await.unwrap().is_empty());
+
+        let prices = HashMap::from([("AAPL".to_string(), 85.0), ("MSFT".to_string(), 310.0)]);
+        assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 1);
+        // Already filled, a second pass doesn't sell again.
+        assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 0);
+
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert!(portfolio.iter().all(|p| p.symbol != "AAPL"));
+
+        let fills = take_bracket_fills(&pool, user_id).await.unwrap();
+        assert_eq!(
+            fills,
+            vec![BracketFill {
+                symbol: "AAPL".into(),
+                quantity: 10,
+                price: 85.0,
+                profit: -150.0,
+            }]
+        );
+        assert!(take_bracket_fills(&pool, user_id).await.unwrap().is_empty());
+
+        // MSFT is still armed until cancelled; cancelling twice is an error.
+        cancel_bracket(&pool, user_id, "MSFT").await.unwrap();
+        assert_eq!(
+            cancel_bracket(&pool, user_id, "MSFT").await,
+            Err(ERR_BRACKET_NOT_FOUND.to_string())
+        );
+
+        // A bracket whose position was sold by hand goes away quietly.
+        set_bracket(&pool, user_id, "MSFT", Some(1.0), None)
+            .await
+            .unwrap();
+        sell_stock(&pool, user_id, "MSFT", 1, 310.0).await.unwrap();
+        let prices = HashMap::from([("MSFT".to_string(), 0.5)]);
+        assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 0);
+        assert!(take_bracket_fills(&pool, user_id).await.unwrap().is_empty());
+        assert_eq!(
+            cancel_bracket(&pool, user_id, "MSFT").await,
+            Err(ERR_BRACKET_NOT_FOUND.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_fill_keeps_the_position() {
+        let pool = test_pool("bracket_fill_atomic").await;
+        let user_id = test_user(&pool, "holder").await;
+
+        buy_stock(&pool, user_id, "AAPL", 4, 100.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        set_bracket(&pool, user_id, "AAPL", Some(90.0), None)
+            .await
+            .unwrap();
+        // The fill can't be recorded, so the sale must not happen either.
+        sqlx::query(
+            "CREATE TRIGGER refuse_fill BEFORE UPDATE ON brackets \
+             BEGIN SELECT RAISE(ABORT, 'disk gone'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let prices = HashMap::from([("AAPL".to_string(), 80.0)]);
+        assert!(execute_brackets(&pool, &prices).await.is_err());
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(
+            (portfolio[0].quantity, portfolio[0].total_price),
+            (4, 400.0)
+        );
+
+        // Still armed: once the fill can be written the position is sold.
+        sqlx::query("DROP TRIGGER refuse_fill")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 1);
+        assert!(get_portfolio(&pool, user_id).await.unwrap().is_empty());
+        assert_eq!(
+            take_bracket_fills(&pool, user_id).await.unwrap()[0].quantity,
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_all_orders_drops_every_pending_bracket() {
+        let pool = test_pool("cancel_all_orders").await;
+        let user_id = test_user(&pool, "panicker").await;
+        let other = test_user(&pool, "bystander").await;
+
+        for symbol in ["AAPL", "MSFT", "TSLA"] {
+            buy_stock(&pool, user_id, symbol, 2, 100.0, DEFAULT_MAX_POSITIONS)
+                .await
+                .unwrap();
+            set_bracket(&pool, user_id, symbol, Some(50.0), Some(150.0))
+                .await
+                .unwrap();
+        }
+        buy_stock(&pool, other, "AAPL", 1, 100.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        set_bracket(&pool, other, "AAPL", Some(50.0), None)
+            .await
+            .unwrap();
+        // TSLA is filled before the cancel, its fill must still be reported.
+        let prices = HashMap::from([("TSLA".to_string(), 40.0)]);
+        assert_eq!(execute_brackets(&pool, &prices).await.unwrap(), 1);
+
+        assert_eq!(cancel_all_orders(&pool, user_id).await, Ok(2));
+        assert_eq!(cancel_all_orders(&pool, user_id).await, Ok(0));
+        for symbol in ["AAPL", "MSFT"] {
+            assert_eq!(
+                cancel_bracket(&pool, user_id, symbol).await,
+                Err(ERR_BRACKET_NOT_FOUND.to_string())
+            );
+        }
+        assert_eq!(take_bracket_fills(&pool, user_id).await.unwrap().len(), 1);
+
+        // Positions and other users' brackets are left alone.
+        assert_eq!(get_portfolio(&pool, user_id).await.unwrap().len(), 2);
+        cancel_bracket(&pool, other, "AAPL").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn leaderboard_ranks_by_return() {
+        let pool = test_pool("leaderboard").await;
+        let alice = test_user(&pool, "alice").await;
+        let bob = test_user(&pool, "bob").await;
+        let carol = test_user(&pool, "carol").await;
+
+        // alice: 10 AAPL at 10, now 12 -> +20%.
+        buy_stock(&pool, alice, "AAPL", 10, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        // bob: 200 at cost, now worth 60 + 50 -> -45%; the unpriced position doesn't count.
+        buy_stock(&pool, bob, "AAPL", 5, 20.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, bob, "MSFT", 1, 100.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        buy_stock(&pool, bob, "XYZ", 3, 1.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        // carol only holds something without a price, so she isn't ranked.
+        buy_stock(&pool, carol, "XYZ", 1, 5.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+
+        let prices = HashMap::from([("AAPL".to_string(), 12.0), ("MSFT".to_string(), 50.0)]);
+        let board = get_leaderboard(&pool, &prices, 10).await.unwrap();
+        let ranked: Vec<(i64, &str, f64)> = board
+            .iter()
+            .map(|(id, entry)| (*id, entry.name.as_str(), entry.return_pct))
+            .collect();
+        assert_eq!(ranked, vec![(alice, "alice", 20.0), (bob, "bob", -45.0)]);
+
+        let board = get_leaderboard(&pool, &prices, 1).await.unwrap();
+        assert_eq!(board.len(), 1);
+        assert_eq!(board[0].0, alice);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_register_same_username() {
+        let pool = test_pool("concurrent_register").await;
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for password in ["one", "two"] {
+            let pool = pool.clone();
+            tasks.spawn(async move { register_user(&pool, "racer", password).await });
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.unwrap());
+        }
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(
+            results
+                .iter()
+                .any(|r| r.as_ref().err().map(String::as_str) == Some(ERR_USER_EXISTS))
+        );
+
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username = 'racer'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(users, 1);
+    }
+
+    #[tokio::test]
+    async fn hashing_failures_are_internal_errors() {
+        let pool = test_pool("hashing_failures").await;
+        // Valid Argon2 params, but the output is longer than a PHC string can hold.
+        let params = argon2::Params::new(
+            argon2::Params::DEFAULT_M_COST,
+            argon2::Params::DEFAULT_T_COST,
+            argon2::Params::DEFAULT_P_COST,
+            Some(128),
+        )
+        .unwrap();
+        let broken = Argon2::from(params);
+
+        let err = register_user_with(&pool, &broken, "carol", "hunter2")
+            .await
+            .unwrap_err();
+        assert_eq!(err, ERR_INTERNAL);
+        let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(users, 0);
+
+        // A duplicate is still told apart from a broken hasher.
+        register_user(&pool, "carol", "hunter2").await.unwrap();
+        let err = register_user(&pool, "carol", "hunter2").await.unwrap_err();
+        assert_eq!(err, ERR_USER_EXISTS);
+
+        // So is an unreadable stored hash from a wrong password.
+        assert_eq!(
+            login_user(&pool, "carol", "wrong").await.unwrap_err(),
+            "Invalid username or password"
+        );
+        let user_id = test_user(&pool, "mallory").await;
+        assert_eq!(
+            login_user(&pool, "mallory", "x").await.unwrap_err(),
+            ERR_INTERNAL
+        );
+        assert_eq!(
+            reset_account(&pool, user_id, "x").await.unwrap_err(),
+            ERR_INTERNAL
+        );
+    }
+
+    #[tokio::test]
+    async fn price_cache_keeps_the_last_price() {
+        let pool = test_pool("price_cache").await;
+        assert!(cached_prices(&pool, 0).await.unwrap().is_empty());
+
+        let prices = [("AAPL".to_string(), 10.0), ("MSFT".to_string(), 300.0)];
+        cache_prices(&pool, &prices, &[]).await.unwrap();
+        cache_prices(&pool, &[("AAPL".to_string(), 12.5)], &["MSFT".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            cached_prices(&pool, 0).await.unwrap(),
+            [("AAPL".to_string(), 12.5)]
+        );
+
+        sqlx::query("UPDATE price_cache SET updated_at = 100")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(cached_prices(&pool, 101).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn trade_refusals_are_told_from_database_failures() {
+        let pool = test_pool("trade_refusals").await;
+        let user_id = test_user(&pool, "trader").await;
+        buy_stock(&pool, user_id, "AAPL", 1, 10.0, 1).await.unwrap();
+
+        for refusal in [
+            sell_stock(&pool, user_id, "AAPL", 5, 10.0).await,
+            sell_stock(&pool, user_id, "MSFT", 1, 10.0).await,
+            sell_stock(&pool, user_id, "AAPL", 0, 10.0).await,
+            buy_stock(&pool, user_id, "MSFT", 1, 10.0, 1)
+                .await
+                .map(|()| 0.0),
+            buy_stock(&pool, user_id, "AAPL", 2, f64::MAX, 1)
+                .await
+                .map(|()| 0.0),
+        ] {
+            let err = refusal.unwrap_err();
+            assert!(is_trade_refusal(&err), "{err}");
+        }
+
+        assert!(!is_trade_refusal(ERR_DB_UNAVAILABLE));
+        pool.close().await;
+        let err = sell_stock(&pool, user_id, "AAPL", 1, 10.0)
+            .await
+            .unwrap_err();
+        assert!(!is_trade_refusal(&err), "{err}");
+    }
+
+    #[test]
+    fn only_passing_sqlite_errors_are_transient() {
+        // BUSY, LOCKED, IOERR and the extended SQLITE_BUSY_SNAPSHOT.
+        for code in [5, 6, 10, 517] {
+            assert!(is_transient_code(code), "{code}");
+        }
+        // FULL, CONSTRAINT and the extended SQLITE_CONSTRAINT_UNIQUE.
+        for code in [13, 19, 2067] {
+            assert!(!is_transient_code(code), "{code}");
+        }
+    }
+
+    #[tokio::test]
+    async fn locked_database_is_reported_as_transient() {
+        let path = std::env::temp_dir().join(format!("db_locked_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let opts = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .busy_timeout(std::time::Duration::ZERO);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(2)
+            .connect_with(opts)
+            .await
+            .unwrap();
+        init_database(&pool).await.unwrap();
+        let user_id = test_user(&pool, "blocked").await;
+
+        // Another writer holds the lock, so the trade can't start its transaction.
+        let blocker = pool.begin_with("BEGIN IMMEDIATE").await.unwrap();
+        let err = buy_stock(&pool, user_id, "AAPL", 1, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ERR_DB_UNAVAILABLE);
+
+        // Once the lock is gone the same request goes through.
+        blocker.rollback().await.unwrap();
+        buy_stock(&pool, user_id, "AAPL", 1, 10.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+
+        // Permanent errors keep their own message.
+        let err = sell_stock(&pool, user_id, "MSFT", 1, 10.0)
+            .await
+            .unwrap_err();
+        assert_ne!(err, ERR_DB_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn mixed_case_directions_are_kept() {
+        let pool = test_pool("mixed_case_directions").await;
+        let user_id = test_user(&pool, "casey").await;
+
+        for direction in ["above", "Below"] {
+            sqlx::query(
+                "INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, 'AAPL', ?, 100.0)",
+            )
+            .bind(user_id)
+            .bind(direction)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let alerts = get_user_alerts(&pool, user_id, false).await.unwrap();
+        let directions: Vec<AlertDirection> = alerts.iter().map(|a| a.direction).collect();
+        assert_eq!(
+            directions,
+            vec![AlertDirection::Above, AlertDirection::Below]
+        );
+
+        let duplicate = AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        };
+        // The stored lowercase "above" is the same alert, nothing new is added.
+        add_alert(&pool, user_id, &duplicate).await.unwrap();
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap().len(),
+            2
+        );
+
+        remove_alert(&pool, user_id, "AAPL", AlertDirection::Above)
+            .await
+            .unwrap();
+        let alerts = get_user_alerts(&pool, user_id, false).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].direction, AlertDirection::Below);
+    }
+
+    #[tokio::test]
+    async fn unknown_direction_is_reported() {
+        let pool = test_pool("unknown_direction").await;
+        let user_id = test_user(&pool, "drift").await;
+
+        for direction in ["ABOVE", "SIDEWAYS"] {
+            sqlx::query(
+                "INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, 'AAPL', ?, 100.0)",
+            )
+            .bind(user_id)
+            .bind(direction)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        // Lenient mode keeps the valid alert instead of failing the whole list.
+        let alerts = get_user_alerts(&pool, user_id, false).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].direction, AlertDirection::Above);
+
+        // Strict mode names the offending row, for every reader of the alerts.
+        let err = get_user_alerts(&pool, user_id, true).await.unwrap_err();
+        assert!(err.contains("SIDEWAYS") && err.contains("AAPL"), "{err}");
+        assert!(
+            get_user_alerts_page(&pool, user_id, 0, 10, true)
+                .await
+                .is_err()
+        );
+        assert!(export_user_data(&pool, user_id, true).await.is_err());
+        assert_eq!(
+            get_user_alerts_page(&pool, user_id, 0, 10, false)
+                .await
+                .unwrap()
+                .0
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_add_skips_duplicates() {
+        let pool = test_pool("batch_add").await;
+        let user_id = test_user(&pool, "importer").await;
+
+        let alert = |symbol: &str, direction| AlertRequest {
+            symbol: symbol.into(),
+            direction,
+            threshold: 100.0,
+        };
+        add_alert(&pool, user_id, &alert("AAPL", AlertDirection::Above))
+            .await
+            .unwrap();
+
+        // One duplicate of a stored alert, one repeated inside the batch.
+        let mut batch: Vec<AlertRequest> = ["MSFT", "NVDA", "TSLA", "AMZN"]
+            .into_iter()
+            .flat_map(|symbol| {
+                [
+                    alert(symbol, AlertDirection::Above),
+                    alert(symbol, AlertDirection::Below),
+                ]
+            })
+            .collect();
+        batch.push(alert("AAPL", AlertDirection::Above));
+        batch.push(alert("MSFT", AlertDirection::Below));
+        assert_eq!(batch.len(), 10);
+
+        let added = add_alerts(&pool, user_id, &batch).await.unwrap();
+        assert_eq!(added.len(), 8);
+        assert!(added.iter().all(|a| a.symbol != "AAPL"));
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap().len(),
+            9
+        );
+    }
+
+    #[tokio::test]
+    async fn remove_alert_drops_alert_and_cooldown_together() {
+        let pool = test_pool("remove_alert_atomic").await;
+        let user_id = test_user(&pool, "remover").await;
+        let alert = AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        };
+        add_alert(&pool, user_id, &alert).await.unwrap();
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 1000, 60)
+                .await
+                .unwrap()
+        );
+
+        // The cooldown can't be cleared, so the alert must stay too.
+        sqlx::query(
+            "CREATE TRIGGER keep_cooldown BEFORE DELETE ON alert_triggers \
+             BEGIN SELECT RAISE(ABORT, 'disk gone'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(
+            remove_alert(&pool, user_id, "AAPL", AlertDirection::Above)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap().len(),
+            1
+        );
+
+        sqlx::query("DROP TRIGGER keep_cooldown")
+            .execute(&pool)
+            .await
+            .unwrap();
+        remove_alert(&pool, user_id, "AAPL", AlertDirection::Above)
+            .await
+            .unwrap();
+        assert!(
+            get_user_alerts(&pool, user_id, false)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        // No stale cooldown: the same alert added again may fire right away.
+        add_alert(&pool, user_id, &alert).await.unwrap();
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 1001, 60)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn update_alert_moves_threshold_and_cooldown_together() {
+        let pool = test_pool("update_alert_atomic").await;
+        let user_id = test_user(&pool, "updater").await;
+        let alert = AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        };
+        add_alert(&pool, user_id, &alert).await.unwrap();
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 1000, 60)
+                .await
+                .unwrap()
+        );
+
+        // The cooldown can't be cleared, so the threshold must stay too.
+        sqlx::query(
+            "CREATE TRIGGER keep_cooldown BEFORE DELETE ON alert_triggers \
+             BEGIN SELECT RAISE(ABORT, 'disk gone'); END",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(
+            update_alert(&pool, user_id, "AAPL", AlertDirection::Above, 120.0)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap()[0].threshold,
+            100.0
+        );
+
+        sqlx::query("DROP TRIGGER keep_cooldown")
+            .execute(&pool)
+            .await
+            .unwrap();
+        update_alert(&pool, user_id, "AAPL", AlertDirection::Above, 120.0)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_user_alerts(&pool, user_id, false).await.unwrap()[0].threshold,
+            120.0
+        );
+        // The cleared cooldown lets it fire again right away.
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 1001, 60)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            update_alert(&pool, user_id, "MSFT", AlertDirection::Above, 1.0).await,
+            Err(ERR_ALERT_NOT_FOUND.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn adding_an_existing_alert_updates_its_threshold() {
+        let pool = test_pool("alert_upsert").await;
+        let user_id = test_user(&pool, "replayer").await;
+        let alert = |threshold| AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold,
+        };
+        let thresholds = |pool| async move {
+            get_user_alerts(pool, user_id, false)
+                .await
+                .unwrap()
+                .iter()
+                .map(|a| a.threshold)
+                .collect::<Vec<_>>()
+        };
+
+        // Replayed after a reconnect: still one alert, no error.
+        add_alert(&pool, user_id, &alert(100.0)).await.unwrap();
+        add_alert(&pool, user_id, &alert(100.0)).await.unwrap();
+        assert_eq!(thresholds(&pool).await, [100.0]);
+
+        // A new threshold moves the alert and lets it fire again right away.
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 0, 300)
+                .await
+                .unwrap()
+        );
+        add_alert(&pool, user_id, &alert(120.0)).await.unwrap();
+        assert_eq!(thresholds(&pool).await, [120.0]);
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, 1, 300)
+                .await
+                .unwrap()
+        );
+
+        // Only an unchanged alert counts as skipped in a batch.
+        let batch = [alert(120.0), alert(130.0)];
+        let added = add_alerts(&pool, user_id, &batch).await.unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].threshold, 130.0);
+        assert_eq!(thresholds(&pool).await, [130.0]);
+
+        // The index holds even for writes that skip add_alert.
+        let raw = sqlx::query(
+            "INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, 'AAPL', 'above', 1.0)",
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await;
+        assert!(raw.is_err());
+    }
+
+    #[tokio::test]
+    async fn init_drops_duplicate_alerts_before_indexing() {
+        let pool = test_pool("alert_dedup_migration").await;
+        let user_id = test_user(&pool, "legacy").await;
+
+        // A database from before the index, with a duplicate the old race could leave.
+        sqlx::query("DROP INDEX alerts_user_symbol_direction")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (direction, threshold) in [("ABOVE", 100.0), ("above", 150.0), ("BELOW", 90.0)] {
+            sqlx::query(
+                "INSERT INTO alerts (user_id, symbol, direction, threshold) VALUES (?, 'AAPL', ?, ?)",
+            )
+            .bind(user_id)
+            .bind(direction)
+            .bind(threshold)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        init_database(&pool).await.unwrap();
+        let thresholds: Vec<f64> = get_user_alerts(&pool, user_id, false)
+            .await
+            .unwrap()
+            .iter()
+            .map(|a| a.threshold)
+            .collect();
+        assert_eq!(thresholds, [100.0, 90.0]);
+    }
+
+    #[tokio::test]
+    async fn init_resets_old_style_cost_basis() {
+        let pool = test_pool("cost_basis_migration").await;
+        let user_id = test_user(&pool, "legacy").await;
+
+        // Old bookkeeping: bought 4 AAPL for 40 and sold 2 for 60, bought 2 MSFT for 20 and
+        // sold 1 for 5, bought 3 TSLA for 30 and sold all of them for 45.
+        for (symbol, quantity, price_total) in
+            [("AAPL", 2, -20.0), ("MSFT", 1, 15.0), ("TSLA", 0, -15.0)]
+        {
+            sqlx::query(
+                "INSERT INTO positions (user_id, symbol, quantity, price_total) VALUES (?, ?, ?, ?)",
+            )
+            .bind(user_id)
+            .bind(symbol)
+            .bind(quantity)
+            .bind(price_total)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        init_database(&pool).await.unwrap();
+        // Closed rows aren't part of the portfolio any more, so read the table itself.
+        let rows: Vec<(String, i32, f64)> = sqlx::query_as(
+            "SELECT symbol, quantity, price_total FROM positions WHERE user_id = ? ORDER BY id",
+        )
+        .bind(user_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("AAPL".to_string(), 2, 0.0),
+                ("MSFT".to_string(), 1, 15.0),
+                ("TSLA".to_string(), 0, 0.0),
+            ]
+        );
+
+        // Running it again leaves rows kept under the new bookkeeping alone.
+        buy_stock(&pool, user_id, "AAPL", 1, 7.0, DEFAULT_MAX_POSITIONS)
+            .await
+            .unwrap();
+        init_database(&pool).await.unwrap();
+        let aapl = get_portfolio(&pool, user_id).await.unwrap().remove(0);
+        assert_eq!((aapl.quantity, aapl.total_price), (3, 7.0));
+    }
+
+    #[tokio::test]
+    async fn removing_missing_alert_is_an_error() {
+        let pool = test_pool("remove_missing").await;
+        let user_id = test_user(&pool, "remover").await;
+        let other_id = test_user(&pool, "other").await;
+
+        let alert = AlertRequest {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 100.0,
+        };
+        add_alert(&pool, other_id, &alert).await.unwrap();
+
+        // Neither a missing alert nor someone else's counts as removed.
+        for (symbol, direction) in [
+            ("MSFT", AlertDirection::Above),
+            ("AAPL", AlertDirection::Below),
+            ("AAPL", AlertDirection::Above),
+        ] {
+            assert_eq!(
+                remove_alert(&pool, user_id, symbol, direction)
+                    .await
+                    .unwrap_err(),
+                ERR_ALERT_NOT_FOUND
+            );
+        }
+
+        add_alert(&pool, user_id, &alert).await.unwrap();
+        remove_alert(&pool, user_id, "AAPL", AlertDirection::Above)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_user_alerts(&pool, other_id, false).await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn alert_trigger_respects_cooldown() {
+        let pool = test_pool("alert_cooldown").await;
+        let user_id = test_user(&pool, "cooler").await;
+        let claim =
+            |now| claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Above, now, 300);
+
+        assert!(claim(0).await.unwrap());
+        assert!(!claim(10).await.unwrap());
+        assert!(!claim(299).await.unwrap());
+        assert!(claim(300).await.unwrap());
+        // The other direction has its own window.
+        assert!(
+            claim_alert_trigger(&pool, user_id, "AAPL", AlertDirection::Below, 10, 300)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn preferences_survive_a_reconnect() {
+        let pool = test_pool("preferences").await;
+        let user_id = test_user(&pool, "quiet").await;
+        assert_eq!(
+            get_preferences(&pool, user_id).await.unwrap(),
+            Preferences::default()
+        );
+
+        let prefs = Preferences {
+            triggers: false,
+            fills: true,
+        };
+        set_preferences(&pool, user_id, &prefs).await.unwrap();
+        pool.close().await;
+
+        let path = std::env::temp_dir().join(format!("preferences_{}.db", std::process::id()));
+        let pool = SqlitePoolOptions::new()
+            .connect_with(SqliteConnectOptions::new().filename(&path))
+            .await
+            .unwrap();
+        init_database(&pool).await.unwrap();
+        assert_eq!(get_preferences(&pool, user_id).await.unwrap(), prefs);
+    }
+
+    #[tokio::test]
+    async fn seeding_populates_new_user() {
+        let pool = test_pool("seed_user").await;
+        let user_id = register_user(&pool, "newbie", "pass").await.unwrap();
+
+        let seed: UserSeed = serde_json::from_str(
+            r#"{
+                "alerts": [
+                    {"symbol": "AAPL", "direction": "ABOVE", "threshold": 240.0},
+                    {"symbol": "AAPL", "direction": "ABOVE", "threshold": 250.0}
+                ],
+                "positions": [{"symbol": "MSFT", "quantity": 3, "total_price": 1200.0}]
+            }"#,
+        )
+        .unwrap();
+        // The repeated alert doesn't fail the seed, the later threshold wins.
+        seed_user(&pool, user_id, &seed).await.unwrap();
+
+        let alerts = get_user_alerts(&pool, user_id, false).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].symbol, "AAPL");
+        assert_eq!(alerts[0].direction, AlertDirection::Above);
+        assert_eq!(alerts[0].threshold, 250.0);
+
+        let portfolio = get_portfolio(&pool, user_id).await.unwrap();
+        assert_eq!(portfolio.len(), 1);
+        assert_eq!(portfolio[0].symbol, "MSFT");
+        assert_eq!(portfolio[0].quantity, 3);
+        assert_eq!(portfolio[0].total_price, 1200.0);
+
+        // Keep the example in the repo loadable.
+        let example: UserSeed =
+            serde_json::from_str(include_str!("../new_user_seed.example.json")).unwrap();
+        assert!(!example.alerts.is_empty());
+    }
 }
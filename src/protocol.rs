@@ -1,21 +1,219 @@
 // Expected format:
 
 // ADD <SYMBOL> <ABOVE|BELOW> <THRESHOLD>
+// ADDMANY <SYMBOL> <ABOVE|BELOW> <THRESHOLD> [<SYMBOL> <ABOVE|BELOW> <THRESHOLD> ...]
 // DEL <SYMBOL> <ABOVE|BELOW>
+// UPDATE <SYMBOL> <ABOVE|BELOW> <THRESHOLD>, moves the threshold of an alert the user has
+// RESET <PASSWORD>
+// LEADERBOARD [LIMIT]
+// BRACKET <SYMBOL> <STOP> <TARGET>, '-' for the side that isn't set
+// UNBRACKET <SYMBOL>
+// CANCELALL, drops every armed bracket of the user
+// WHOAMI
+// TIME
+// EXPORT
+// PREFS [TRIGGERS=<ON|OFF> FILLS=<ON|OFF>], without arguments only asks for the current ones
+// PING <NONCE>, also before login, the server echoes the nonce so the client can time the round trip
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 // TRIGGER <SYMBOL> <DIRECTION> <THRESHOLD> <CURRENT>
-// ALERTADDED <SYMBOL> <DIRECTION> <THRESHOLD>
+// PRICE <SYMBOL> <PRICE> [<VOLUME> <52W_LOW> <52W_HIGH>], '-' for a missing value
+// ALERTADDED <SYMBOL> <DIRECTION> <THRESHOLD> [NOPRICE|NOW], NOPRICE when the symbol has no price yet,
+// NOW when the price is already past the threshold
+// ALERTFAILED <SYMBOL> <DIRECTION> <THRESHOLD> <REASON>, the reason escaped like ERR text
+// ALERTSADDED <ADDED> <SKIPPED> [<UNPRICED>], UNPRICED of the batch's symbols have no price yet
+// TRADEREJECTED <SYMBOL> <REASON>, BUY, SELL or CLOSE failed, the reason escaped like ERR text
+// ALERTUPDATED <SYMBOL> <DIRECTION> <THRESHOLD>
+// HISTORY <SYMBOL> <TIMESTAMP>:<CLOSE> ...
+// SYMBOLS <HAS_MORE> <SYMBOL>[:<NAME>] ..., names escaped like ERR text with every space as \s
+// LEADERBOARD <NAME>:<RETURN_PCT> ...
+// BRACKET <SYMBOL> <STOP> <TARGET> and UNBRACKET <SYMBOL> confirm the client's request
+// BRACKETTRIGGERED <SYMBOL> <QUANTITY> <PRICE> <PROFIT>
+// CANCELALL <COUNT>, how many brackets CANCELALL dropped
+// WHOAMI <USER_ID> <USERNAME>
+// TIME <UNIX_SECS>, the server's clock, so clients can tell how far theirs is off
+// PREFS TRIGGERS=<ON|OFF> FILLS=<ON|OFF>, the user's notification preferences
+// EXPORT <JSON>, everything stored for the account (database::UserExport) on a single line
+// DATA <JSON>, with "partial": true and the "failed" sections when part of it couldn't be loaded
 // ERR <MESSAGE>
+// PING, answered by the client with PONG
+// PONG <NONCE>, the reply to the client's PING with its nonce unchanged
+
+// Any line may start with an optional request id: #<ID> <COMMAND> ...
+// The server echoes the id on every reply to that request.
 use crate::database::{PortfolioStock, StoredAlert};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct Price {
     pub value: f64,
 }
 
+// Extra quote context from Yahoo, any of it may be missing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct QuoteStats {
+    pub volume: Option<u64>,
+    pub low_52w: Option<f64>,
+    pub high_52w: Option<f64>,
+}
+
+impl QuoteStats {
+    pub fn is_empty(&self) -> bool {
+        self.volume.is_none() && self.low_52w.is_none() && self.high_52w.is_none()
+    }
+
+    // Human readable suffix for price lines, empty when nothing is known.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        if let Some(volume) = self.volume {
+            out.push_str(&format!(" volume={volume}"));
+        }
+        if self.low_52w.is_some() || self.high_52w.is_some() {
+            out.push_str(&format!(
+                " 52w={}..{}",
+                optional_to_wire(self.low_52w),
+                optional_to_wire(self.high_52w)
+            ));
+        }
+        out
+    }
+}
+
+fn optional_to_wire<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+fn bracket_to_wire(symbol: &str, stop: Option<f64>, target: Option<f64>) -> String {
+    format!(
+        "{CMD_BRACKET} {} {} {}\n",
+        symbol,
+        optional_to_wire(stop),
+        optional_to_wire(target)
+    )
+}
+
+fn parse_bracket<'a>(
+    parts: &mut impl Iterator<Item = &'a str>,
+) -> Option<(String, Option<f64>, Option<f64>)> {
+    let symbol = parts.next()?.to_string();
+    let stop = parse_optional(parts.next()?)?;
+    let target = parse_optional(parts.next()?)?;
+    Some((symbol, stop, target))
+}
+
+fn parse_optional<T: std::str::FromStr>(token: &str) -> Option<Option<T>> {
+    if token == "-" {
+        Some(None)
+    } else {
+        token.parse().ok().map(Some)
+    }
+}
+
+// Which events the server pushes without being asked: alert triggers and bracket fills.
+// A new user only gets triggers, fills stay stored until they are wanted.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub triggers: bool,
+    pub fills: bool,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            triggers: true,
+            fills: false,
+        }
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "ON" } else { "OFF" }
+}
+
+fn preferences_to_wire(prefs: &Preferences) -> String {
+    format!(
+        "{CMD_PREFS} TRIGGERS={} FILLS={}\n",
+        on_off(prefs.triggers),
+        on_off(prefs.fills)
+    )
+}
+
+// Both keys are required, in any order and any case.
+fn parse_preferences<'a>(parts: impl Iterator<Item = &'a str>) -> Option<Preferences> {
+    let mut triggers = None;
+    let mut fills = None;
+    for token in parts {
+        let (key, value) = token.split_once('=')?;
+        let value = match value.to_ascii_uppercase().as_str() {
+            "ON" => true,
+            "OFF" => false,
+            _ => return None,
+        };
+        match key.to_ascii_uppercase().as_str() {
+            "TRIGGERS" => triggers = Some(value),
+            "FILLS" => fills = Some(value),
+            _ => return None,
+        }
+    }
+    Some(Preferences {
+        triggers: triggers?,
+        fills: fills?,
+    })
+}
+
+// One intraday close, timestamp in unix seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct HistoryPoint {
+    pub timestamp: i64,
+    pub close: f64,
+}
+
+// One leaderboard row: return on the cost of held shares at current prices, in percent.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub return_pct: f64,
+}
+
+impl LeaderboardEntry {
+    pub fn summary(&self) -> String {
+        format!("{} {:+.2}%", self.name, self.return_pct)
+    }
+}
+
+// How far back a HISTORY request reaches from the newest point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HistoryRange {
+    Hour,
+    Day,
+}
+
+impl HistoryRange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryRange::Hour => "HOUR",
+            HistoryRange::Day => "DAY",
+        }
+    }
+
+    pub fn as_msg(token: &str) -> Option<Self> {
+        match token {
+            "HOUR" => Some(HistoryRange::Hour),
+            "DAY" => Some(HistoryRange::Day),
+            _ => None,
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            HistoryRange::Hour => 60 * 60,
+            HistoryRange::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AlertDirection {
     Above,
     Below,
@@ -30,11 +228,40 @@ impl AlertDirection {
     }
 
     pub fn as_msg(token: &str) -> Option<Self> {
-        match token {
-            "ABOVE" => Some(AlertDirection::Above),
-            "BELOW" => Some(AlertDirection::Below),
-            _ => None,
-        }
+        token.parse().ok()
+    }
+}
+
+// Case-insensitive, so rows or lines written as "above"/"Below" still parse.
+impl std::str::FromStr for AlertDirection {
+    type Err = String;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        [AlertDirection::Above, AlertDirection::Below]
+            .into_iter()
+            .find(|direction| direction.as_str().eq_ignore_ascii_case(token))
+            .ok_or_else(|| format!("unknown alert direction {token:?}, expected ABOVE or BELOW"))
+    }
+}
+
+// User-facing text shows the wire token too, so messages read ABOVE/BELOW everywhere.
+impl std::fmt::Display for AlertDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+// JSON (DATA payloads, seed files, --json output) uses the same ABOVE/BELOW tokens as the wire.
+impl Serialize for AlertDirection {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for AlertDirection {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        token.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -49,11 +276,23 @@ pub struct AlertRequest {
 pub enum ClientMsg {
     AddAlert(AlertRequest),
 
+    // Many alerts in one round trip, stored in a single transaction.
+    AddAlerts {
+        alerts: Vec<AlertRequest>,
+    },
+
     RemoveAlert {
         symbol: String,
         direction: AlertDirection,
     },
 
+    // Changes the threshold in place, the alert has to exist already.
+    UpdateAlert {
+        symbol: String,
+        direction: AlertDirection,
+        new_threshold: f64,
+    },
+
     RegisterClient {
         username: String,
         password: String,
@@ -78,10 +317,73 @@ pub enum ClientMsg {
         quantity: i32,
     },
 
-    GetAllClientData,
+    ClosePosition {
+        symbol: String,
+    },
+
+    // Both lists are paged with the same offset/limit, the server picks a default limit when it's missing.
+    GetAllClientData {
+        offset: usize,
+        limit: Option<usize>,
+    },
+
+    ListSymbols {
+        offset: usize,
+        limit: Option<usize>,
+    },
+
+    GetHistory {
+        symbol: String,
+        range: HistoryRange,
+    },
+
+    // Reply to the server's PING, keeps a quiet connection from being dropped as idle.
+    Pong,
+
+    // Latency probe, the nonce is whatever the client wants back (a counter, a timestamp).
+    Ping {
+        nonce: u64,
+    },
+
+    // Drops every position of the logged-in user, the password is asked again to confirm.
+    ResetAccount {
+        password: String,
+    },
+
+    // Best returns first, the server picks the size when the limit is missing.
+    GetLeaderboard {
+        limit: Option<usize>,
+    },
+
+    // Sell the whole position once the price falls to `stop` or rises to `target`.
+    SetBracket {
+        symbol: String,
+        stop: Option<f64>,
+        target: Option<f64>,
+    },
+
+    CancelBracket {
+        symbol: String,
+    },
+
+    // Every armed bracket of the user at once, answered with how many there were.
+    CancelAllOrders,
+
+    // Which account this connection is logged into.
+    WhoAmI,
+
+    GetServerTime,
+
+    // All of the user's alerts, positions and brackets at once.
+    ExportData,
+
+    GetPreferences,
+
+    // Answered with the saved preferences, they apply from the next heartbeat on.
+    SetPreferences(Preferences),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum ServerMsg {
     AlertTriggered {
         symbol: String,
@@ -90,10 +392,14 @@ pub enum ServerMsg {
         current_price: Price,
     },
 
+    // `price_available` is false when the alert was stored before the scraper has a price,
+    // `triggers_now` when the current price already satisfies it, so it fires on the next check.
     AlertAdded {
         symbol: String,
         direction: AlertDirection,
         threshold: f64,
+        price_available: bool,
+        triggers_now: bool,
     },
 
     AlertRemoved {
@@ -101,13 +407,87 @@ pub enum ServerMsg {
         direction: AlertDirection,
     },
 
+    AlertUpdated {
+        symbol: String,
+        direction: AlertDirection,
+        threshold: f64,
+    },
+
+    // ADD was rejected, carries the alert so the client knows which one failed.
+    AlertFailed {
+        symbol: String,
+        direction: AlertDirection,
+        threshold: f64,
+        reason: String,
+    },
+
+    // BUY, SELL or CLOSE refused, for example selling more shares than the user holds.
+    TradeRejected {
+        symbol: String,
+        reason: String,
+    },
+
+    // Reply to ADDMANY, skipped counts duplicates. Alerts on symbols without a price are
+    // kept like a single ADD does, `unpriced` says how many of them there were.
+    AlertsAdded {
+        added: usize,
+        skipped: usize,
+        unpriced: usize,
+    },
+
     UserLogged,
 
     UserRegistered,
 
+    AccountReset,
+
+    Leaderboard {
+        entries: Vec<LeaderboardEntry>,
+    },
+
+    BracketSet {
+        symbol: String,
+        stop: Option<f64>,
+        target: Option<f64>,
+    },
+
+    BracketCancelled {
+        symbol: String,
+    },
+
+    OrdersCancelled {
+        count: u64,
+    },
+
+    Identity {
+        user_id: i64,
+        username: String,
+    },
+
+    ServerTime {
+        unix_secs: i64,
+    },
+
+    // Compact JSON of a database::UserExport. It must not contain a raw newline, compact
+    // serde_json output never does (newlines in strings are escaped).
+    DataExport {
+        json: String,
+    },
+
+    Preferences(Preferences),
+
+    // Sent when a bracket sold the position, possibly while the user was away.
+    BracketTriggered {
+        symbol: String,
+        quantity: i32,
+        price: f64,
+        profit: f64,
+    },
+
     PriceChecked {
         symbol: String,
         price: f64,
+        stats: QuoteStats,
     },
 
     StockBought {
@@ -118,20 +498,53 @@ pub enum ServerMsg {
     StockSold {
         symbol: String,
         quantity: i32,
+        // Realized profit, only sent when a whole position gets closed.
+        profit: Option<f64>,
     },
 
     AllClientData {
         stocks: Vec<PortfolioStock>,
         alerts: Vec<StoredAlert>,
+        has_more: bool,
+        // Sections that failed to load and are sent empty, see DATA_SECTION_*.
+        failed: Vec<String>,
+    },
+
+    SymbolList {
+        symbols: Vec<String>,
+        // Company names of the symbols on this page that have one.
+        names: HashMap<String, String>,
+        // More symbols can be fetched with a LIST starting after this page.
+        has_more: bool,
+    },
+
+    History {
+        symbol: String,
+        points: Vec<HistoryPoint>,
     },
 
     Error(String),
+
+    Ping,
+
+    // Answer to ClientMsg::Ping.
+    Heartbeat {
+        nonce: u64,
+    },
 }
 
 pub const CMD_ADD: &str = "ADD";
 pub const CMD_DEL: &str = "DEL";
+pub const CMD_UPDATE: &str = "UPDATE";
+pub const CMD_ALERT_UPDATED: &str = "ALERTUPDATED";
 pub const CMD_TRIGGER: &str = "TRIGGER";
 pub const CMD_ALERT_ADDED: &str = "ALERTADDED";
+pub const ALERT_NO_PRICE: &str = "NOPRICE";
+pub const ALERT_TRIGGERS_NOW: &str = "NOW";
+pub const CMD_ALERT_FAILED: &str = "ALERTFAILED";
+pub const CMD_TRADE_REJECTED: &str = "TRADEREJECTED";
+pub const CMD_ADD_MANY: &str = "ADDMANY";
+pub const CMD_ALERTS_ADDED: &str = "ALERTSADDED";
 pub const CMD_ERR: &str = "ERR";
 pub const CMD_LOGIN: &str = "LOGIN";
 pub const CMD_REGISTER: &str = "REGISTER";
@@ -140,10 +553,117 @@ pub const CMD_BUY: &str = "BUY";
 pub const CMD_SELL: &str = "SELL";
 pub const CMD_BOUGHT: &str = "BOUGHT";
 pub const CMD_SOLD: &str = "SOLD";
+pub const CMD_CLOSE: &str = "CLOSE";
+pub const CMD_LIST: &str = "LIST";
+pub const CMD_SYMBOLS: &str = "SYMBOLS";
+pub const CMD_HISTORY: &str = "HISTORY";
+pub const CMD_PING: &str = "PING";
+pub const CMD_PONG: &str = "PONG";
+pub const CMD_RESET: &str = "RESET";
+pub const CMD_LEADERBOARD: &str = "LEADERBOARD";
+pub const CMD_BRACKET: &str = "BRACKET";
+pub const CMD_UNBRACKET: &str = "UNBRACKET";
+pub const CMD_CANCEL_ALL: &str = "CANCELALL";
+pub const CMD_WHOAMI: &str = "WHOAMI";
+pub const CMD_TIME: &str = "TIME";
+pub const CMD_EXPORT: &str = "EXPORT";
+pub const CMD_PREFS: &str = "PREFS";
+pub const CMD_BRACKET_TRIGGERED: &str = "BRACKETTRIGGERED";
 pub const CMD_DATA: &str = "DATA";
 pub const CMD_ALERT_DELETED: &str = "ALERTDELETED";
+pub const REQ_ID_PREFIX: char = '#';
+
+// Sent as an ERR message right before the server closes a connection it can't serve.
+pub const ERR_SERVER_FULL: &str = "server at capacity";
+// Sent for every command except LOGIN, REGISTER, PONG and PING on a connection without a session.
+pub const ERR_NOT_LOGGED_IN: &str = "login required";
+pub const ERR_LOGIN_FAILED: &str = "Failed to log-in!";
+// PRICE misses: the symbol isn't tracked at all vs the scraper hasn't fetched it yet.
+pub const ERR_UNKNOWN_SYMBOL: &str = "unknown symbol";
+pub const ERR_PRICE_NOT_READY: &str = "price not yet available";
+// The database was locked or unreachable, the same request can be retried later.
+pub const ERR_DB_UNAVAILABLE: &str = "database temporarily unavailable, try again";
+// The server broke, not the request: password hashing failed or a stored hash is unreadable.
+pub const ERR_INTERNAL: &str = "internal server error";
+// Sent right before the server drops a connection that neither sent commands nor answered PING.
+pub const ERR_IDLE_TIMEOUT: &str = "idle timeout, closing connection";
+// Sent right before the server drops a connection whose command line is over its size limit.
+pub const ERR_LINE_TOO_LONG: &str = "command too long, closing connection";
+// ADDMANY with more alerts than the server accepts in one batch, nothing is added.
+pub const ERR_BATCH_TOO_LARGE: &str = "too many alerts in one batch";
+// EXPORT of an account whose data is over the server's size limit.
+pub const ERR_EXPORT_TOO_LARGE: &str = "export too large";
+
+// Alert thresholds must be finite and at most this far from zero, past that an alert can't
+// mean anything. The server's limit can be changed (MAX_ALERT_THRESHOLD), the GUI uses this one.
+pub const DEFAULT_MAX_THRESHOLD: f64 = 1_000_000.0;
+pub const ERR_THRESHOLD_NOT_FINITE: &str = "threshold must be a finite number";
+
+// Names of the DATA sections listed in `failed` of a partial snapshot.
+pub const DATA_SECTION_PORTFOLIO: &str = "portfolio";
+pub const DATA_SECTION_ALERTS: &str = "alerts";
+
+pub fn check_threshold(threshold: f64, max: f64) -> Result<(), String> {
+    if !threshold.is_finite() {
+        return Err(ERR_THRESHOLD_NOT_FINITE.to_string());
+    }
+    if threshold.abs() > max {
+        return Err(format!("threshold out of range, the limit is {max}"));
+    }
+    Ok(())
+}
+
+// Splits an optional leading "#<ID>" token from the rest of the line.
+pub fn split_req_id(line: &str) -> (Option<u64>, &str) {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix(REQ_ID_PREFIX) {
+        let (token, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if let Ok(req_id) = token.parse() {
+            return (Some(req_id), rest.trim_start());
+        }
+    }
+    (None, line)
+}
+
+// Windows clients and telnet end lines with \r\n, so the \r has to go before tokenizing.
+fn strip_line_ending(line: &str) -> &str {
+    line.trim_end_matches(['\r', '\n'])
+}
+
+pub fn tag_wire(wire: String, req_id: Option<u64>) -> String {
+    match req_id {
+        Some(req_id) => format!("{REQ_ID_PREFIX}{req_id} {wire}"),
+        None => wire,
+    }
+}
+
+fn page_to_wire(offset: usize, limit: Option<usize>) -> String {
+    match limit {
+        Some(limit) => format!(" {} {}", offset, limit),
+        None => format!(" {}", offset),
+    }
+}
+
+// Reads the optional "[OFFSET [LIMIT]]" tail of a list command, the CLI reads its typed
+// `data` and `symbols` with it too.
+pub fn parse_page<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Option<(usize, Option<usize>)> {
+    let offset: usize = match parts.next() {
+        Some(token) => token.parse().ok()?,
+        None => 0,
+    };
+    let limit: Option<usize> = match parts.next() {
+        Some(token) => Some(token.parse().ok()?),
+        None => None,
+    };
+
+    Some((offset, limit))
+}
 
 impl ClientMsg {
+    pub fn to_wire_with_id(&self, req_id: Option<u64>) -> String {
+        tag_wire(self.to_wire(), req_id)
+    }
+
     pub fn to_wire(&self) -> String {
         match self {
             ClientMsg::AddAlert(alert) => {
@@ -154,9 +674,32 @@ impl ClientMsg {
                     alert.threshold
                 )
             }
+            ClientMsg::AddAlerts { alerts } => {
+                let mut wire = CMD_ADD_MANY.to_string();
+                for alert in alerts {
+                    wire.push_str(&format!(
+                        " {} {} {}",
+                        alert.symbol,
+                        alert.direction.as_str(),
+                        alert.threshold
+                    ));
+                }
+                wire.push('\n');
+                wire
+            }
             ClientMsg::RemoveAlert { symbol, direction } => {
                 format!("{CMD_DEL} {} {}\n", symbol, direction.as_str())
             }
+            ClientMsg::UpdateAlert {
+                symbol,
+                direction,
+                new_threshold,
+            } => format!(
+                "{CMD_UPDATE} {} {} {}\n",
+                symbol,
+                direction.as_str(),
+                new_threshold
+            ),
             ClientMsg::LoginClient { username, password } => {
                 format!("{CMD_LOGIN} {} {}\n", username, password)
             }
@@ -172,15 +715,49 @@ impl ClientMsg {
             ClientMsg::SellStock { symbol, quantity } => {
                 format!("{CMD_SELL} {} {}\n", symbol, quantity)
             }
-            ClientMsg::GetAllClientData => {
-                format!("{CMD_DATA}\n")
+            ClientMsg::ClosePosition { symbol } => {
+                format!("{CMD_CLOSE} {}\n", symbol)
+            }
+            ClientMsg::GetAllClientData { offset, limit } => {
+                format!("{CMD_DATA}{}\n", page_to_wire(*offset, *limit))
+            }
+            ClientMsg::ListSymbols { offset, limit } => {
+                format!("{CMD_LIST}{}\n", page_to_wire(*offset, *limit))
+            }
+
+            ClientMsg::GetHistory { symbol, range } => {
+                format!("{CMD_HISTORY} {} {}\n", symbol, range.as_str())
             }
+            ClientMsg::Pong => format!("{CMD_PONG}\n"),
+            ClientMsg::Ping { nonce } => format!("{CMD_PING} {nonce}\n"),
+            ClientMsg::ResetAccount { password } => format!("{CMD_RESET} {}\n", password),
+            ClientMsg::GetLeaderboard { limit } => match limit {
+                Some(limit) => format!("{CMD_LEADERBOARD} {}\n", limit),
+                None => format!("{CMD_LEADERBOARD}\n"),
+            },
+            ClientMsg::SetBracket {
+                symbol,
+                stop,
+                target,
+            } => bracket_to_wire(symbol, *stop, *target),
+            ClientMsg::CancelBracket { symbol } => format!("{CMD_UNBRACKET} {}\n", symbol),
+            ClientMsg::CancelAllOrders => format!("{CMD_CANCEL_ALL}\n"),
+            ClientMsg::WhoAmI => format!("{CMD_WHOAMI}\n"),
+            ClientMsg::GetServerTime => format!("{CMD_TIME}\n"),
+            ClientMsg::ExportData => format!("{CMD_EXPORT}\n"),
+            ClientMsg::GetPreferences => format!("{CMD_PREFS}\n"),
+            ClientMsg::SetPreferences(prefs) => preferences_to_wire(prefs),
         }
     }
 }
 
+pub fn parse_server_line(line: &str) -> (Option<u64>, Option<ServerMsg>) {
+    let (req_id, rest) = split_req_id(strip_line_ending(line));
+    (req_id, parse_server_msg(rest))
+}
+
 pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
-    let line = line.trim();
+    let (_, line) = split_req_id(strip_line_ending(line));
     if line.is_empty() {
         return None;
     }
@@ -208,11 +785,61 @@ pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
             let symbol = parts.next()?.to_string();
             let direction = AlertDirection::as_msg(parts.next()?)?;
             let threshold: f64 = parts.next()?.parse().ok()?;
+            let (price_available, triggers_now) = match parts.next() {
+                None => (true, false),
+                Some(ALERT_NO_PRICE) => (false, false),
+                Some(ALERT_TRIGGERS_NOW) => (true, true),
+                Some(_) => return None,
+            };
 
             Some(ServerMsg::AlertAdded {
                 symbol,
                 direction,
                 threshold,
+                price_available,
+                triggers_now,
+            })
+        }
+
+        CMD_ALERTS_ADDED => {
+            let added: usize = parts.next()?.parse().ok()?;
+            let skipped: usize = parts.next()?.parse().ok()?;
+            // Missing from servers that still dropped unpriced alerts.
+            let unpriced: usize = match parts.next() {
+                Some(raw) => raw.parse().ok()?,
+                None => 0,
+            };
+
+            Some(ServerMsg::AlertsAdded {
+                added,
+                skipped,
+                unpriced,
+            })
+        }
+
+        // Like ERR, the raw remainder keeps the reason's inner spacing.
+        CMD_ALERT_FAILED => {
+            let mut fields = line[cmd.len()..].trim_start().splitn(4, ' ');
+            let symbol = fields.next()?.to_string();
+            let direction = AlertDirection::as_msg(fields.next()?)?;
+            let threshold: f64 = fields.next()?.parse().ok()?;
+            let reason = fields.next().map(unescape_text).unwrap_or_default();
+
+            Some(ServerMsg::AlertFailed {
+                symbol,
+                direction,
+                threshold,
+                reason,
+            })
+        }
+
+        // Like ERR, the raw remainder keeps the reason's inner spacing.
+        CMD_TRADE_REJECTED => {
+            let rest = line[cmd.len()..].trim_start();
+            let (symbol, reason) = rest.split_once(' ')?;
+            Some(ServerMsg::TradeRejected {
+                symbol: symbol.to_string(),
+                reason: unescape_text(reason),
             })
         }
 
@@ -223,11 +850,35 @@ pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
             Some(ServerMsg::AlertRemoved { symbol, direction })
         }
 
+        CMD_ALERT_UPDATED => {
+            let symbol = parts.next()?.to_string();
+            let direction = AlertDirection::as_msg(parts.next()?)?;
+            let threshold: f64 = parts.next()?.parse().ok()?;
+
+            Some(ServerMsg::AlertUpdated {
+                symbol,
+                direction,
+                threshold,
+            })
+        }
+
         CMD_PRICE => {
             let symbol = parts.next()?.to_string();
             let price: f64 = parts.next()?.parse().ok()?;
+            let stats = match parts.next() {
+                Some(volume) => QuoteStats {
+                    volume: parse_optional(volume)?,
+                    low_52w: parse_optional(parts.next()?)?,
+                    high_52w: parse_optional(parts.next()?)?,
+                },
+                None => QuoteStats::default(),
+            };
 
-            Some(ServerMsg::PriceChecked { symbol, price })
+            Some(ServerMsg::PriceChecked {
+                symbol,
+                price,
+                stats,
+            })
         }
 
         CMD_DATA => {
@@ -237,6 +888,11 @@ pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
             struct DataPayload {
                 stocks: Vec<PortfolioStock>,
                 alerts: Vec<StoredAlert>,
+                #[serde(default)]
+                has_more: bool,
+                // `partial` only mirrors a non-empty `failed`.
+                #[serde(default)]
+                failed: Vec<String>,
             }
 
             let payload: DataPayload = serde_json::from_str(&json_content).ok()?;
@@ -244,6 +900,8 @@ pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
             Some(ServerMsg::AllClientData {
                 stocks: payload.stocks,
                 alerts: payload.alerts,
+                has_more: payload.has_more,
+                failed: payload.failed,
             })
         }
 
@@ -256,52 +914,210 @@ pub fn parse_server_msg(line: &str) -> Option<ServerMsg> {
         CMD_SOLD => {
             let symbol = parts.next()?.to_string();
             let quantity: i32 = parts.next()?.parse().ok()?;
-            Some(ServerMsg::StockSold { symbol, quantity })
+            let profit: Option<f64> = match parts.next() {
+                Some(token) => Some(token.parse().ok()?),
+                None => None,
+            };
+            Some(ServerMsg::StockSold {
+                symbol,
+                quantity,
+                profit,
+            })
+        }
+
+        CMD_HISTORY => {
+            let symbol = parts.next()?.to_string();
+            let points = parts
+                .map(|token| {
+                    let (timestamp, close) = token.split_once(':')?;
+                    Some(HistoryPoint {
+                        timestamp: timestamp.parse().ok()?,
+                        close: close.parse().ok()?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(ServerMsg::History { symbol, points })
+        }
+
+        CMD_SYMBOLS => {
+            let has_more: bool = parts.next()?.parse().ok()?;
+            let mut symbols = Vec::new();
+            let mut names = HashMap::new();
+            // Tickers never hold ':', so the first one starts the name.
+            for token in parts {
+                match token.split_once(':') {
+                    Some((symbol, name)) => {
+                        names.insert(symbol.to_string(), unescape_text(name));
+                        symbols.push(symbol.to_string());
+                    }
+                    None => symbols.push(token.to_string()),
+                }
+            }
+            Some(ServerMsg::SymbolList {
+                symbols,
+                names,
+                has_more,
+            })
         }
 
         CMD_LOGIN => Some(ServerMsg::UserLogged),
 
         CMD_REGISTER => Some(ServerMsg::UserRegistered),
 
-        CMD_ERR => {
-            let rest = parts.collect::<Vec<_>>().join(" ");
-            Some(ServerMsg::Error(rest))
-        }
-        _ => None,
-    }
-}
+        CMD_PING => Some(ServerMsg::Ping),
 
-pub fn parse_client_msg(line: &str) -> Option<ClientMsg> {
-    let line = line.trim();
-    if line.is_empty() {
-        return None;
-    }
+        CMD_PONG => Some(ServerMsg::Heartbeat {
+            nonce: parts.next()?.parse().ok()?,
+        }),
 
-    let mut parts = line.split_whitespace();
-    let cmd = parts.next()?;
+        CMD_RESET => Some(ServerMsg::AccountReset),
 
-    match cmd {
-        CMD_ADD => {
-            let symbol = parts.next()?.to_string();
-            let direction_str = parts.next()?;
-            let direction = AlertDirection::as_msg(direction_str)?;
-            let threshold: f64 = parts.next()?.parse().ok()?;
+        // Names can't hold spaces, so the last ':' of a token starts the return.
+        CMD_LEADERBOARD => {
+            let entries = parts
+                .map(|token| {
+                    let (name, return_pct) = token.rsplit_once(':')?;
+                    Some(LeaderboardEntry {
+                        name: name.to_string(),
+                        return_pct: return_pct.parse().ok()?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
 
-            Some(ClientMsg::AddAlert(AlertRequest {
+            Some(ServerMsg::Leaderboard { entries })
+        }
+
+        CMD_BRACKET => {
+            let (symbol, stop, target) = parse_bracket(&mut parts)?;
+            Some(ServerMsg::BracketSet {
                 symbol,
-                direction,
-                threshold,
-            }))
+                stop,
+                target,
+            })
         }
 
-        CMD_DEL => {
+        CMD_UNBRACKET => {
             let symbol = parts.next()?.to_string();
-            let direction_str = parts.next()?;
-            let direction = AlertDirection::as_msg(direction_str)?;
+            Some(ServerMsg::BracketCancelled { symbol })
+        }
+
+        CMD_CANCEL_ALL => Some(ServerMsg::OrdersCancelled {
+            count: parts.next()?.parse().ok()?,
+        }),
+
+        CMD_WHOAMI => {
+            let user_id: i64 = parts.next()?.parse().ok()?;
+            let username = parts.next()?.to_string();
+            Some(ServerMsg::Identity { user_id, username })
+        }
+
+        CMD_TIME => Some(ServerMsg::ServerTime {
+            unix_secs: parts.next()?.parse().ok()?,
+        }),
+
+        // The payload is taken as is, splitting on whitespace would change spaces inside strings.
+        CMD_EXPORT => {
+            let json = line[cmd.len()..].trim_start();
+            if json.is_empty() {
+                return None;
+            }
+            Some(ServerMsg::DataExport {
+                json: json.to_string(),
+            })
+        }
+
+        CMD_PREFS => Some(ServerMsg::Preferences(parse_preferences(parts)?)),
+
+        CMD_BRACKET_TRIGGERED => {
+            let symbol = parts.next()?.to_string();
+            let quantity: i32 = parts.next()?.parse().ok()?;
+            let price: f64 = parts.next()?.parse().ok()?;
+            let profit: f64 = parts.next()?.parse().ok()?;
+            Some(ServerMsg::BracketTriggered {
+                symbol,
+                quantity,
+                price,
+                profit,
+            })
+        }
+
+        CMD_ERR => {
+            // Take the raw remainder instead of rejoining tokens, so inner spacing survives.
+            let rest = line[cmd.len()..].strip_prefix(' ').unwrap_or("");
+            Some(ServerMsg::Error(unescape_text(rest)))
+        }
+        _ => None,
+    }
+}
+
+pub fn parse_client_line(line: &str) -> (Option<u64>, Option<ClientMsg>) {
+    let (req_id, rest) = split_req_id(strip_line_ending(line));
+    (req_id, parse_client_msg(rest))
+}
+
+pub fn parse_client_msg(line: &str) -> Option<ClientMsg> {
+    let (_, line) = split_req_id(strip_line_ending(line));
+    if line.is_empty() {
+        return None;
+    }
+
+    let mut parts = line.split_whitespace();
+    let cmd = parts.next()?;
+
+    match cmd {
+        CMD_ADD => {
+            let symbol = parts.next()?.to_string();
+            let direction_str = parts.next()?;
+            let direction = AlertDirection::as_msg(direction_str)?;
+            let threshold: f64 = parts.next()?.parse().ok()?;
+
+            Some(ClientMsg::AddAlert(AlertRequest {
+                symbol,
+                direction,
+                threshold,
+            }))
+        }
+
+        CMD_ADD_MANY => {
+            let tokens: Vec<&str> = parts.collect();
+            if tokens.is_empty() || !tokens.len().is_multiple_of(3) {
+                return None;
+            }
+            let alerts = tokens
+                .chunks(3)
+                .map(|chunk| {
+                    Some(AlertRequest {
+                        symbol: chunk[0].to_string(),
+                        direction: AlertDirection::as_msg(chunk[1])?,
+                        threshold: chunk[2].parse().ok()?,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(ClientMsg::AddAlerts { alerts })
+        }
+
+        CMD_DEL => {
+            let symbol = parts.next()?.to_string();
+            let direction_str = parts.next()?;
+            let direction = AlertDirection::as_msg(direction_str)?;
 
             Some(ClientMsg::RemoveAlert { symbol, direction })
         }
 
+        CMD_UPDATE => {
+            let symbol = parts.next()?.to_string();
+            let direction = AlertDirection::as_msg(parts.next()?)?;
+            let new_threshold: f64 = parts.next()?.parse().ok()?;
+
+            Some(ClientMsg::UpdateAlert {
+                symbol,
+                direction,
+                new_threshold,
+            })
+        }
+
         CMD_LOGIN => {
             let username = parts.next()?.to_string();
             let password = parts.next()?.to_string();
@@ -336,13 +1152,90 @@ pub fn parse_client_msg(line: &str) -> Option<ClientMsg> {
             Some(ClientMsg::SellStock { symbol, quantity })
         }
 
-        CMD_DATA => Some(ClientMsg::GetAllClientData),
+        CMD_CLOSE => {
+            let symbol = parts.next()?.to_string();
+
+            Some(ClientMsg::ClosePosition { symbol })
+        }
+
+        CMD_DATA => {
+            let (offset, limit) = parse_page(&mut parts)?;
+
+            Some(ClientMsg::GetAllClientData { offset, limit })
+        }
+
+        CMD_LIST => {
+            let (offset, limit) = parse_page(&mut parts)?;
+
+            Some(ClientMsg::ListSymbols { offset, limit })
+        }
+
+        CMD_HISTORY => {
+            let symbol = parts.next()?.to_string();
+            let range = HistoryRange::as_msg(parts.next()?)?;
+
+            Some(ClientMsg::GetHistory { symbol, range })
+        }
+
+        CMD_PONG => Some(ClientMsg::Pong),
+
+        CMD_PING => Some(ClientMsg::Ping {
+            nonce: parts.next()?.parse().ok()?,
+        }),
+
+        CMD_RESET => {
+            let password = parts.next()?.to_string();
+            Some(ClientMsg::ResetAccount { password })
+        }
+
+        CMD_LEADERBOARD => {
+            let limit = match parts.next() {
+                Some(token) => Some(token.parse().ok()?),
+                None => None,
+            };
+            Some(ClientMsg::GetLeaderboard { limit })
+        }
+
+        CMD_BRACKET => {
+            let (symbol, stop, target) = parse_bracket(&mut parts)?;
+            Some(ClientMsg::SetBracket {
+                symbol,
+                stop,
+                target,
+            })
+        }
+
+        CMD_UNBRACKET => {
+            let symbol = parts.next()?.to_string();
+            Some(ClientMsg::CancelBracket { symbol })
+        }
+
+        CMD_CANCEL_ALL => Some(ClientMsg::CancelAllOrders),
+
+        CMD_WHOAMI => Some(ClientMsg::WhoAmI),
+
+        CMD_TIME => Some(ClientMsg::GetServerTime),
+
+        CMD_EXPORT => Some(ClientMsg::ExportData),
+
+        CMD_PREFS => {
+            let mut parts = parts.peekable();
+            if parts.peek().is_none() {
+                Some(ClientMsg::GetPreferences)
+            } else {
+                Some(ClientMsg::SetPreferences(parse_preferences(parts)?))
+            }
+        }
 
         _ => None,
     }
 }
 
 impl ServerMsg {
+    pub fn to_wire_with_id(&self, req_id: Option<u64>) -> String {
+        tag_wire(self.to_wire(), req_id)
+    }
+
     pub fn to_wire(&self) -> String {
         match self {
             ServerMsg::AlertTriggered {
@@ -361,52 +1254,237 @@ impl ServerMsg {
                 symbol,
                 direction,
                 threshold,
+                price_available,
+                triggers_now,
             } => format!(
-                "{CMD_ALERT_ADDED} {} {} {}\n",
+                "{CMD_ALERT_ADDED} {} {} {}{}\n",
                 symbol,
                 direction.as_str(),
-                threshold
+                threshold,
+                if !*price_available {
+                    format!(" {ALERT_NO_PRICE}")
+                } else if *triggers_now {
+                    format!(" {ALERT_TRIGGERS_NOW}")
+                } else {
+                    String::new()
+                }
             ),
 
+            ServerMsg::AlertsAdded {
+                added,
+                skipped,
+                unpriced,
+            } => {
+                format!("{CMD_ALERTS_ADDED} {} {} {}\n", added, skipped, unpriced)
+            }
+
+            ServerMsg::AlertFailed {
+                symbol,
+                direction,
+                threshold,
+                reason,
+            } => format!(
+                "{CMD_ALERT_FAILED} {} {} {} {}\n",
+                symbol,
+                direction.as_str(),
+                threshold,
+                escape_text(reason)
+            ),
+
+            ServerMsg::TradeRejected { symbol, reason } => {
+                format!("{CMD_TRADE_REJECTED} {} {}\n", symbol, escape_text(reason))
+            }
+
             ServerMsg::AlertRemoved { symbol, direction } => {
                 format!("{CMD_ALERT_DELETED} {} {}\n", symbol, direction.as_str())
             }
 
-            ServerMsg::PriceChecked { symbol, price } => {
-                format!("{CMD_PRICE} {} {}\n", symbol, price)
+            ServerMsg::AlertUpdated {
+                symbol,
+                direction,
+                threshold,
+            } => format!(
+                "{CMD_ALERT_UPDATED} {} {} {}\n",
+                symbol,
+                direction.as_str(),
+                threshold
+            ),
+
+            ServerMsg::PriceChecked {
+                symbol,
+                price,
+                stats,
+            } => {
+                if stats.is_empty() {
+                    format!("{CMD_PRICE} {} {}\n", symbol, price)
+                } else {
+                    format!(
+                        "{CMD_PRICE} {} {} {} {} {}\n",
+                        symbol,
+                        price,
+                        optional_to_wire(stats.volume),
+                        optional_to_wire(stats.low_52w),
+                        optional_to_wire(stats.high_52w)
+                    )
+                }
             }
 
             ServerMsg::StockBought { symbol, quantity } => {
                 format!("{CMD_BOUGHT} {} {}\n", symbol, quantity)
             }
 
-            ServerMsg::StockSold { symbol, quantity } => {
-                format!("{CMD_SOLD} {} {}\n", symbol, quantity)
-            }
+            ServerMsg::StockSold {
+                symbol,
+                quantity,
+                profit,
+            } => match profit {
+                Some(profit) => format!("{CMD_SOLD} {} {} {}\n", symbol, quantity, profit),
+                None => format!("{CMD_SOLD} {} {}\n", symbol, quantity),
+            },
 
             ServerMsg::Error(msg) => {
-                format!("{CMD_ERR} {}\n", msg)
+                format!("{CMD_ERR} {}\n", escape_text(msg))
             }
 
-            ServerMsg::AllClientData { stocks, alerts } => {
-                let json_data = serde_json::json!({
+            ServerMsg::AllClientData {
+                stocks,
+                alerts,
+                has_more,
+                failed,
+            } => {
+                let mut json_data = serde_json::json!({
                     "stocks": stocks,
-                    "alerts": alerts
+                    "alerts": alerts,
+                    "has_more": has_more
                 });
+                if !failed.is_empty() {
+                    json_data["partial"] = true.into();
+                    json_data["failed"] = failed.clone().into();
+                }
 
                 let json_payload = json_data.to_string();
 
                 format!("{CMD_DATA} {}\n", json_payload)
             }
 
+            ServerMsg::SymbolList {
+                symbols,
+                names,
+                has_more,
+            } => {
+                let mut wire = format!("{CMD_SYMBOLS} {}", has_more);
+                for symbol in symbols {
+                    wire.push(' ');
+                    wire.push_str(symbol);
+                    if let Some(name) = names.get(symbol) {
+                        wire.push(':');
+                        wire.push_str(&escape_text(name).replace(' ', "\\s"));
+                    }
+                }
+                wire.push('\n');
+                wire
+            }
+
+            ServerMsg::History { symbol, points } => {
+                let mut wire = format!("{CMD_HISTORY} {}", symbol);
+                for point in points {
+                    wire.push_str(&format!(" {}:{}", point.timestamp, point.close));
+                }
+                wire.push('\n');
+                wire
+            }
+
             ServerMsg::UserLogged => format!("{CMD_LOGIN}\n"),
             ServerMsg::UserRegistered => format!("{CMD_REGISTER}\n"),
+            ServerMsg::Ping => format!("{CMD_PING}\n"),
+            ServerMsg::Heartbeat { nonce } => format!("{CMD_PONG} {nonce}\n"),
+            ServerMsg::AccountReset => format!("{CMD_RESET}\n"),
+
+            ServerMsg::Leaderboard { entries } => {
+                let mut wire = CMD_LEADERBOARD.to_string();
+                for entry in entries {
+                    wire.push_str(&format!(" {}:{}", entry.name, entry.return_pct));
+                }
+                wire.push('\n');
+                wire
+            }
+
+            ServerMsg::BracketSet {
+                symbol,
+                stop,
+                target,
+            } => bracket_to_wire(symbol, *stop, *target),
+
+            ServerMsg::BracketCancelled { symbol } => format!("{CMD_UNBRACKET} {}\n", symbol),
+            ServerMsg::OrdersCancelled { count } => format!("{CMD_CANCEL_ALL} {}\n", count),
+
+            ServerMsg::Identity { user_id, username } => {
+                format!("{CMD_WHOAMI} {} {}\n", user_id, username)
+            }
+
+            ServerMsg::ServerTime { unix_secs } => format!("{CMD_TIME} {}\n", unix_secs),
+
+            ServerMsg::DataExport { json } => format!("{CMD_EXPORT} {}\n", json),
+            ServerMsg::Preferences(prefs) => preferences_to_wire(prefs),
+
+            ServerMsg::BracketTriggered {
+                symbol,
+                quantity,
+                price,
+                profit,
+            } => format!(
+                "{CMD_BRACKET_TRIGGERED} {} {} {} {}\n",
+                symbol, quantity, price, profit
+            ),
         }
     }
 }
 
 pub fn wire_error(msg: impl Into<String>) -> String {
-    format!("{CMD_ERR} {}\n", msg.into())
+    ServerMsg::Error(msg.into()).to_wire()
+}
+
+// Free text (ERR messages) must fit in one line and survive the trimming done by the parser,
+// so line breaks, backslashes and whitespace at either end are escaped.
+fn escape_text(text: &str) -> String {
+    let last = text.chars().count().saturating_sub(1);
+    let mut out = String::with_capacity(text.len());
+    for (idx, c) in text.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' ' if idx == 0 || idx == last => out.push_str("\\s"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('s') => out.push(' '),
+            // Unknown escapes are kept as they are.
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
 }
 
 #[cfg(test)]
@@ -489,6 +1567,112 @@ mod tests {
         }
     }
 
+    #[test]
+    fn export_roundtrip() {
+        assert_eq!(ClientMsg::ExportData.to_wire(), "EXPORT\n");
+        assert!(matches!(
+            parse_client_msg("EXPORT\n"),
+            Some(ClientMsg::ExportData)
+        ));
+
+        let export = crate::database::UserExport {
+            username: "alice".into(),
+            alerts: vec![StoredAlert {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Below,
+                threshold: 150.25,
+            }],
+            positions: vec![PortfolioStock {
+                symbol: "MSFT".into(),
+                quantity: 3,
+                total_price: 900.0,
+            }],
+            brackets: vec![crate::database::StoredBracket {
+                symbol: "MSFT".into(),
+                stop: None,
+                target: Some(350.0),
+            }],
+        };
+        let reply = ServerMsg::DataExport {
+            json: serde_json::to_string(&export).unwrap(),
+        };
+        let (req_id, parsed) = parse_server_line(&reply.to_wire_with_id(Some(9)));
+        assert_eq!(req_id, Some(9));
+        match parsed {
+            Some(ServerMsg::DataExport { json }) => {
+                let back: crate::database::UserExport = serde_json::from_str(&json).unwrap();
+                assert_eq!(back, export);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        assert!(parse_server_msg("EXPORT\n").is_none());
+    }
+
+    #[test]
+    fn preferences_roundtrip() {
+        let prefs = Preferences {
+            triggers: true,
+            fills: false,
+        };
+        assert_eq!(
+            ClientMsg::SetPreferences(prefs).to_wire(),
+            "PREFS TRIGGERS=ON FILLS=OFF\n"
+        );
+        assert!(matches!(
+            parse_client_msg("PREFS fills=on triggers=off\n"),
+            Some(ClientMsg::SetPreferences(Preferences {
+                triggers: false,
+                fills: true
+            }))
+        ));
+        assert!(matches!(
+            parse_client_msg("PREFS\n"),
+            Some(ClientMsg::GetPreferences)
+        ));
+        assert!(parse_client_msg("PREFS TRIGGERS=ON\n").is_none());
+        assert!(parse_client_msg("PREFS TRIGGERS=YES FILLS=ON\n").is_none());
+
+        let (req_id, parsed) =
+            parse_server_line(&ServerMsg::Preferences(prefs).to_wire_with_id(Some(4)));
+        assert_eq!(req_id, Some(4));
+        assert!(matches!(parsed, Some(ServerMsg::Preferences(p)) if p == prefs));
+    }
+
+    #[test]
+    fn update_alert_roundtrip() {
+        let msg = ClientMsg::UpdateAlert {
+            symbol: "TSLA".into(),
+            direction: AlertDirection::Above,
+            new_threshold: 250.5,
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "UPDATE TSLA ABOVE 250.5\n");
+        match parse_client_msg(&wire) {
+            Some(ClientMsg::UpdateAlert {
+                symbol,
+                direction,
+                new_threshold,
+            }) => {
+                assert_eq!(symbol, "TSLA");
+                assert_eq!(direction, AlertDirection::Above);
+                assert_eq!(new_threshold, 250.5);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        assert!(parse_client_msg("UPDATE TSLA ABOVE\n").is_none());
+
+        let reply = ServerMsg::AlertUpdated {
+            symbol: "TSLA".into(),
+            direction: AlertDirection::Above,
+            threshold: 250.5,
+        };
+        assert_eq!(reply.to_wire(), "ALERTUPDATED TSLA ABOVE 250.5\n");
+        assert!(matches!(
+            parse_server_msg(&reply.to_wire()),
+            Some(ServerMsg::AlertUpdated { threshold, .. }) if threshold == 250.5
+        ));
+    }
+
     #[test]
     fn trigger_parse() {
         let wire = "TRIGGER AAPL ABOVE 150 155\n";
@@ -516,15 +1700,100 @@ mod tests {
                 symbol,
                 direction,
                 threshold,
+                price_available,
+                triggers_now,
             }) => {
                 assert_eq!(symbol, "AAPL");
                 assert_eq!(direction, AlertDirection::Below);
                 assert_eq!(threshold, 120.25);
+                assert!(price_available);
+                assert!(!triggers_now);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        assert!(matches!(
+            parse_server_msg("ALERTADDED NEWCO ABOVE 5 NOPRICE\n"),
+            Some(ServerMsg::AlertAdded {
+                price_available: false,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_server_msg("ALERTADDED AAPL ABOVE 5 NOW\n"),
+            Some(ServerMsg::AlertAdded {
+                price_available: true,
+                triggers_now: true,
+                ..
+            })
+        ));
+        assert!(parse_server_msg("ALERTADDED NEWCO ABOVE 5 MAYBE\n").is_none());
+    }
+
+    #[test]
+    fn alert_failed_round_trip() {
+        let msg = ServerMsg::AlertFailed {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Above,
+            threshold: 200.0,
+            reason: "Alert already exists".into(),
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "ALERTFAILED AAPL ABOVE 200 Alert already exists\n");
+
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::AlertFailed {
+                symbol,
+                direction,
+                threshold,
+                reason,
+            }) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(direction, AlertDirection::Above);
+                assert_eq!(threshold, 200.0);
+                assert_eq!(reason, "Alert already exists");
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        let msg = ServerMsg::AlertFailed {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Below,
+            threshold: 5.0,
+            reason: "bad  threshold\nTRIGGER AAPL ABOVE 1 2 ".into(),
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire.matches('\n').count(), 1);
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::AlertFailed { reason, .. }) => {
+                assert_eq!(reason, "bad  threshold\nTRIGGER AAPL ABOVE 1 2 ");
             }
             other => panic!("unexpected parse result: {:?}", other),
         }
     }
 
+    #[test]
+    fn trade_rejected_keeps_the_reason() {
+        let msg = ServerMsg::TradeRejected {
+            symbol: "AAPL".into(),
+            reason: "You have only 2 actions of given stock!.".into(),
+        };
+        let wire = msg.to_wire();
+        assert_eq!(
+            wire,
+            "TRADEREJECTED AAPL You have only 2 actions of given stock!.\n"
+        );
+
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::TradeRejected { symbol, reason }) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(reason, "You have only 2 actions of given stock!.");
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        assert!(parse_server_msg("TRADEREJECTED AAPL\n").is_none());
+    }
+
     #[test]
     fn alert_removed_parse() {
         let wire = "ALERTDELETED AAPL ABOVE\n";
@@ -541,14 +1810,54 @@ mod tests {
     fn price_checked_parse() {
         let wire = "PRICE AAPL 123.45\n";
         match parse_server_msg(wire) {
-            Some(ServerMsg::PriceChecked { symbol, price }) => {
+            Some(ServerMsg::PriceChecked {
+                symbol,
+                price,
+                stats,
+            }) => {
                 assert_eq!(symbol, "AAPL");
                 assert_eq!(price, 123.45);
+                assert!(stats.is_empty());
             }
             other => panic!("unexpected parse result: {:?}", other),
         }
     }
 
+    fn stats_summary_of(wire: &str) -> String {
+        match parse_server_msg(wire) {
+            Some(ServerMsg::PriceChecked { stats, .. }) => stats.summary(),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn price_with_stats_round_trip() {
+        let msg = ServerMsg::PriceChecked {
+            symbol: "AAPL".into(),
+            price: 190.5,
+            stats: QuoteStats {
+                volume: Some(52_000_000),
+                low_52w: None,
+                high_52w: Some(199.62),
+            },
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "PRICE AAPL 190.5 52000000 - 199.62\n");
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::PriceChecked { stats, .. }) => {
+                assert_eq!(stats.volume, Some(52_000_000));
+                assert_eq!(stats.low_52w, None);
+                assert_eq!(stats.high_52w, Some(199.62));
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        assert_eq!(stats_summary_of(&wire), " volume=52000000 52w=-..199.62");
+
+        // Stats come as a full triple or not at all.
+        assert!(parse_server_msg("PRICE AAPL 190.5 100").is_none());
+    }
+
     #[test]
     fn bought_sold_parse() {
         let buy_wire = "BOUGHT AAPL 3\n";
@@ -562,9 +1871,47 @@ mod tests {
 
         let sell_wire = "SOLD TSLA 2\n";
         match parse_server_msg(sell_wire) {
-            Some(ServerMsg::StockSold { symbol, quantity }) => {
+            Some(ServerMsg::StockSold {
+                symbol,
+                quantity,
+                profit,
+            }) => {
                 assert_eq!(symbol, "TSLA");
                 assert_eq!(quantity, 2);
+                assert_eq!(profit, None);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn close_position_roundtrip() {
+        let msg = ClientMsg::ClosePosition {
+            symbol: "AAPL".into(),
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "CLOSE AAPL\n");
+        match parse_client_msg(&wire) {
+            Some(ClientMsg::ClosePosition { symbol }) => assert_eq!(symbol, "AAPL"),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        let sold = ServerMsg::StockSold {
+            symbol: "AAPL".into(),
+            quantity: 4,
+            profit: Some(-12.5),
+        };
+        let wire = sold.to_wire();
+        assert_eq!(wire, "SOLD AAPL 4 -12.5\n");
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::StockSold {
+                symbol,
+                quantity,
+                profit,
+            }) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(quantity, 4);
+                assert_eq!(profit, Some(-12.5));
             }
             other => panic!("unexpected parse result: {:?}", other),
         }
@@ -582,9 +1929,22 @@ mod tests {
             direction: AlertDirection::Above,
             threshold: 150.0,
         }];
-        let wire = ServerMsg::AllClientData { stocks, alerts }.to_wire();
+        let wire = ServerMsg::AllClientData {
+            stocks,
+            alerts,
+            has_more: true,
+            failed: Vec::new(),
+        }
+        .to_wire();
         match parse_server_msg(&wire) {
-            Some(ServerMsg::AllClientData { stocks, alerts }) => {
+            Some(ServerMsg::AllClientData {
+                stocks,
+                alerts,
+                has_more,
+                failed,
+            }) => {
+                assert!(has_more);
+                assert!(failed.is_empty());
                 assert_eq!(stocks.len(), 1);
                 assert_eq!(stocks[0].symbol, "AAPL");
                 assert_eq!(stocks[0].quantity, 2);
@@ -598,6 +1958,347 @@ mod tests {
         }
     }
 
+    #[test]
+    fn data_snapshot_roundtrip() {
+        let stock = |symbol: &str, quantity, total_price| PortfolioStock {
+            symbol: symbol.into(),
+            quantity,
+            total_price,
+        };
+        let alert = |symbol: &str, direction, threshold| StoredAlert {
+            symbol: symbol.into(),
+            direction,
+            threshold,
+        };
+        // Negative totals are positions that earned more than they cost, prices with
+        // many digits and exponents have to survive the JSON payload bit for bit.
+        let stocks = vec![
+            stock("AAPL", 12, 2287.44),
+            stock("MSFT", 3, -15.5),
+            stock("BRK.B", 1, -0.1 - 0.2),
+            stock("TSLA", 250, 61_234.567_891_234),
+            stock("GOOG", 0, -1e-7),
+            stock("NVDA", i32::MAX, 1e15 + 0.5),
+        ];
+        let alerts = vec![
+            alert("AAPL", AlertDirection::Above, 200.0),
+            alert("AAPL", AlertDirection::Below, 150.25),
+            alert("MSFT", AlertDirection::Below, 0.1 + 0.2),
+            alert("BRK.B", AlertDirection::Above, 412.123_456_789),
+            alert("TSLA", AlertDirection::Below, 1e-3),
+        ];
+
+        // A partial snapshot carries the sections that failed to load.
+        for (has_more, failed) in [
+            (true, vec![]),
+            (false, vec![DATA_SECTION_ALERTS.to_string()]),
+        ] {
+            let wire = ServerMsg::AllClientData {
+                stocks: stocks.clone(),
+                alerts: alerts.clone(),
+                has_more,
+                failed: failed.clone(),
+            }
+            .to_wire();
+            assert_eq!(wire.contains(r#""partial":true"#), !failed.is_empty());
+            assert!(wire.starts_with("DATA {") && wire.ends_with("}\n"));
+            assert_eq!(wire.matches('\n').count(), 1);
+
+            let (req_id, parsed) = parse_server_line(&format!("#12 {wire}"));
+            assert_eq!(req_id, Some(12));
+            match parsed {
+                Some(ServerMsg::AllClientData {
+                    stocks: parsed_stocks,
+                    alerts: parsed_alerts,
+                    has_more: parsed_more,
+                    failed: parsed_failed,
+                }) => {
+                    assert_eq!(parsed_failed, failed);
+                    assert_eq!(parsed_stocks, stocks);
+                    assert_eq!(parsed_alerts, alerts);
+                    assert_eq!(parsed_more, has_more);
+                }
+                other => panic!("unexpected parse result: {:?}", other),
+            }
+        }
+
+        // An empty page is still a valid reply.
+        let wire = ServerMsg::AllClientData {
+            stocks: Vec::new(),
+            alerts: Vec::new(),
+            has_more: false,
+            failed: Vec::new(),
+        }
+        .to_wire();
+        assert!(matches!(
+            parse_server_msg(&wire),
+            Some(ServerMsg::AllClientData { stocks, alerts, has_more: false, failed })
+                if stocks.is_empty() && alerts.is_empty() && failed.is_empty()
+        ));
+    }
+
+    #[test]
+    fn leaderboard_entry_summary_shows_sign() {
+        let entry = |return_pct| LeaderboardEntry {
+            name: "alice".into(),
+            return_pct,
+        };
+        assert_eq!(entry(20.0).summary(), "alice +20.00%");
+        assert_eq!(entry(-4.567).summary(), "alice -4.57%");
+    }
+
+    #[test]
+    fn server_msg_to_json() {
+        let msg = ServerMsg::StockBought {
+            symbol: "AAPL".into(),
+            quantity: 3,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(json, r#"{"StockBought":{"symbol":"AAPL","quantity":3}}"#);
+
+        let json = serde_json::to_string(&ServerMsg::UserLogged).unwrap();
+        assert_eq!(json, r#""UserLogged""#);
+    }
+
+    #[test]
+    fn thresholds_must_be_finite_and_bounded() {
+        assert_eq!(check_threshold(199.5, DEFAULT_MAX_THRESHOLD), Ok(()));
+        assert_eq!(check_threshold(-5.0, DEFAULT_MAX_THRESHOLD), Ok(()));
+        for bad in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert_eq!(
+                check_threshold(bad, DEFAULT_MAX_THRESHOLD),
+                Err(ERR_THRESHOLD_NOT_FINITE.to_string())
+            );
+        }
+        assert_eq!(
+            check_threshold(1e308, 1000.0),
+            Err("threshold out of range, the limit is 1000".to_string())
+        );
+        assert!(check_threshold(1000.0, 1000.0).is_ok());
+
+        // "inf" and "NaN" parse as f64, so they do reach the check from the wire.
+        match parse_client_msg("ADD AAPL ABOVE inf\n") {
+            Some(ClientMsg::AddAlert(alert)) => {
+                assert!(check_threshold(alert.threshold, 1e6).is_err())
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn alert_direction_parse_display_roundtrip() {
+        for direction in [AlertDirection::Above, AlertDirection::Below] {
+            let text = direction.to_string();
+            assert_eq!(text, direction.as_str());
+            assert_eq!(text.parse::<AlertDirection>(), Ok(direction));
+            assert_eq!(text.to_lowercase().parse::<AlertDirection>(), Ok(direction));
+        }
+        assert_eq!("Below".parse::<AlertDirection>(), Ok(AlertDirection::Below));
+        assert_eq!(
+            "sideways".parse::<AlertDirection>(),
+            Err("unknown alert direction \"sideways\", expected ABOVE or BELOW".to_string())
+        );
+        assert_eq!(format!("{:>6}|", AlertDirection::Above), " ABOVE|");
+    }
+
+    #[test]
+    fn alert_direction_serde_uses_wire_tokens() {
+        for direction in [AlertDirection::Above, AlertDirection::Below] {
+            let json = serde_json::to_string(&direction).unwrap();
+            assert_eq!(json, format!("\"{}\"", direction.as_str()));
+            let back: AlertDirection = serde_json::from_str(&json).unwrap();
+            assert_eq!(back, direction);
+        }
+
+        let alert = StoredAlert {
+            symbol: "AAPL".into(),
+            direction: AlertDirection::Below,
+            threshold: 150.0,
+        };
+        let json = serde_json::to_string(&alert).unwrap();
+        assert_eq!(
+            json,
+            r#"{"symbol":"AAPL","direction":"BELOW","threshold":150.0}"#
+        );
+
+        assert_eq!(AlertDirection::as_msg("below"), Some(AlertDirection::Below));
+        assert_eq!(AlertDirection::as_msg("Above"), Some(AlertDirection::Above));
+        assert_eq!(AlertDirection::as_msg("ABOVEX"), None);
+
+        // JSON written by the old derive ("Above") still loads.
+        let back: AlertDirection = serde_json::from_str(r#""Above""#).unwrap();
+        assert_eq!(back, AlertDirection::Above);
+
+        let err = serde_json::from_str::<AlertDirection>(r#""SIDEWAYS""#).unwrap_err();
+        assert!(err.to_string().contains("SIDEWAYS"));
+    }
+
+    #[test]
+    fn data_request_paging() {
+        let msg = ClientMsg::GetAllClientData {
+            offset: 100,
+            limit: Some(50),
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "DATA 100 50\n");
+        assert!(matches!(
+            parse_client_msg(&wire),
+            Some(ClientMsg::GetAllClientData {
+                offset: 100,
+                limit: Some(50)
+            })
+        ));
+        assert!(matches!(
+            parse_client_msg("DATA"),
+            Some(ClientMsg::GetAllClientData {
+                offset: 0,
+                limit: None
+            })
+        ));
+        assert!(parse_client_msg("DATA 1 x").is_none());
+
+        // Servers without paging don't send has_more at all.
+        let wire = r#"DATA {"stocks":[],"alerts":[]}"#;
+        match parse_server_msg(wire) {
+            Some(ServerMsg::AllClientData { has_more, .. }) => assert!(!has_more),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn symbol_list_roundtrip() {
+        let list = ClientMsg::ListSymbols {
+            offset: 0,
+            limit: None,
+        };
+        assert_eq!(list.to_wire(), "LIST 0\n");
+        assert!(matches!(
+            parse_client_msg("LIST"),
+            Some(ClientMsg::ListSymbols {
+                offset: 0,
+                limit: None
+            })
+        ));
+        assert!(matches!(
+            parse_client_msg("LIST 500 20"),
+            Some(ClientMsg::ListSymbols {
+                offset: 500,
+                limit: Some(20)
+            })
+        ));
+        assert!(parse_client_msg("LIST abc").is_none());
+
+        let msg = ServerMsg::SymbolList {
+            symbols: vec!["AAPL".into(), "MSFT".into()],
+            names: HashMap::new(),
+            has_more: true,
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "SYMBOLS true AAPL MSFT\n");
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::SymbolList {
+                symbols,
+                names,
+                has_more,
+            }) => {
+                assert_eq!(symbols, vec!["AAPL", "MSFT"]);
+                assert!(names.is_empty());
+                assert!(has_more);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        // Names keep their spaces, colons and backslashes.
+        let names = HashMap::from([
+            ("AAPL".to_string(), "Apple Inc.".to_string()),
+            ("BRK-B".to_string(), "Berkshire: Class B \\ ".to_string()),
+        ]);
+        let msg = ServerMsg::SymbolList {
+            symbols: vec!["AAPL".into(), "MSFT".into(), "BRK-B".into()],
+            names: names.clone(),
+            has_more: false,
+        };
+        let wire = msg.to_wire();
+        assert_eq!(
+            wire,
+            "SYMBOLS false AAPL:Apple\\sInc. MSFT BRK-B:Berkshire:\\sClass\\sB\\s\\\\\\s\n"
+        );
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::SymbolList {
+                symbols,
+                names: parsed,
+                ..
+            }) => {
+                assert_eq!(symbols, vec!["AAPL", "MSFT", "BRK-B"]);
+                assert_eq!(parsed, names);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        let empty = ServerMsg::SymbolList {
+            symbols: vec![],
+            names: HashMap::new(),
+            has_more: false,
+        }
+        .to_wire();
+        assert_eq!(empty, "SYMBOLS false\n");
+        match parse_server_msg(&empty) {
+            Some(ServerMsg::SymbolList {
+                symbols, has_more, ..
+            }) => {
+                assert!(symbols.is_empty());
+                assert!(!has_more);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_id_roundtrip() {
+        let msg = ClientMsg::CheckPrice {
+            symbol: "AAPL".into(),
+        };
+        let wire = msg.to_wire_with_id(Some(7));
+        assert_eq!(wire, "#7 PRICE AAPL\n");
+        match parse_client_line(&wire) {
+            (Some(7), Some(ClientMsg::CheckPrice { symbol })) => assert_eq!(symbol, "AAPL"),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        let reply = ServerMsg::PriceChecked {
+            symbol: "AAPL".into(),
+            price: 10.5,
+            stats: QuoteStats::default(),
+        };
+        let wire = reply.to_wire_with_id(Some(7));
+        assert_eq!(wire, "#7 PRICE AAPL 10.5\n");
+        match parse_server_line(&wire) {
+            (Some(7), Some(ServerMsg::PriceChecked { symbol, price, .. })) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(price, 10.5);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn req_id_is_optional() {
+        assert_eq!(
+            ServerMsg::UserLogged.to_wire_with_id(None),
+            ServerMsg::UserLogged.to_wire()
+        );
+        assert!(matches!(
+            parse_server_line("LOGIN\n"),
+            (None, Some(ServerMsg::UserLogged))
+        ));
+        assert!(matches!(
+            parse_client_msg("#3 DATA"),
+            Some(ClientMsg::GetAllClientData { .. })
+        ));
+        assert!(matches!(parse_client_line("#x DATA"), (None, None)));
+        assert!(matches!(parse_client_line("#5 NOPE"), (Some(5), None)));
+    }
+
     #[test]
     fn error_roundtrip() {
         let wire = wire_error("Something went wrong");
@@ -608,4 +2309,614 @@ mod tests {
             other => panic!("unexpected parse result: {:?}", other),
         }
     }
+
+    #[test]
+    fn wire_error_format_is_pinned() {
+        // Every error the server sends by name must go out verbatim and come back unchanged.
+        for text in [
+            ERR_SERVER_FULL,
+            ERR_NOT_LOGGED_IN,
+            ERR_LOGIN_FAILED,
+            ERR_UNKNOWN_SYMBOL,
+            ERR_PRICE_NOT_READY,
+            ERR_DB_UNAVAILABLE,
+            ERR_INTERNAL,
+            ERR_IDLE_TIMEOUT,
+            ERR_LINE_TOO_LONG,
+            ERR_BATCH_TOO_LARGE,
+            ERR_THRESHOLD_NOT_FINITE,
+        ] {
+            let wire = wire_error(text);
+            assert_eq!(wire, format!("{CMD_ERR} {text}\n"));
+            match parse_server_line(&wire) {
+                (None, Some(ServerMsg::Error(msg))) => assert_eq!(msg, text),
+                other => panic!("unexpected parse result: {:?}", other),
+            }
+        }
+
+        // Owned strings, empty text and text that needs escaping.
+        assert_eq!(wire_error(String::from("boom")), "ERR boom\n");
+        assert_eq!(wire_error(""), "ERR \n");
+        assert!(
+            matches!(parse_server_msg(&wire_error("")), Some(ServerMsg::Error(msg)) if msg.is_empty())
+        );
+        assert_eq!(wire_error("a\nb"), "ERR a\\nb\n");
+
+        // Tagging with a request id only prefixes the line.
+        assert_eq!(
+            ServerMsg::Error(ERR_UNKNOWN_SYMBOL.into()).to_wire_with_id(Some(3)),
+            format!("#3 {}", wire_error(ERR_UNKNOWN_SYMBOL))
+        );
+    }
+
+    fn crlf(wire: String) -> String {
+        wire.replace('\n', "\r\n")
+    }
+
+    // One of every client message, shared by the CRLF and fuzz tests.
+    fn sample_client_msgs() -> Vec<ClientMsg> {
+        vec![
+            ClientMsg::AddAlert(AlertRequest {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+            }),
+            ClientMsg::RemoveAlert {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Below,
+            },
+            ClientMsg::UpdateAlert {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Below,
+                new_threshold: 180.25,
+            },
+            ClientMsg::RegisterClient {
+                username: "bob".into(),
+                password: "hunter2".into(),
+            },
+            ClientMsg::LoginClient {
+                username: "bob".into(),
+                password: "hunter2".into(),
+            },
+            ClientMsg::CheckPrice {
+                symbol: "MSFT".into(),
+            },
+            ClientMsg::BuyStock {
+                symbol: "MSFT".into(),
+                quantity: 3,
+            },
+            ClientMsg::SellStock {
+                symbol: "MSFT".into(),
+                quantity: 1,
+            },
+            ClientMsg::ClosePosition {
+                symbol: "MSFT".into(),
+            },
+            ClientMsg::GetAllClientData {
+                offset: 100,
+                limit: Some(50),
+            },
+            ClientMsg::ListSymbols {
+                offset: 0,
+                limit: None,
+            },
+            ClientMsg::GetHistory {
+                symbol: "MSFT".into(),
+                range: HistoryRange::Hour,
+            },
+            ClientMsg::Pong,
+            ClientMsg::Ping { nonce: 42 },
+            ClientMsg::ResetAccount {
+                password: "hunter2".into(),
+            },
+            ClientMsg::GetLeaderboard { limit: None },
+            ClientMsg::GetLeaderboard { limit: Some(5) },
+            ClientMsg::SetBracket {
+                symbol: "AAPL".into(),
+                stop: Some(90.5),
+                target: None,
+            },
+            ClientMsg::SetBracket {
+                symbol: "AAPL".into(),
+                stop: Some(90.5),
+                target: Some(130.0),
+            },
+            ClientMsg::CancelBracket {
+                symbol: "AAPL".into(),
+            },
+            ClientMsg::CancelAllOrders,
+            ClientMsg::WhoAmI,
+            ClientMsg::GetServerTime,
+            ClientMsg::ExportData,
+            ClientMsg::GetPreferences,
+            ClientMsg::SetPreferences(Preferences {
+                triggers: false,
+                fills: true,
+            }),
+            ClientMsg::AddAlerts {
+                alerts: vec![AlertRequest {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 200.0,
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn client_lines_with_crlf_parse() {
+        for msg in sample_client_msgs() {
+            let wire = msg.to_wire();
+            let parsed = parse_client_msg(&crlf(wire.clone()))
+                .unwrap_or_else(|| panic!("failed to parse CRLF line: {wire:?}"));
+            assert_eq!(parsed.to_wire(), wire);
+
+            let (req_id, parsed) = parse_client_line(&crlf(msg.to_wire_with_id(Some(9))));
+            assert_eq!(req_id, Some(9));
+            assert_eq!(parsed.map(|m| m.to_wire()), Some(wire));
+        }
+    }
+
+    // One of every server message, shared by the CRLF and fuzz tests.
+    fn sample_server_msgs() -> Vec<ServerMsg> {
+        vec![
+            ServerMsg::AlertTriggered {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+                current_price: Price { value: 201.5 },
+            },
+            ServerMsg::AlertAdded {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+                price_available: true,
+                triggers_now: false,
+            },
+            ServerMsg::AlertAdded {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Below,
+                threshold: 250.0,
+                price_available: true,
+                triggers_now: true,
+            },
+            ServerMsg::AlertAdded {
+                symbol: "NEWCO".into(),
+                direction: AlertDirection::Below,
+                threshold: 5.5,
+                price_available: false,
+                triggers_now: false,
+            },
+            ServerMsg::AlertRemoved {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+            },
+            ServerMsg::AlertUpdated {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 210.0,
+            },
+            ServerMsg::AlertFailed {
+                symbol: "AAPL".into(),
+                direction: AlertDirection::Above,
+                threshold: 200.0,
+                reason: "Alert already exists".into(),
+            },
+            ServerMsg::TradeRejected {
+                symbol: "AAPL".into(),
+                reason: " Not  enough\nshares ".into(),
+            },
+            ServerMsg::AlertsAdded {
+                added: 8,
+                skipped: 2,
+                unpriced: 1,
+            },
+            ServerMsg::UserLogged,
+            ServerMsg::UserRegistered,
+            ServerMsg::Ping,
+            ServerMsg::Heartbeat { nonce: 42 },
+            ServerMsg::AccountReset,
+            ServerMsg::Leaderboard {
+                entries: vec![
+                    LeaderboardEntry {
+                        name: "alice".into(),
+                        return_pct: 12.5,
+                    },
+                    LeaderboardEntry {
+                        name: "b:ob".into(),
+                        return_pct: -3.25,
+                    },
+                ],
+            },
+            ServerMsg::Leaderboard {
+                entries: Vec::new(),
+            },
+            ServerMsg::BracketSet {
+                symbol: "AAPL".into(),
+                stop: None,
+                target: Some(130.0),
+            },
+            ServerMsg::BracketCancelled {
+                symbol: "AAPL".into(),
+            },
+            ServerMsg::OrdersCancelled { count: 3 },
+            ServerMsg::BracketTriggered {
+                symbol: "AAPL".into(),
+                quantity: 10,
+                price: 85.0,
+                profit: -150.0,
+            },
+            ServerMsg::Identity {
+                user_id: 7,
+                username: "alice".into(),
+            },
+            ServerMsg::ServerTime {
+                unix_secs: 1_760_000_000,
+            },
+            ServerMsg::DataExport {
+                json: r#"{"username":"alice","alerts":[],"positions":[],"brackets":[]}"#.into(),
+            },
+            ServerMsg::Preferences(Preferences::default()),
+            ServerMsg::PriceChecked {
+                symbol: "AAPL".into(),
+                price: 199.0,
+                stats: QuoteStats::default(),
+            },
+            ServerMsg::PriceChecked {
+                symbol: "AAPL".into(),
+                price: 199.0,
+                stats: QuoteStats {
+                    volume: None,
+                    low_52w: Some(150.0),
+                    high_52w: Some(200.0),
+                },
+            },
+            ServerMsg::StockBought {
+                symbol: "AAPL".into(),
+                quantity: 2,
+            },
+            ServerMsg::StockSold {
+                symbol: "AAPL".into(),
+                quantity: 2,
+                profit: Some(12.5),
+            },
+            ServerMsg::AllClientData {
+                stocks: vec![PortfolioStock {
+                    symbol: "AAPL".into(),
+                    quantity: 2,
+                    total_price: 398.0,
+                }],
+                alerts: Vec::new(),
+                has_more: true,
+                failed: vec![DATA_SECTION_ALERTS.into()],
+            },
+            ServerMsg::SymbolList {
+                symbols: vec!["AAPL".into(), "MSFT".into()],
+                names: HashMap::from([("AAPL".into(), "Apple Inc.".into())]),
+                has_more: false,
+            },
+            ServerMsg::History {
+                symbol: "AAPL".into(),
+                points: vec![HistoryPoint {
+                    timestamp: 1_700_000_000,
+                    close: 190.25,
+                }],
+            },
+            ServerMsg::Error("Stock not available!".into()),
+        ]
+    }
+
+    #[test]
+    fn server_lines_with_crlf_parse() {
+        for msg in sample_server_msgs() {
+            let wire = msg.to_wire();
+            let parsed = parse_server_msg(&crlf(wire.clone()))
+                .unwrap_or_else(|| panic!("failed to parse CRLF line: {wire:?}"));
+            assert_eq!(parsed.to_wire(), wire);
+        }
+    }
+
+    #[test]
+    fn crlf_error_message_keeps_text() {
+        match parse_server_msg("ERR Not enough stocks to sell\r\n") {
+            Some(ServerMsg::Error(msg)) => assert_eq!(msg, "Not enough stocks to sell"),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn error_with_spaces_round_trips() {
+        let messages = [
+            "Stock not available!",
+            "two  spaces   inside",
+            " leading and trailing ",
+            "line one\nline two",
+            "tab\there",
+            "back\\slash \\n not a newline",
+            "",
+            " ",
+        ];
+
+        for msg in messages {
+            let wire = ServerMsg::Error(msg.into()).to_wire();
+            assert_eq!(
+                wire.matches('\n').count(),
+                1,
+                "wire must be one line: {wire:?}"
+            );
+
+            match parse_server_msg(&wire) {
+                Some(ServerMsg::Error(parsed)) => assert_eq!(parsed, msg),
+                other => panic!("unexpected parse result: {:?}", other),
+            }
+            match parse_server_line(&format!("#3 {wire}")) {
+                (Some(3), Some(ServerMsg::Error(parsed))) => assert_eq!(parsed, msg),
+                other => panic!("unexpected parse result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn plain_error_wire_is_unchanged() {
+        // Plain messages stay readable on the wire for telnet users and older clients.
+        assert_eq!(
+            wire_error("Stock not available!"),
+            "ERR Stock not available!\n"
+        );
+        match parse_server_msg("ERR  Wrong   password") {
+            Some(ServerMsg::Error(msg)) => assert_eq!(msg, " Wrong   password"),
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let req = ClientMsg::GetHistory {
+            symbol: "AAPL".into(),
+            range: HistoryRange::Day,
+        };
+        assert_eq!(req.to_wire(), "HISTORY AAPL DAY\n");
+        match parse_client_msg("HISTORY AAPL DAY") {
+            Some(ClientMsg::GetHistory { symbol, range }) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(range, HistoryRange::Day);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+        assert!(parse_client_msg("HISTORY AAPL WEEK").is_none());
+
+        let points = vec![
+            HistoryPoint {
+                timestamp: 1_700_000_000,
+                close: 190.5,
+            },
+            HistoryPoint {
+                timestamp: 1_700_000_300,
+                close: 191.0,
+            },
+        ];
+        let wire = ServerMsg::History {
+            symbol: "AAPL".into(),
+            points: points.clone(),
+        }
+        .to_wire();
+        assert_eq!(wire, "HISTORY AAPL 1700000000:190.5 1700000300:191\n");
+        match parse_server_msg(&wire) {
+            Some(ServerMsg::History {
+                symbol,
+                points: parsed,
+            }) => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(parsed, points);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        assert!(matches!(
+            parse_server_msg("HISTORY AAPL"),
+            Some(ServerMsg::History { points, .. }) if points.is_empty()
+        ));
+        assert!(parse_server_msg("HISTORY AAPL 1700000000-190.5").is_none());
+    }
+
+    #[test]
+    fn ping_pong_wire() {
+        assert_eq!(ServerMsg::Ping.to_wire(), "PING\n");
+        assert!(matches!(parse_server_msg("PING"), Some(ServerMsg::Ping)));
+        assert_eq!(ClientMsg::Pong.to_wire(), "PONG\n");
+        assert!(matches!(
+            parse_client_msg("PONG\r\n"),
+            Some(ClientMsg::Pong)
+        ));
+    }
+
+    #[test]
+    fn latency_probe_echoes_the_nonce() {
+        let nonce = u64::MAX - 7;
+        let ping = ClientMsg::Ping { nonce };
+        assert_eq!(ping.to_wire(), format!("PING {nonce}\n"));
+        assert!(matches!(
+            parse_client_msg(&ping.to_wire()),
+            Some(ClientMsg::Ping { nonce: n }) if n == nonce
+        ));
+        assert!(parse_client_msg("PING").is_none());
+        assert!(parse_client_msg("PING abc").is_none());
+
+        let reply = ServerMsg::Heartbeat { nonce };
+        assert!(matches!(
+            parse_server_line(&reply.to_wire_with_id(Some(3))),
+            (Some(3), Some(ServerMsg::Heartbeat { nonce: n })) if n == nonce
+        ));
+        // The bare forms keep their old meaning.
+        assert!(matches!(parse_server_msg("PING"), Some(ServerMsg::Ping)));
+        assert!(parse_server_msg("PONG").is_none());
+    }
+
+    #[test]
+    fn add_many_round_trip() {
+        let msg = ClientMsg::AddAlerts {
+            alerts: vec![
+                AlertRequest {
+                    symbol: "AAPL".into(),
+                    direction: AlertDirection::Above,
+                    threshold: 200.0,
+                },
+                AlertRequest {
+                    symbol: "MSFT".into(),
+                    direction: AlertDirection::Below,
+                    threshold: 310.5,
+                },
+            ],
+        };
+        let wire = msg.to_wire();
+        assert_eq!(wire, "ADDMANY AAPL ABOVE 200 MSFT BELOW 310.5\n");
+        match parse_client_msg(&wire) {
+            Some(ClientMsg::AddAlerts { alerts }) => {
+                assert_eq!(alerts.len(), 2);
+                assert_eq!(alerts[1].symbol, "MSFT");
+                assert_eq!(alerts[1].direction, AlertDirection::Below);
+                assert_eq!(alerts[1].threshold, 310.5);
+            }
+            other => panic!("unexpected parse result: {:?}", other),
+        }
+
+        assert!(parse_client_msg("ADDMANY").is_none());
+        assert!(parse_client_msg("ADDMANY AAPL ABOVE").is_none());
+        assert!(parse_client_msg("ADDMANY AAPL UP 200").is_none());
+
+        assert_eq!(
+            ServerMsg::AlertsAdded {
+                added: 8,
+                skipped: 2,
+                unpriced: 3,
+            }
+            .to_wire(),
+            "ALERTSADDED 8 2 3\n"
+        );
+        assert!(matches!(
+            parse_server_msg("ALERTSADDED 8 2"),
+            Some(ServerMsg::AlertsAdded {
+                added: 8,
+                skipped: 2,
+                unpriced: 0,
+            })
+        ));
+        assert!(parse_server_msg("ALERTSADDED 8 2 x").is_none());
+    }
+
+    // xorshift64, so a failing case can be replayed from the printed line.
+    struct Fuzz(u64);
+
+    impl Fuzz {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+
+        fn mutate(&mut self, line: &str) -> String {
+            const TOKENS: &[&str] = &[
+                "",
+                " ",
+                "  ",
+                "\t",
+                "\r",
+                "\n",
+                "#",
+                "#7",
+                "#18446744073709551616",
+                "-",
+                "-1",
+                "0",
+                "1e309",
+                "NaN",
+                "inf",
+                "-inf",
+                "18446744073709551616",
+                "ABOVE",
+                "below",
+                "ż",
+                "€",
+                "🚀",
+                "\\",
+                "\\n",
+                "\\s",
+                ":",
+                "1700000000:",
+                ":190.5",
+                "[",
+                "{",
+                "\"",
+                "ADD",
+                "DATA",
+                "ERR",
+                "PRICE",
+            ];
+            let mut words: Vec<String> = line.split(' ').map(str::to_string).collect();
+            for _ in 0..=self.below(3) {
+                let at = self.below(words.len() + 1);
+                match self.below(6) {
+                    0 => words.insert(at, self.pick(TOKENS).to_string()),
+                    1 if at < words.len() => {
+                        words.remove(at);
+                    }
+                    2 if at < words.len() => words[at] = self.pick(TOKENS).to_string(),
+                    3 if at < words.len() => {
+                        let word = words[at].clone();
+                        words.insert(at, word);
+                    }
+                    4 if at < words.len() => {
+                        // Cut the word at a random char, may land inside a multi-byte one.
+                        let chars: Vec<char> = words[at].chars().collect();
+                        let keep = self.below(chars.len() + 1);
+                        words[at] = chars[..keep].iter().collect();
+                    }
+                    _ => {
+                        let bytes: Vec<u8> =
+                            (0..self.below(12)).map(|_| self.next() as u8).collect();
+                        words.insert(at, String::from_utf8_lossy(&bytes).into_owned());
+                    }
+                }
+            }
+            words.join(" ")
+        }
+    }
+
+    #[test]
+    fn parsers_survive_random_and_mutated_lines() {
+        let client: Vec<String> = sample_client_msgs().iter().map(|m| m.to_wire()).collect();
+        let server: Vec<String> = sample_server_msgs().iter().map(|m| m.to_wire()).collect();
+        let corpus: Vec<&str> = client.iter().chain(&server).map(|l| l.trim_end()).collect();
+        let mut fuzz = Fuzz(0x9e37_79b9_7f4a_7c15);
+
+        for round in 0..20_000 {
+            let line = if round % 4 == 0 {
+                let bytes: Vec<u8> = (0..fuzz.below(64)).map(|_| fuzz.next() as u8).collect();
+                String::from_utf8_lossy(&bytes).into_owned()
+            } else {
+                let base = fuzz.pick(&corpus);
+                fuzz.mutate(base)
+            };
+
+            // Anything the parsers accept has to serialize and parse back.
+            let result = std::panic::catch_unwind(|| {
+                if let Some(msg) = parse_client_line(&line).1 {
+                    assert!(parse_client_msg(&msg.to_wire()).is_some());
+                }
+                if let Some(msg) = parse_server_line(&line).1 {
+                    assert!(parse_server_msg(&msg.to_wire()).is_some());
+                }
+            });
+            assert!(result.is_ok(), "parser panicked on {line:?}");
+        }
+    }
 }
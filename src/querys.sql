@@ -20,4 +20,64 @@ CREATE TABLE IF NOT EXISTS positions (
     quantity INTEGER NOT NULL,
     price_total REAL NOT NULL,
     FOREIGN KEY(user_id) REFERENCES users(id)
-);
\ No newline at end of file
+);
+
+-- Stop-loss / take-profit for a whole position, at most one per user and symbol.
+-- fill_* stay NULL while the bracket is armed and are set once it sold the position,
+-- the row is removed after the user was told about it.
+CREATE TABLE IF NOT EXISTS brackets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    symbol TEXT NOT NULL,
+    stop REAL,
+    target REAL,
+    fill_quantity INTEGER,
+    fill_price REAL,
+    fill_profit REAL,
+    UNIQUE(user_id, symbol),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- When each alert last fired, an alert stays quiet for the cooldown after that.
+CREATE TABLE IF NOT EXISTS alert_triggers (
+    user_id INTEGER NOT NULL,
+    symbol TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    triggered_at INTEGER NOT NULL,
+    PRIMARY KEY(user_id, symbol, direction),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- What the server pushes on its own, a user without a row gets protocol::Preferences::default().
+CREATE TABLE IF NOT EXISTS preferences (
+    user_id INTEGER PRIMARY KEY,
+    notify_triggers INTEGER NOT NULL,
+    notify_fills INTEGER NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+-- Last scraped price of every tracked symbol, loaded into the price map at startup so
+-- portfolios can be valued before the first scrape fills in the gaps.
+CREATE TABLE IF NOT EXISTS price_cache (
+    symbol TEXT PRIMARY KEY,
+    price REAL NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+-- A user has at most one alert per symbol and direction, adding it again only moves the
+-- threshold (see add_alert). Duplicates left by the old check-then-insert are dropped first,
+-- the oldest row stays.
+DELETE FROM alerts WHERE id NOT IN (
+    SELECT MIN(id) FROM alerts GROUP BY user_id, symbol, direction COLLATE NOCASE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS alerts_user_symbol_direction
+    ON alerts (user_id, symbol, direction COLLATE NOCASE);
+
+-- price_total is the cost of the shares held. The old "spent minus earned" bookkeeping left
+-- a remainder on closed positions and took the proceeds of partial sales off open ones.
+-- Closed positions are reset so a new buy starts from a clean basis. Without a trade history
+-- the real cost of an open position can't be recomputed: one whose proceeds outgrew its cost
+-- (basis at or below 0) is reset to 0, a positive basis is kept and stays understated by
+-- whatever was sold before the upgrade.
+UPDATE positions SET price_total = 0
+    WHERE price_total != 0 AND (quantity = 0 OR price_total < 0);
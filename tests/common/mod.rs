@@ -0,0 +1,149 @@
+// Helpers shared by the e2e suites, they talk to a server already running at SERVER_ADDR
+// (127.0.0.1:1234 by default). Not every suite uses every helper.
+// E2E_TIMEOUT_SECS overrides how long a reply may take, for slow CI machines.
+#![allow(dead_code)]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use rust_huge_project::protocol::{ClientMsg, ServerMsg, parse_server_msg};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const TIMEOUT_ENV: &str = "E2E_TIMEOUT_SECS";
+
+// Lines kept for the panic message when a wait fails.
+const SEEN_LINES_SHOWN: usize = 5;
+
+pub fn unique_suffix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// DEFAULT_TIMEOUT unless E2E_TIMEOUT_SECS holds a whole number of seconds.
+pub fn reply_timeout() -> Duration {
+    match std::env::var(TIMEOUT_ENV) {
+        Ok(raw) => match raw.trim().parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => panic!("{TIMEOUT_ENV} must be a number of seconds: {raw}"),
+        },
+        Err(_) => DEFAULT_TIMEOUT,
+    }
+}
+
+pub struct TestClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+    // Used by `request`, `request_within` takes its own.
+    timeout: Duration,
+}
+
+impl TestClient {
+    pub async fn connect() -> Self {
+        let addr = std::env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:1234".into());
+        let stream = TcpStream::connect(&addr)
+            .await
+            .unwrap_or_else(|e| panic!("failed to connect to live server at {addr}: {e}"));
+        let (read_half, write) = stream.into_split();
+        Self {
+            lines: BufReader::new(read_half).lines(),
+            write,
+            timeout: reply_timeout(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn send(&mut self, msg: &ClientMsg) {
+        self.write
+            .write_all(msg.to_wire().as_bytes())
+            .await
+            .unwrap_or_else(|e| panic!("failed to send {msg:?}: {e}"));
+        self.write.flush().await.unwrap();
+    }
+
+    // Skips PINGs, other replies and unparsable lines until `pred` matches. An ERR the
+    // predicate doesn't accept fails the test right away instead of running into the timeout.
+    pub async fn wait_for<F>(&mut self, label: &str, timeout: Duration, mut pred: F) -> ServerMsg
+    where
+        F: FnMut(&ServerMsg) -> bool,
+    {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut seen: Vec<String> = Vec::new();
+        loop {
+            let line = match tokio::time::timeout_at(deadline, self.lines.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                Ok(Ok(None)) => panic!(
+                    "server closed the connection while waiting for {label}, last lines: {seen:?}"
+                ),
+                Ok(Err(e)) => panic!("failed to read while waiting for {label}: {e}"),
+                Err(_) => {
+                    panic!("timeout after {timeout:?} waiting for {label}, last lines: {seen:?}")
+                }
+            };
+            if let Some(msg) = parse_server_msg(&line) {
+                if pred(&msg) {
+                    return msg;
+                }
+                if let ServerMsg::Error(error) = msg {
+                    panic!("server error while waiting for {label}: {error}, last lines: {seen:?}");
+                }
+            }
+            if seen.len() == SEEN_LINES_SHOWN {
+                seen.remove(0);
+            }
+            seen.push(line);
+        }
+    }
+
+    // Sends `msg` and waits for the reply `pred` accepts, with the client's timeout.
+    pub async fn request<F>(&mut self, msg: ClientMsg, label: &str, pred: F) -> ServerMsg
+    where
+        F: FnMut(&ServerMsg) -> bool,
+    {
+        let timeout = self.timeout;
+        self.request_within(msg, label, timeout, pred).await
+    }
+
+    pub async fn request_within<F>(
+        &mut self,
+        msg: ClientMsg,
+        label: &str,
+        timeout: Duration,
+        pred: F,
+    ) -> ServerMsg
+    where
+        F: FnMut(&ServerMsg) -> bool,
+    {
+        self.send(&msg).await;
+        self.wait_for(label, timeout, pred).await
+    }
+
+    pub async fn register_and_login(&mut self, username: &str, password: &str) {
+        self.request(
+            ClientMsg::RegisterClient {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+            "UserRegistered",
+            |msg| matches!(msg, ServerMsg::UserRegistered),
+        )
+        .await;
+        self.request(
+            ClientMsg::LoginClient {
+                username: username.to_string(),
+                password: password.to_string(),
+            },
+            "UserLogged",
+            |msg| matches!(msg, ServerMsg::UserLogged),
+        )
+        .await;
+    }
+}
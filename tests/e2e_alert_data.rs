@@ -0,0 +1,55 @@
+mod common;
+
+use common::{TestClient, unique_suffix};
+use rust_huge_project::protocol::{AlertDirection, AlertRequest, ClientMsg, ServerMsg};
+
+#[tokio::test]
+async fn e2e_added_alert_is_returned_by_data() {
+    let mut client = TestClient::connect().await;
+    client
+        .register_and_login(&format!("alerts_{}", unique_suffix()), "pass123")
+        .await;
+
+    // Stored even while the server has no price for the symbol yet.
+    let add = ClientMsg::AddAlert(AlertRequest {
+        symbol: "AAPL".into(),
+        direction: AlertDirection::Below,
+        threshold: 123.5,
+    });
+    client
+        .request(
+            add,
+            "AlertAdded",
+            |msg| matches!(msg, ServerMsg::AlertAdded { symbol, .. } if symbol == "AAPL"),
+        )
+        .await;
+
+    let data = ClientMsg::GetAllClientData {
+        offset: 0,
+        limit: None,
+    };
+    match client
+        .request(data, "AllClientData", |msg| {
+            matches!(msg, ServerMsg::AllClientData { .. })
+        })
+        .await
+    {
+        ServerMsg::AllClientData { alerts, .. } => {
+            assert_eq!(alerts.len(), 1, "expected the added alert, got {alerts:?}");
+            assert_eq!(alerts[0].symbol, "AAPL");
+            assert_eq!(alerts[0].direction, AlertDirection::Below);
+            assert_eq!(alerts[0].threshold, 123.5);
+        }
+        other => panic!("expected AllClientData, got {other:?}"),
+    }
+
+    let remove = ClientMsg::RemoveAlert {
+        symbol: "AAPL".into(),
+        direction: AlertDirection::Below,
+    };
+    client
+        .request(remove, "AlertRemoved", |msg| {
+            matches!(msg, ServerMsg::AlertRemoved { .. })
+        })
+        .await;
+}
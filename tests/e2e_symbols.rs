@@ -0,0 +1,28 @@
+mod common;
+
+use common::{TestClient, unique_suffix};
+use rust_huge_project::protocol::{ClientMsg, ServerMsg};
+
+#[tokio::test]
+async fn e2e_list_symbols() {
+    let mut client = TestClient::connect().await;
+    client
+        .register_and_login(&format!("symbols_{}", unique_suffix()), "pass123")
+        .await;
+
+    let list = ClientMsg::ListSymbols {
+        offset: 0,
+        limit: None,
+    };
+    match client
+        .request(list, "SymbolList", |msg| {
+            matches!(msg, ServerMsg::SymbolList { .. })
+        })
+        .await
+    {
+        ServerMsg::SymbolList { symbols, .. } => {
+            assert!(!symbols.is_empty(), "expected tracked symbols");
+        }
+        other => panic!("expected SymbolList, got {other:?}"),
+    }
+}